@@ -0,0 +1,170 @@
+//! Store benchmarks across store sizes
+//!
+//! Run with `cargo bench -p portal_setting`; compare runs with
+//! `--save-baseline` and `--baseline` to quantify a change.
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use portal_setting::{config, ChangeObserver, Config, LimitsConfig, SettingChange, SettingsStore};
+use std::hint::black_box;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use tokio::runtime::Runtime;
+use zbus::zvariant::{OwnedValue, Value};
+
+/// Number of extra keys stored besides the defaults
+const SIZES: [usize; 3] = [10, 100, 10_000];
+/// Keys are spread over this many namespaces
+const NAMESPACES: usize = 10;
+
+fn namespace(index: usize) -> String {
+    format!("org.example.bench{}", index % NAMESPACES)
+}
+
+/// A store holding the defaults plus `size` string keys
+fn store(runtime: &Runtime, size: usize) -> SettingsStore {
+    let store = SettingsStore::new().with_limits(LimitsConfig::unlimited());
+    runtime.block_on(async {
+        for index in 0..size {
+            let value = OwnedValue::try_from(Value::from(format!("value-{}", index))).unwrap();
+            store.write(&namespace(index), &format!("key-{}", index), value).await.unwrap();
+        }
+    });
+    store
+}
+
+struct Counter(AtomicUsize);
+
+impl ChangeObserver for Counter {
+    fn on_change(&self, change: &SettingChange) {
+        black_box(change);
+        self.0.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+fn startup(c: &mut Criterion) {
+    let mut group = c.benchmark_group("startup");
+    group.bench_function("builtin", |b| b.iter(SettingsStore::new));
+    let empty = Config::default();
+    group.bench_function("empty-config", |b| b.iter(|| SettingsStore::from_config(&empty).unwrap()));
+    // The seed sets every built-in key, so no built-in default is used
+    let full = Config::parse(&config::seed()).unwrap();
+    group.bench_function("full-config", |b| b.iter(|| SettingsStore::from_config(&full).unwrap()));
+    group.finish();
+}
+
+fn read(c: &mut Criterion) {
+    let runtime = Runtime::new().unwrap();
+    let mut group = c.benchmark_group("read");
+    for size in SIZES {
+        let store = store(&runtime, size);
+        group.bench_with_input(BenchmarkId::from_parameter(size), &size, |b, _| {
+            b.to_async(&runtime)
+                .iter(|| async { black_box(store.read("org.freedesktop.appearance", "color-scheme").await) });
+        });
+    }
+    group.finish();
+}
+
+fn read_all(c: &mut Criterion) {
+    let runtime = Runtime::new().unwrap();
+    let mut group = c.benchmark_group("read_all");
+    for size in SIZES {
+        let store = store(&runtime, size);
+        // No filter, one namespace, and half of the namespaces
+        for filter in [0, 1, NAMESPACES / 2] {
+            let namespaces: Vec<String> = (0..filter).map(namespace).collect();
+            let id = BenchmarkId::new(format!("{}-namespaces", filter), size);
+            group.bench_with_input(id.clone(), &namespaces, |b, namespaces| {
+                b.to_async(&runtime)
+                    .iter(|| async { black_box(store.read_all(namespaces.clone()).await) });
+            });
+            group.bench_with_input(
+                BenchmarkId::new(format!("{}-namespaces-reply", filter), size),
+                &namespaces,
+                |b, namespaces| {
+                    b.to_async(&runtime)
+                        .iter(|| async { black_box(store.read_all_reply(namespaces.clone()).await) });
+                },
+            );
+        }
+    }
+    group.finish();
+}
+
+fn write_notify(c: &mut Criterion) {
+    let runtime = Runtime::new().unwrap();
+    let mut group = c.benchmark_group("write_notify");
+    for size in SIZES {
+        let store = store(&runtime, size);
+        store.add_observer(Arc::new(Counter(AtomicUsize::new(0))));
+        let mut scheme = 0u32;
+        group.bench_with_input(BenchmarkId::from_parameter(size), &size, |b, _| {
+            b.to_async(&runtime).iter(|| {
+                scheme = (scheme + 1) % 3;
+                let value = OwnedValue::from(scheme);
+                let store = &store;
+                async move { store.write("org.freedesktop.appearance", "color-scheme", value).await.unwrap() }
+            });
+        });
+    }
+    group.finish();
+}
+
+fn fan_out(c: &mut Criterion) {
+    let runtime = Runtime::new().unwrap();
+    let mut group = c.benchmark_group("fan_out");
+    for observers in [1, 10, 100] {
+        let store = store(&runtime, 100);
+        let counter = Arc::new(Counter(AtomicUsize::new(0)));
+        for _ in 0..observers {
+            store.add_observer(counter.clone());
+        }
+        let mut scheme = 0u32;
+        group.throughput(Throughput::Elements(observers as u64));
+        group.bench_with_input(BenchmarkId::from_parameter(observers), &observers, |b, _| {
+            b.to_async(&runtime).iter(|| {
+                scheme = (scheme + 1) % 3;
+                let value = OwnedValue::from(scheme);
+                let store = &store;
+                async move { store.write("org.freedesktop.appearance", "color-scheme", value).await.unwrap() }
+            });
+        });
+    }
+    group.finish();
+}
+
+/// Opening a state file of 1000 entries in each format
+#[cfg(feature = "persistence")]
+fn state_load(c: &mut Criterion) {
+    use portal_setting::config::StateFormat;
+    use portal_setting::StateFile;
+
+    let runtime = Runtime::new().unwrap();
+    let dir = std::env::temp_dir().join(format!("portal-bench-state-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let mut group = c.benchmark_group("state-load");
+    for (name, format) in [("toml", StateFormat::Toml), ("binary", StateFormat::Binary)] {
+        let path = dir.join(name);
+        let state = Arc::new(StateFile::open(&path).unwrap().with_format(format));
+        let store = SettingsStore::new().with_limits(LimitsConfig::unlimited());
+        store.add_observer(state.clone());
+        runtime.block_on(async {
+            let values = (0..1000)
+                .map(|index| {
+                    let value = OwnedValue::try_from(Value::from(format!("value-{}", index))).unwrap();
+                    (namespace(index), format!("key-{}", index), value)
+                })
+                .collect();
+            store.write_many_from(portal_setting::ChangeSource::Api, values).await.unwrap();
+        });
+        group.bench_function(name, |b| b.iter(|| StateFile::open(&path).unwrap()));
+    }
+    group.finish();
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[cfg(not(feature = "persistence"))]
+fn state_load(_c: &mut Criterion) {}
+
+criterion_group!(benches, startup, read, read_all, write_notify, fan_out, state_load);
+criterion_main!(benches);
@@ -0,0 +1,23 @@
+//! Decode raw bytes the way a `Variant` argument of `Write` is decoded, then
+//! write whatever comes out
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use portal_setting_fuzz::{write, FuzzKey};
+use zbus::zvariant::serialized::{Context, Data};
+use zbus::zvariant::{Value, BE, LE};
+
+fuzz_target!(|input: (FuzzKey, bool, &[u8])| {
+    let (key, big_endian, bytes) = input;
+    let context = match big_endian {
+        true => Context::new_dbus(BE, 0),
+        false => Context::new_dbus(LE, 0),
+    };
+    let data = Data::new(bytes, context);
+    let Ok((value, _)) = data.deserialize::<Value<'_>>() else {
+        return;
+    };
+    let (namespace, key) = key.names();
+    write(&namespace, &key, value);
+});
@@ -0,0 +1,13 @@
+//! Write generated values, of any type and nesting, to built-in, constrained
+//! and arbitrary settings
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use portal_setting_fuzz::{write, FuzzKey, FuzzValue};
+
+fuzz_target!(|input: (FuzzKey, FuzzValue)| {
+    let (key, value) = input;
+    let (namespace, key) = key.names();
+    write(&namespace, &key, value.to_value());
+});
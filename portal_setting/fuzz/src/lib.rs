@@ -0,0 +1,177 @@
+//! Inputs shared by the fuzz targets
+
+use arbitrary::Arbitrary;
+use portal_setting::{schema, Config, SettingsStore};
+use std::sync::OnceLock;
+use zbus::zvariant::{Array, Dict, ObjectPath, OwnedValue, Signature, StructureBuilder, Value};
+
+/// Containers nested deeper than this become strings; D-Bus allows 32
+/// levels, so values at the limit are still generated
+const MAX_DEPTH: usize = 40;
+
+/// A value of any D-Bus type the generator knows, including nested
+/// containers and very long strings
+#[derive(Debug, Arbitrary)]
+pub enum FuzzValue {
+    U8(u8),
+    Bool(bool),
+    I16(i16),
+    U16(u16),
+    I32(i32),
+    U32(u32),
+    I64(i64),
+    U64(u64),
+    F64(f64),
+    Str(String),
+    /// `len` copies of `c`, up to 1 MiB
+    Huge(char, u32),
+    ObjectPath(String),
+    Signature(String),
+    /// Elements with another signature than the first are left out
+    Array(Vec<FuzzValue>),
+    Dict(Vec<(String, FuzzValue)>),
+    Structure(Vec<FuzzValue>),
+    Variant(Box<FuzzValue>),
+}
+
+impl FuzzValue {
+    pub fn to_value(&self) -> Value<'static> {
+        self.at_depth(0)
+    }
+
+    fn at_depth(&self, depth: usize) -> Value<'static> {
+        if depth > MAX_DEPTH {
+            return Value::from("too deep");
+        }
+        match self {
+            FuzzValue::U8(v) => Value::U8(*v),
+            FuzzValue::Bool(v) => Value::Bool(*v),
+            FuzzValue::I16(v) => Value::I16(*v),
+            FuzzValue::U16(v) => Value::U16(*v),
+            FuzzValue::I32(v) => Value::I32(*v),
+            FuzzValue::U32(v) => Value::U32(*v),
+            FuzzValue::I64(v) => Value::I64(*v),
+            FuzzValue::U64(v) => Value::U64(*v),
+            FuzzValue::F64(v) => Value::F64(*v),
+            FuzzValue::Str(s) => Value::from(s.clone()),
+            FuzzValue::Huge(c, len) => Value::from(c.to_string().repeat((*len % (1 << 20)) as usize)),
+            FuzzValue::ObjectPath(s) => match ObjectPath::try_from(s.clone()) {
+                Ok(path) => Value::ObjectPath(path),
+                Err(_) => Value::from(s.clone()),
+            },
+            FuzzValue::Signature(s) => match Signature::try_from(s.clone()) {
+                Ok(signature) => Value::Signature(signature),
+                Err(_) => Value::from(s.clone()),
+            },
+            FuzzValue::Array(items) => {
+                let items: Vec<Value<'static>> = items.iter().map(|item| item.at_depth(depth + 1)).collect();
+                let signature = match items.first() {
+                    Some(first) => first.value_signature().to_owned(),
+                    None => Signature::from_static_str_unchecked("s"),
+                };
+                let mut array = Array::new(signature);
+                for item in items {
+                    // Mixed element types are not representable
+                    let _ = array.append(item);
+                }
+                Value::Array(array)
+            }
+            FuzzValue::Dict(entries) => {
+                let mut dict = Dict::new(
+                    Signature::from_static_str_unchecked("s"),
+                    Signature::from_static_str_unchecked("v"),
+                );
+                for (key, value) in entries {
+                    let value = Value::Value(Box::new(value.at_depth(depth + 2)));
+                    let _ = dict.append(Value::from(key.clone()), value);
+                }
+                Value::Dict(dict)
+            }
+            // D-Bus has no empty structures
+            FuzzValue::Structure(fields) if fields.is_empty() => Value::from("()"),
+            FuzzValue::Structure(fields) => {
+                let builder = fields
+                    .iter()
+                    .fold(StructureBuilder::new(), |builder, field| builder.append_field(field.at_depth(depth + 1)));
+                Value::Structure(builder.build())
+            }
+            FuzzValue::Variant(inner) => Value::Value(Box::new(inner.at_depth(depth + 1))),
+        }
+    }
+}
+
+/// A setting to write: a built-in one, one with a `[schema]` constraint, or
+/// any name
+#[derive(Debug, Arbitrary)]
+pub enum FuzzKey {
+    Builtin(u8),
+    Constrained(u8),
+    Custom(String, String),
+}
+
+/// Keys of the `[schema]` tables of `store`
+const CONSTRAINED: &[&str] = &["days", "clock", "label", "tags", "ratio", "big"];
+
+impl FuzzKey {
+    pub fn names(&self) -> (String, String) {
+        match self {
+            FuzzKey::Builtin(i) => {
+                let setting = &schema::BUILTIN[*i as usize % schema::BUILTIN.len()];
+                (setting.namespace.to_string(), setting.key.to_string())
+            }
+            FuzzKey::Constrained(i) => (
+                "com.example.Fuzz".to_string(),
+                CONSTRAINED[*i as usize % CONSTRAINED.len()].to_string(),
+            ),
+            FuzzKey::Custom(namespace, key) => (namespace.clone(), key.clone()),
+        }
+    }
+}
+
+/// A store with a constraint of every kind
+pub fn store() -> SettingsStore {
+    static CONFIG: OnceLock<Config> = OnceLock::new();
+    let config = CONFIG.get_or_init(|| {
+        Config::parse(
+            r#"
+            [limits]
+            allow_any_namespace = true
+
+            [schema."com.example.Fuzz"]
+            days = { type = "i", min = 1, max = 365 }
+            clock = { type = "s", choices = ["12h", "24h"] }
+            label = { type = "s", pattern = "^[A-Za-z0-9 .-]+$", min_length = 1, max_length = 32 }
+            tags = { type = "as", max_length = 4 }
+            ratio = { type = "d", min = 0.0, max = 1.0 }
+            big = { type = "x", min = -1 }
+            "#,
+        )
+        .unwrap()
+    });
+    SettingsStore::from_config(config).unwrap()
+}
+
+/// Write `value` to `namespace`/`key` of a fresh store, after checking it
+/// against every built-in setting
+///
+/// Errors are expected; only panics are findings.
+pub fn write(namespace: &str, key: &str, value: Value<'_>) {
+    static RUNTIME: OnceLock<tokio::runtime::Runtime> = OnceLock::new();
+    let runtime = RUNTIME.get_or_init(|| tokio::runtime::Builder::new_current_thread().build().unwrap());
+
+    let Ok(value) = OwnedValue::try_from(value) else {
+        return;
+    };
+    for setting in schema::BUILTIN {
+        let _ = SettingsStore::validate_setting(setting.namespace, setting.key, &value);
+    }
+    let store = store();
+    runtime.block_on(async {
+        if store.write(namespace, key, value).await.is_ok() {
+            // What was accepted must be readable and serializable again
+            let stored = store.read(namespace, key).await.expect("accepted value is stored");
+            let context = zbus::zvariant::serialized::Context::new_dbus(zbus::zvariant::LE, 0);
+            zbus::zvariant::to_bytes(context, &*stored.0).expect("accepted value serializes");
+        }
+    });
+}
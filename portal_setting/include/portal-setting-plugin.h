@@ -0,0 +1,58 @@
+/*
+ * Plugin interface of portal-setting-service
+ *
+ * A plugin is a shared object installed in the plugin directory
+ * (`--plugin-dir` or `[plugins] dir`) exporting
+ * `portal_setting_plugin_init`. It can provide default settings (`load`),
+ * observe changes (`on_change`), or both.
+ *
+ * Values are passed as a D-Bus signature and the value in TOML syntax, e.g.
+ * "u" and "1", "s" and "\"Adwaita\"" or "(ddd)" and "[0.2, 0.4, 0.6]".
+ */
+
+#ifndef PORTAL_SETTING_PLUGIN_H
+#define PORTAL_SETTING_PLUGIN_H
+
+#include <stdint.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+#define PORTAL_SETTING_PLUGIN_ABI_VERSION 1
+
+/* Report one setting from `load`; returns 0 if it was accepted */
+typedef int (*portal_setting_emit_fn)(void *ctx, const char *namespace_, const char *key,
+                                      const char *signature, const char *value);
+
+struct portal_setting_plugin {
+    /* PORTAL_SETTING_PLUGIN_ABI_VERSION */
+    uint32_t abi_version;
+    /* Name used in log messages, or NULL for the file name */
+    const char *name;
+    /* Passed back as the first argument of every callback */
+    void *data;
+
+    /* Report default settings by calling `emit(ctx, ...)`; return 0 on success */
+    int (*load)(void *data, void *ctx, portal_setting_emit_fn emit);
+
+    /*
+     * Called after every change. `kind` is "write", "delete" or "reset";
+     * `signature` and `value` are NULL if the key no longer exists. May be
+     * called from any thread.
+     */
+    void (*on_change)(void *data, const char *namespace_, const char *key, const char *kind,
+                      const char *source, const char *signature, const char *value);
+
+    /* Release `data`; called once before the plugin is unloaded */
+    void (*destroy)(void *data);
+};
+
+/* Every callback may be NULL. The returned table must stay valid until `destroy` */
+const struct portal_setting_plugin *portal_setting_plugin_init(void);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif /* PORTAL_SETTING_PLUGIN_H */
@@ -0,0 +1,41 @@
+use crate::{Result, SettingValue, SettingsStore};
+use futures_util::future::BoxFuture;
+use std::collections::HashMap;
+use zbus::zvariant::OwnedValue;
+
+/// What applications do with a settings store, as a trait object
+///
+/// Code taking an `Arc<dyn SettingsAccess>` runs against a `SettingsStore`
+/// in production and a `testing::MockSettingsStore` in its unit tests.
+pub trait SettingsAccess: Send + Sync {
+    /// The value of `namespace`/`key`, if set
+    fn read<'a>(&'a self, namespace: &'a str, key: &'a str) -> BoxFuture<'a, Option<SettingValue>>;
+
+    /// The settings in `namespaces`, or all of them if empty
+    fn read_all(&self, namespaces: Vec<String>) -> BoxFuture<'_, HashMap<String, HashMap<String, SettingValue>>>;
+
+    /// Set `namespace`/`key`, or fail with the reason the value is refused
+    fn write<'a>(&'a self, namespace: &'a str, key: &'a str, value: OwnedValue) -> BoxFuture<'a, Result<()>>;
+
+    /// Restore `namespace`/`key` to its default, returning whether anything
+    /// changed
+    fn reset<'a>(&'a self, namespace: &'a str, key: &'a str) -> BoxFuture<'a, bool>;
+}
+
+impl SettingsAccess for SettingsStore {
+    fn read<'a>(&'a self, namespace: &'a str, key: &'a str) -> BoxFuture<'a, Option<SettingValue>> {
+        Box::pin(SettingsStore::read(self, namespace, key))
+    }
+
+    fn read_all(&self, namespaces: Vec<String>) -> BoxFuture<'_, HashMap<String, HashMap<String, SettingValue>>> {
+        Box::pin(SettingsStore::read_all(self, namespaces))
+    }
+
+    fn write<'a>(&'a self, namespace: &'a str, key: &'a str, value: OwnedValue) -> BoxFuture<'a, Result<()>> {
+        Box::pin(SettingsStore::write(self, namespace, key, value))
+    }
+
+    fn reset<'a>(&'a self, namespace: &'a str, key: &'a str) -> BoxFuture<'a, bool> {
+        Box::pin(SettingsStore::reset(self, namespace, key))
+    }
+}
@@ -0,0 +1,74 @@
+use crate::shards::Shards;
+use crate::{Lookup, SettingKey};
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+/// Deprecated names of settings and the settings they stand for
+///
+/// Reads and writes of an alias go to its target, and the snapshot served
+/// holds a copy of the target's value under the alias, so `ReadAll` lists
+/// both names while clients move to the new one.
+#[derive(Default)]
+pub(crate) struct Aliases {
+    targets: HashMap<SettingKey, Alias>,
+    /// The aliases of each target
+    aliases: HashMap<SettingKey, Vec<SettingKey>>,
+}
+
+struct Alias {
+    target: SettingKey,
+    /// Whether the deprecation was logged; it is only logged once
+    warned: AtomicBool,
+}
+
+impl Aliases {
+    pub(crate) fn new(targets: impl IntoIterator<Item = (SettingKey, SettingKey)>) -> Self {
+        let mut aliases = Self::default();
+        for (alias, target) in targets {
+            aliases.aliases.entry(target.clone()).or_default().push(alias.clone());
+            let warned = AtomicBool::new(false);
+            aliases.targets.insert(alias, Alias { target, warned });
+        }
+        aliases
+    }
+
+    /// The setting `namespace`/`key` stands for, logging the deprecation the
+    /// first time an alias is used
+    pub(crate) fn resolve<'a>(&'a self, namespace: &'a str, key: &'a str) -> (&'a str, &'a str) {
+        let Some(alias) = self.targets.get(&(namespace, key) as &dyn Lookup) else {
+            return (namespace, key);
+        };
+        if !alias.warned.swap(true, Ordering::Relaxed) {
+            eprintln!(
+                "{}/{} is deprecated, use {}/{}",
+                namespace, key, alias.target.namespace, alias.target.key
+            );
+        }
+        (&alias.target.namespace, &alias.target.key)
+    }
+
+    /// The setting `namespace`/`key` stands for, if it is an alias, without
+    /// logging anything
+    pub(crate) fn target(&self, namespace: &str, key: &str) -> Option<&SettingKey> {
+        self.targets.get(&(namespace, key) as &dyn Lookup).map(|alias| &alias.target)
+    }
+
+    /// Copy the value of `namespace`/`key` in `settings` to its aliases, or
+    /// remove them with it
+    pub(crate) fn mirror(&self, settings: &mut Shards, namespace: &str, key: &str) {
+        let Some(aliases) = self.aliases.get(&(namespace, key) as &dyn Lookup) else {
+            return;
+        };
+        let value = settings.get(namespace, key).cloned();
+        for alias in aliases {
+            settings.mirror(&alias.namespace, &alias.key, value.clone());
+        }
+    }
+
+    /// `mirror` every target, for a new store
+    pub(crate) fn mirror_all(&self, settings: &mut Shards) {
+        for target in self.aliases.keys() {
+            self.mirror(settings, &target.namespace, &target.key);
+        }
+    }
+}
@@ -0,0 +1,466 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+#[cfg(feature = "audit")]
+use {
+    crate::{Caller, ChangeObserver, RefusedChange, SensitiveKeys, SettingChange, SettingValue},
+    crate::error::{Context, Result},
+    std::fs::{self, File, OpenOptions},
+    std::io::Write,
+    std::os::unix::net::UnixDatagram,
+    std::path::{Path, PathBuf},
+    std::sync::Mutex,
+};
+
+/// Default size at which the audit log is rotated (1 MiB)
+pub const DEFAULT_MAX_BYTES: u64 = 1024 * 1024;
+
+/// Default number of rotated audit log files to keep
+pub const DEFAULT_KEEP: usize = 3;
+
+/// Socket of the native protocol of the systemd journal
+pub const JOURNAL_SOCKET: &str = "/run/systemd/journal/socket";
+
+/// `SYSLOG_IDENTIFIER` of the records sent to the journal
+pub const JOURNAL_IDENTIFIER: &str = "portal-settings";
+
+/// Append-only audit log of settings mutations
+///
+/// Each successful change is written as one tab-separated line:
+/// `timestamp kind source namespace key old new caller`, where missing values
+/// and unknown callers are written as `-`. When the file would grow beyond
+/// `max_bytes` it is rotated to `<path>.1`, `<path>.2`, ... keeping at most
+/// `keep` old files. The values of sensitive settings are written as
+/// `<redacted>`.
+#[cfg(feature = "audit")]
+pub struct AuditLog {
+    path: PathBuf,
+    max_bytes: u64,
+    keep: usize,
+    sensitive: SensitiveKeys,
+    file: Mutex<Option<File>>,
+}
+
+#[cfg(feature = "audit")]
+impl AuditLog {
+    pub fn open(path: impl Into<PathBuf>, max_bytes: u64, keep: usize) -> Result<Self> {
+        let path = path.into();
+        let file = open_append(&path)?;
+        Ok(Self {
+            path,
+            max_bytes,
+            keep,
+            sensitive: SensitiveKeys::default(),
+            file: Mutex::new(Some(file)),
+        })
+    }
+
+    /// Mask the values of `sensitive` settings
+    pub fn with_sensitive_keys(mut self, sensitive: SensitiveKeys) -> Self {
+        self.sensitive = sensitive;
+        self
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Append a record for `change`, rotating the file first if needed
+    pub fn record(&self, change: &SettingChange) -> Result<()> {
+        let line = format_record(SystemTime::now(), change, &self.sensitive);
+        let mut file = self.file.lock().unwrap();
+
+        if let Some(current) = file.as_ref() {
+            let len = current.metadata()?.len();
+            if self.max_bytes > 0 && len > 0 && len + line.len() as u64 > self.max_bytes {
+                // Close the current file before renaming it
+                *file = None;
+                self.rotate()?;
+            }
+        }
+        if file.is_none() {
+            *file = Some(open_append(&self.path)?);
+        }
+
+        let current = file.as_mut().unwrap();
+        current.write_all(line.as_bytes())?;
+        current.flush()?;
+        Ok(())
+    }
+
+    fn rotate(&self) -> Result<()> {
+        if self.keep == 0 {
+            fs::remove_file(&self.path)?;
+            return Ok(());
+        }
+        for i in (1..self.keep).rev() {
+            let from = rotated_path(&self.path, i);
+            if from.exists() {
+                fs::rename(&from, rotated_path(&self.path, i + 1))?;
+            }
+        }
+        fs::rename(&self.path, rotated_path(&self.path, 1))?;
+        Ok(())
+    }
+}
+
+#[cfg(feature = "audit")]
+impl ChangeObserver for AuditLog {
+    fn on_change(&self, change: &SettingChange) {
+        if let Err(e) = self.record(change) {
+            eprintln!("Failed to write audit log {}: {:#}", self.path.display(), e);
+        }
+    }
+}
+
+/// Audit records sent to the systemd journal, with structured fields
+///
+/// Each successful change and each change refused over D-Bus is one entry
+/// tagged `portal-settings` with `AUDIT=1`, so `journalctl -t portal-settings
+/// AUDIT=1` lists them. Besides `MESSAGE`, entries carry `RESULT` (`ok` or
+/// `refused`), `SETTING_KIND`, `SETTING_NAMESPACE` and `SETTING_KEY`; changes
+/// add `SETTING_SOURCE`, `SETTING_OLD` and `SETTING_NEW` when present, and
+/// refusals the D-Bus `ERROR` and `ERROR_MESSAGE`. The caller, when known,
+/// is described by `SENDER`, `SENDER_UID`, `SENDER_PID`, `SENDER_APP_ID`
+/// and `SENDER_LABEL`. The values of sensitive settings are sent as
+/// `<redacted>`.
+#[cfg(feature = "audit")]
+pub struct AuditJournal {
+    socket: UnixDatagram,
+    sensitive: SensitiveKeys,
+}
+
+#[cfg(feature = "audit")]
+impl AuditJournal {
+    /// Send records to the journal at `JOURNAL_SOCKET`
+    pub fn connect() -> Result<Self> {
+        Self::connect_to(JOURNAL_SOCKET)
+    }
+
+    pub fn connect_to(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let socket = UnixDatagram::unbound()?;
+        socket
+            .connect(path)
+            .with_context(|| format!("cannot connect to the journal at {}", path.display()))?;
+        Ok(Self {
+            socket,
+            sensitive: SensitiveKeys::default(),
+        })
+    }
+
+    /// Mask the values of `sensitive` settings
+    pub fn with_sensitive_keys(mut self, sensitive: SensitiveKeys) -> Self {
+        self.sensitive = sensitive;
+        self
+    }
+
+    pub fn record(&self, change: &SettingChange) -> Result<()> {
+        let mut fields = vec![
+            (
+                "MESSAGE",
+                format!(
+                    "{} {}/{} from {} by {}",
+                    change.kind,
+                    change.namespace,
+                    change.key,
+                    change.source,
+                    caller_text(change.caller.as_ref())
+                ),
+            ),
+            ("PRIORITY", "5".to_string()),
+            ("RESULT", "ok".to_string()),
+            ("SETTING_KIND", change.kind.to_string()),
+            ("SETTING_SOURCE", change.source.to_string()),
+            ("SETTING_NAMESPACE", change.namespace.clone()),
+            ("SETTING_KEY", change.key.clone()),
+        ];
+        let show = |value: &SettingValue| self.sensitive.show(&change.namespace, &change.key, &value.0);
+        if let Some(old) = &change.old {
+            fields.push(("SETTING_OLD", show(old)));
+        }
+        if let Some(new) = &change.new {
+            fields.push(("SETTING_NEW", show(new)));
+        }
+        self.send(fields, change.caller.as_ref())
+    }
+
+    pub fn record_refused(&self, refused: &RefusedChange) -> Result<()> {
+        let fields = vec![
+            (
+                "MESSAGE",
+                format!(
+                    "{} {}/{} by {} refused: {}",
+                    refused.kind,
+                    refused.namespace,
+                    refused.key,
+                    caller_text(refused.caller.as_ref()),
+                    refused.message
+                ),
+            ),
+            ("PRIORITY", "4".to_string()),
+            ("RESULT", "refused".to_string()),
+            ("SETTING_KIND", refused.kind.to_string()),
+            ("SETTING_NAMESPACE", refused.namespace.clone()),
+            ("SETTING_KEY", refused.key.clone()),
+            ("ERROR", refused.error.clone()),
+            ("ERROR_MESSAGE", refused.message.clone()),
+        ];
+        self.send(fields, refused.caller.as_ref())
+    }
+
+    fn send(&self, mut fields: Vec<(&str, String)>, caller: Option<&Caller>) -> Result<()> {
+        fields.push(("SYSLOG_IDENTIFIER", JOURNAL_IDENTIFIER.to_string()));
+        fields.push(("AUDIT", "1".to_string()));
+        if let Some(caller) = caller {
+            let known = [
+                ("SENDER", caller.sender.clone()),
+                ("SENDER_UID", caller.uid.map(|uid| uid.to_string())),
+                ("SENDER_PID", caller.pid.map(|pid| pid.to_string())),
+                ("SENDER_APP_ID", caller.app_id.clone()),
+                ("SENDER_LABEL", caller.security_label.clone()),
+            ];
+            fields.extend(known.into_iter().filter_map(|(name, value)| Some((name, value?))));
+        }
+        self.socket.send(&encode_entry(&fields)).context("cannot send to the journal")?;
+        Ok(())
+    }
+}
+
+#[cfg(feature = "audit")]
+impl ChangeObserver for AuditJournal {
+    fn on_change(&self, change: &SettingChange) {
+        if let Err(e) = self.record(change) {
+            eprintln!("Failed to audit {}/{}: {:#}", change.namespace, change.key, e);
+        }
+    }
+
+    fn on_refused(&self, refused: &RefusedChange) {
+        if let Err(e) = self.record_refused(refused) {
+            eprintln!("Failed to audit {}/{}: {:#}", refused.namespace, refused.key, e);
+        }
+    }
+}
+
+#[cfg(feature = "audit")]
+fn caller_text(caller: Option<&Caller>) -> String {
+    caller.map_or_else(|| "-".to_string(), Caller::to_string)
+}
+
+/// An entry in the journal's native protocol: `NAME=value` lines, or for
+/// values spanning lines the name, a newline, the little-endian 64-bit
+/// length and the value
+#[cfg(feature = "audit")]
+fn encode_entry(fields: &[(&str, String)]) -> Vec<u8> {
+    let mut entry = Vec::new();
+    for (name, value) in fields {
+        entry.extend_from_slice(name.as_bytes());
+        match value.contains('\n') {
+            true => {
+                entry.push(b'\n');
+                entry.extend_from_slice(&(value.len() as u64).to_le_bytes());
+            }
+            false => entry.push(b'='),
+        }
+        entry.extend_from_slice(value.as_bytes());
+        entry.push(b'\n');
+    }
+    entry
+}
+
+#[cfg(feature = "audit")]
+fn open_append(path: &Path) -> Result<File> {
+    OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .with_context(|| format!("cannot open audit log {}", path.display()))
+}
+
+#[cfg(feature = "audit")]
+fn rotated_path(path: &Path, index: usize) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(format!(".{}", index));
+    PathBuf::from(name)
+}
+
+#[cfg(feature = "audit")]
+fn format_record(time: SystemTime, change: &SettingChange, sensitive: &SensitiveKeys) -> String {
+    let format_value = |value: Option<&SettingValue>| match value {
+        Some(v) => escape(&sensitive.show(&change.namespace, &change.key, &v.0)),
+        None => "-".to_string(),
+    };
+    format!(
+        "{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\n",
+        format_timestamp(time),
+        change.kind,
+        change.source,
+        escape(&change.namespace),
+        escape(&change.key),
+        format_value(change.old.as_ref()),
+        format_value(change.new.as_ref()),
+        match &change.caller {
+            Some(caller) => escape(&caller.to_string()),
+            None => "-".to_string(),
+        },
+    )
+}
+
+/// Keep every record on a single line
+#[cfg(feature = "audit")]
+fn escape(s: &str) -> String {
+    s.replace('\\', "\\\\")
+        .replace('\t', "\\t")
+        .replace('\n', "\\n")
+}
+
+/// Format as an RFC 3339 UTC timestamp with second precision
+pub fn format_timestamp(time: SystemTime) -> String {
+    let secs = time
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0);
+    let (days, rem) = (secs / 86_400, secs % 86_400);
+
+    // Civil-from-days conversion (proleptic Gregorian calendar)
+    let z = days as i64 + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1_460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+        year,
+        month,
+        day,
+        rem / 3_600,
+        rem % 3_600 / 60,
+        rem % 60
+    )
+}
+
+#[cfg(all(test, feature = "audit"))]
+mod tests {
+    use super::*;
+    use crate::{Caller, ChangeKind, ChangeSource};
+    use std::time::Duration;
+    use zbus::zvariant::Value;
+
+    fn change(new: u32) -> SettingChange {
+        SettingChange {
+            namespace: "org.freedesktop.appearance".to_string(),
+            key: "color-scheme".to_string(),
+            kind: ChangeKind::Write,
+            source: ChangeSource::DBus,
+            old: None,
+            new: Some(SettingValue::new(Value::U32(new).try_into().unwrap())),
+            caller: None,
+        }
+    }
+
+    fn temp_path(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("portal-audit-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir.join("audit.log")
+    }
+
+    #[test]
+    fn test_format_timestamp() {
+        let time = UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        assert_eq!(format_timestamp(time), "2023-11-14T22:13:20Z");
+        assert_eq!(format_timestamp(UNIX_EPOCH), "1970-01-01T00:00:00Z");
+    }
+
+    #[test]
+    fn test_record_format() {
+        let none = SensitiveKeys::default();
+        let line = format_record(UNIX_EPOCH, &change(1), &none);
+        assert_eq!(
+            line,
+            "1970-01-01T00:00:00Z\twrite\tdbus\torg.freedesktop.appearance\tcolor-scheme\t-\tuint32 1\t-\n"
+        );
+        let caller = Caller {
+            uid: Some(1000),
+            pid: Some(42),
+            ..Caller::default()
+        };
+        let line = format_record(UNIX_EPOCH, &SettingChange { caller: Some(caller), ..change(1) }, &none);
+        assert!(line.ends_with("\tuint32 1\tuid=1000 pid=42\n"));
+
+        let sensitive = SensitiveKeys::new(["org.freedesktop.appearance/color-*"]).unwrap();
+        let line = format_record(UNIX_EPOCH, &change(1), &sensitive);
+        assert!(line.ends_with("\tcolor-scheme\t-\t<redacted>\t-\n"));
+    }
+
+    #[test]
+    fn test_rotation() {
+        let path = temp_path("rotation");
+        let line_len = format_record(SystemTime::now(), &change(1), &SensitiveKeys::default()).len() as u64;
+        let log = AuditLog::open(&path, line_len * 2, 2).unwrap();
+
+        for i in 0..7 {
+            log.record(&change(i)).unwrap();
+        }
+
+        assert!(path.exists());
+        assert!(rotated_path(&path, 1).exists());
+        assert!(rotated_path(&path, 2).exists());
+        assert!(!rotated_path(&path, 3).exists());
+
+        let current = fs::read_to_string(&path).unwrap();
+        assert_eq!(current.lines().count(), 1);
+        assert!(current.ends_with("\t-\tuint32 6\t-\n"));
+
+        fs::remove_dir_all(path.parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn test_journal_entries() {
+        assert_eq!(encode_entry(&[("A", "b".to_string())]), b"A=b\n");
+        assert_eq!(encode_entry(&[("A", "b\nc".to_string())]), b"A\n\x03\0\0\0\0\0\0\0b\nc\n");
+
+        let path = temp_path("journal").with_file_name("socket");
+        let journal = UnixDatagram::bind(&path).unwrap();
+        let audit = AuditJournal::connect_to(&path).unwrap();
+        let caller = Caller {
+            sender: Some(":1.42".to_string()),
+            uid: Some(1000),
+            ..Caller::default()
+        };
+        audit.record(&SettingChange { caller: Some(caller.clone()), ..change(1) }).unwrap();
+        let mut buf = vec![0; 4096];
+        let len = journal.recv(&mut buf).unwrap();
+        let entry = String::from_utf8_lossy(&buf[..len]).into_owned();
+        for field in ["RESULT=ok", "SETTING_NEW=uint32 1", "SENDER=:1.42", "SENDER_UID=1000", "AUDIT=1"] {
+            assert!(entry.lines().any(|line| line == field), "{} not in {:?}", field, entry);
+        }
+        assert!(!entry.contains("SENDER_PID"));
+
+        audit
+            .record_refused(&RefusedChange {
+                namespace: "com.example".to_string(),
+                key: "secret".to_string(),
+                kind: ChangeKind::Write,
+                caller: Some(caller),
+                error: "org.freedesktop.DBus.Error.AccessDenied".to_string(),
+                message: "the policy does not allow changing com.example/secret".to_string(),
+            })
+            .unwrap();
+        let len = journal.recv(&mut buf).unwrap();
+        let entry = String::from_utf8_lossy(&buf[..len]).into_owned();
+        assert!(entry.contains("RESULT=refused\n"));
+        assert!(entry.contains("ERROR=org.freedesktop.DBus.Error.AccessDenied\n"));
+
+        let sensitive = SensitiveKeys::new(["org.freedesktop.appearance/*"]).unwrap();
+        let audit = AuditJournal::connect_to(&path).unwrap().with_sensitive_keys(sensitive);
+        audit.record(&change(1)).unwrap();
+        let len = journal.recv(&mut buf).unwrap();
+        assert!(String::from_utf8_lossy(&buf[..len]).contains("SETTING_NEW=<redacted>\n"));
+
+        fs::remove_dir_all(path.parent().unwrap()).unwrap();
+    }
+}
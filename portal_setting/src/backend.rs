@@ -0,0 +1,14 @@
+use crate::Result;
+use zbus::zvariant::OwnedValue;
+
+/// A source of default settings besides the configuration file
+///
+/// Backends are queried once when the store is created. Their values are
+/// validated like configured ones and are what a reset restores.
+pub trait SettingsBackend: Send + Sync {
+    /// Name used in messages about this backend
+    fn name(&self) -> &str;
+
+    /// All settings provided by this backend
+    fn load(&self) -> Result<Vec<(String, String, OwnedValue)>>;
+}
@@ -0,0 +1,109 @@
+use crate::{Caller, SettingValue};
+use std::fmt;
+
+/// The kind of mutation applied to a setting
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChangeKind {
+    /// A value was stored (created or replaced)
+    Write,
+    /// A key was removed from the store
+    Delete,
+    /// A key was restored to its built-in default
+    Reset,
+}
+
+impl fmt::Display for ChangeKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            ChangeKind::Write => "write",
+            ChangeKind::Delete => "delete",
+            ChangeKind::Reset => "reset",
+        })
+    }
+}
+
+/// Where a mutation originated
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChangeSource {
+    /// Direct calls through the library API
+    Api,
+    /// A D-Bus method call
+    DBus,
+    /// Values applied while (re)loading the configuration
+    ConfigReload,
+    /// Changes applied by a scheduled job
+    Scheduler,
+    /// A request to the HTTP bridge
+    Http,
+    /// An update received from the MQTT broker
+    Mqtt,
+    /// A call to the gRPC service
+    Grpc,
+    /// A change copied from the implementation being mirrored
+    Mirror,
+    /// A value restored from the state file
+    State,
+    /// A value written for the length of a login session, or restored when
+    /// the session ended
+    Session,
+}
+
+impl fmt::Display for ChangeSource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            ChangeSource::Api => "api",
+            ChangeSource::DBus => "dbus",
+            ChangeSource::ConfigReload => "config-reload",
+            ChangeSource::Scheduler => "scheduler",
+            ChangeSource::Http => "http",
+            ChangeSource::Mqtt => "mqtt",
+            ChangeSource::Grpc => "grpc",
+            ChangeSource::Mirror => "mirror",
+            ChangeSource::State => "state",
+            ChangeSource::Session => "session",
+        })
+    }
+}
+
+/// A successful mutation of the settings store
+#[derive(Debug, Clone)]
+pub struct SettingChange {
+    pub namespace: String,
+    pub key: String,
+    pub kind: ChangeKind,
+    pub source: ChangeSource,
+    /// Value before the change, if the key existed
+    pub old: Option<SettingValue>,
+    /// Value after the change, if the key still exists
+    pub new: Option<SettingValue>,
+    /// Who asked for the change over D-Bus, if known
+    pub caller: Option<Caller>,
+}
+
+/// A mutation asked for over D-Bus that failed, such as one the policy
+/// denied or a value that did not validate
+#[derive(Debug, Clone)]
+pub struct RefusedChange {
+    pub namespace: String,
+    pub key: String,
+    pub kind: ChangeKind,
+    pub caller: Option<Caller>,
+    /// The error the caller got, e.g. `org.freedesktop.DBus.Error.AccessDenied`
+    pub error: String,
+    pub message: String,
+}
+
+/// Receives every successful mutation of a `SettingsStore`
+///
+/// Observers are called after the store lock has been released, in the order
+/// they were registered, and receive changes in the order they were made.
+/// While one thread is notifying observers, changes made on other threads
+/// are delivered by it, so a write may return before its observers ran.
+/// Observers may change the store themselves.
+pub trait ChangeObserver: Send + Sync {
+    fn on_change(&self, change: &SettingChange);
+
+    /// Called right away when a mutation is refused; see
+    /// `SettingsStore::report_refused`
+    fn on_refused(&self, _refused: &RefusedChange) {}
+}
@@ -0,0 +1,1186 @@
+use crate::sandbox::{self, Sandbox};
+use crate::{audit, schema, DeniedNamespaces, SensitiveKeys, SettingKey, SettingsStore, FRONTEND_BUS_NAME};
+use crate::error::{bail, Context, Result};
+use crate::pattern::check_namespace_pattern;
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::path::{Path, PathBuf};
+use zbus::names::OwnedWellKnownName;
+use zbus::zvariant::{Array, OwnedValue, Str, Value};
+
+/// Service configuration, loaded from a TOML file
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    /// Deprecated names of settings, keyed by namespace and key
+    #[serde(default)]
+    pub aliases: BTreeMap<String, BTreeMap<String, AliasConfig>>,
+    /// Namespaces that are never served, as namespaces or globs like
+    /// `com.vendor.*`: the interfaces refuse them before consulting the
+    /// store, whatever it holds and the policy allows
+    #[serde(default)]
+    pub denied_namespaces: Vec<String>,
+    /// Namespaces that are never served: they have no defaults, are left out
+    /// of ReadAll and reject writes
+    #[serde(default)]
+    pub disabled_namespaces: Vec<String>,
+    /// Settings whose values are masked in logs, dumps and exports, as
+    /// `namespace/key` globs
+    #[serde(default)]
+    pub sensitive_keys: Vec<String>,
+    /// Values overriding the built-in defaults, keyed by namespace and key
+    #[serde(default)]
+    pub settings: BTreeMap<String, BTreeMap<String, toml::Spanned<toml::Value>>>,
+    #[serde(default)]
+    pub audit: Option<AuditConfig>,
+    #[serde(default)]
+    pub compat: CompatConfig,
+    #[serde(default)]
+    pub hooks: Option<HooksConfig>,
+    #[serde(default)]
+    pub journal: JournalConfig,
+    #[serde(default)]
+    pub limits: LimitsConfig,
+    #[serde(default)]
+    pub management: ManagementConfig,
+    #[serde(default)]
+    pub mqtt: Option<MqttConfig>,
+    #[serde(default)]
+    pub plugins: Option<PluginsConfig>,
+    #[serde(default)]
+    pub sandbox: SandboxConfig,
+    /// Constraints of settings, keyed by namespace and key
+    #[serde(default)]
+    pub schema: schema::Constraints,
+    #[serde(default)]
+    pub service: ServiceConfig,
+    #[serde(default)]
+    pub state: Option<StateConfig>,
+}
+
+/// An `[aliases]` entry: the setting a deprecated name stands for
+///
+/// `namespace` defaults to the namespace of the alias.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct AliasConfig {
+    #[serde(default)]
+    pub namespace: Option<String>,
+    pub key: String,
+}
+
+/// `[state]` section
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct StateConfig {
+    /// File persisting settings changed at runtime
+    pub path: PathBuf,
+    /// Changes are written together at most this often, in milliseconds
+    #[serde(default = "default_state_debounce_ms")]
+    pub debounce_ms: u64,
+    #[serde(default)]
+    pub format: StateFormat,
+}
+
+/// How the state file is written; either is read
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum StateFormat {
+    /// Readable and editable by hand
+    #[default]
+    Toml,
+    /// The D-Bus wire format, fastest to load at boot
+    Binary,
+}
+
+impl std::str::FromStr for StateFormat {
+    type Err = crate::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "toml" => Ok(Self::Toml),
+            "binary" => Ok(Self::Binary),
+            _ => bail!("expected \"toml\" or \"binary\""),
+        }
+    }
+}
+
+/// Interval of state file writes, in milliseconds
+pub const DEFAULT_STATE_DEBOUNCE_MS: u64 = 500;
+
+fn default_state_debounce_ms() -> u64 {
+    DEFAULT_STATE_DEBOUNCE_MS
+}
+
+/// `[audit]` section
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct AuditConfig {
+    pub path: PathBuf,
+    #[serde(default = "default_audit_max_size")]
+    pub max_size: u64,
+    #[serde(default = "default_audit_keep")]
+    pub keep: usize,
+}
+
+fn default_audit_max_size() -> u64 {
+    audit::DEFAULT_MAX_BYTES
+}
+
+fn default_audit_keep() -> usize {
+    audit::DEFAULT_KEEP
+}
+
+/// `[compat]` section
+///
+/// Behaviors of earlier versions that clients may rely on, so deployments
+/// can upgrade without changing them. All are off by default in favor of
+/// the portal specification.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct CompatConfig {
+    /// Wrap `Read` results of the impl interface in an extra variant, like
+    /// the frontend's deprecated `Read`
+    #[serde(default)]
+    pub double_wrapped_read: bool,
+    /// Compare `ReadAll` filters with whole namespaces, without globs or
+    /// checking them
+    #[serde(default)]
+    pub exact_match_read_all: bool,
+    /// Fail `Read` of unknown settings with `org.freedesktop.DBus.Error.Failed`
+    /// instead of `org.freedesktop.portal.Error.NotFound`
+    #[serde(default)]
+    pub failed_not_found: bool,
+}
+
+/// `[hooks]` section
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct HooksConfig {
+    pub dir: PathBuf,
+}
+
+/// Changes kept for clients catching up
+pub const DEFAULT_JOURNAL_SIZE: usize = 256;
+
+/// `[journal]` section
+///
+/// The last `size` changes are kept in memory for `GetRecentChanges`; 0 keeps
+/// none.
+///
+/// Writes of a setting's current value are not changes: they keep the
+/// generation, are not journaled and emit no `SettingChanged`, unless
+/// `record_unchanged` is set.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct JournalConfig {
+    #[serde(default = "default_journal_size")]
+    pub size: usize,
+    #[serde(default)]
+    pub record_unchanged: bool,
+}
+
+impl Default for JournalConfig {
+    fn default() -> Self {
+        Self {
+            size: DEFAULT_JOURNAL_SIZE,
+            record_unchanged: false,
+        }
+    }
+}
+
+fn default_journal_size() -> usize {
+    DEFAULT_JOURNAL_SIZE
+}
+
+/// Namespaces without any default that clients may create
+pub const DEFAULT_MAX_NAMESPACES: usize = 64;
+/// Keys a client may create in a namespace without defaults
+pub const DEFAULT_MAX_KEYS: usize = 1024;
+/// Keys one D-Bus sender may create in namespaces without defaults
+pub const DEFAULT_MAX_KEYS_PER_SENDER: usize = 256;
+/// Serialized size of a single value, in bytes
+pub const DEFAULT_MAX_VALUE_SIZE: usize = 64 * 1024;
+/// Length of any string in a value, in bytes
+pub const DEFAULT_MAX_STRING_LENGTH: usize = 4096;
+/// Length of a namespace or key, in bytes, like D-Bus names
+pub const DEFAULT_MAX_NAME_LENGTH: usize = 255;
+
+/// `[limits]` section
+///
+/// Bounds what clients can add at runtime. Namespaces with defaults (built
+/// in, from a backend or from `[settings]`) are registered and only subject
+/// to the size and length limits; writes beyond a limit are rejected. 0
+/// disables a limit.
+///
+/// In strict mode, only keys with a default can be written at all, in every
+/// namespace or in `strict_namespaces`.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct LimitsConfig {
+    #[serde(default = "default_max_namespaces")]
+    pub max_namespaces: usize,
+    #[serde(default = "default_max_keys")]
+    pub max_keys: usize,
+    #[serde(default = "default_max_keys_per_sender")]
+    pub max_keys_per_sender: usize,
+    #[serde(default = "default_max_value_size")]
+    pub max_value_size: usize,
+    /// Longest string allowed anywhere in a value, in bytes
+    #[serde(default = "default_max_string_length")]
+    pub max_string_length: usize,
+    /// Longest namespace or key that can be written, in bytes
+    #[serde(default = "default_max_name_length")]
+    pub max_name_length: usize,
+    /// Reject writes to keys without a default in every namespace
+    #[serde(default)]
+    pub strict: bool,
+    /// Reject writes to keys without a default in these namespaces
+    #[serde(default)]
+    pub strict_namespaces: Vec<String>,
+    /// Accept writes to namespaces that are not reverse-DNS names, such as
+    /// `appearance` or `org..example`
+    #[serde(default)]
+    pub allow_any_namespace: bool,
+}
+
+impl LimitsConfig {
+    /// Whether writes to keys of `namespace` without a default are rejected
+    pub fn is_strict(&self, namespace: &str) -> bool {
+        self.strict || self.strict_namespaces.iter().any(|strict| strict == namespace)
+    }
+
+    /// No limits at all
+    pub fn unlimited() -> Self {
+        Self {
+            max_namespaces: 0,
+            max_keys: 0,
+            max_keys_per_sender: 0,
+            max_value_size: 0,
+            max_string_length: 0,
+            max_name_length: 0,
+            strict: false,
+            strict_namespaces: Vec::new(),
+            allow_any_namespace: true,
+        }
+    }
+}
+
+impl Default for LimitsConfig {
+    fn default() -> Self {
+        Self {
+            max_namespaces: DEFAULT_MAX_NAMESPACES,
+            max_keys: DEFAULT_MAX_KEYS,
+            max_keys_per_sender: DEFAULT_MAX_KEYS_PER_SENDER,
+            max_value_size: DEFAULT_MAX_VALUE_SIZE,
+            max_string_length: DEFAULT_MAX_STRING_LENGTH,
+            max_name_length: DEFAULT_MAX_NAME_LENGTH,
+            strict: false,
+            strict_namespaces: Vec::new(),
+            allow_any_namespace: false,
+        }
+    }
+}
+
+fn default_max_namespaces() -> usize {
+    DEFAULT_MAX_NAMESPACES
+}
+
+fn default_max_keys() -> usize {
+    DEFAULT_MAX_KEYS
+}
+
+fn default_max_keys_per_sender() -> usize {
+    DEFAULT_MAX_KEYS_PER_SENDER
+}
+
+fn default_max_value_size() -> usize {
+    DEFAULT_MAX_VALUE_SIZE
+}
+
+fn default_max_string_length() -> usize {
+    DEFAULT_MAX_STRING_LENGTH
+}
+
+fn default_max_name_length() -> usize {
+    DEFAULT_MAX_NAME_LENGTH
+}
+
+/// `[management]` section
+///
+/// Without an allow-list anyone on the bus may use the management interface,
+/// served next to the portal interface. With one, it is served at
+/// `MANAGEMENT_PATH` instead and only answers the owners of `allow_names`
+/// and the users of `allow_uids`.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ManagementConfig {
+    /// Well-known bus names whose owners may manage settings
+    #[serde(default)]
+    pub allow_names: Vec<OwnedWellKnownName>,
+    #[serde(default)]
+    pub allow_uids: Vec<u32>,
+}
+
+impl ManagementConfig {
+    /// Whether only the allowed callers may manage settings
+    pub fn is_restricted(&self) -> bool {
+        !self.allow_names.is_empty() || !self.allow_uids.is_empty()
+    }
+}
+
+/// `[sandbox]` section: how the service behaves in a container or a
+/// Flatpak test environment
+///
+/// Sandboxing is opt-in: it applies only with `mode` set to `auto` or `on`.
+/// When sandboxed, runtime changes are kept in the session's runtime
+/// directory instead of the state file on the host, and only `namespaces`
+/// of the built-in ones are served; see `sandbox::restrict`.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SandboxConfig {
+    #[serde(default)]
+    pub mode: SandboxMode,
+    /// Built-in namespaces served when sandboxed, as namespace filters
+    #[serde(default = "default_sandbox_namespaces")]
+    pub namespaces: Vec<String>,
+}
+
+impl Default for SandboxConfig {
+    fn default() -> Self {
+        Self {
+            mode: SandboxMode::default(),
+            namespaces: default_sandbox_namespaces(),
+        }
+    }
+}
+
+impl SandboxConfig {
+    /// The sandbox the service runs in, if it is to behave as sandboxed
+    pub fn sandbox(&self) -> Option<Sandbox> {
+        match self.mode {
+            SandboxMode::Auto => sandbox::detect(),
+            SandboxMode::On => Some(sandbox::detect().unwrap_or(Sandbox::Configured)),
+            SandboxMode::Off => None,
+        }
+    }
+
+    /// Fail unless each of `namespaces` is a namespace filter
+    pub fn check(&self) -> Result<()> {
+        for pattern in &self.namespaces {
+            check_namespace_pattern(pattern, true).with_context(|| format!("sandbox.namespaces: {:?}", pattern))?;
+        }
+        Ok(())
+    }
+}
+
+/// Whether the service behaves as sandboxed
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SandboxMode {
+    /// When a container or Flatpak sandbox is detected
+    Auto,
+    /// Always
+    On,
+    /// Never
+    #[default]
+    Off,
+}
+
+fn default_sandbox_namespaces() -> Vec<String> {
+    vec!["org.freedesktop.appearance".to_string()]
+}
+
+/// `[mqtt]` section
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct MqttConfig {
+    /// Host name or address of the broker
+    pub host: String,
+    #[serde(default = "default_mqtt_port")]
+    pub port: u16,
+    /// Client identifier, unique per device [default: portal-setting-<hostname>]
+    pub client_id: Option<String>,
+    /// Changes are published (retained) to `<topic>/<namespace>/<key>`
+    pub topic: String,
+    /// Updates are read from `<set_topic>/<namespace>/<key>` [default: <topic>/set]
+    pub set_topic: Option<String>,
+}
+
+impl MqttConfig {
+    pub fn set_topic(&self) -> String {
+        self.set_topic
+            .clone()
+            .unwrap_or_else(|| format!("{}/set", self.topic))
+    }
+}
+
+fn default_mqtt_port() -> u16 {
+    1883
+}
+
+/// `[plugins]` section
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct PluginsConfig {
+    /// Directory of `*.so` plugins, loaded when built with the `plugins` feature
+    pub dir: PathBuf,
+}
+
+/// Bus name owned for the `org.freedesktop.impl.portal.Settings` backend
+pub const DEFAULT_BUS_NAME: &str = "org.freedesktop.impl.portal.Settings";
+
+/// `[service]` section
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ServiceConfig {
+    /// Bus name for the impl (backend) interface
+    #[serde(default = "default_bus_name")]
+    pub bus_name: String,
+    /// Also serve `org.freedesktop.portal.Settings` to applications directly
+    #[serde(default)]
+    pub frontend: bool,
+    /// Bus name for the frontend interface
+    #[serde(default = "default_frontend_name")]
+    pub frontend_name: String,
+    /// What to do when another implementation takes over `bus_name`
+    #[serde(default)]
+    pub on_name_lost: NameLostAction,
+}
+
+impl Default for ServiceConfig {
+    fn default() -> Self {
+        Self {
+            bus_name: default_bus_name(),
+            frontend: false,
+            frontend_name: default_frontend_name(),
+            on_name_lost: NameLostAction::default(),
+        }
+    }
+}
+
+/// Behavior after losing the bus name to another implementation
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum NameLostAction {
+    /// Persist state and exit
+    #[default]
+    Exit,
+    /// Persist state, stop serving and wait to get the name back
+    Passive,
+}
+
+impl std::str::FromStr for NameLostAction {
+    type Err = crate::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "exit" => Ok(Self::Exit),
+            "passive" => Ok(Self::Passive),
+            _ => bail!("expected \"exit\" or \"passive\""),
+        }
+    }
+}
+
+/// The seat every system has; its instance keeps the unsuffixed names
+pub const PRIMARY_SEAT: &str = "seat0";
+
+/// A graphical seat served by its own instance of the service
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Seat(String);
+
+impl Seat {
+    pub fn name(&self) -> &str {
+        &self.0
+    }
+
+    pub fn is_primary(&self) -> bool {
+        self.0 == PRIMARY_SEAT
+    }
+
+    /// `name` with the seat appended as a last element, e.g.
+    /// `org.freedesktop.impl.portal.Settings.seat1`
+    pub fn bus_name(&self, name: &str) -> String {
+        if self.is_primary() {
+            name.to_string()
+        } else {
+            format!("{}.{}", name, self.0)
+        }
+    }
+
+    /// `path` with the seat inserted before the extension, e.g.
+    /// `state.seat1.toml`
+    pub fn state_path(&self, path: &Path) -> PathBuf {
+        match self.is_primary() {
+            true => path.to_path_buf(),
+            false => with_suffix(path, &self.0),
+        }
+    }
+}
+
+/// `path` with `uid` inserted before the extension, e.g. `state.1000.toml`,
+/// for the store of that user
+pub fn user_state_path(path: &Path, uid: u32) -> PathBuf {
+    with_suffix(path, &uid.to_string())
+}
+
+fn with_suffix(path: &Path, suffix: &str) -> PathBuf {
+    let mut file_name = path.file_stem().unwrap_or_default().to_os_string();
+    file_name.push(".");
+    file_name.push(suffix);
+    if let Some(extension) = path.extension() {
+        file_name.push(".");
+        file_name.push(extension);
+    }
+    path.with_file_name(file_name)
+}
+
+impl std::str::FromStr for Seat {
+    type Err = crate::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        // logind seat names are "seat" followed by [a-zA-Z0-9_-], all of
+        // which are also valid in bus name elements
+        let valid = s.strip_prefix("seat").is_some_and(|rest| {
+            rest.chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+        });
+        if !valid {
+            bail!("invalid seat name {:?}", s);
+        }
+        Ok(Self(s.to_string()))
+    }
+}
+
+fn default_bus_name() -> String {
+    DEFAULT_BUS_NAME.to_string()
+}
+
+fn default_frontend_name() -> String {
+    FRONTEND_BUS_NAME.to_string()
+}
+
+impl Config {
+    pub fn load(path: &Path) -> Result<Self> {
+        let text = std::fs::read_to_string(path)
+            .with_context(|| format!("cannot read config {}", path.display()))?;
+        Self::parse(&text).with_context(|| format!("invalid config {}", path.display()))
+    }
+
+    pub fn parse(text: &str) -> Result<Self> {
+        Ok(toml::from_str(text)?)
+    }
+
+    pub fn is_disabled(&self, namespace: &str) -> bool {
+        self.disabled_namespaces.iter().any(|n| n == namespace)
+    }
+
+    /// The `denied_namespaces` patterns
+    pub fn denied_namespaces(&self) -> Result<DeniedNamespaces> {
+        DeniedNamespaces::new(self.denied_namespaces.clone()).context("denied_namespaces")
+    }
+
+    /// The `sensitive_keys` patterns
+    pub fn sensitive_keys(&self) -> Result<SensitiveKeys> {
+        SensitiveKeys::new(&self.sensitive_keys).context("sensitive_keys")
+    }
+
+    /// The constraint the `[schema]` tables declare for `namespace`/`key`
+    pub fn constraint(&self, namespace: &str, key: &str) -> Option<&schema::Constraint> {
+        self.schema.get(namespace)?.get(key)
+    }
+
+    /// Convert a TOML value like `toml_to_value`, to the declared type when
+    /// the key has a constraint
+    pub fn convert(&self, namespace: &str, key: &str, value: &toml::Value) -> Result<OwnedValue> {
+        match self.constraint(namespace, key) {
+            Some(constraint) => toml_to_typed(Some(&constraint.signature), value),
+            None => toml_to_value(namespace, key, value),
+        }
+    }
+
+    /// Each alias of the `[aliases]` tables with the setting it stands for
+    ///
+    /// Aliases may not stand for other aliases, nor have a value of their
+    /// own.
+    pub fn alias_targets(&self) -> Result<Vec<(SettingKey, SettingKey)>> {
+        let mut targets = Vec::new();
+        for (namespace, keys) in &self.aliases {
+            for (key, alias) in keys {
+                let target = SettingKey::new(alias.namespace.as_deref().unwrap_or(namespace), &alias.key);
+                let context = || format!("aliases.\"{}\".{}", namespace, key);
+                if self.aliases.get(&*target.namespace).is_some_and(|keys| keys.contains_key(&*target.key)) {
+                    bail!("{}: {}/{} is an alias too", context(), target.namespace, target.key);
+                }
+                if self.is_disabled(namespace) || self.is_disabled(&target.namespace) {
+                    bail!("{}: namespace is disabled", context());
+                }
+                let configured = self.settings.get(namespace).is_some_and(|keys| keys.contains_key(key));
+                if configured || schema::lookup(namespace, key).is_some() {
+                    bail!("{}: the alias has a value of its own", context());
+                }
+                targets.push((SettingKey::new(namespace, key), target));
+            }
+        }
+        Ok(targets)
+    }
+
+    /// The `[settings]` tables converted to D-Bus values
+    pub fn setting_values(&self) -> Result<Vec<(String, String, OwnedValue)>> {
+        let mut values = Vec::new();
+        for (namespace, keys) in &self.settings {
+            for (key, value) in keys {
+                let converted = self
+                    .convert(namespace, key, value.get_ref())
+                    .with_context(|| format!("settings.\"{}\".{}", namespace, key))?;
+                values.push((namespace.clone(), key.clone(), converted));
+            }
+        }
+        Ok(values)
+    }
+}
+
+/// A problem found while checking a configuration file
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Diagnostic {
+    /// 1-based line and column, when the problem can be located
+    pub location: Option<(usize, usize)>,
+    pub message: String,
+}
+
+impl std::fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.location {
+            Some((line, column)) => write!(f, "{}:{}: {}", line, column, self.message),
+            None => f.write_str(&self.message),
+        }
+    }
+}
+
+/// Parse and fully validate a configuration file, reporting every problem
+///
+/// Unlike `Config::parse` followed by `SettingsStore::from_config`, this does
+/// not stop at the first invalid setting.
+pub fn check(text: &str) -> Vec<Diagnostic> {
+    let config: Config = match toml::from_str(text) {
+        Ok(config) => config,
+        Err(e) => {
+            return vec![Diagnostic {
+                location: e.span().map(|span| location(text, span.start)),
+                message: e.message().trim_end().to_string(),
+            }];
+        }
+    };
+
+    let mut diagnostics = Vec::new();
+    if let Err(e) = config.alias_targets() {
+        diagnostics.push(Diagnostic {
+            location: None,
+            message: format!("{:#}", e),
+        });
+    }
+    if let Err(e) = config.denied_namespaces() {
+        diagnostics.push(Diagnostic {
+            location: None,
+            message: format!("{:#}", e),
+        });
+    }
+    if let Err(e) = config.sensitive_keys() {
+        diagnostics.push(Diagnostic {
+            location: None,
+            message: format!("{:#}", e),
+        });
+    }
+    if let Err(e) = config.sandbox.check() {
+        diagnostics.push(Diagnostic {
+            location: None,
+            message: format!("{:#}", e),
+        });
+    }
+    for (namespace, keys) in &config.schema {
+        for (key, constraint) in keys {
+            if let Err(e) = constraint.check() {
+                diagnostics.push(Diagnostic {
+                    location: None,
+                    message: format!("schema.\"{}\".{}: {:#}", namespace, key, e),
+                });
+            }
+        }
+    }
+    for (namespace, keys) in &config.settings {
+        for (key, value) in keys {
+            if config.is_disabled(namespace) {
+                diagnostics.push(Diagnostic {
+                    location: Some(location(text, value.span().start)),
+                    message: format!("settings.\"{}\".{}: namespace is disabled", namespace, key),
+                });
+                continue;
+            }
+            let result = config
+                .convert(namespace, key, value.get_ref())
+                .and_then(|v| SettingsStore::validate_constrained(&config, namespace, key, &v));
+            if let Err(e) = result {
+                diagnostics.push(Diagnostic {
+                    location: Some(location(text, value.span().start)),
+                    message: format!("settings.\"{}\".{}: {:#}", namespace, key, e),
+                });
+            }
+        }
+    }
+    diagnostics.sort_by_key(|d| d.location);
+    diagnostics
+}
+
+/// Line and column of a byte offset
+pub(crate) fn location(text: &str, offset: usize) -> (usize, usize) {
+    let before = &text[..offset.min(text.len())];
+    let line = before.matches('\n').count() + 1;
+    let column = before.len() - before.rfind('\n').map_or(0, |i| i + 1) + 1;
+    (line, column)
+}
+
+/// Convert a TOML value to the D-Bus type expected for `namespace`/`key`
+///
+/// Built-in keys use the type of their default. Other keys infer it from the
+/// TOML value: booleans, strings, floats (`d`), integers (`i`, or `x` when out
+/// of `i32` range) and arrays of strings (`as`).
+pub fn toml_to_value(namespace: &str, key: &str, value: &toml::Value) -> Result<OwnedValue> {
+    toml_to_typed(schema::lookup(namespace, key).map(|s| s.default.signature()), value)
+}
+
+/// Convert a TOML value to a D-Bus value of type `signature`, or of the type
+/// inferred from the value when there is none
+fn toml_to_typed(signature: Option<&str>, value: &toml::Value) -> Result<OwnedValue> {
+    let converted = match signature {
+        Some("u") => match value {
+            toml::Value::Integer(i) => match u32::try_from(*i) {
+                Ok(v) => Value::U32(v),
+                Err(_) => bail!("{} is out of range for u32", i),
+            },
+            _ => bail!("expected an unsigned integer"),
+        },
+        Some("i") => match value {
+            toml::Value::Integer(i) => match i32::try_from(*i) {
+                Ok(v) => Value::I32(v),
+                Err(_) => bail!("{} is out of range for i32", i),
+            },
+            _ => bail!("expected an integer"),
+        },
+        Some("x") => match value {
+            toml::Value::Integer(i) => Value::I64(*i),
+            _ => bail!("expected an integer"),
+        },
+        Some("d") => Value::F64(number(value)?),
+        Some("b") => match value {
+            toml::Value::Boolean(b) => Value::Bool(*b),
+            _ => bail!("expected a boolean"),
+        },
+        Some("s") => match value {
+            toml::Value::String(s) => Value::Str(Str::from(s.clone())),
+            _ => bail!("expected a string"),
+        },
+        Some("as") => match strings(value) {
+            Some(strings) => Value::Array(Array::from(strings)),
+            None => bail!("expected an array of strings"),
+        },
+        Some("(ddd)") => match value.as_array().map(|a| a.as_slice()) {
+            Some([r, g, b]) => Value::Structure((number(r)?, number(g)?, number(b)?).into()),
+            _ => bail!("expected an array of three numbers"),
+        },
+        Some(signature) => bail!("unsupported type {}", signature),
+        None => match value {
+            toml::Value::Boolean(b) => Value::Bool(*b),
+            toml::Value::String(s) => Value::Str(Str::from(s.clone())),
+            toml::Value::Float(f) => Value::F64(*f),
+            toml::Value::Integer(i) => match i32::try_from(*i) {
+                Ok(v) => Value::I32(v),
+                Err(_) => Value::I64(*i),
+            },
+            toml::Value::Array(_) => match strings(value) {
+                Some(strings) => Value::Array(Array::from(strings)),
+                None => bail!("only arrays of strings are supported"),
+            },
+            _ => bail!("unsupported value type {}", value.type_str()),
+        },
+    };
+    Ok(converted.try_into()?)
+}
+
+fn strings(value: &toml::Value) -> Option<Vec<String>> {
+    value
+        .as_array()?
+        .iter()
+        .map(|item| item.as_str().map(str::to_string))
+        .collect()
+}
+
+fn number(value: &toml::Value) -> Result<f64> {
+    match value {
+        toml::Value::Float(f) => Ok(*f),
+        toml::Value::Integer(i) => Ok(*i as f64),
+        _ => bail!("expected a number"),
+    }
+}
+
+fn default_to_toml(value: schema::DefaultValue) -> toml::Value {
+    match value {
+        schema::DefaultValue::U32(v) => toml::Value::Integer(v.into()),
+        schema::DefaultValue::I32(v) => toml::Value::Integer(v.into()),
+        schema::DefaultValue::Bool(v) => toml::Value::Boolean(v),
+        schema::DefaultValue::Str(v) => toml::Value::String(v.to_string()),
+        schema::DefaultValue::Rgb(r, g, b) => toml::Value::Array(vec![
+            toml::Value::Float(r),
+            toml::Value::Float(g),
+            toml::Value::Float(b),
+        ]),
+    }
+}
+
+/// A fully commented configuration file containing the built-in defaults
+pub fn seed() -> String {
+    let mut out = String::new();
+    out.push_str(
+        "# Configuration for portal-setting-service\n\
+         #\n\
+         # Every value below is a built-in default. Change or remove entries as\n\
+         # needed; keys missing from this file keep their built-in default.\n\
+         \n\
+         # Namespaces that are never exposed to applications, e.g.\n\
+         # [\"org.gnome.desktop.privacy\"]\n\
+         # disabled_namespaces = []\n\
+         \n\
+         # Namespaces the interfaces refuse to serve or accept, whatever the\n\
+         # policy allows and values come from backends, plugins or the state\n\
+         # file; globs like \"com.vendor.*\" cover every namespace below\n\
+         # denied_namespaces = [\"org.gnome.desktop.privacy\"]\n\
+         \n\
+         # Settings whose values are masked in audit records, --watch output,\n\
+         # SIGUSR1 dumps and client exports, as namespace/key globs\n\
+         # sensitive_keys = [\"com.example.Proxy/password\"]\n\
+         \n\
+         # Deprecated names of settings, by namespace and key. Reads and writes\n\
+         # of an alias go to the setting it stands for, in the same namespace\n\
+         # unless set, and ReadAll lists both names\n\
+         # [aliases.\"com.example.Kiosk\"]\n\
+         # old-clock = { key = \"clock\" }\n\
+         # theme = { namespace = \"org.gnome.desktop.interface\", key = \"gtk-theme\" }\n\
+         \n\
+         # Append a record of every settings change to a file\n\
+         # [audit]\n",
+    );
+    let _ = writeln!(out, "# path = \"/var/log/portal-settings.audit\"");
+    let _ = writeln!(out, "# max_size = {}", audit::DEFAULT_MAX_BYTES);
+    let _ = writeln!(out, "# keep = {}", audit::DEFAULT_KEEP);
+    out.push_str(
+        "\n\
+         # Behaviors of earlier versions for clients relying on them\n\
+         # [compat]\n\
+         # Wrap impl Read results in an extra variant\n\
+         # double_wrapped_read = false\n\
+         # Match ReadAll filters exactly, without globs\n\
+         # exact_match_read_all = false\n\
+         # Fail Read of unknown settings with Failed instead of NotFound\n\
+         # failed_not_found = false\n\
+         \n\
+         # Bounds on what clients can add at runtime: namespaces without\n\
+         # defaults, keys in each of them, keys created by one D-Bus sender, the\n\
+         # serialized size of any value, and the length of strings in values and\n\
+         # of namespaces and keys, in bytes. Writes beyond a limit are rejected;\n\
+         # 0 disables a limit\n\
+         # [limits]\n",
+    );
+    let _ = writeln!(out, "# max_namespaces = {}", DEFAULT_MAX_NAMESPACES);
+    let _ = writeln!(out, "# max_keys = {}", DEFAULT_MAX_KEYS);
+    let _ = writeln!(out, "# max_keys_per_sender = {}", DEFAULT_MAX_KEYS_PER_SENDER);
+    let _ = writeln!(out, "# max_value_size = {}", DEFAULT_MAX_VALUE_SIZE);
+    let _ = writeln!(out, "# max_string_length = {}", DEFAULT_MAX_STRING_LENGTH);
+    let _ = writeln!(out, "# max_name_length = {}", DEFAULT_MAX_NAME_LENGTH);
+    out.push_str(
+        "# Accept only keys with a default, everywhere or in some namespaces\n\
+         # strict = false\n\
+         # strict_namespaces = [\"org.freedesktop.appearance\"]\n\
+         # Accept namespaces that are not reverse-DNS names like org.example.App\n\
+         # allow_any_namespace = false\n",
+    );
+    out.push_str(
+        "\n\
+         # Run executables from a directory after settings change\n\
+         # [hooks]\n\
+         # dir = \"/etc/portal-settings/hooks.d\"\n\
+         \n\
+         # Changes kept for clients catching up with GetRecentChanges; 0 keeps\n\
+         # none\n\
+         # [journal]\n",
+    );
+    let _ = writeln!(out, "# size = {}", DEFAULT_JOURNAL_SIZE);
+    out.push_str(
+        "# Count writes of the current value as changes, signalling them again\n\
+         # record_unchanged = false\n",
+    );
+    out.push_str(
+        "\n\
+         # Callers allowed to change settings through the management interface,\n\
+         # which is then served at /io/github/meta_flutter/PortalSettings only.\n\
+         # Anyone may use it when both lists are empty\n\
+         # [management]\n\
+         # allow_names = [\"com.example.Provisioning\"]\n\
+         # allow_uids = [0]\n\
+         \n\
+         # Publish changes to an MQTT broker and apply updates published to\n\
+         # <set_topic>/<namespace>/<key> (requires a build with the `mqtt` feature)\n\
+         # [mqtt]\n\
+         # host = \"broker.example.com\"\n\
+         # port = 1883\n\
+         # topic = \"devices/kiosk-1/settings\"\n\
+         # set_topic = \"fleet/settings/set\"\n\
+         \n\
+         # Load settings backends and change observers from shared objects\n\
+         # (requires a build with the `plugins` feature)\n\
+         # [plugins]\n\
+         # dir = \"/usr/lib/portal-settings/plugins\"\n\
+         \n\
+         # Behavior in a container or Flatpak test environment: keep runtime\n\
+         # changes in $XDG_RUNTIME_DIR for the session instead of the state\n\
+         # file, serve only these built-in namespaces and accept only settings\n\
+         # with a default. mode is \"off\" (the default), \"auto\" (when one is\n\
+         # detected) or \"on\"\n\
+         # [sandbox]\n\
+         # mode = \"auto\"\n\
+         # namespaces = [\"org.freedesktop.appearance\"]\n\
+         \n\
+         # Constraints values must meet, by namespace and key: a type (b, i, u,\n\
+         # x, d, s or as) and optionally min/max, choices, a pattern and\n\
+         # min_length/max_length; coerce = true converts i and u into each\n\
+         # other and \"true\"/\"false\" into b, and read_only = true refuses\n\
+         # every change\n\
+         # [schema.\"com.example.Kiosk\"]\n\
+         # retention-days = { type = \"i\", min = 1, max = 365 }\n\
+         # clock = { type = \"s\", choices = [\"12h\", \"24h\"] }\n\
+         # label = { type = \"s\", pattern = \"^[A-Za-z0-9 .-]+$\", max_length = 32 }\n\
+         # enabled = { type = \"b\", coerce = true }\n\
+         # kiosk-mode = { type = \"b\", read_only = true }\n\
+         \n\
+         # D-Bus names. With `frontend = true` the service also serves\n\
+         # org.freedesktop.portal.Settings to applications, so xdg-desktop-portal\n\
+         # is not needed\n\
+         # [service]\n",
+    );
+    let _ = writeln!(out, "# bus_name = \"{}\"", DEFAULT_BUS_NAME);
+    let _ = writeln!(out, "# frontend = false");
+    let _ = writeln!(out, "# frontend_name = \"{}\"", FRONTEND_BUS_NAME);
+    out.push_str(
+        "# What to do when another implementation takes over bus_name:\n\
+         # \"exit\", or \"passive\" to wait until the name is available again\n\
+         # on_name_lost = \"exit\"\n\
+         \n\
+         # Persist settings changed at runtime across restarts. Changes are\n\
+         # written together once per debounce_ms, and on exit\n\
+         # [state]\n\
+         # path = \"/var/lib/portal-settings/state.toml\"\n",
+    );
+    let _ = writeln!(out, "# debounce_ms = {}", DEFAULT_STATE_DEBOUNCE_MS);
+    out.push_str(
+        "# \"binary\" loads faster at boot; \"toml\" can be edited by hand\n\
+         # format = \"toml\"\n",
+    );
+
+    let mut namespace = "";
+    for setting in schema::BUILTIN {
+        if setting.namespace != namespace {
+            namespace = setting.namespace;
+            let _ = writeln!(out, "\n[settings.\"{}\"]", namespace);
+        }
+        let _ = writeln!(out, "# {}", setting.description);
+        let _ = writeln!(out, "{} = {}", setting.key, default_to_toml(setting.default));
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_seed_round_trips() {
+        let config = Config::parse(&seed()).unwrap();
+        assert!(config.audit.is_none());
+        assert!(config.hooks.is_none());
+
+        let values = config.setting_values().unwrap();
+        assert_eq!(values.len(), schema::BUILTIN.len());
+        for (namespace, key, value) in values {
+            let builtin = schema::lookup(&namespace, &key).unwrap();
+            assert_eq!(value, builtin.default.to_value(), "{}/{}", namespace, key);
+        }
+    }
+
+    #[test]
+    fn test_typed_conversion() {
+        let config = Config::parse(
+            r#"
+            [settings."org.freedesktop.appearance"]
+            color-scheme = 1
+            accent-color = [1, 0.5, 0]
+
+            [settings."com.example.vendor"]
+            name = "demo"
+            big = 5000000000
+            tags = ["a", "b"]
+            "#,
+        )
+        .unwrap();
+        let values = config.setting_values().unwrap();
+        let signatures: Vec<String> = values
+            .iter()
+            .map(|(_, key, v)| format!("{}={}", key, v.value_signature()))
+            .collect();
+        assert_eq!(
+            signatures,
+            ["big=x", "name=s", "tags=as", "accent-color=(ddd)", "color-scheme=u"]
+        );
+    }
+
+    #[test]
+    fn test_invalid_config() {
+        let config = Config::parse(
+            r#"
+            [settings."org.freedesktop.appearance"]
+            color-scheme = -1
+            "#,
+        )
+        .unwrap();
+        let err = config.setting_values().unwrap_err();
+        assert!(format!("{:#}", err).contains("color-scheme"));
+
+        assert!(Config::parse("[unknown]\n").is_err());
+    }
+
+    #[test]
+    fn test_check_reports_every_error() {
+        let text = r#"[settings."org.freedesktop.appearance"]
+color-scheme = 5
+contrast = "high"
+accent-color = [0.5, 0.5, 0.5]
+
+[settings."org.gnome.desktop.privacy"]
+remember-recent-files = 1
+"#;
+        let diagnostics = check(text);
+        let locations: Vec<_> = diagnostics.iter().map(|d| d.location).collect();
+        assert_eq!(locations, [Some((2, 16)), Some((3, 12)), Some((7, 25))]);
+        assert!(diagnostics[1].message.contains("contrast"));
+
+        assert!(check(&seed()).is_empty());
+
+        let syntax = check("[settings\ncolor = 1\n");
+        assert_eq!(syntax.len(), 1);
+        assert_eq!(syntax[0].location.map(|(line, _)| line), Some(1));
+
+        let disabled = check(
+            "disabled_namespaces = [\"org.gnome.desktop.privacy\"]\n\
+             [settings.\"org.gnome.desktop.privacy\"]\n\
+             remember-recent-files = false\n",
+        );
+        assert_eq!(disabled.len(), 1);
+        assert!(disabled[0].message.contains("disabled"));
+    }
+
+    #[test]
+    fn test_schema_constraints() {
+        let text = r#"[schema."com.example.Kiosk"]
+retention-days = { type = "u", min = 1, max = 365 }
+tags = { type = "as", max_length = 2 }
+colour = { type = "b", choices = ["on"] }
+
+[settings."com.example.Kiosk"]
+retention-days = 400
+tags = ["a"]
+"#;
+        let config = Config::parse(text).unwrap();
+        let values = config.setting_values().unwrap();
+        assert_eq!(values[0].2.value_signature(), "u");
+        assert_eq!(values[1].2.value_signature(), "as");
+
+        let diagnostics = check(text);
+        assert_eq!(diagnostics.len(), 2);
+        assert!(diagnostics[0].message.contains("choices and pattern only apply to strings"));
+        assert_eq!(diagnostics[1].location, Some((7, 18)));
+        assert!(diagnostics[1].message.contains("1..=365"), "{}", diagnostics[1]);
+
+        let pattern = "[schema.a]\nb = { type = \"s\", pattern = \"^[A-Za-z0-9 .-]+$\" }\n";
+        #[cfg(feature = "regex")]
+        {
+            let config = Config::parse(pattern).unwrap();
+            let constraint = config.constraint("a", "b").unwrap();
+            let validate = |v: &str| constraint.validate("a", "b", &Value::from(v).try_into().unwrap());
+            assert!(validate("Lobby 1").is_ok());
+            assert!(validate("Lobby #1").is_err());
+            assert!(Config::parse("[schema.a]\nb = { type = \"s\", pattern = \"[\" }\n").is_err());
+        }
+        #[cfg(not(feature = "regex"))]
+        assert!(Config::parse(pattern).is_err());
+    }
+
+    #[test]
+    fn test_seat_names() {
+        let primary: Seat = "seat0".parse().unwrap();
+        assert_eq!(primary.bus_name(DEFAULT_BUS_NAME), DEFAULT_BUS_NAME);
+        assert_eq!(
+            primary.state_path(Path::new("/var/lib/state.toml")),
+            Path::new("/var/lib/state.toml")
+        );
+
+        let second: Seat = "seat1".parse().unwrap();
+        assert_eq!(
+            second.bus_name(DEFAULT_BUS_NAME),
+            "org.freedesktop.impl.portal.Settings.seat1"
+        );
+        assert_eq!(
+            second.state_path(Path::new("/var/lib/state.toml")),
+            Path::new("/var/lib/state.seat1.toml")
+        );
+        assert_eq!(second.state_path(Path::new("state")), Path::new("state.seat1"));
+        assert_eq!(user_state_path(Path::new("/var/lib/state.toml"), 1000), Path::new("/var/lib/state.1000.toml"));
+
+        assert!("seat.1".parse::<Seat>().is_err());
+        assert!("display1".parse::<Seat>().is_err());
+    }
+
+    #[test]
+    fn test_mqtt_section() {
+        let config = Config::parse("[mqtt]\nhost = \"broker\"\ntopic = \"devices/a/settings\"\n").unwrap();
+        let mqtt = config.mqtt.unwrap();
+        assert_eq!(mqtt.port, 1883);
+        assert_eq!(mqtt.set_topic(), "devices/a/settings/set");
+
+        let config = Config::parse("[mqtt]\nhost = \"broker\"\ntopic = \"t\"\nset_topic = \"fleet/set\"\n").unwrap();
+        assert_eq!(config.mqtt.unwrap().set_topic(), "fleet/set");
+        assert!(Config::parse("[mqtt]\nhost = \"broker\"\n").is_err());
+    }
+
+    #[test]
+    fn test_management_section() {
+        assert!(!Config::default().management.is_restricted());
+        let config = Config::parse("[management]\nallow_names = [\"com.example.Provisioning\"]\n").unwrap();
+        assert!(config.management.is_restricted());
+        assert_eq!(config.management.allow_names[0].as_str(), "com.example.Provisioning");
+        assert!(Config::parse("[management]\nallow_uids = [0]\n").unwrap().management.is_restricted());
+        // Unique names change with every connection
+        assert!(Config::parse("[management]\nallow_names = [\":1.42\"]\n").is_err());
+    }
+
+    #[test]
+    fn test_sensitive_keys() {
+        let config = Config::parse("sensitive_keys = [\"com.example.Proxy/*\"]\n").unwrap();
+        assert!(config.sensitive_keys().unwrap().contains("com.example.Proxy", "password"));
+        assert_eq!(check("sensitive_keys = [\"com.example.Proxy\"]\n").len(), 1);
+    }
+
+    #[test]
+    fn test_denied_namespaces() {
+        let config = Config::parse("denied_namespaces = [\"org.gnome.desktop.privacy\"]\n").unwrap();
+        assert!(config.denied_namespaces().unwrap().contains("org.gnome.desktop.privacy"));
+        assert_eq!(check("denied_namespaces = [\"org.*.privacy\"]\n").len(), 1);
+    }
+}
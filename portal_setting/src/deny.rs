@@ -0,0 +1,50 @@
+use crate::error::{Context, Result};
+use crate::pattern::{check_namespace_pattern, matches_namespace};
+use std::sync::Arc;
+
+/// Namespaces that are never served nor accepted, whatever the store holds,
+/// such as `org.gnome.desktop.privacy` on a product without it
+///
+/// Patterns are namespaces, or whole elements followed by `*` as in
+/// `ReadAll` filters (`com.vendor.*`). Unlike disabled namespaces, which
+/// keep defaults out of the store, they are checked by the interfaces before
+/// the store is consulted, so values from backends, plugins or the state
+/// file stay hidden too, whatever the policy allows. Clones share the
+/// patterns.
+#[derive(Debug, Clone, Default)]
+pub struct DeniedNamespaces(Arc<Vec<String>>);
+
+impl DeniedNamespaces {
+    pub fn new(patterns: Vec<String>) -> Result<Self> {
+        for pattern in &patterns {
+            check_namespace_pattern(pattern, true).with_context(|| format!("{:?}", pattern))?;
+        }
+        Ok(Self(Arc::new(patterns)))
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    pub fn contains(&self, namespace: &str) -> bool {
+        self.0.iter().any(|pattern| matches_namespace(pattern, namespace))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_denied_namespaces() {
+        let denied = DeniedNamespaces::new(vec!["org.gnome.desktop.privacy".to_string(), "com.vendor.*".to_string()]);
+        let denied = denied.unwrap();
+        assert!(denied.contains("org.gnome.desktop.privacy"));
+        assert!(denied.contains("com.vendor.Telemetry"));
+        assert!(!denied.contains("org.gnome.desktop.interface"));
+        assert!(!denied.contains("com.vendor"));
+
+        assert!(DeniedNamespaces::new(vec!["org.*.privacy".to_string()]).is_err());
+        assert!(DeniedNamespaces::default().is_empty());
+    }
+}
@@ -0,0 +1,337 @@
+use crate::{emit_values, SettingKey};
+use std::collections::{BTreeSet, HashMap, VecDeque};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use tokio::sync::mpsc::{self, error::TryRecvError, error::TrySendError};
+use zbus::names::BusName;
+use zbus::object_server::SignalContext;
+use zbus::zvariant::{OwnedObjectPath, OwnedValue};
+use zbus::Connection;
+
+/// Signals queued before further changes are coalesced
+pub const DEFAULT_CAPACITY: usize = 256;
+
+type Emission = (SettingKey, OwnedValue);
+
+/// Signals queued together, emitted as one batch
+type Batch = Vec<Emission>;
+
+/// Unique bus names signals are sent to, instead of broadcasting them
+///
+/// Shared with whoever adds and removes names; each batch goes to the names
+/// present when it is emitted.
+pub type Destinations = Arc<Mutex<BTreeSet<String>>>;
+
+/// Changes that did not fit the channel, at most one per setting, in the
+/// order they were first queued
+#[derive(Default)]
+struct Overflow {
+    order: VecDeque<SettingKey>,
+    values: HashMap<SettingKey, OwnedValue>,
+}
+
+impl Overflow {
+    /// Queue `value`, returning whether it replaced a queued one
+    fn push(&mut self, key: SettingKey, value: OwnedValue) -> bool {
+        if self.values.insert(key.clone(), value).is_some() {
+            return true;
+        }
+        self.order.push_back(key);
+        false
+    }
+
+    /// Everything queued, as one batch
+    fn take(&mut self) -> Option<Batch> {
+        let batch: Batch = self
+            .order
+            .drain(..)
+            .filter_map(|key| {
+                let value = self.values.remove(&key)?;
+                Some((key, value))
+            })
+            .collect();
+        (!batch.is_empty()).then_some(batch)
+    }
+}
+
+/// Counts of what happened to queued signals
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct EmitterStats {
+    pub emitted: u64,
+    /// Replaced by a later change to the same setting before being emitted
+    pub coalesced: u64,
+    /// Not emitted because the bus failed or the emitter stopped
+    pub dropped: u64,
+}
+
+#[derive(Default)]
+struct Shared {
+    overflow: Mutex<Overflow>,
+    emitted: AtomicU64,
+    coalesced: AtomicU64,
+    dropped: AtomicU64,
+}
+
+/// Emits `SettingChanged` from a background task, so writes return without
+/// waiting for the bus
+///
+/// Signals go through a bounded channel. Once it is full, further changes
+/// wait in an overflow queue holding only the latest value of each setting,
+/// so a burst of writes to one key is emitted once with its final value.
+/// Signals are emitted in the order their settings first changed, and those
+/// queued together, or waiting in the overflow, are sent as one batch.
+/// Clones share the same queue.
+#[derive(Clone)]
+pub struct SignalEmitter {
+    sender: mpsc::Sender<Batch>,
+    shared: Arc<Shared>,
+}
+
+impl SignalEmitter {
+    /// Emit on the settings interfaces exported at `path` on `connection`,
+    /// until every clone is dropped
+    pub fn spawn(connection: Connection, path: OwnedObjectPath, capacity: usize) -> Self {
+        Self::spawn_with(connection, path, capacity, None)
+    }
+
+    /// `spawn`, sending the signals only to `destinations`
+    pub fn spawn_to(connection: Connection, path: OwnedObjectPath, capacity: usize, destinations: Destinations) -> Self {
+        Self::spawn_with(connection, path, capacity, Some(destinations))
+    }
+
+    fn spawn_with(connection: Connection, path: OwnedObjectPath, capacity: usize, destinations: Option<Destinations>) -> Self {
+        let (emitter, mut queue) = Self::channel(capacity);
+        crate::task::spawn("signal-emitter", async move {
+            while let Some(batch) = queue.next().await {
+                let emitted = match (SignalContext::new(&connection, &path), &destinations) {
+                    (Ok(ctxt), None) => emit_values(&ctxt, &batch).await,
+                    (Ok(ctxt), Some(destinations)) => emit_to(&ctxt, destinations, &batch).await,
+                    (Err(e), _) => Err(e),
+                };
+                let counter = match emitted {
+                    Ok(()) => &queue.shared.emitted,
+                    Err(e) => {
+                        let keys: Vec<_> = batch.iter().map(|(key, _)| format!("{}/{}", key.namespace, key.key)).collect();
+                        eprintln!("Cannot emit SettingChanged for {}: {}", keys.join(", "), e);
+                        &queue.shared.dropped
+                    }
+                };
+                counter.fetch_add(batch.len() as u64, Ordering::Relaxed);
+            }
+        });
+        emitter
+    }
+
+    fn channel(capacity: usize) -> (Self, Queue) {
+        let (sender, receiver) = mpsc::channel(capacity.max(1));
+        let shared = Arc::new(Shared::default());
+        let queue = Queue {
+            receiver,
+            shared: shared.clone(),
+        };
+        (Self { sender, shared }, queue)
+    }
+
+    /// Queue `SettingChanged` for a setting, without waiting
+    pub fn emit(&self, namespace: &str, key: &str, value: OwnedValue) {
+        self.emit_many(vec![(SettingKey::new(namespace, key), value)]);
+    }
+
+    /// Queue `SettingChanged` for several settings changed together, to be
+    /// emitted as one batch, without waiting
+    pub fn emit_many(&self, batch: Vec<(SettingKey, OwnedValue)>) {
+        if batch.is_empty() {
+            return;
+        }
+        let mut overflow = self.shared.overflow.lock().unwrap();
+        // Bypassing a non-empty overflow would emit out of order
+        let batch = match overflow.order.is_empty() {
+            true => match self.sender.try_send(batch) {
+                Ok(()) => return,
+                Err(TrySendError::Full(batch)) => batch,
+                Err(TrySendError::Closed(batch)) => {
+                    self.shared.dropped.fetch_add(batch.len() as u64, Ordering::Relaxed);
+                    return;
+                }
+            },
+            false => batch,
+        };
+        for (key, value) in batch {
+            if overflow.push(key, value) {
+                self.shared.coalesced.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+    }
+
+    pub fn stats(&self) -> EmitterStats {
+        EmitterStats {
+            emitted: self.shared.emitted.load(Ordering::Relaxed),
+            coalesced: self.shared.coalesced.load(Ordering::Relaxed),
+            dropped: self.shared.dropped.load(Ordering::Relaxed),
+        }
+    }
+}
+
+/// Emit `batch` to each of `destinations`, failing if sending to any fails
+async fn emit_to(ctxt: &SignalContext<'_>, destinations: &Destinations, batch: &[Emission]) -> zbus::Result<()> {
+    let names: Vec<String> = destinations.lock().unwrap().iter().cloned().collect();
+    let mut result = Ok(());
+    for name in names {
+        let destination = BusName::try_from(name)?;
+        if let Err(e) = emit_values(&ctxt.clone().set_destination(destination), batch).await {
+            result = Err(e);
+        }
+    }
+    result
+}
+
+/// The receiving end, draining the channel before the overflow
+struct Queue {
+    receiver: mpsc::Receiver<Batch>,
+    shared: Arc<Shared>,
+}
+
+impl Queue {
+    /// The next signals to emit, or `None` once every emitter is dropped and
+    /// nothing is left
+    async fn next(&mut self) -> Option<Batch> {
+        match self.receiver.try_recv() {
+            Ok(batch) => Some(batch),
+            // Nothing enters the channel while the overflow is non-empty, so
+            // everything left in the channel is older than the overflow
+            Err(TryRecvError::Empty) => {
+                let overflowed = self.shared.overflow.lock().unwrap().take();
+                match overflowed {
+                    Some(batch) => Some(batch),
+                    None => self.receiver.recv().await,
+                }
+            }
+            Err(TryRecvError::Disconnected) => self.shared.overflow.lock().unwrap().take(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_overflow_is_coalesced_in_order() {
+        let (emitter, mut queue) = SignalEmitter::channel(1);
+        for (key, value) in [("a", 1u32), ("b", 1), ("c", 1), ("b", 2), ("b", 3)] {
+            emitter.emit("com.example", key, OwnedValue::from(value));
+        }
+        assert_eq!(emitter.stats().coalesced, 2);
+        drop(emitter);
+
+        let mut emitted = Vec::new();
+        while let Some(batch) = queue.next().await {
+            emitted.push(batch.into_iter().map(|(key, value)| (key.key.to_string(), u32::try_from(value).unwrap())).collect::<Vec<_>>());
+        }
+        // Everything that overflowed goes out together
+        let expected = [vec![("a", 1)], vec![("b", 3), ("c", 1)]]
+            .map(|batch| batch.into_iter().map(|(key, value)| (key.to_string(), value)).collect::<Vec<_>>());
+        assert_eq!(emitted, expected);
+    }
+
+    #[tokio::test]
+    async fn test_batches_stay_together() {
+        let (emitter, mut queue) = SignalEmitter::channel(4);
+        let batch = ["a", "b"].map(|key| (SettingKey::new("com.example", key), OwnedValue::from(1u32)));
+        emitter.emit_many(batch.into());
+        emitter.emit_many(Vec::new());
+        emitter.emit("com.example", "c", OwnedValue::from(1u32));
+        drop(emitter);
+
+        let mut sizes = Vec::new();
+        while let Some(batch) = queue.next().await {
+            sizes.push(batch.len());
+        }
+        assert_eq!(sizes, [2, 1]);
+    }
+
+    /// Records the values written to a setting, in the order they were made
+    #[derive(Default)]
+    struct Commits(Mutex<Vec<u32>>);
+
+    impl crate::ChangeObserver for Commits {
+        fn on_change(&self, change: &crate::SettingChange) {
+            if let Some(value) = &change.new {
+                self.0.lock().unwrap().push(u32::try_from(&*value.0).unwrap());
+            }
+        }
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn test_signals_follow_commit_order() {
+        use crate::testing::{TestPortal, PATH};
+        use futures_util::StreamExt;
+        use zbus::{MatchRule, MessageStream};
+
+        const INTERFACE: &str = "org.freedesktop.impl.portal.Settings";
+        const WRITERS: u32 = 8;
+        const WRITES: u32 = 50;
+        let portal = TestPortal::p2p(crate::SettingsStore::new()).await.unwrap();
+        let commits = Arc::new(Commits::default());
+        portal.store.add_observer(commits.clone());
+        let path = OwnedObjectPath::try_from(PATH).unwrap();
+        portal.store.set_signal_emitter(Some(SignalEmitter::spawn(portal.server.clone(), path, DEFAULT_CAPACITY)));
+
+        let rule = MatchRule::builder()
+            .msg_type(zbus::message::Type::Signal)
+            .interface(INTERFACE)
+            .unwrap()
+            .member("SettingChanged")
+            .unwrap()
+            .build();
+        let mut signals = MessageStream::for_match_rule(rule, &portal.client, None).await.unwrap();
+
+        // Every value is written once; 0 comes last, after all writers
+        let write = |store: crate::SettingsStore, value: u32| async move {
+            store.write("com.example.Race", "value", OwnedValue::from(value)).await.unwrap();
+        };
+        let writers: Vec<_> = (0..WRITERS)
+            .map(|writer| {
+                let store = portal.store.clone();
+                tokio::spawn(async move {
+                    for i in 0..WRITES {
+                        write(store.clone(), writer * WRITES + i + 1).await;
+                    }
+                })
+            })
+            .collect();
+        let store = portal.store.clone();
+        let done = tokio::spawn(async move {
+            for writer in writers {
+                writer.await.unwrap();
+            }
+            write(store, 0).await;
+        });
+
+        // Read right after each signal, while the writers keep going
+        let mut seen = Vec::new();
+        loop {
+            let signal = signals.next().await.unwrap().unwrap();
+            let (_, _, value): (String, String, OwnedValue) = signal.body().deserialize().unwrap();
+            let signalled = u32::try_from(value).unwrap();
+            // Not over the bus: awaiting a reply while signals pile up in the
+            // client's queue would stall the connection
+            let read = portal.store.read("com.example.Race", "value").await.unwrap();
+            seen.push((signalled, u32::try_from(&*read.0).unwrap()));
+            if signalled == 0 {
+                break;
+            }
+        }
+        done.await.unwrap();
+
+        let commits = commits.0.lock().unwrap().clone();
+        assert_eq!(commits.len() as u32, WRITERS * WRITES + 1);
+        let position = |value: u32| commits.iter().position(|v| *v == value).unwrap();
+        for pair in seen.windows(2) {
+            assert!(position(pair[0].0) < position(pair[1].0), "signals out of order: {:?}", pair);
+        }
+        for (signalled, read) in seen {
+            assert!(position(read) >= position(signalled), "read {} after the signal of {}", read, signalled);
+        }
+    }
+}
@@ -0,0 +1,164 @@
+use std::error::Error as StdError;
+use std::fmt;
+
+type Source = Box<dyn StdError + Send + Sync>;
+
+/// Error returned by the library
+///
+/// A message, optionally caused by another error. `{}` shows the message
+/// and `{:#}` the message followed by each cause, separated by `: `.
+pub struct Error(Repr);
+
+enum Repr {
+    Message(String, Option<Source>),
+    /// An error of another crate, shown as it is
+    Wrapped(Source),
+}
+
+pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+impl Error {
+    pub fn msg(message: impl fmt::Display) -> Self {
+        Self(Repr::Message(message.to_string(), None))
+    }
+
+    fn wrap(message: impl fmt::Display, source: impl StdError + Send + Sync + 'static) -> Self {
+        Self(Repr::Message(message.to_string(), Some(Box::new(source))))
+    }
+
+    /// This error or the first of its causes that is an `E`
+    pub fn find<E: StdError + 'static>(&self) -> Option<&E> {
+        if let Repr::Wrapped(error) = &self.0 {
+            if let Some(error) = error.downcast_ref() {
+                return Some(error);
+            }
+        }
+        let mut cause = self.source();
+        while let Some(error) = cause {
+            if let Some(error) = error.downcast_ref() {
+                return Some(error);
+            }
+            cause = error.source();
+        }
+        None
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.0 {
+            Repr::Message(message, _) => f.write_str(message)?,
+            Repr::Wrapped(error) => fmt::Display::fmt(error, f)?,
+        }
+        if f.alternate() {
+            let mut cause = self.source();
+            while let Some(error) = cause {
+                write!(f, ": {}", error)?;
+                cause = error.source();
+            }
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Debug for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:#}", self)
+    }
+}
+
+impl StdError for Error {
+    fn source(&self) -> Option<&(dyn StdError + 'static)> {
+        match &self.0 {
+            Repr::Message(_, source) => source.as_deref().map(|e| e as &(dyn StdError + 'static)),
+            Repr::Wrapped(error) => error.source(),
+        }
+    }
+}
+
+macro_rules! wrap_errors {
+    ($($error:ty),* $(,)?) => {
+        $(
+            impl From<$error> for Error {
+                fn from(error: $error) -> Self {
+                    Self(Repr::Wrapped(Box::new(error)))
+                }
+            }
+        )*
+    };
+}
+
+wrap_errors!(
+    std::io::Error,
+    std::str::Utf8Error,
+    toml::de::Error,
+    toml::ser::Error,
+    zbus::Error,
+    zbus::zvariant::Error,
+    crate::ValidationError,
+    crate::ReadOnlyError,
+);
+
+#[cfg(feature = "plugins")]
+wrap_errors!(libloading::Error);
+
+/// Describe the error of a `Result`, or the `None` of an `Option`
+pub trait Context<T> {
+    fn context(self, message: impl fmt::Display) -> Result<T>;
+
+    fn with_context<M: fmt::Display>(self, message: impl FnOnce() -> M) -> Result<T>;
+}
+
+impl<T, E: StdError + Send + Sync + 'static> Context<T> for Result<T, E> {
+    fn context(self, message: impl fmt::Display) -> Result<T> {
+        self.map_err(|e| Error::wrap(message, e))
+    }
+
+    fn with_context<M: fmt::Display>(self, message: impl FnOnce() -> M) -> Result<T> {
+        self.map_err(|e| Error::wrap(message(), e))
+    }
+}
+
+impl<T> Context<T> for Option<T> {
+    fn context(self, message: impl fmt::Display) -> Result<T> {
+        self.ok_or_else(|| Error::msg(message))
+    }
+
+    fn with_context<M: fmt::Display>(self, message: impl FnOnce() -> M) -> Result<T> {
+        self.ok_or_else(|| Error::msg(message()))
+    }
+}
+
+/// Return an `Error` with a formatted message
+macro_rules! bail {
+    ($($arg:tt)*) => {
+        return Err($crate::Error::msg(format!($($arg)*)))
+    };
+}
+pub(crate) use bail;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_causes_are_shown_in_alternate_form() {
+        let io = std::io::Error::new(std::io::ErrorKind::NotFound, "no such file");
+        let error = Err::<(), _>(io).context("cannot read a.toml").context("invalid configuration").unwrap_err();
+        assert_eq!(error.to_string(), "invalid configuration");
+        assert_eq!(format!("{:#}", error), "invalid configuration: cannot read a.toml: no such file");
+
+        let wrapped = Error::from(toml::from_str::<toml::Table>("a =").unwrap_err());
+        assert!(wrapped.source().is_none());
+        assert_eq!(format!("{:#}", wrapped), wrapped.to_string());
+    }
+
+    #[test]
+    fn test_find_looks_through_causes() {
+        let io = || std::io::Error::new(std::io::ErrorKind::NotFound, "no such file");
+        assert!(Error::from(io()).find::<std::io::Error>().is_some());
+        let error = Err::<(), _>(io()).context("cannot read a.toml").unwrap_err();
+        assert!(error.find::<std::io::Error>().is_some());
+        assert!(error.find::<std::str::Utf8Error>().is_none());
+    }
+}
@@ -0,0 +1,113 @@
+/// Smallest size a font description may give, in points or pixels
+pub const MIN_SIZE: f64 = 1.0;
+/// Largest size a font description may give, in points or pixels
+pub const MAX_SIZE: f64 = 256.0;
+
+/// Words Pango reads as style options rather than as part of the family
+const STYLES: &[&str] = &[
+    // Style and variant
+    "normal", "roman", "oblique", "italic", "small-caps", "all-small-caps", "petite-caps", "all-petite-caps",
+    "unicase", "title-caps",
+    // Weight
+    "thin", "ultra-light", "extra-light", "light", "semi-light", "demi-light", "book", "regular", "medium",
+    "semi-bold", "demi-bold", "bold", "ultra-bold", "extra-bold", "heavy", "black", "ultra-black", "extra-black",
+    // Stretch
+    "ultra-condensed", "extra-condensed", "condensed", "semi-condensed", "semi-expanded", "expanded",
+    "extra-expanded", "ultra-expanded",
+];
+
+/// A font description as GNOME stores it: `Family [Style…] Size`, e.g.
+/// `Cantarell 11`, `Source Code Pro Bold Italic 10` or `Sans 14px`
+///
+/// This follows Pango closely enough to catch values that are not font
+/// descriptions at all, not every form Pango accepts: the family and the
+/// size are required, and variations (`@wght=200`) are not supported.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FontDescription<'a> {
+    /// One family or a comma-separated list of them
+    pub family: &'a str,
+    /// Style options, in the order given
+    pub styles: Vec<&'a str>,
+    pub size: f64,
+    /// Whether `size` is in pixels rather than points
+    pub pixels: bool,
+}
+
+impl<'a> FontDescription<'a> {
+    /// Parse `text`, or `None` unless it is a family, any style options and
+    /// a size within `MIN_SIZE..=MAX_SIZE`
+    pub fn parse(text: &'a str) -> Option<Self> {
+        if text.chars().any(char::is_control) {
+            return None;
+        }
+        let (rest, size) = text.trim().rsplit_once(char::is_whitespace)?;
+        let (size, pixels) = match size.strip_suffix("px") {
+            Some(size) => (size, true),
+            None => (size, false),
+        };
+        let size: f64 = size.parse().ok()?;
+        if !(MIN_SIZE..=MAX_SIZE).contains(&size) {
+            return None;
+        }
+
+        // Style options come last; a family may not consist of them alone
+        let mut family = rest.trim_end();
+        let mut styles = Vec::new();
+        while !family.is_empty() {
+            let (head, word) = family.rsplit_once(char::is_whitespace).unwrap_or(("", family));
+            if !STYLES.iter().any(|style| style.eq_ignore_ascii_case(word)) {
+                break;
+            }
+            styles.push(word);
+            family = head.trim_end();
+        }
+        styles.reverse();
+        let family = family.trim_end_matches(',').trim_end();
+        if family.is_empty() || family.split(',').any(|name| name.trim().is_empty()) {
+            return None;
+        }
+        Some(Self {
+            family,
+            styles,
+            size,
+            pixels,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        let font = FontDescription::parse("Source Code Pro Bold Italic 10").unwrap();
+        assert_eq!(font.family, "Source Code Pro");
+        assert_eq!(font.styles, ["Bold", "Italic"]);
+        assert_eq!((font.size, font.pixels), (10.0, false));
+
+        let font = FontDescription::parse(" Cantarell, Noto Sans  semi-bold 10.5px ").unwrap();
+        assert_eq!(font.family, "Cantarell, Noto Sans");
+        assert_eq!(font.styles, ["semi-bold"]);
+        assert_eq!((font.size, font.pixels), (10.5, true));
+        // Only the trailing words are style options
+        assert_eq!(FontDescription::parse("Light Sans 11").unwrap().family, "Light Sans");
+
+        for text in [
+            "",
+            "Cantarell",
+            "11",
+            "Bold 11",
+            "Cantarell eleven",
+            "Cantarell 0",
+            "Cantarell 257",
+            "Cantarell NaN",
+            "Cantarell inf",
+            "Cantarell 11pt",
+            "Cantarell,, Sans 11",
+            "Cantarell\n11",
+        ] {
+            assert!(FontDescription::parse(text).is_none(), "{:?}", text);
+        }
+    }
+}
@@ -0,0 +1,122 @@
+use crate::{SettingsReply, SettingsStore};
+use zbus::message::Header;
+use zbus::zvariant::{OwnedValue, Value};
+use zbus::{interface, Connection, DBusError};
+
+/// Bus name owned by xdg-desktop-portal, which serves the frontend interface
+pub const FRONTEND_BUS_NAME: &str = "org.freedesktop.portal.Desktop";
+
+/// Errors returned by the frontend interface
+#[derive(Debug, DBusError)]
+#[zbus(prefix = "org.freedesktop.portal.Error")]
+pub enum PortalError {
+    #[zbus(error)]
+    ZBus(zbus::Error),
+    /// The requested setting does not exist
+    NotFound(String),
+    /// An argument is malformed, such as a namespace filter
+    InvalidArgument(String),
+}
+
+/// D-Bus interface implementation for org.freedesktop.portal.Settings
+///
+/// This is the interface applications talk to, normally provided by
+/// xdg-desktop-portal on top of an `org.freedesktop.impl.portal.Settings`
+/// backend. Serving it directly lets small images run without
+/// xdg-desktop-portal.
+pub struct SettingsFrontend {
+    store: SettingsStore,
+}
+
+impl SettingsFrontend {
+    pub fn new(store: SettingsStore) -> Self {
+        Self { store }
+    }
+
+    pub fn store(&self) -> &SettingsStore {
+        &self.store
+    }
+
+    /// The setting for the sender of `header`, unless it does not exist, its
+    /// namespace is denied or the policy hides it from the sender
+    async fn lookup(
+        &self,
+        connection: &Connection,
+        header: &Header<'_>,
+        namespace: &str,
+        key: &str,
+    ) -> Result<OwnedValue, PortalError> {
+        if self.store.is_denied(namespace, key) {
+            return Err(PortalError::NotFound("Requested setting not found".to_string()));
+        }
+        let (caller, store) = self.store.serve(connection, header).await.map_err(|e| PortalError::ZBus(e.into()))?;
+        let value = match store.is_visible(&caller, namespace, key) {
+            true => store.read(namespace, key).await,
+            false => None,
+        };
+        value
+            .ok_or_else(|| PortalError::NotFound("Requested setting not found".to_string()))?
+            .to_owned_value()
+            .map_err(|e| PortalError::ZBus(e.into()))
+    }
+}
+
+#[interface(name = "org.freedesktop.portal.Settings")]
+impl SettingsFrontend {
+    /// Read all settings, optionally filtered by namespaces and trailing
+    /// globs like `org.gnome.*`
+    async fn read_all(
+        &self,
+        #[zbus(connection)] connection: &Connection,
+        #[zbus(header)] header: Header<'_>,
+        namespaces: Vec<String>,
+    ) -> Result<SettingsReply, PortalError> {
+        let (caller, store) = self.store.serve(connection, &header).await.map_err(|e| PortalError::ZBus(e.into()))?;
+        store
+            .read_all_call(namespaces, &caller)
+            .await
+            .map_err(|e| PortalError::InvalidArgument(format!("{:#}", e)))
+    }
+
+    /// Read a single setting (deprecated in favor of ReadOne)
+    ///
+    /// Like xdg-desktop-portal, the value is wrapped in an additional variant.
+    async fn read(
+        &self,
+        #[zbus(connection)] connection: &Connection,
+        #[zbus(header)] header: Header<'_>,
+        namespace: &str,
+        key: &str,
+    ) -> Result<OwnedValue, PortalError> {
+        let value = self.lookup(connection, &header, namespace, key).await?;
+        Value::Value(Box::new(Value::from(value)))
+            .try_into()
+            .map_err(|e: zbus::zvariant::Error| PortalError::ZBus(e.into()))
+    }
+
+    /// Read a single setting
+    async fn read_one(
+        &self,
+        #[zbus(connection)] connection: &Connection,
+        #[zbus(header)] header: Header<'_>,
+        namespace: &str,
+        key: &str,
+    ) -> Result<OwnedValue, PortalError> {
+        self.lookup(connection, &header, namespace, key).await
+    }
+
+    /// Version of the frontend interface implemented
+    #[zbus(property(emits_changed_signal = "const"), name = "version")]
+    fn version(&self) -> u32 {
+        2
+    }
+
+    /// Signal emitted when a setting changes
+    #[zbus(signal)]
+    pub(crate) async fn setting_changed(
+        signal_ctxt: &zbus::SignalContext<'_>,
+        namespace: &str,
+        key: &str,
+        value: Value<'_>,
+    ) -> zbus::Result<()>;
+}
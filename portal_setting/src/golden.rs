@@ -0,0 +1,69 @@
+//! Golden tests for what goes over the wire
+//!
+//! Each payload is compared, signature and bytes, with a file in `golden/`.
+//! A change in either breaks clients, so it should be deliberate; after one,
+//! `UPDATE_GOLDEN=1 cargo test -p portal_setting golden` rewrites the files.
+
+use crate::SettingsStore;
+use serde::Serialize;
+use std::path::PathBuf;
+use zbus::zvariant::{serialized::Context, DynamicType, OwnedValue, Value, LE};
+
+/// Signature, then the bytes in hex, 16 to a line
+fn encode<T: Serialize + DynamicType>(value: &T) -> String {
+    let bytes = zbus::zvariant::to_bytes(Context::new_dbus(LE, 0), value).unwrap();
+    let mut text = format!("{}\n", value.dynamic_signature());
+    for line in bytes.chunks(16) {
+        let hex: Vec<String> = line.iter().map(|byte| format!("{:02x}", byte)).collect();
+        text.push_str(&hex.join(" "));
+        text.push('\n');
+    }
+    text
+}
+
+fn check<T: Serialize + DynamicType>(name: &str, value: &T) {
+    let path = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("golden").join(format!("{}.txt", name));
+    let actual = encode(value);
+    if std::env::var_os("UPDATE_GOLDEN").is_some() {
+        std::fs::write(&path, &actual).unwrap();
+        return;
+    }
+    let expected = std::fs::read_to_string(&path).unwrap_or_else(|e| panic!("cannot read {}: {}", path.display(), e));
+    assert_eq!(actual, expected, "{} changed", name);
+}
+
+async fn store() -> SettingsStore {
+    let store = SettingsStore::new();
+    let write = |namespace, key, value: Value<'static>| {
+        let store = store.clone();
+        async move { store.write(namespace, key, OwnedValue::try_from(value).unwrap()).await.unwrap() }
+    };
+    write("org.freedesktop.appearance", "color-scheme", Value::U32(1)).await;
+    write("org.freedesktop.appearance", "accent-color", Value::from((0.25, 0.5, 0.75))).await;
+    write("com.example.Golden", "greeting", Value::from("hello")).await;
+    store
+}
+
+#[tokio::test]
+async fn test_read() {
+    let store = store().await;
+    let read = |key| {
+        let store = store.clone();
+        async move { store.read("org.freedesktop.appearance", key).await.unwrap().to_owned_value().unwrap() }
+    };
+    check("read-color-scheme", &read("color-scheme").await);
+    check("read-accent-color", &read("accent-color").await);
+}
+
+#[tokio::test]
+async fn test_read_all() {
+    let store = store().await;
+    check("read-all", &store.read_all_reply(vec!["com.example.Golden".to_string()]).await);
+    check("read-all-empty", &store.read_all_reply(vec!["com.example.Missing".to_string()]).await);
+}
+
+#[test]
+fn test_setting_changed() {
+    check("setting-changed", &("org.freedesktop.appearance", "color-scheme", Value::U32(1)));
+    check("setting-changed-bool", &("org.gnome.desktop.privacy", "remember-recent-files", Value::Bool(false)));
+}
@@ -0,0 +1,435 @@
+//! Types and ranges of the installed GSettings schemas
+//!
+//! GNOME describes its settings in `*.gschema.xml` files, next to the
+//! `gschemas.compiled` GLib builds from them. The XML is read rather than
+//! the compiled file, whose format is private to GLib. Writes to an
+//! `org.gnome.*` namespace with an installed schema are then checked
+//! against it, catching values GNOME itself would refuse.
+
+use crate::error::{bail, Context, Result};
+use crate::{schema, ValidationError, Validator};
+use futures_util::future::BoxFuture;
+use std::collections::HashMap;
+use std::path::Path;
+use zbus::zvariant::{OwnedValue, Value};
+
+/// Where distributions install the schemas
+pub const DEFAULT_SCHEMA_DIR: &str = "/usr/share/glib-2.0/schemas";
+
+/// A key of a GSettings schema
+#[derive(Debug, Clone, PartialEq)]
+pub struct GSettingsKey {
+    /// GVariant type, which for the types of settings is the D-Bus signature
+    pub signature: String,
+    /// Smallest and largest accepted number, from `<range>`
+    pub range: Option<(f64, f64)>,
+    /// Accepted strings, from `<choices>` or the nicks of an enum or flags
+    /// type; empty accepts any
+    pub choices: Vec<String>,
+}
+
+/// The schemas of a directory, keyed by schema id, which is the portal
+/// namespace
+#[derive(Debug, Default)]
+pub struct GSettingsSchemas {
+    schemas: HashMap<String, HashMap<String, GSettingsKey>>,
+}
+
+impl GSettingsSchemas {
+    /// Read every `*.xml` file of `dir`: schemas and the enums they use
+    pub fn load_dir(dir: &Path) -> Result<Self> {
+        let entries = std::fs::read_dir(dir).with_context(|| format!("cannot read {}", dir.display()))?;
+        let mut sources = Vec::new();
+        for entry in entries {
+            let path = entry?.path();
+            if path.extension().is_some_and(|ext| ext == "xml") {
+                let text = std::fs::read_to_string(&path).with_context(|| format!("cannot read {}", path.display()))?;
+                sources.push((path, text));
+            }
+        }
+        sources.sort();
+
+        let mut documents = Vec::new();
+        for (path, text) in &sources {
+            let document = roxmltree::Document::parse(text).with_context(|| format!("cannot parse {}", path.display()))?;
+            documents.push((path, document));
+        }
+
+        // Enums may be declared in another file than the keys using them
+        let mut enums = HashMap::new();
+        for (_, document) in &documents {
+            for node in document.root_element().children().filter(|n| n.has_tag_name("enum") || n.has_tag_name("flags")) {
+                let nicks = node.children().filter(|n| n.has_tag_name("value")).filter_map(|n| n.attribute("nick"));
+                enums.insert(node.attribute("id").unwrap_or_default(), nicks.map(str::to_string).collect::<Vec<_>>());
+            }
+        }
+
+        let mut schemas = Self::default();
+        let mut extends = Vec::new();
+        for (path, document) in &documents {
+            for node in document.root_element().children().filter(|n| n.has_tag_name("schema")) {
+                let Some(id) = node.attribute("id") else {
+                    bail!("{}: schema without an id", path.display());
+                };
+                let mut keys = HashMap::new();
+                for key in node.children().filter(|n| n.has_tag_name("key")) {
+                    let name = key.attribute("name").unwrap_or_default();
+                    let parsed = parse_key(key, &enums).with_context(|| format!("{}: key {}/{}", path.display(), id, name))?;
+                    keys.insert(name.to_string(), parsed);
+                }
+                if let Some(base) = node.attribute("extends") {
+                    extends.push((id.to_string(), base.to_string()));
+                }
+                schemas.schemas.insert(id.to_string(), keys);
+            }
+        }
+        // A schema extending another has its keys too, unless it overrides them
+        for (id, base) in extends {
+            let Some(inherited) = schemas.schemas.get(&base).cloned() else {
+                bail!("schema {} extends the unknown schema {}", id, base);
+            };
+            let keys = schemas.schemas.get_mut(&id).unwrap();
+            for (name, key) in inherited {
+                keys.entry(name).or_insert(key);
+            }
+        }
+        Ok(schemas)
+    }
+
+    /// Number of schemas loaded
+    pub fn len(&self) -> usize {
+        self.schemas.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.schemas.is_empty()
+    }
+
+    /// Whether a schema has the id `namespace`
+    pub fn has_schema(&self, namespace: &str) -> bool {
+        self.schemas.contains_key(namespace)
+    }
+
+    pub fn key(&self, namespace: &str, key: &str) -> Option<&GSettingsKey> {
+        self.schemas.get(namespace)?.get(key)
+    }
+
+    /// Accept `value` for `namespace`/`key` if the schema of `namespace`
+    /// does, or if there is no such schema
+    pub fn check(&self, namespace: &str, key: &str, value: &Value<'_>) -> Result<()> {
+        let Some(keys) = self.schemas.get(namespace) else {
+            return Ok(());
+        };
+        let Some(schema) = keys.get(key) else {
+            bail!("{}/{} is not in the installed GSettings schema", namespace, key);
+        };
+        let invalid = || ValidationError {
+            namespace: namespace.to_string(),
+            key: key.to_string(),
+            expected: schema.signature.clone(),
+            constraint: None,
+            received: value.value_signature().to_string(),
+        };
+        if value.value_signature().as_str() != schema.signature {
+            return Err(invalid().into());
+        }
+        if let Some((min, max)) = schema.range {
+            if to_number(value).is_some_and(|number| !(min..=max).contains(&number)) {
+                return Err(invalid().with_constraint(format!("{}..={}", min, max)).into());
+            }
+        }
+        if !schema.choices.is_empty() {
+            let accepted = |value: &Value<'_>| match value {
+                Value::Str(text) => schema.choices.iter().any(|choice| choice == text.as_str()),
+                _ => true,
+            };
+            let valid = match value {
+                Value::Array(items) => items.iter().all(accepted),
+                value => accepted(value),
+            };
+            if !valid {
+                return Err(invalid().with_constraint(schema.choices.join("|")).into());
+            }
+        }
+        Ok(())
+    }
+
+    /// Where the built-in `org.gnome.*` settings disagree with these
+    /// schemas, one message per difference
+    pub fn drift(&self) -> Vec<String> {
+        let mut drift = Vec::new();
+        for builtin in schema::BUILTIN.iter().filter(|s| s.namespace.starts_with("org.gnome.")) {
+            let (namespace, key) = (builtin.namespace, builtin.key);
+            if !self.has_schema(namespace) {
+                drift.push(format!("{}/{}: no installed schema {}", namespace, key, namespace));
+                continue;
+            }
+            let Some(installed) = self.key(namespace, key) else {
+                drift.push(format!("{}/{}: not in the installed schema", namespace, key));
+                continue;
+            };
+            if builtin.default.signature() != installed.signature {
+                drift.push(format!(
+                    "{}/{}: type {} here, {} in GSettings",
+                    namespace,
+                    key,
+                    builtin.default.signature(),
+                    installed.signature
+                ));
+                continue;
+            }
+            let default = builtin.default.to_value();
+            if let Err(e) = self.check(namespace, key, &default) {
+                drift.push(format!("{}/{}: default {:?} rejected by GSettings: {}", namespace, key, default, e));
+            }
+            for choice in builtin.choices {
+                if let Err(e) = self.check(namespace, key, &Value::from(*choice)) {
+                    drift.push(format!("{}/{}: choice {:?} rejected by GSettings: {}", namespace, key, choice, e));
+                }
+            }
+        }
+        drift
+    }
+}
+
+/// Checks writes to `org.gnome.*` namespaces against the installed schemas
+impl Validator for GSettingsSchemas {
+    fn name(&self) -> &str {
+        "gsettings"
+    }
+
+    fn validate<'a>(&'a self, namespace: &'a str, key: &'a str, value: &'a OwnedValue) -> BoxFuture<'a, Result<()>> {
+        Box::pin(async move {
+            match namespace.starts_with("org.gnome.") {
+                true => self.check(namespace, key, value),
+                false => Ok(()),
+            }
+        })
+    }
+}
+
+/// The type, range and choices of a `<key>` element
+fn parse_key(node: roxmltree::Node<'_, '_>, enums: &HashMap<&str, Vec<String>>) -> Result<GSettingsKey> {
+    let nicks = |id: &str| match enums.get(id) {
+        Some(nicks) => Ok(nicks.clone()),
+        None => bail!("unknown enum {}", id),
+    };
+    let (signature, mut choices) = match (node.attribute("type"), node.attribute("enum"), node.attribute("flags")) {
+        (Some(signature), None, None) => (signature.to_string(), Vec::new()),
+        (None, Some(id), None) => ("s".to_string(), nicks(id)?),
+        (None, None, Some(id)) => ("as".to_string(), nicks(id)?),
+        _ => bail!("expected one of the type, enum and flags attributes"),
+    };
+
+    let mut range = None;
+    for child in node.children().filter(|n| n.is_element()) {
+        match child.tag_name().name() {
+            "range" => {
+                let bound = |name| -> Result<f64> {
+                    match child.attribute(name).and_then(parse_number) {
+                        Some(bound) => Ok(bound),
+                        None => bail!("invalid range {}", name),
+                    }
+                };
+                range = Some((bound("min")?, bound("max")?));
+            }
+            "choices" => {
+                let values = child.children().filter(|n| n.has_tag_name("choice")).filter_map(|n| n.attribute("value"));
+                choices.extend(values.map(str::to_string));
+            }
+            _ => {}
+        }
+    }
+    Ok(GSettingsKey {
+        signature,
+        range,
+        choices,
+    })
+}
+
+/// A number in GVariant text format, with or without a type annotation,
+/// e.g. `20`, `0.5` or `uint32 20`
+fn parse_number(text: &str) -> Option<f64> {
+    text.split_whitespace().last()?.parse().ok()
+}
+
+fn to_number(value: &Value<'_>) -> Option<f64> {
+    match *value {
+        Value::U8(n) => Some(n.into()),
+        Value::I16(n) => Some(n.into()),
+        Value::U16(n) => Some(n.into()),
+        Value::I32(n) => Some(n.into()),
+        Value::U32(n) => Some(n.into()),
+        Value::I64(n) => Some(n as f64),
+        Value::U64(n) => Some(n as f64),
+        Value::F64(n) => Some(n),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const INTERFACE: &str = r#"<schemalist>
+  <schema id="org.gnome.desktop.interface" path="/org/gnome/desktop/interface/">
+    <key name="font-name" type="s">
+      <default>'Cantarell 11'</default>
+    </key>
+    <key name="cursor-size" type="i">
+      <default>24</default>
+      <range min="1" max="128"/>
+    </key>
+    <key name="text-scaling-factor" type="d">
+      <default>1.0</default>
+      <range min="0.5" max="3.0"/>
+    </key>
+    <key name="clock-format" enum="org.gnome.desktop.GDesktopClockFormat">
+      <default>'24h'</default>
+    </key>
+    <key name="toolbar-style" type="s">
+      <choices>
+        <choice value="icons"/>
+        <choice value="text"/>
+      </choices>
+      <default>'icons'</default>
+    </key>
+  </schema>
+</schemalist>"#;
+
+    const PRIVACY: &str = r#"<schemalist>
+  <schema id="org.gnome.desktop.privacy" path="/org/gnome/desktop/privacy/">
+    <key name="remember-recent-files" type="b">
+      <default>true</default>
+    </key>
+    <key name="recent-files-max-age" type="u">
+      <default>7</default>
+    </key>
+  </schema>
+</schemalist>"#;
+
+    const ENUMS: &str = r#"<schemalist>
+  <enum id="org.gnome.desktop.GDesktopClockFormat">
+    <value nick="24h" value="0"/>
+    <value nick="12h" value="1"/>
+  </enum>
+  <flags id="org.gnome.desktop.Modifiers">
+    <value nick="shift" value="1"/>
+    <value nick="control" value="2"/>
+  </flags>
+</schemalist>"#;
+
+    const EXTENDED: &str = r#"<schemalist>
+  <schema id="org.gnome.example.base">
+    <key name="modifiers" flags="org.gnome.desktop.Modifiers">
+      <default>[]</default>
+    </key>
+  </schema>
+  <schema id="org.gnome.example.derived" extends="org.gnome.example.base">
+    <key name="enabled" type="b">
+      <default>false</default>
+    </key>
+  </schema>
+</schemalist>"#;
+
+    fn load(name: &str, files: &[(&str, &str)]) -> Result<GSettingsSchemas> {
+        let dir = std::env::temp_dir().join(format!("portal-gschema-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        for (file, text) in files {
+            std::fs::write(dir.join(file), text).unwrap();
+        }
+        std::fs::write(dir.join("gschemas.compiled"), b"GVariant").unwrap();
+        let schemas = GSettingsSchemas::load_dir(&dir);
+        std::fs::remove_dir_all(&dir).unwrap();
+        schemas
+    }
+
+    fn schemas() -> GSettingsSchemas {
+        load(
+            "check",
+            &[
+                ("org.gnome.desktop.interface.gschema.xml", INTERFACE),
+                ("org.gnome.desktop.enums.xml", ENUMS),
+                ("org.gnome.example.gschema.xml", EXTENDED),
+            ],
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn test_load_dir() {
+        let schemas = schemas();
+        assert_eq!(schemas.len(), 3);
+        let key = schemas.key("org.gnome.desktop.interface", "clock-format").unwrap();
+        assert_eq!(key.signature, "s");
+        assert_eq!(key.choices, ["24h", "12h"]);
+        assert_eq!(schemas.key("org.gnome.desktop.interface", "cursor-size").unwrap().range, Some((1.0, 128.0)));
+        // Keys of the base schema, with the nicks of its flags
+        let key = schemas.key("org.gnome.example.derived", "modifiers").unwrap();
+        assert_eq!((key.signature.as_str(), key.choices.len()), ("as", 2));
+
+        let error = load("unknown-enum", &[("a.gschema.xml", INTERFACE)]).unwrap_err();
+        assert!(format!("{:#}", error).contains("unknown enum org.gnome.desktop.GDesktopClockFormat"), "{:#}", error);
+        let error = load("malformed", &[("a.gschema.xml", "<schemalist>")]).unwrap_err();
+        assert!(error.to_string().contains("cannot parse"), "{}", error);
+    }
+
+    #[test]
+    fn test_check() {
+        let schemas = schemas();
+        let check = |namespace, key, value: Value<'_>| schemas.check(namespace, key, &value);
+        let interface = "org.gnome.desktop.interface";
+
+        check(interface, "font-name", Value::from("Sans 12")).unwrap();
+        check(interface, "cursor-size", Value::from(128i32)).unwrap();
+        check(interface, "text-scaling-factor", Value::from(1.25)).unwrap();
+        check(interface, "clock-format", Value::from("12h")).unwrap();
+        check("org.gnome.example.derived", "modifiers", Value::from(vec!["shift", "control"])).unwrap();
+        // Namespaces without a schema are not checked
+        check("org.gnome.Unknown", "anything", Value::from(1u32)).unwrap();
+
+        let error = check(interface, "cursor-size", Value::from(24u32)).unwrap_err();
+        let error = error.find::<ValidationError>().unwrap();
+        assert_eq!((error.expected.as_str(), error.received.as_str()), ("i", "u"));
+        let error = check(interface, "cursor-size", Value::from(129i32)).unwrap_err();
+        assert_eq!(error.find::<ValidationError>().unwrap().constraint.as_deref(), Some("1..=128"));
+        let error = check(interface, "text-scaling-factor", Value::from(0.25)).unwrap_err();
+        assert_eq!(error.find::<ValidationError>().unwrap().constraint.as_deref(), Some("0.5..=3"));
+        let error = check(interface, "clock-format", Value::from("13h")).unwrap_err();
+        assert_eq!(error.find::<ValidationError>().unwrap().constraint.as_deref(), Some("24h|12h"));
+        assert!(check(interface, "toolbar-style", Value::from("both")).is_err());
+        assert!(check("org.gnome.example.derived", "modifiers", Value::from(vec!["alt"])).is_err());
+        let error = check(interface, "missing", Value::from(1u32)).unwrap_err();
+        assert!(error.find::<ValidationError>().is_none());
+        assert_eq!(error.to_string(), "org.gnome.desktop.interface/missing is not in the installed GSettings schema");
+    }
+
+    #[tokio::test]
+    async fn test_validator() {
+        let store = crate::SettingsStore::new().with_validator(std::sync::Arc::new(schemas()));
+        let value = |value: Value<'static>| OwnedValue::try_from(value).unwrap();
+        store.write("org.gnome.desktop.interface", "cursor-size", value(Value::from(32i32))).await.unwrap();
+        assert!(store.write("org.gnome.desktop.interface", "cursor-size", value(Value::from(0i32))).await.is_err());
+        // Only org.gnome.* namespaces are checked
+        store.write("com.example", "cursor-size", value(Value::from(0i32))).await.unwrap();
+    }
+
+    #[cfg(feature = "gnome")]
+    #[test]
+    fn test_drift() {
+        let drift = schemas().drift();
+        let expected = [
+            "org.gnome.desktop.interface/gtk-theme: not in the installed schema",
+            "org.gnome.desktop.interface/icon-theme: not in the installed schema",
+            "org.gnome.desktop.interface/cursor-theme: not in the installed schema",
+            "org.gnome.desktop.interface/monospace-font-name: not in the installed schema",
+            "org.gnome.desktop.privacy/remember-recent-files: no installed schema org.gnome.desktop.privacy",
+            "org.gnome.desktop.privacy/recent-files-max-age: no installed schema org.gnome.desktop.privacy",
+        ];
+        assert_eq!(drift, expected);
+
+        let drift = load("drift", &[("privacy.gschema.xml", PRIVACY)]).unwrap().drift();
+        assert!(drift.contains(&"org.gnome.desktop.privacy/recent-files-max-age: type i here, u in GSettings".to_string()), "{:?}", drift);
+    }
+}
@@ -0,0 +1,223 @@
+use crate::error::{Context, Result};
+use crate::{ChangeObserver, SettingChange};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::sync::mpsc::{self, Sender};
+use std::sync::Mutex;
+use std::thread;
+
+/// Runs executables from a hooks directory after settings change
+///
+/// Hooks are looked up on every change, so they can be added or removed
+/// without restarting the service:
+///
+/// - `<dir>/*` run for every change
+/// - `<dir>/<namespace>/*` run for changes in that namespace
+/// - `<dir>/<namespace>/<key>/*` run for changes to that key
+///
+/// Each hook is invoked as `hook <namespace> <key> <value>` with the same
+/// information in `PORTAL_SETTING_*` environment variables. Values use the
+/// GVariant text format; the value is empty when the key was deleted. Hooks
+/// are looked up and run sequentially on a background thread in the order
+/// changes happened, so the directory is never read on the write path.
+pub struct HookRunner {
+    dir: PathBuf,
+    jobs: Mutex<Sender<HookJob>>,
+}
+
+struct HookJob {
+    namespace: String,
+    key: String,
+    value: String,
+    kind: String,
+    source: String,
+}
+
+impl HookRunner {
+    pub fn new(dir: impl Into<PathBuf>) -> Result<Self> {
+        let dir = dir.into();
+        let (tx, rx) = mpsc::channel::<HookJob>();
+        let hooks_dir = dir.clone();
+        thread::Builder::new()
+            .name("portal-setting-hooks".to_string())
+            .spawn(move || {
+                for job in rx {
+                    job.run(&matching_hooks(&hooks_dir, &job.namespace, &job.key));
+                }
+            })
+            .context("cannot spawn the hook thread")?;
+
+        Ok(Self {
+            dir,
+            jobs: Mutex::new(tx),
+        })
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// Hooks matching a change to `namespace`/`key`, in execution order
+    pub fn matching_hooks(&self, namespace: &str, key: &str) -> Vec<PathBuf> {
+        matching_hooks(&self.dir, namespace, key)
+    }
+}
+
+impl ChangeObserver for HookRunner {
+    fn on_change(&self, change: &SettingChange) {
+        // Namespaces and keys come from clients; never let them escape the hooks directory
+        if !is_safe_component(&change.namespace) || !is_safe_component(&change.key) {
+            return;
+        }
+
+        let job = HookJob {
+            namespace: change.namespace.clone(),
+            key: change.key.clone(),
+            value: change
+                .new
+                .as_ref()
+                .map(|v| v.0.to_string())
+                .unwrap_or_default(),
+            kind: change.kind.to_string(),
+            source: change.source.to_string(),
+        };
+        if self.jobs.lock().unwrap().send(job).is_err() {
+            eprintln!("Hook thread is gone, skipping hooks for {}/{}", change.namespace, change.key);
+        }
+    }
+}
+
+impl HookJob {
+    fn run(&self, hooks: &[PathBuf]) {
+        for hook in hooks {
+            let status = Command::new(hook)
+                .arg(&self.namespace)
+                .arg(&self.key)
+                .arg(&self.value)
+                .env("PORTAL_SETTING_NAMESPACE", &self.namespace)
+                .env("PORTAL_SETTING_KEY", &self.key)
+                .env("PORTAL_SETTING_VALUE", &self.value)
+                .env("PORTAL_SETTING_CHANGE", &self.kind)
+                .env("PORTAL_SETTING_SOURCE", &self.source)
+                .status();
+
+            match status {
+                Ok(status) if status.success() => {}
+                Ok(status) => eprintln!("Hook {} failed: {}", hook.display(), status),
+                Err(e) => eprintln!("Failed to run hook {}: {}", hook.display(), e),
+            }
+        }
+    }
+}
+
+fn is_safe_component(name: &str) -> bool {
+    !name.is_empty() && name != "." && name != ".." && !name.contains('/') && !name.contains('\0')
+}
+
+fn matching_hooks(dir: &Path, namespace: &str, key: &str) -> Vec<PathBuf> {
+    let mut hooks = executables_in(dir);
+    hooks.extend(executables_in(&dir.join(namespace)));
+    hooks.extend(executables_in(&dir.join(namespace).join(key)));
+    hooks
+}
+
+/// Executable regular files directly inside `dir`, sorted by name
+fn executables_in(dir: &Path) -> Vec<PathBuf> {
+    let Ok(entries) = fs::read_dir(dir) else {
+        return Vec::new();
+    };
+
+    let mut hooks: Vec<PathBuf> = entries
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.path())
+        .filter(|path| is_executable(path))
+        .collect();
+    hooks.sort();
+    hooks
+}
+
+#[cfg(unix)]
+fn is_executable(path: &Path) -> bool {
+    use std::os::unix::fs::PermissionsExt;
+    fs::metadata(path)
+        .map(|m| m.is_file() && m.permissions().mode() & 0o111 != 0)
+        .unwrap_or(false)
+}
+
+#[cfg(not(unix))]
+fn is_executable(path: &Path) -> bool {
+    path.is_file()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::SettingsStore;
+    use std::sync::Arc;
+    use std::time::{Duration, Instant};
+    use zbus::zvariant::Value;
+
+    #[cfg(unix)]
+    fn write_hook(path: &Path, script: &str) {
+        use std::os::unix::fs::PermissionsExt;
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, script).unwrap();
+        fs::set_permissions(path, fs::Permissions::from_mode(0o755)).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_hooks_run_for_matching_keys() {
+        let dir = std::env::temp_dir().join(format!("portal-hooks-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let out = dir.join("out");
+
+        let script = format!(
+            "#!/bin/sh\necho \"$(basename \"$0\") $1 $2 $3 $PORTAL_SETTING_CHANGE\" >> {}\n",
+            out.display()
+        );
+        write_hook(&dir.join("hooks/00-all"), &script);
+        write_hook(&dir.join("hooks/org.freedesktop.appearance/10-ns"), &script);
+        write_hook(&dir.join("hooks/org.freedesktop.appearance/contrast/20-key"), &script);
+        // Not executable, never run
+        fs::write(dir.join("hooks/README"), "").unwrap();
+
+        let store = SettingsStore::new();
+        store.add_observer(Arc::new(HookRunner::new(dir.join("hooks")).unwrap()));
+        store
+            .write("org.freedesktop.appearance", "color-scheme", Value::U32(1).try_into().unwrap())
+            .await
+            .unwrap();
+        store
+            .write("org.freedesktop.appearance", "contrast", Value::U32(1).try_into().unwrap())
+            .await
+            .unwrap();
+
+        let expected = "00-all org.freedesktop.appearance color-scheme uint32 1 write\n\
+                        10-ns org.freedesktop.appearance color-scheme uint32 1 write\n\
+                        00-all org.freedesktop.appearance contrast uint32 1 write\n\
+                        10-ns org.freedesktop.appearance contrast uint32 1 write\n\
+                        20-key org.freedesktop.appearance contrast uint32 1 write\n";
+        let deadline = Instant::now() + Duration::from_secs(10);
+        let mut output = String::new();
+        while Instant::now() < deadline {
+            output = fs::read_to_string(&out).unwrap_or_default();
+            if output.len() >= expected.len() {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+        assert_eq!(output, expected);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_unsafe_components_rejected() {
+        assert!(is_safe_component("org.freedesktop.appearance"));
+        assert!(!is_safe_component(".."));
+        assert!(!is_safe_component("a/b"));
+        assert!(!is_safe_component(""));
+    }
+}
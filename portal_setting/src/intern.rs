@@ -0,0 +1,43 @@
+use crate::SettingKey;
+use std::collections::HashSet;
+use std::sync::Arc;
+
+/// Hands out one shared copy of equal strings
+///
+/// Used while building a store, so the namespace of every key in a namespace
+/// is one allocation, shared by the defaults and the snapshot served.
+#[derive(Default)]
+pub(crate) struct Interner(HashSet<Arc<str>>);
+
+impl Interner {
+    pub(crate) fn intern(&mut self, string: &str) -> Arc<str> {
+        if let Some(interned) = self.0.get(string) {
+            return interned.clone();
+        }
+        let interned: Arc<str> = Arc::from(string);
+        self.0.insert(interned.clone());
+        interned
+    }
+
+    pub(crate) fn key(&mut self, namespace: &str, key: &str) -> SettingKey {
+        SettingKey {
+            namespace: self.intern(namespace),
+            key: self.intern(key),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_equal_strings_share_one_copy() {
+        let mut interner = Interner::default();
+        let a = interner.key("com.example", "a");
+        let b = interner.key(&String::from("com.example"), "b");
+        assert!(Arc::ptr_eq(&a.namespace, &b.namespace));
+        assert!(!Arc::ptr_eq(&a.key, &b.key));
+        assert_eq!(a, SettingKey::new("com.example", "a"));
+    }
+}
@@ -0,0 +1,107 @@
+use crate::shards::FIRST_GENERATION;
+use crate::SettingChange;
+use std::collections::VecDeque;
+
+/// Changes since a generation, as reported by `SettingsStore::recent_changes`
+#[derive(Debug, Clone)]
+pub struct RecentChanges {
+    /// Generation of the last change to the store
+    pub generation: u64,
+    /// Whether `changes` holds every change since the requested generation;
+    /// if not, some were dropped and everything must be read again
+    pub complete: bool,
+    /// Each change after the requested generation with its generation, oldest
+    /// first
+    pub changes: Vec<(u64, SettingChange)>,
+}
+
+/// The last changes to the store, with the generation each one was made at
+#[derive(Debug)]
+pub(crate) struct Journal {
+    changes: VecDeque<(u64, SettingChange)>,
+    size: usize,
+    generation: u64,
+    /// Generation of the last change no longer kept
+    dropped: u64,
+}
+
+impl Journal {
+    pub(crate) fn new(size: usize) -> Self {
+        Self {
+            changes: VecDeque::new(),
+            size,
+            generation: FIRST_GENERATION,
+            // Clients without settings are missing the store's initial ones
+            dropped: FIRST_GENERATION,
+        }
+    }
+
+    /// Keep `changes`, made at `generation` and the generations after it
+    pub(crate) fn record(&mut self, generation: u64, changes: &[SettingChange]) {
+        for (change, generation) in changes.iter().zip(generation..) {
+            self.changes.push_back((generation, change.clone()));
+            self.generation = generation;
+        }
+        while self.changes.len() > self.size {
+            if let Some((generation, _)) = self.changes.pop_front() {
+                self.dropped = generation;
+            }
+        }
+    }
+
+    pub(crate) fn since(&self, generation: u64) -> RecentChanges {
+        RecentChanges {
+            generation: self.generation,
+            // A generation from the future was handed out by an earlier run
+            complete: generation >= self.dropped && generation <= self.generation,
+            changes: self
+                .changes
+                .iter()
+                .filter(|(changed, _)| *changed > generation)
+                .cloned()
+                .collect(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{ChangeKind, ChangeSource};
+
+    fn change(key: &str) -> SettingChange {
+        SettingChange {
+            namespace: "com.example".to_string(),
+            key: key.to_string(),
+            kind: ChangeKind::Delete,
+            source: ChangeSource::Api,
+            old: None,
+            new: None,
+            caller: None,
+        }
+    }
+
+    #[test]
+    fn test_journal_keeps_the_last_changes() {
+        let mut journal = Journal::new(3);
+        assert!(journal.since(FIRST_GENERATION).complete);
+        assert!(!journal.since(0).complete);
+        journal.record(2, &[change("a")]);
+        journal.record(3, &[change("b"), change("c"), change("d")]);
+
+        let keys = |recent: &RecentChanges| -> Vec<(u64, String)> {
+            recent.changes.iter().map(|(generation, change)| (*generation, change.key.clone())).collect()
+        };
+        let recent = journal.since(3);
+        assert!(recent.complete);
+        assert_eq!(recent.generation, 5);
+        assert_eq!(keys(&recent), [(4, "c".to_string()), (5, "d".to_string())]);
+
+        assert!(journal.since(2).complete);
+        assert_eq!(journal.since(2).changes.len(), 3);
+        // The change at generation 2 was dropped
+        assert!(!journal.since(1).complete);
+        assert!(journal.since(5).changes.is_empty());
+        assert!(!journal.since(6).complete);
+    }
+}
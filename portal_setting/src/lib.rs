@@ -1,341 +1,2994 @@
-use anyhow::Result;
-use std::collections::HashMap;
-use std::sync::Arc;
-use tokio::sync::RwLock;
-use zbus::{interface, zvariant::{OwnedValue, Str, Value}};
+use error::{bail, Context};
+use std::borrow::Borrow;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::hash::{Hash, Hasher};
+use std::sync::{Arc, OnceLock};
+use alias::Aliases;
+use arc_swap::{ArcSwap, ArcSwapOption};
+use futures_util::FutureExt;
+use intern::Interner;
+use journal::Journal;
+use reply::ReplyCache;
+use shards::Shards;
+use zbus::{interface, zvariant::{OwnedValue, Value}};
+
+mod access;
+mod alias;
+pub mod audit;
+mod backend;
+mod change;
+pub mod config;
+mod deny;
+pub mod error;
+pub mod emitter;
+pub mod font;
+#[cfg(test)]
+mod golden;
+#[cfg(feature = "gsettings")]
+pub mod gschema;
+mod frontend;
+mod hooks;
+mod intern;
+mod journal;
+#[cfg(feature = "management")]
+mod logind;
+#[cfg(feature = "management")]
+mod management;
+#[cfg(test)]
+mod model;
+pub mod pattern;
+pub mod policy;
+#[cfg(feature = "plugins")]
+pub mod plugin;
+#[cfg(feature = "management")]
+mod quota;
+mod reply;
+pub mod sandbox;
+pub mod sensitive;
+mod shards;
+pub mod schema;
+#[cfg(test)]
+mod strategy;
+pub mod state;
+pub mod task;
+#[cfg(any(test, feature = "testing"))]
+pub mod testing;
+mod users;
+mod validator;
+
+pub use access::SettingsAccess;
+#[cfg(feature = "audit")]
+pub use audit::{AuditJournal, AuditLog};
+pub use backend::SettingsBackend;
+pub use change::{ChangeKind, ChangeObserver, ChangeSource, RefusedChange, SettingChange};
+pub use config::{CompatConfig, Config, LimitsConfig};
+pub use deny::DeniedNamespaces;
+pub use emitter::{EmitterStats, SignalEmitter};
+pub use error::{Error, Result};
+pub use frontend::{PortalError, SettingsFrontend, FRONTEND_BUS_NAME};
+pub use hooks::HookRunner;
+pub use journal::RecentChanges;
+#[cfg(feature = "management")]
+pub use logind::SessionOverrides;
+#[cfg(feature = "management")]
+pub use management::{SettingsManagement, MANAGEMENT_INTERFACE, MANAGEMENT_PATH};
+pub use policy::{Caller, Policy};
+#[cfg(feature = "management")]
+pub use quota::{Scope, SenderKeys};
+pub use reply::SettingsReply;
+pub use sensitive::SensitiveKeys;
+#[cfg(feature = "persistence")]
+pub use state::StateFile;
+pub use users::UserStores;
+pub use validator::{ReadOnlyError, ValidationError, Validator};
 
 /// Represents the namespace and key for a setting
-#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+///
+/// Clones share the strings, and the keys of a store share one copy of each
+/// namespace.
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct SettingKey {
-    pub namespace: String,
-    pub key: String,
+    pub namespace: Arc<str>,
+    pub key: Arc<str>,
 }
 
 impl SettingKey {
-    pub fn new(namespace: impl Into<String>, key: impl Into<String>) -> Self {
+    pub fn new(namespace: impl AsRef<str>, key: impl AsRef<str>) -> Self {
         Self {
-            namespace: namespace.into(),
-            key: key.into(),
+            namespace: Arc::from(namespace.as_ref()),
+            key: Arc::from(key.as_ref()),
         }
     }
 }
 
-/// The value type for settings - wraps zvariant::OwnedValue
-#[derive(Debug)]
-pub struct SettingValue(pub OwnedValue);
+/// A namespace and key that maps keyed by `SettingKey` can be searched with,
+/// so lookups by `(&str, &str)` need not allocate a `SettingKey`
+///
+/// `SettingKey` hashes like its parts, which keeps both forms equivalent.
+trait Lookup {
+    fn parts(&self) -> (&str, &str);
+}
+
+impl Lookup for SettingKey {
+    fn parts(&self) -> (&str, &str) {
+        (&self.namespace, &self.key)
+    }
+}
+
+impl Lookup for (&str, &str) {
+    fn parts(&self) -> (&str, &str) {
+        *self
+    }
+}
+
+impl<'a> Borrow<dyn Lookup + 'a> for SettingKey {
+    fn borrow(&self) -> &(dyn Lookup + 'a) {
+        self
+    }
+}
+
+impl Hash for dyn Lookup + '_ {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.parts().hash(state)
+    }
+}
+
+impl PartialEq for dyn Lookup + '_ {
+    fn eq(&self, other: &Self) -> bool {
+        self.parts() == other.parts()
+    }
+}
+
+impl Eq for dyn Lookup + '_ {}
+
+impl Hash for SettingKey {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.parts().hash(state)
+    }
+}
+
+/// The value type for settings - shares a zvariant::OwnedValue
+///
+/// Clones share the same value, so copying a setting around the store never
+/// has to duplicate (and possibly fail to duplicate) its file descriptors.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SettingValue(pub Arc<OwnedValue>);
+
+impl SettingValue {
+    pub fn new(value: OwnedValue) -> Self {
+        Self(Arc::new(value))
+    }
+
+    /// An owned copy of the value, for replies that need one
+    pub fn to_owned_value(&self) -> zbus::zvariant::Result<OwnedValue> {
+        self.0.try_clone()
+    }
+}
+
+// Replies carry shared values as the variants they wrap
+impl serde::Serialize for SettingValue {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+        self.0.serialize(serializer)
+    }
+}
+
+impl zbus::zvariant::Type for SettingValue {
+    fn signature() -> zbus::zvariant::Signature<'static> {
+        OwnedValue::signature()
+    }
+}
+
+impl From<OwnedValue> for SettingValue {
+    fn from(value: OwnedValue) -> Self {
+        Self::new(value)
+    }
+}
 
-impl Clone for SettingValue {
-    fn clone(&self) -> Self {
-        // Clone by serializing and deserializing the OwnedValue
-        Self(self.0.try_clone().unwrap())
+/// Where the current value of a setting comes from
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Layer {
+    /// The built-in default
+    Builtin,
+    /// A settings backend loaded from a plugin
+    Backend,
+    /// The `[settings]` tables of the configuration file
+    Config,
+    /// A change made while the service is running
+    Runtime,
+}
+
+impl Layer {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Layer::Builtin => "builtin",
+            Layer::Backend => "backend",
+            Layer::Config => "config",
+            Layer::Runtime => "runtime",
+        }
     }
 }
 
 /// Settings store that maintains all portal settings
 #[derive(Clone)]
 pub struct SettingsStore {
-    settings: Arc<RwLock<HashMap<SettingKey, SettingValue>>>,
+    /// Replaced as a whole on every change, so reads never wait
+    settings: Arc<ArcSwap<Shards>>,
+    /// Serializes changes, which copy the changed namespace and swap the
+    /// changed snapshot in
+    writer: Arc<std::sync::Mutex<()>>,
+    /// `ReadAll` replies of the current snapshot
+    replies: Arc<std::sync::Mutex<ReplyCache>>,
+    defaults: Arc<HashMap<SettingKey, SettingValue>>,
+    /// Layer of each default; absent keys are built in
+    origins: Arc<HashMap<SettingKey, Layer>>,
+    disabled: Arc<HashSet<String>>,
+    /// Namespaces with defaults, exempt from the namespace and key limits
+    registered: Arc<HashSet<Arc<str>>>,
+    limits: Arc<LimitsConfig>,
+    compat: Arc<CompatConfig>,
+    sensitive: SensitiveKeys,
+    denied: DeniedNamespaces,
+    policy: Arc<Policy>,
+    users: Option<UserStores>,
+    observers: Arc<std::sync::RwLock<Observers>>,
+    namespace_observers: Arc<std::sync::RwLock<HashMap<String, Observers>>>,
+    /// Queued while publishing and delivered one at a time, so observers see
+    /// changes in the order they were published
+    pending: Arc<std::sync::Mutex<Pending>>,
+    /// Recorded while publishing, so generations follow the order of changes
+    journal: Arc<std::sync::Mutex<Journal>>,
+    validators: Arc<Validators>,
+    /// Validators of single keys, which may be registered at any time
+    key_validators: Arc<std::sync::RwLock<HashMap<SettingKey, Validators>>>,
+    constraints: Arc<schema::Constraints>,
+    aliases: Arc<Aliases>,
+    /// Queues `SettingChanged` while publishing, so signals follow the
+    /// order of changes
+    signals: Arc<ArcSwapOption<SignalEmitter>>,
+    /// Whether writes of the current value count as changes
+    record_unchanged: bool,
 }
 
-impl SettingsStore {
-    pub fn new() -> Self {
-        let mut settings = HashMap::new();
-        
-        // Initialize default settings according to the XDG portal spec
-        // https://flatpak.github.io/xdg-desktop-portal/docs/doc-org.freedesktop.portal.Settings.html
-        
-        // org.freedesktop.appearance namespace
-        settings.insert(
-            SettingKey::new("org.freedesktop.appearance", "color-scheme"),
-            SettingValue(Value::U32(0).try_into().unwrap()), // 0: no preference, 1: dark, 2: light
-        );
-        settings.insert(
-            SettingKey::new("org.freedesktop.appearance", "accent-color"),
-            SettingValue(Value::Structure((0.0, 0.0, 0.0).into()).try_into().unwrap()), // RGB tuple
-        );
-        settings.insert(
-            SettingKey::new("org.freedesktop.appearance", "contrast"),
-            SettingValue(Value::U32(0).try_into().unwrap()), // 0: no preference, 1: high contrast
-        );
-        
-        // org.gnome.desktop.interface namespace
-        settings.insert(
-            SettingKey::new("org.gnome.desktop.interface", "gtk-theme"),
-            SettingValue(Value::Str(Str::from_static("Adwaita")).try_into().unwrap()),
-        );
-        settings.insert(
-            SettingKey::new("org.gnome.desktop.interface", "icon-theme"),
-            SettingValue(Value::Str(Str::from_static("Adwaita")).try_into().unwrap()),
-        );
-        settings.insert(
-            SettingKey::new("org.gnome.desktop.interface", "cursor-theme"),
-            SettingValue(Value::Str(Str::from_static("Adwaita")).try_into().unwrap()),
-        );
-        settings.insert(
-            SettingKey::new("org.gnome.desktop.interface", "font-name"),
-            SettingValue(Value::Str(Str::from_static("Cantarell 11")).try_into().unwrap()),
-        );
-        settings.insert(
-            SettingKey::new("org.gnome.desktop.interface", "monospace-font-name"),
-            SettingValue(Value::Str(Str::from_static("Source Code Pro 10")).try_into().unwrap()),
-        );
-        settings.insert(
-            SettingKey::new("org.gnome.desktop.interface", "clock-format"),
-            SettingValue(Value::Str(Str::from_static("24h")).try_into().unwrap()),
-        );
-        
-        // org.gnome.desktop.privacy namespace
-        settings.insert(
-            SettingKey::new("org.gnome.desktop.privacy", "remember-recent-files"),
-            SettingValue(Value::Bool(true).try_into().unwrap()),
-        );
-        settings.insert(
-            SettingKey::new("org.gnome.desktop.privacy", "recent-files-max-age"),
-            SettingValue(Value::I32(30).try_into().unwrap()), // days
-        );
-        
-        Self {
-            settings: Arc::new(RwLock::new(settings)),
-        }
-    }
+type Observers = Vec<Arc<dyn ChangeObserver>>;
+type Validators = Vec<Arc<dyn Validator>>;
 
-    pub async fn read(&self, namespace: &str, key: &str) -> Option<SettingValue> {
-        let settings = self.settings.read().await;
-        settings.get(&SettingKey::new(namespace, key)).cloned()
-    }
+/// Changes and observer registrations not yet delivered
+#[derive(Default)]
+struct Pending {
+    deliveries: VecDeque<Delivery>,
+    /// Whether a thread is delivering; others leave their deliveries to it
+    delivering: bool,
+}
 
-    pub async fn read_all(&self, namespaces: Vec<String>) -> HashMap<String, HashMap<String, SettingValue>> {
-        let settings = self.settings.read().await;
-        let mut result: HashMap<String, HashMap<String, SettingValue>> = HashMap::new();
+enum Delivery {
+    Change(SettingChange),
+    /// Register an observer of one namespace, or of all of them
+    Observe(Option<String>, Arc<dyn ChangeObserver>),
+}
+
+/// Stops delivering if an observer panics, so the next change delivers again
+struct Delivering<'a>(&'a std::sync::Mutex<Pending>);
 
-        for (key, value) in settings.iter() {
-            // Filter by namespaces if provided, otherwise return all
-            if namespaces.is_empty() || namespaces.contains(&key.namespace) {
-                result
-                    .entry(key.namespace.clone())
-                    .or_default()
-                    .insert(key.key.clone(), value.clone());
+impl Drop for Delivering<'_> {
+    fn drop(&mut self) {
+        if std::thread::panicking() {
+            if let Ok(mut pending) = self.0.lock() {
+                pending.delivering = false;
             }
         }
-
-        result
     }
+}
 
-    pub async fn write(&self, namespace: &str, key: &str, value: OwnedValue) -> Result<()> {
-        // Validate the setting based on namespace and key
-        self.validate_setting(namespace, key, &value)?;
+/// Where the model tests may switch to another thread; never reached while
+/// holding a lock
+fn yield_point() {
+    #[cfg(test)]
+    model::yield_point();
+}
 
-        let mut settings = self.settings.write().await;
-        settings.insert(
-            SettingKey::new(namespace, key),
-            SettingValue(value),
-        );
-        Ok(())
+impl SettingsStore {
+    pub fn new() -> Self {
+        Self::with_defaults(Self::default_settings())
     }
 
-    fn validate_setting(&self, namespace: &str, key: &str, value: &OwnedValue) -> Result<()> {
-        match (namespace, key) {
-            // org.freedesktop.appearance validations
-            ("org.freedesktop.appearance", "color-scheme") => {
-                if let Ok(v) = <u32>::try_from(value) {
-                    if v <= 2 {
-                        return Ok(());
-                    }
-                }
-                anyhow::bail!("color-scheme must be u32 (0-2)");
-            }
-            ("org.freedesktop.appearance", "accent-color") => {
-                // Check signature for tuple of three f64s
-                if value.value_signature().as_str() == "(ddd)" {
-                    return Ok(());
-                }
-                anyhow::bail!("accent-color must be (f64, f64, f64) tuple");
-            }
-            ("org.freedesktop.appearance", "contrast") => {
-                if let Ok(v) = <u32>::try_from(value) {
-                    if v <= 1 {
-                        return Ok(());
-                    }
-                }
-                anyhow::bail!("contrast must be u32 (0-1)");
-            }
-            // org.gnome.desktop.interface validations
-            ("org.gnome.desktop.interface", "gtk-theme") |
-            ("org.gnome.desktop.interface", "icon-theme") |
-            ("org.gnome.desktop.interface", "cursor-theme") |
-            ("org.gnome.desktop.interface", "font-name") |
-            ("org.gnome.desktop.interface", "monospace-font-name") => {
-                if value.value_signature().as_str() == "s" {
-                    return Ok(());
-                }
-                anyhow::bail!("{} must be a string", key);
+    /// Create a store whose defaults are the built-in settings overridden by
+    /// the `[settings]` tables of `config`, without `disabled_namespaces`
+    pub fn from_config(config: &Config) -> Result<Self> {
+        Self::from_sources(config, &[])
+    }
+
+    /// Like `from_config`, with the values of `backends` applied (in order)
+    /// between the built-in defaults and the configuration file
+    ///
+    /// Built-in defaults are only converted for keys no backend or
+    /// configuration value replaces.
+    pub fn from_sources(config: &Config, backends: &[Arc<dyn SettingsBackend>]) -> Result<Self> {
+        for (namespace, keys) in &config.schema {
+            for (key, constraint) in keys {
+                constraint.check().with_context(|| format!("schema.\"{}\".{}", namespace, key))?;
             }
-            ("org.gnome.desktop.interface", "clock-format") => {
-                if value.value_signature().as_str() == "s" {
-                    // Just check it's a string, actual value validation would require more complex checking
-                    return Ok(());
+        }
+        let aliases = Aliases::new(config.alias_targets()?);
+        let mut interner = Interner::default();
+        let mut defaults = HashMap::new();
+        let mut origins = HashMap::new();
+        for backend in backends {
+            for (namespace, key, value) in backend.load().with_context(|| format!("backend {}", backend.name()))? {
+                // Backends may still use a deprecated name
+                let (namespace, key) = aliases.resolve(&namespace, &key);
+                if config.is_disabled(namespace) {
+                    continue;
                 }
-                anyhow::bail!("clock-format must be '12h' or '24h'");
+                Self::validate_constrained(config, namespace, key, &value)
+                    .with_context(|| format!("backend {}: {}/{}", backend.name(), namespace, key))?;
+                let setting_key = interner.key(namespace, key);
+                origins.insert(setting_key.clone(), Layer::Backend);
+                defaults.insert(setting_key, SettingValue::new(value));
             }
-            // org.gnome.desktop.privacy validations
-            ("org.gnome.desktop.privacy", "remember-recent-files") => {
-                if <bool>::try_from(value).is_ok() {
-                    return Ok(());
-                }
-                anyhow::bail!("remember-recent-files must be a boolean");
+        }
+        for (namespace, key, value) in config.setting_values()? {
+            if config.is_disabled(&namespace) {
+                bail!("settings.\"{}\".{}: namespace is disabled", namespace, key);
             }
-            ("org.gnome.desktop.privacy", "recent-files-max-age") => {
-                if <i32>::try_from(value).is_ok() {
-                    return Ok(());
-                }
-                anyhow::bail!("recent-files-max-age must be an i32");
+            Self::validate_constrained(config, &namespace, &key, &value)
+                .with_context(|| format!("settings.\"{}\".{}", namespace, key))?;
+            let setting_key = interner.key(&namespace, &key);
+            origins.insert(setting_key.clone(), Layer::Config);
+            defaults.insert(setting_key, SettingValue::new(value));
+        }
+
+        let disabled: HashSet<String> = config.disabled_namespaces.iter().cloned().collect();
+        for setting in schema::BUILTIN {
+            if !disabled.contains(setting.namespace) {
+                defaults
+                    .entry(interner.key(setting.namespace, setting.key))
+                    .or_insert_with(|| SettingValue::new(setting.default.to_value()));
             }
-            // Unknown settings are allowed (for extensibility)
-            _ => Ok(()),
         }
+        let mut store = Self::with_defaults(defaults);
+        store.disabled = Arc::new(disabled);
+        store.origins = Arc::new(origins);
+        store.limits = Arc::new(config.limits.clone());
+        store.compat = Arc::new(config.compat.clone());
+        store.sensitive = config.sensitive_keys()?;
+        store.denied = config.denied_namespaces()?;
+        store.constraints = Arc::new(config.schema.clone());
+        let mut settings = Shards::clone(&store.settings.load());
+        aliases.mirror_all(&mut settings);
+        store.settings.store(Arc::new(settings));
+        store.aliases = Arc::new(aliases);
+        Ok(store
+            .with_journal_size(config.journal.size)
+            .with_record_unchanged(config.journal.record_unchanged))
     }
-}
 
-impl Default for SettingsStore {
-    fn default() -> Self {
-        Self::new()
+    fn with_defaults(defaults: HashMap<SettingKey, SettingValue>) -> Self {
+        let registered = defaults.keys().map(|key| key.namespace.clone()).collect();
+        Self {
+            settings: Arc::new(ArcSwap::from_pointee(Shards::new(defaults.clone()))),
+            writer: Arc::new(std::sync::Mutex::new(())),
+            replies: Arc::new(std::sync::Mutex::new(ReplyCache::new())),
+            defaults: Arc::new(defaults),
+            origins: Arc::new(HashMap::new()),
+            disabled: Arc::new(HashSet::new()),
+            registered: Arc::new(registered),
+            limits: Arc::new(LimitsConfig::default()),
+            compat: Arc::new(CompatConfig::default()),
+            sensitive: SensitiveKeys::default(),
+            denied: DeniedNamespaces::default(),
+            policy: Arc::new(Policy::default()),
+            users: None,
+            observers: Arc::new(std::sync::RwLock::new(Vec::new())),
+            namespace_observers: Arc::new(std::sync::RwLock::new(HashMap::new())),
+            pending: Arc::new(std::sync::Mutex::new(Pending::default())),
+            journal: Arc::new(std::sync::Mutex::new(Journal::new(config::DEFAULT_JOURNAL_SIZE))),
+            validators: Arc::new(Vec::new()),
+            key_validators: Arc::new(std::sync::RwLock::new(HashMap::new())),
+            constraints: Arc::new(schema::Constraints::new()),
+            aliases: Arc::new(Aliases::default()),
+            signals: Arc::new(ArcSwapOption::empty()),
+            record_unchanged: false,
+        }
     }
-}
 
-/// D-Bus interface implementation for org.freedesktop.impl.portal.Settings
-pub struct SettingsPortal {
-    store: SettingsStore,
-}
+    /// The built-in defaults, converted once and shared by every store
+    fn default_settings() -> HashMap<SettingKey, SettingValue> {
+        static DEFAULTS: OnceLock<HashMap<SettingKey, SettingValue>> = OnceLock::new();
+        DEFAULTS
+            .get_or_init(|| {
+                let mut interner = Interner::default();
+                schema::BUILTIN
+                    .iter()
+                    .map(|s| {
+                        (
+                            interner.key(s.namespace, s.key),
+                            SettingValue::new(s.default.to_value()),
+                        )
+                    })
+                    .collect()
+            })
+            .clone()
+    }
 
-impl SettingsPortal {
-    pub fn new() -> Self {
-        Self {
-            store: SettingsStore::new(),
-        }
+    /// Replace the limits on what clients can add, `LimitsConfig::default()`
+    /// unless set by the configuration
+    pub fn with_limits(mut self, limits: LimitsConfig) -> Self {
+        self.limits = Arc::new(limits);
+        self
     }
 
-    pub fn with_store(store: SettingsStore) -> Self {
-        Self { store }
+    pub fn limits(&self) -> &LimitsConfig {
+        &self.limits
     }
 
-    pub fn store(&self) -> &SettingsStore {
-        &self.store
+    /// Serve the legacy behaviors enabled in `compat` on the interfaces of
+    /// this store
+    pub fn with_compat(mut self, compat: CompatConfig) -> Self {
+        self.compat = Arc::new(compat);
+        self
     }
-}
 
-impl Default for SettingsPortal {
-    fn default() -> Self {
-        Self::new()
+    pub fn compat(&self) -> &CompatConfig {
+        &self.compat
     }
-}
 
-#[interface(name = "org.freedesktop.impl.portal.Settings")]
-impl SettingsPortal {
-    /// Read a single setting
-    async fn read(&self, namespace: &str, key: &str) -> zbus::fdo::Result<OwnedValue> {
-        self.store
-            .read(namespace, key)
-            .await
-            .map(|v| v.0)
-            .ok_or_else(|| zbus::fdo::Error::Failed("Setting not found".to_string()))
+    /// Keep the values of `sensitive` settings out of what the interfaces
+    /// of this store report about them
+    pub fn with_sensitive_keys(mut self, sensitive: SensitiveKeys) -> Self {
+        self.sensitive = sensitive;
+        self
     }
 
-    /// Read all settings, optionally filtered by namespaces
-    async fn read_all(&self, namespaces: Vec<String>) -> HashMap<String, HashMap<String, OwnedValue>> {
-        let result = self.store.read_all(namespaces).await;
-        
-        // Convert SettingValue to OwnedValue
-        result
-            .into_iter()
-            .map(|(ns, keys)| {
-                let converted_keys = keys
-                    .into_iter()
-                    .map(|(k, v)| (k, v.0))
-                    .collect();
-                (ns, converted_keys)
-            })
-            .collect()
+    pub fn sensitive_keys(&self) -> &SensitiveKeys {
+        &self.sensitive
     }
 
-    /// Signal emitted when a setting changes
-    #[zbus(signal)]
-    async fn setting_changed(
-        signal_ctxt: &zbus::SignalContext<'_>,
-        namespace: &str,
-        key: &str,
-        value: Value<'_>,
-    ) -> zbus::Result<()>;
-}
+    /// Never serve nor accept the `denied` namespaces on the interfaces of
+    /// this store, nor signal their changes
+    pub fn with_denied_namespaces(mut self, denied: DeniedNamespaces) -> Self {
+        self.denied = denied;
+        self
+    }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+    pub fn denied_namespaces(&self) -> &DeniedNamespaces {
+        &self.denied
+    }
 
-    #[tokio::test]
-    async fn test_settings_store_creation() {
-        let store = SettingsStore::new();
-        let value = store.read("org.freedesktop.appearance", "color-scheme").await;
-        assert!(value.is_some());
+    /// Whether the interfaces refuse `namespace`/`key`, as its namespace or
+    /// that of the setting it is an alias of is denied
+    pub fn is_denied(&self, namespace: &str, key: &str) -> bool {
+        let (canonical, _) = self.canonical(namespace, key);
+        self.denied.contains(namespace) || self.denied.contains(canonical)
     }
 
-    #[tokio::test]
-    async fn test_read_write_setting() {
-        let store = SettingsStore::new();
-        
-        // Write a new value
-        store
-            .write("org.freedesktop.appearance", "color-scheme", Value::U32(1).try_into().unwrap())
-            .await
-            .unwrap();
-        
-        // Read it back
-        let value = store.read("org.freedesktop.appearance", "color-scheme").await;
-        assert!(value.is_some());
-        let val: u32 = value.unwrap().0.try_into().unwrap();
-        assert_eq!(val, 1);
+    /// Limit what callers of the interfaces of this store see and change to
+    /// what `policy` allows them
+    pub fn with_policy(mut self, policy: Policy) -> Self {
+        self.policy = Arc::new(policy);
+        self
     }
 
-    #[tokio::test]
-    async fn test_validation() {
-        let store = SettingsStore::new();
-        
-        // Valid value
-        assert!(store
-            .write("org.freedesktop.appearance", "color-scheme", Value::U32(1).try_into().unwrap())
-            .await
-            .is_ok());
-        
-        // Invalid value (out of range)
-        assert!(store
-            .write("org.freedesktop.appearance", "color-scheme", Value::U32(5).try_into().unwrap())
-            .await
-            .is_err());
-        
-        // Invalid type
-        assert!(store
-            .write("org.freedesktop.appearance", "color-scheme", Value::Str(Str::from("invalid")).try_into().unwrap())
+    pub fn policy(&self) -> &Policy {
+        &self.policy
+    }
+
+    /// Serve each user from its own store in `users`, instead of this one
+    ///
+    /// The stores `users` makes should be configured like this one, as
+    /// their policy and compatibility options apply to their callers.
+    pub fn with_user_stores(mut self, users: UserStores) -> Self {
+        self.users = Some(users);
+        self
+    }
+
+    pub fn user_stores(&self) -> Option<&UserStores> {
+        self.users.as_ref()
+    }
+
+    /// The caller `header` comes from, only asking the bus when the policy
+    /// or the user stores tell callers apart
+    pub(crate) async fn caller(&self, connection: &zbus::Connection, header: &zbus::message::Header<'_>) -> Caller {
+        match self.policy.is_open() && self.users.is_none() {
+            true => Caller::default(),
+            false => Caller::of(connection, header).await,
+        }
+    }
+
+    /// The store serving `caller`, who sent `header`: that of its user with
+    /// `with_user_stores`, or this one
+    pub(crate) async fn store_for(&self, caller: &Caller, header: &zbus::message::Header<'_>) -> Result<SettingsStore> {
+        let Some(users) = &self.users else {
+            return Ok(self.clone());
+        };
+        let sender = header.sender().map(|sender| sender.as_str());
+        let store = users.get(caller.uid.unwrap_or(0), sender).await?;
+        Ok(store.unwrap_or_else(|| self.clone()))
+    }
+
+    /// `caller` and `store_for` it
+    pub(crate) async fn serve(
+        &self,
+        connection: &zbus::Connection,
+        header: &zbus::message::Header<'_>,
+    ) -> zbus::fdo::Result<(Caller, SettingsStore)> {
+        let caller = self.caller(connection, header).await;
+        let store = self
+            .store_for(&caller, header)
             .await
-            .is_err());
+            .map_err(|e| zbus::fdo::Error::Failed(format!("cannot load the settings of {}: {:#}", caller, e)))?;
+        Ok((caller, store))
     }
 
-    #[tokio::test]
-    async fn test_read_all_with_filter() {
-        let store = SettingsStore::new();
-        
-        // Read all settings in a specific namespace
-        let result = store.read_all(vec!["org.freedesktop.appearance".to_string()]).await;
-        assert!(result.contains_key("org.freedesktop.appearance"));
-        assert!(!result.contains_key("org.gnome.desktop.interface"));
+    /// Whether the policy lets `caller` see and change `namespace`/`key`,
+    /// and the setting an alias stands for
+    pub(crate) fn is_visible(&self, caller: &Caller, namespace: &str, key: &str) -> bool {
+        let (canonical, _) = self.canonical(namespace, key);
+        self.policy.allows(caller, namespace) && self.policy.allows(caller, canonical)
     }
 
-    #[tokio::test]
-    async fn test_read_all_no_filter() {
-        let store = SettingsStore::new();
-        
-        // Read all settings
-        let result = store.read_all(vec![]).await;
-        assert!(result.contains_key("org.freedesktop.appearance"));
-        assert!(result.contains_key("org.gnome.desktop.interface"));
-        assert!(result.contains_key("org.gnome.desktop.privacy"));
+    /// Whether the policy lets every caller see `namespace`/`key`, so its
+    /// `SettingChanged` may be broadcast without revealing it to others
+    pub(crate) fn is_public(&self, namespace: &str, key: &str) -> bool {
+        let (canonical, _) = self.canonical(namespace, key);
+        self.policy.allows_everyone(namespace) && self.policy.allows_everyone(canonical)
+    }
+
+    /// Keep the last `size` changes for `recent_changes`, instead of
+    /// `config::DEFAULT_JOURNAL_SIZE`
+    pub fn with_journal_size(mut self, size: usize) -> Self {
+        self.journal = Arc::new(std::sync::Mutex::new(Journal::new(size)));
+        self
+    }
+
+    /// Count writes of a setting's current value as changes, which bump the
+    /// generation, are journaled, reach observers and emit `SettingChanged`
+    ///
+    /// Off by default, so jobs enforcing a configuration periodically do not
+    /// make every application apply it again.
+    pub fn with_record_unchanged(mut self, record: bool) -> Self {
+        self.record_unchanged = record;
+        self
+    }
+
+    /// Check every written value with `validator` too, after the validators
+    /// added before it
+    pub fn with_validator(mut self, validator: Arc<dyn Validator>) -> Self {
+        Arc::make_mut(&mut self.validators).push(validator);
+        self
+    }
+
+    /// Check every value written to `namespace`/`key` with `validator`, after
+    /// the store-wide validators and those registered for the key before
+    ///
+    /// Shared by every clone of the store; writes already being validated
+    /// are not checked with it.
+    pub fn register_validator(&self, namespace: &str, key: &str, validator: Box<dyn Validator>) {
+        let mut validators = self.key_validators.write().unwrap();
+        validators.entry(SettingKey::new(namespace, key)).or_default().push(validator.into());
+    }
+
+    /// Whether `namespace` has defaults, which exempts it from the namespace
+    /// and key limits
+    pub fn is_registered(&self, namespace: &str) -> bool {
+        self.registered.contains(namespace)
+    }
+
+    /// Queue `SettingChanged` on `emitter` for every change that leaves a
+    /// value, or stop with `None`
+    ///
+    /// Signals are queued as changes are published, so they follow the order
+    /// of changes even when several writers race: a client reading a setting
+    /// after its `SettingChanged` gets the signalled value or a newer one.
+    /// Interfaces serving the store then leave signals to it.
+    pub fn set_signal_emitter(&self, emitter: Option<SignalEmitter>) {
+        self.signals.store(emitter.map(Arc::new));
+    }
+
+    /// Whether changes queue their own signals; see `set_signal_emitter`
+    pub fn emits_signals(&self) -> bool {
+        self.signals.load().is_some()
+    }
+
+    /// Register an observer notified after every successful mutation
+    pub fn add_observer(&self, observer: Arc<dyn ChangeObserver>) {
+        self.observe(None, observer);
+    }
+
+    /// Register an observer notified only of mutations in `namespace`
+    pub fn add_namespace_observer(&self, namespace: &str, observer: Arc<dyn ChangeObserver>) {
+        self.observe(Some(namespace.to_string()), observer);
+    }
+
+    /// Register `observer` for every change published after the returned
+    /// snapshot
+    fn observe(&self, namespace: Option<String>, observer: Arc<dyn ChangeObserver>) -> Arc<Shards> {
+        let writer = self.writer.lock().unwrap();
+        let snapshot = self.settings.load_full();
+        let delivery = Delivery::Observe(namespace, observer);
+        self.pending.lock().unwrap().deliveries.push_back(delivery);
+        drop(writer);
+        self.deliver();
+        snapshot
+    }
+
+    /// Deliver the pending changes and registrations, unless another thread
+    /// already is
+    fn deliver(&self) {
+        let mut pending = self.pending.lock().unwrap();
+        if pending.delivering {
+            return;
+        }
+        pending.delivering = true;
+        let _delivering = Delivering(&self.pending);
+        loop {
+            let Some(delivery) = pending.deliveries.pop_front() else {
+                pending.delivering = false;
+                return;
+            };
+            // Observers may change the store; their changes are queued
+            // behind this one
+            drop(pending);
+            match delivery {
+                Delivery::Change(change) => self.notify(&change),
+                Delivery::Observe(None, observer) => self.observers.write().unwrap().push(observer),
+                Delivery::Observe(Some(namespace), observer) => {
+                    let mut observers = self.namespace_observers.write().unwrap();
+                    observers.entry(namespace).or_default().push(observer);
+                }
+            }
+            yield_point();
+            pending = self.pending.lock().unwrap();
+        }
+    }
+
+    /// Tell the observers that a mutation was refused, for auditing
+    ///
+    /// Unlike changes, refusals are not queued: observers get them on the
+    /// calling thread.
+    pub fn report_refused(&self, refused: &RefusedChange) {
+        for observer in self.observers.read().unwrap().iter() {
+            observer.on_refused(refused);
+        }
+        if let Some(observers) = self.namespace_observers.read().unwrap().get(&refused.namespace) {
+            for observer in observers {
+                observer.on_refused(refused);
+            }
+        }
+    }
+
+    fn notify(&self, change: &SettingChange) {
+        for observer in self.observers.read().unwrap().iter() {
+            observer.on_change(change);
+        }
+        if let Some(observers) = self.namespace_observers.read().unwrap().get(&change.namespace) {
+            for observer in observers {
+                observer.on_change(change);
+            }
+        }
+    }
+
+    /// Apply `change` to a snapshot of the settings and publish it, returning
+    /// whether anything changed
+    ///
+    /// Nothing is published if `change` returns no changes. Observers receive
+    /// the returned changes once they received those published before.
+    fn update(&self, change: impl FnOnce(&mut Shards) -> Vec<SettingChange>) -> bool {
+        self.try_update(|settings| Ok(change(settings))).unwrap_or_default()
+    }
+
+    /// Like `update`, publishing the snapshot only if `change` succeeds
+    fn try_update(&self, change: impl FnOnce(&mut Shards) -> Result<Vec<SettingChange>>) -> Result<bool> {
+        let writer = self.writer.lock().unwrap();
+        let mut settings = Shards::clone(&self.settings.load());
+        // Every change increases the generation by one
+        let generation = settings.generation() + 1;
+        let changes = change(&mut settings)?;
+        if changes.is_empty() {
+            return Ok(false);
+        }
+        for change in &changes {
+            self.aliases.mirror(&mut settings, &change.namespace, &change.key);
+        }
+        self.settings.store(Arc::new(settings));
+        self.journal.lock().unwrap().record(generation, &changes);
+        // Still holding the writer, so no later change is signalled first
+        if let Some(emitter) = &*self.signals.load() {
+            let mut values = signal_values(&changes);
+            values.retain(|(key, _)| !self.denied.contains(&key.namespace) && self.is_public(&key.namespace, &key.key));
+            emitter.emit_many(values);
+        }
+        self.pending.lock().unwrap().deliveries.extend(changes.into_iter().map(Delivery::Change));
+        drop(writer);
+        yield_point();
+        self.deliver();
+        Ok(true)
+    }
+
+    /// Check the name of a setting and the strings of its value against the
+    /// length limits, and refuse control characters in either
+    fn check_strings(&self, namespace: &str, key: &str, value: &OwnedValue) -> Result<()> {
+        let max_name = self.limits.max_name_length;
+        if max_name > 0 && (namespace.len() > max_name || key.len() > max_name) {
+            bail!("namespaces and keys are limited to {} bytes", max_name);
+        }
+        if namespace.chars().chain(key.chars()).any(char::is_control) {
+            bail!("invalid setting name {:?}/{:?}: control characters are not allowed", namespace, key);
+        }
+        if let Some(text) = schema::find_string(value, &|text| !schema::is_clean_text(text)) {
+            bail!("invalid string {:?}: NULs and control characters other than tab and newline are not allowed", text);
+        }
+        let max_string = self.limits.max_string_length;
+        if max_string > 0 {
+            if let Some(text) = schema::find_string(value, &|text| text.len() > max_string) {
+                bail!("string of {} bytes exceeds the maximum of {}", text.len(), max_string);
+            }
+        }
+        Ok(())
+    }
+
+    /// Whether writing `value` to `namespace`/`key` leaves it as it is, and
+    /// is therefore not recorded
+    fn is_unchanged(&self, settings: &Shards, namespace: &str, key: &str, value: &OwnedValue) -> bool {
+        !self.record_unchanged && settings.get(namespace, key).is_some_and(|current| *current.0 == *value)
+    }
+
+    /// Fail if `namespace`/`key` holds a value of another type than `value`
+    fn check_type(&self, settings: &Shards, namespace: &str, key: &str, value: &OwnedValue) -> Result<()> {
+        match settings.get(namespace, key) {
+            Some(current) if current.0.value_signature() != value.value_signature() => {
+                let expected = current.0.value_signature().to_string();
+                Err(ValidationError::new(namespace, key, &expected, value).into())
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Check that storing `namespace`/`key` in `settings` stays within the
+    /// namespace and key limits
+    fn check_capacity(&self, settings: &Shards, namespace: &str, key: &str) -> Result<()> {
+        if self.registered.contains(namespace) || settings.contains(namespace, key) {
+            return Ok(());
+        }
+        let keys = settings.namespace(namespace).map_or(0, |n| n.keys.len());
+        if self.limits.max_keys > 0 && keys >= self.limits.max_keys {
+            bail!("namespace {} already has the maximum of {} keys", namespace, self.limits.max_keys);
+        }
+        if self.limits.max_namespaces > 0 && keys == 0 {
+            let namespaces = settings
+                .namespaces()
+                .filter(|(name, _)| !self.registered.contains(*name))
+                .count();
+            if namespaces >= self.limits.max_namespaces {
+                bail!(
+                    "cannot add namespace {}: the maximum of {} unregistered namespaces is reached",
+                    namespace,
+                    self.limits.max_namespaces
+                );
+            }
+        }
+        Ok(())
+    }
+
+    /// The setting `namespace`/`key` stands for: its target if it is a
+    /// deprecated alias, otherwise itself
+    ///
+    /// The first use of each alias is logged.
+    pub fn canonical<'a>(&'a self, namespace: &'a str, key: &'a str) -> (&'a str, &'a str) {
+        self.aliases.resolve(namespace, key)
+    }
+
+    pub async fn read(&self, namespace: &str, key: &str) -> Option<SettingValue> {
+        let (namespace, key) = self.canonical(namespace, key);
+        self.settings.load().get(namespace, key).cloned()
+    }
+
+    /// Fail unless every filter in `namespaces` is a namespace or a trailing
+    /// glob such as `org.gnome.*`, so malformed filters are refused instead
+    /// of matching nothing
+    pub fn check_namespace_filters(&self, namespaces: &[String]) -> Result<()> {
+        for filter in namespaces {
+            pattern::check_namespace_pattern(filter, self.limits.allow_any_namespace)
+                .with_context(|| format!("invalid namespace filter {:?}", filter))?;
+        }
+        Ok(())
+    }
+
+    /// The settings in `namespaces`, or all of them, as a copy the caller may
+    /// change; `read_all_reply` shares them instead
+    ///
+    /// Filters ending in `*` select every namespace starting with what
+    /// precedes it; see `check_namespace_filters`.
+    pub async fn read_all(&self, namespaces: Vec<String>) -> HashMap<String, HashMap<String, SettingValue>> {
+        select(&self.settings.load(), &namespaces)
+    }
+
+    /// `read_all`, registering `observer` to be notified of exactly the
+    /// changes made after the returned settings
+    ///
+    /// Calling `read_all` and then `add_observer` may miss the changes made
+    /// in between.
+    pub async fn read_all_and_observe(
+        &self,
+        namespaces: Vec<String>,
+        observer: Arc<dyn ChangeObserver>,
+    ) -> HashMap<String, HashMap<String, SettingValue>> {
+        select(&self.observe(None, observer), &namespaces)
+    }
+
+    /// `read_all` as a D-Bus reply, shared by all calls with the same
+    /// namespaces until the settings change
+    pub async fn read_all_reply(&self, namespaces: Vec<String>) -> SettingsReply {
+        let snapshot = self.settings.load();
+        self.replies
+            .lock()
+            .unwrap()
+            .get(&snapshot, namespaces)
+    }
+
+    /// `read_all_reply` for a `ReadAll` call by `caller`, failing on
+    /// malformed filters and leaving out what the policy hides from it
+    ///
+    /// With `compat.exact_match_read_all`, filters are only compared with
+    /// whole namespaces, as before globs were supported.
+    pub(crate) async fn read_all_call(&self, mut namespaces: Vec<String>, caller: &Caller) -> Result<SettingsReply> {
+        if self.compat.exact_match_read_all {
+            // Globs are taken literally, and no namespace ends in `*`
+            let filtered = !namespaces.is_empty();
+            namespaces.retain(|namespace| !namespace.ends_with('*'));
+            if filtered && namespaces.is_empty() {
+                return Ok(SettingsReply::default());
+            }
+        } else {
+            self.check_namespace_filters(&namespaces)?;
+        }
+        let reply = self.read_all_reply(namespaces).await;
+        Ok(self.visible(reply, caller))
+    }
+
+    /// `reply` without the denied namespaces
+    pub(crate) fn served(&self, reply: SettingsReply) -> SettingsReply {
+        match self.denied.is_empty() {
+            true => reply,
+            false => reply.retain(|namespace| !self.denied.contains(namespace)),
+        }
+    }
+
+    /// `reply` without the namespaces the policy hides from `caller`, nor
+    /// the denied ones
+    pub(crate) fn visible(&self, reply: SettingsReply, caller: &Caller) -> SettingsReply {
+        let reply = self.served(reply);
+        match self.policy.is_open() {
+            true => reply,
+            false => reply.retain(|namespace| self.policy.allows(caller, namespace)),
+        }
+    }
+
+    /// `read_all_reply`, or `None` if none of `namespaces` changed after
+    /// `generation`, with the generation to pass next time
+    ///
+    /// 0 always returns the settings. So do generations this store has not
+    /// reached, which were handed out before the store was created again.
+    pub async fn read_all_if_changed(&self, namespaces: Vec<String>, generation: u64) -> (u64, Option<SettingsReply>) {
+        let snapshot = self.settings.load();
+        if generation != 0 && !snapshot.changed_since(&namespaces, generation) {
+            return (snapshot.generation(), None);
+        }
+        let reply = self
+            .replies
+            .lock()
+            .unwrap()
+            .get(&snapshot, namespaces);
+        (snapshot.generation(), Some(reply))
+    }
+
+    /// The generation of the last change to each namespace, filtered like
+    /// `read_all`
+    ///
+    /// Generations only increase, and a namespace keeps its generation while
+    /// other namespaces change, so clients can tell which namespaces to read
+    /// again.
+    pub async fn generations(&self, namespaces: Vec<String>) -> HashMap<String, u64> {
+        self.settings
+            .load()
+            .namespaces()
+            .filter(|(name, _)| namespaces.is_empty() || namespaces.iter().any(|n| pattern::matches_namespace(n, name)))
+            .map(|(name, namespace)| (name.to_string(), namespace.generation))
+            .collect()
+    }
+
+    /// The changes made after `generation`, as far as the journal goes back
+    ///
+    /// Clients that kept the returned generation can catch up with the
+    /// changes they missed, unless more changes were made than the journal
+    /// keeps. Generations start over when the store is created.
+    pub async fn recent_changes(&self, generation: u64) -> RecentChanges {
+        self.journal.lock().unwrap().since(generation)
+    }
+
+    /// The layer of every current value, filtered like `read_all`
+    ///
+    /// Values equal to their default are attributed to the default's layer,
+    /// all others to `Layer::Runtime`.
+    pub async fn layers(&self, namespaces: Vec<String>) -> HashMap<String, HashMap<String, Layer>> {
+        let settings = self.settings.load();
+        let mut result: HashMap<String, HashMap<String, Layer>> = HashMap::new();
+
+        for (name, namespace) in settings.namespaces() {
+            if !namespaces.is_empty() && !namespaces.iter().any(|n| pattern::matches_namespace(n, name)) {
+                continue;
+            }
+            let layers = result.entry(name.to_string()).or_default();
+            for (key, value) in namespace.keys.iter() {
+                // Aliases hold a copy of their target's value
+                let id: &dyn Lookup = match self.aliases.target(name, key) {
+                    Some(target) => target,
+                    None => &(&**name, &**key),
+                };
+                let layer = match self.defaults.get(id) {
+                    Some(default) if default.0 == value.0 => self.origins.get(id).copied().unwrap_or(Layer::Builtin),
+                    _ => Layer::Runtime,
+                };
+                layers.insert(key.to_string(), layer);
+            }
+        }
+
+        result
+    }
+
+    pub async fn write(&self, namespace: &str, key: &str, value: OwnedValue) -> Result<()> {
+        self.write_from(ChangeSource::Api, namespace, key, value).await?;
+        Ok(())
+    }
+
+    /// Write a setting, recording `source` as the origin of the change, and
+    /// return whether it changed
+    ///
+    /// A setting keeps the type of its value: writes of another type fail
+    /// with a `ValidationError` unless made with `migrate_from`.
+    pub async fn write_from(
+        &self,
+        source: ChangeSource,
+        namespace: &str,
+        key: &str,
+        value: OwnedValue,
+    ) -> Result<bool> {
+        self.write_checked(source, namespace, key, value, false).await
+    }
+
+    /// Write a setting that may change its type, e.g. when a custom setting
+    /// moves from a string to a number
+    pub async fn migrate_from(
+        &self,
+        source: ChangeSource,
+        namespace: &str,
+        key: &str,
+        value: OwnedValue,
+    ) -> Result<bool> {
+        self.write_checked(source, namespace, key, value, true).await
+    }
+
+    async fn write_checked(
+        &self,
+        source: ChangeSource,
+        namespace: &str,
+        key: &str,
+        value: OwnedValue,
+        migrate: bool,
+    ) -> Result<bool> {
+        let (namespace, key) = self.canonical(namespace, key);
+        let value = self.coerce(namespace, key, value);
+        self.check_write(namespace, key, &value, migrate).await?;
+
+        let new = SettingValue::new(value);
+        self.try_update(|settings| {
+            if self.is_unchanged(settings, namespace, key, &new.0) {
+                return Ok(Vec::new());
+            }
+            self.check_capacity(settings, namespace, key)?;
+            if !migrate {
+                self.check_type(settings, namespace, key, &new.0)?;
+            }
+            let old = settings.insert(namespace, key, new.clone());
+            Ok(vec![SettingChange {
+                namespace: namespace.to_string(),
+                key: key.to_string(),
+                kind: ChangeKind::Write,
+                source,
+                old,
+                new: Some(new),
+                caller: Caller::current(),
+            }])
+        })
+    }
+
+    /// Write a setting unless it changed since the store was created,
+    /// returning whether it was written
+    ///
+    /// Values equal to the current one are not written either.
+    ///
+    /// Used to layer values in after the store is already served: a value
+    /// that differs from its default was written meanwhile and is newer.
+    pub async fn restore_from(
+        &self,
+        source: ChangeSource,
+        namespace: &str,
+        key: &str,
+        value: OwnedValue,
+    ) -> Result<bool> {
+        let (namespace, key) = self.canonical(namespace, key);
+        let value = self.coerce(namespace, key, value);
+        self.validate(namespace, key, &value).await?;
+
+        let new = SettingValue::new(value);
+        self.try_update(|settings| {
+            if !self.is_restorable(settings, namespace, key, &new) {
+                return Ok(Vec::new());
+            }
+            self.check_capacity(settings, namespace, key)?;
+            let old = settings.insert(namespace, key, new.clone());
+            Ok(vec![SettingChange {
+                namespace: namespace.to_string(),
+                key: key.to_string(),
+                kind: ChangeKind::Write,
+                source,
+                old,
+                new: Some(new),
+                caller: Caller::current(),
+            }])
+        })
+    }
+
+    /// `restore_from` for several settings, published together so their
+    /// `SettingChanged` signals go out as one batch
+    ///
+    /// Settings that cannot be restored are left out and returned with the
+    /// reason.
+    pub async fn restore_many_from(
+        &self,
+        source: ChangeSource,
+        values: Vec<(String, String, OwnedValue)>,
+    ) -> Vec<(SettingKey, Error)> {
+        let mut valid = Vec::new();
+        let mut refused = Vec::new();
+        for (namespace, key, value) in values {
+            let (namespace, key) = match self.canonical(&namespace, &key) {
+                (n, k) if n == namespace && k == key => (namespace, key),
+                (n, k) => (n.to_string(), k.to_string()),
+            };
+            let value = self.coerce(&namespace, &key, value);
+            match self.validate(&namespace, &key, &value).await {
+                Ok(()) => valid.push((namespace, key, SettingValue::new(value))),
+                Err(e) => refused.push((SettingKey::new(namespace, key), e)),
+            }
+        }
+
+        // Never fails: settings over a limit are refused one by one
+        let _ = self.try_update(|settings| {
+            let mut changes = Vec::new();
+            for (namespace, key, new) in valid {
+                if !self.is_restorable(settings, &namespace, &key, &new) {
+                    continue;
+                }
+                if let Err(e) = self.check_capacity(settings, &namespace, &key) {
+                    refused.push((SettingKey::new(namespace, key), e));
+                    continue;
+                }
+                let old = settings.insert(&namespace, &key, new.clone());
+                changes.push(SettingChange {
+                    namespace,
+                    key,
+                    kind: ChangeKind::Write,
+                    source,
+                    old,
+                    new: Some(new),
+                    caller: Caller::current(),
+                });
+            }
+            Ok(changes)
+        });
+        refused
+    }
+
+    /// Whether `new` would be restored over the current value: the setting
+    /// still has its default, and `new` is different
+    fn is_restorable(&self, settings: &Shards, namespace: &str, key: &str, new: &SettingValue) -> bool {
+        let default = self.defaults.get(&(namespace, key) as &dyn Lookup);
+        let current = settings.get(namespace, key).map(|v| &v.0);
+        current == default.map(|v| &v.0) && current != Some(&new.0)
+    }
+
+    /// Write several settings as one transaction
+    ///
+    /// Either every value passes validation and all of them are stored at
+    /// once, or nothing is stored and the error lists each invalid setting.
+    /// Returns the settings that changed, leaving out values written again.
+    pub async fn write_many_from(
+        &self,
+        source: ChangeSource,
+        values: Vec<(String, String, OwnedValue)>,
+    ) -> Result<Vec<SettingKey>> {
+        let values: Vec<_> = values
+            .into_iter()
+            .map(|(namespace, key, value)| {
+                let (namespace, key) = match self.canonical(&namespace, &key) {
+                    (n, k) if n == namespace && k == key => (namespace, key),
+                    (n, k) => (n.to_string(), k.to_string()),
+                };
+                let value = self.coerce(&namespace, &key, value);
+                (namespace, key, value)
+            })
+            .collect();
+        // Refused as a whole, so callers can tell it from invalid values
+        if let Some((namespace, key, _)) = values.iter().find(|(namespace, key, _)| self.is_read_only(namespace, key)) {
+            return Err(ReadOnlyError::new(namespace, key).into());
+        }
+        // Validators of different settings wait for each other only here
+        let checks = values.iter().map(|(namespace, key, value)| self.validate(namespace, key, value));
+        let mut errors = Vec::new();
+        for ((namespace, key, _), result) in values.iter().zip(futures_util::future::join_all(checks).await) {
+            if let Err(e) = result {
+                errors.push(format!("{}/{}: {:#}", namespace, key, e));
+            }
+        }
+        if !errors.is_empty() {
+            bail!("{}", errors.join("; "));
+        }
+
+        // Readers see either none or all of the new values
+        let mut changed = Vec::new();
+        self.try_update(|settings| {
+            let mut changes = Vec::new();
+            for (namespace, key, value) in values {
+                if self.is_unchanged(settings, &namespace, &key, &value) {
+                    continue;
+                }
+                self.check_capacity(settings, &namespace, &key)
+                    .with_context(|| format!("{}/{}", namespace, key))?;
+                self.check_type(settings, &namespace, &key, &value)
+                    .with_context(|| format!("{}/{}", namespace, key))?;
+                let new = SettingValue::new(value);
+                let old = settings.insert(&namespace, &key, new.clone());
+                changed.push(SettingKey::new(&namespace, &key));
+                changes.push(SettingChange {
+                    namespace,
+                    key,
+                    kind: ChangeKind::Write,
+                    source,
+                    old,
+                    new: Some(new),
+                    caller: Caller::current(),
+                });
+            }
+            Ok(changes)
+        })?;
+        Ok(changed)
+    }
+
+    /// Remove a setting, returning whether it existed
+    ///
+    /// Read-only settings are never removed.
+    pub async fn delete(&self, namespace: &str, key: &str) -> bool {
+        self.delete_from(ChangeSource::Api, namespace, key).await
+    }
+
+    pub async fn delete_from(&self, source: ChangeSource, namespace: &str, key: &str) -> bool {
+        let (namespace, key) = self.canonical(namespace, key);
+        if self.is_read_only(namespace, key) {
+            return false;
+        }
+        self.update(|settings| match settings.remove(namespace, key) {
+            Some(old) => vec![SettingChange {
+                namespace: namespace.to_string(),
+                key: key.to_string(),
+                kind: ChangeKind::Delete,
+                source,
+                old: Some(old),
+                new: None,
+                caller: Caller::current(),
+            }],
+            None => Vec::new(),
+        })
+    }
+
+    /// Restore a setting to its built-in default
+    ///
+    /// Keys without a default are removed. Returns whether anything changed.
+    pub async fn reset(&self, namespace: &str, key: &str) -> bool {
+        self.reset_from(ChangeSource::Api, namespace, key).await
+    }
+
+    pub async fn reset_from(&self, source: ChangeSource, namespace: &str, key: &str) -> bool {
+        let (namespace, key) = self.canonical(namespace, key);
+        let new = self.defaults.get(&(namespace, key) as &dyn Lookup).cloned();
+        self.update(|settings| {
+            let old = match &new {
+                Some(value) => settings.insert(namespace, key, value.clone()),
+                None => settings.remove(namespace, key),
+            };
+            if old.is_none() && new.is_none() {
+                return Vec::new();
+            }
+            vec![SettingChange {
+                namespace: namespace.to_string(),
+                key: key.to_string(),
+                kind: ChangeKind::Reset,
+                source,
+                old,
+                new,
+                caller: Caller::current(),
+            }]
+        })
+    }
+
+    /// The value a write of `value` to `namespace`/`key` stores: converted to
+    /// the declared type when the key's constraint allows coercion
+    pub fn coerce(&self, namespace: &str, key: &str, value: OwnedValue) -> OwnedValue {
+        match self.constraint(namespace, key).and_then(|c| c.coerce(&value)) {
+            Some(coerced) => coerced,
+            None => value,
+        }
+    }
+
+    /// The constraint the configuration declares for `namespace`/`key`
+    pub fn constraint(&self, namespace: &str, key: &str) -> Option<&schema::Constraint> {
+        self.constraints.get(namespace)?.get(key)
+    }
+
+    /// Whether the configuration declares `namespace`/`key` read-only, so
+    /// it keeps its default
+    pub fn is_read_only(&self, namespace: &str, key: &str) -> bool {
+        let (namespace, key) = self.aliases.target(namespace, key).map_or((namespace, key), |t| (&t.namespace, &t.key));
+        self.constraint(namespace, key).is_some_and(|constraint| constraint.read_only)
+    }
+
+    /// The value `namespace`/`key` is reset to, if it has one
+    pub fn default_value(&self, namespace: &str, key: &str) -> Option<SettingValue> {
+        self.defaults.get(&(namespace, key) as &dyn Lookup).cloned()
+    }
+
+    /// Run the full validation path for a proposed write without applying it
+    ///
+    /// Writes validate before locking the store, so validators that wait do
+    /// not hold up other writes.
+    pub async fn validate(&self, namespace: &str, key: &str, value: &OwnedValue) -> Result<()> {
+        self.check_write(namespace, key, value, false).await
+    }
+
+    /// `validate` for `migrate_from`, which accepts a value of another type
+    pub async fn validate_migration(&self, namespace: &str, key: &str, value: &OwnedValue) -> Result<()> {
+        self.check_write(namespace, key, value, true).await
+    }
+
+    async fn check_write(&self, namespace: &str, key: &str, value: &OwnedValue, migrate: bool) -> Result<()> {
+        let (namespace, key) = self.canonical(namespace, key);
+        if self.is_read_only(namespace, key) {
+            return Err(ReadOnlyError::new(namespace, key).into());
+        }
+        let coerced = self.constraint(namespace, key).and_then(|c| c.coerce(value));
+        let value = coerced.as_ref().unwrap_or(value);
+        if self.disabled.contains(namespace) {
+            bail!("namespace {} is disabled", namespace);
+        }
+        // Namespaces with defaults were chosen by the configuration
+        if !self.limits.allow_any_namespace && !self.registered.contains(namespace) && !schema::is_reverse_dns(namespace) {
+            bail!("invalid namespace {:?}: expected a reverse-DNS name such as com.example.App", namespace);
+        }
+        // Before serializing, which fails on NULs with a less helpful message
+        self.check_strings(namespace, key, value)?;
+        if self.limits.max_value_size > 0 {
+            let context = zbus::zvariant::serialized::Context::new_dbus(zbus::zvariant::LE, 0);
+            let size = zbus::zvariant::to_bytes(context, &**value)?.len();
+            if size > self.limits.max_value_size {
+                bail!("value of {} bytes exceeds the maximum of {}", size, self.limits.max_value_size);
+            }
+        }
+        if self.limits.is_strict(namespace) && !self.defaults.contains_key(&(namespace, key) as &dyn Lookup) {
+            bail!("unknown setting {}/{}: only settings with a default can be written", namespace, key);
+        }
+        self.check_capacity(&self.settings.load(), namespace, key)?;
+        // Validate the setting based on namespace and key, ahead of the type
+        // check so known settings report their constraint
+        Self::validate_setting(namespace, key, value)?;
+        if let Some(constraint) = self.constraint(namespace, key) {
+            constraint.validate(namespace, key, value)?;
+        }
+        if !migrate {
+            self.check_type(&self.settings.load(), namespace, key, value)?;
+        }
+        let key_validators = match self.key_validators.read().unwrap().get(&(namespace, key) as &dyn Lookup) {
+            Some(validators) => validators.clone(),
+            None => Vec::new(),
+        };
+        for validator in self.validators.iter().chain(&key_validators) {
+            validator
+                .validate(namespace, key, value)
+                .await
+                .with_context(|| format!("rejected by {}", validator.name()))?;
+        }
+        Ok(())
+    }
+
+    /// `validate_setting` followed by the constraint `config` declares
+    pub(crate) fn validate_constrained(config: &Config, namespace: &str, key: &str, value: &OwnedValue) -> Result<()> {
+        Self::validate_setting(namespace, key, value)?;
+        match config.constraint(namespace, key) {
+            Some(constraint) => constraint.validate(namespace, key, value),
+            None => Ok(()),
+        }
+    }
+
+    /// Check `value` against the constraints of a known setting
+    ///
+    /// Unknown settings are accepted, unless a double in the value is NaN or
+    /// infinite. Rejections are `ValidationError`s.
+    pub fn validate_setting(namespace: &str, key: &str, value: &OwnedValue) -> Result<()> {
+        // NaN and infinities cross D-Bus fine but break what consumers compute
+        if schema::find_non_finite(value).is_some() {
+            let signature = value.value_signature();
+            return Err(ValidationError::new(namespace, key, signature.as_str(), value)
+                .with_constraint("finite numbers")
+                .into());
+        }
+        let invalid = |expected| ValidationError::new(namespace, key, expected, value);
+        let result = match (namespace, key) {
+            // org.freedesktop.appearance validations
+            ("org.freedesktop.appearance", "color-scheme") => match <u32>::try_from(value) {
+                Ok(v) if v <= 2 => Ok(()),
+                _ => Err(invalid("u").with_constraint("0..=2")),
+            },
+            ("org.freedesktop.appearance", "accent-color") => {
+                let in_range = |color: (f64, f64, f64)| {
+                    let (r, g, b) = color;
+                    [r, g, b].iter().all(|c| (0.0..=1.0).contains(c)) || color == schema::ACCENT_COLOR_UNSET
+                };
+                // Converting structures of fewer fields panics
+                let color = match value.value_signature() == "(ddd)" {
+                    true => <(f64, f64, f64)>::try_from(value.try_clone()?).ok(),
+                    false => None,
+                };
+                match color {
+                    Some(color) if in_range(color) => Ok(()),
+                    _ => Err(invalid("(ddd)").with_constraint("0.0..=1.0 or (-1, -1, -1)")),
+                }
+            }
+            ("org.freedesktop.appearance", "contrast") => match <u32>::try_from(value) {
+                Ok(v) if v <= 1 => Ok(()),
+                _ => Err(invalid("u").with_constraint("0..=1")),
+            },
+            // org.gnome.desktop.interface validations
+            ("org.gnome.desktop.interface", "gtk-theme") |
+            ("org.gnome.desktop.interface", "icon-theme") |
+            ("org.gnome.desktop.interface", "cursor-theme") => match <&str>::try_from(value) {
+                Ok(_) => Ok(()),
+                Err(_) => Err(invalid("s")),
+            },
+            ("org.gnome.desktop.interface", "font-name") |
+            ("org.gnome.desktop.interface", "monospace-font-name") => {
+                match <&str>::try_from(value).ok().and_then(font::FontDescription::parse) {
+                    Some(_) => Ok(()),
+                    None => Err(invalid("s").with_constraint(format!(
+                        "\"Family [Style] Size\" with a size of {}..={}",
+                        font::MIN_SIZE,
+                        font::MAX_SIZE
+                    ))),
+                }
+            }
+            ("org.gnome.desktop.interface", "clock-format") => {
+                let choices = schema::lookup(namespace, key).map_or(&[][..], |s| s.choices);
+                match <&str>::try_from(value) {
+                    Ok(v) if choices.is_empty() || choices.contains(&v) => Ok(()),
+                    _ => Err(invalid("s").with_constraint(choices.join("|"))),
+                }
+            }
+            // org.gnome.desktop.privacy validations
+            ("org.gnome.desktop.privacy", "remember-recent-files") => match <bool>::try_from(value) {
+                Ok(_) => Ok(()),
+                Err(_) => Err(invalid("b")),
+            },
+            ("org.gnome.desktop.privacy", "recent-files-max-age") => match <i32>::try_from(value) {
+                Ok(_) => Ok(()),
+                Err(_) => Err(invalid("i")),
+            },
+            // Unknown settings are allowed (for extensibility)
+            _ => Ok(()),
+        };
+        Ok(result?)
+    }
+}
+
+impl Default for SettingsStore {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// D-Bus interface implementation for org.freedesktop.impl.portal.Settings
+pub struct SettingsPortal {
+    store: SettingsStore,
+}
+
+impl SettingsPortal {
+    pub fn new() -> Self {
+        Self {
+            store: SettingsStore::new(),
+        }
+    }
+
+    pub fn with_store(store: SettingsStore) -> Self {
+        Self { store }
+    }
+
+    pub fn store(&self) -> &SettingsStore {
+        &self.store
+    }
+}
+
+impl Default for SettingsPortal {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// The settings in the namespaces `namespaces` select, or all of them
+fn select(settings: &Shards, namespaces: &[String]) -> HashMap<String, HashMap<String, SettingValue>> {
+    let copy = |keys: &HashMap<Arc<str>, SettingValue>| -> HashMap<String, SettingValue> {
+        keys.iter().map(|(key, value)| (key.to_string(), value.clone())).collect()
+    };
+    match namespaces.is_empty() {
+        true => settings
+            .namespaces()
+            .map(|(name, namespace)| (name.to_string(), copy(&namespace.keys)))
+            .collect(),
+        false => namespaces
+            .iter()
+            .flat_map(|pattern| settings.matching(pattern))
+            .map(|(name, namespace)| (name.to_string(), copy(&namespace.keys)))
+            .collect(),
+    }
+}
+
+/// Emit `SettingChanged` on every settings interface exported at the signal
+/// context's path
+///
+/// Used after changing the store outside of the management interface, so
+/// applications see the change too.
+pub async fn emit_setting_changed(
+    ctxt: &zbus::SignalContext<'_>,
+    namespace: &str,
+    key: &str,
+    value: &Value<'_>,
+) -> zbus::Result<()> {
+    let server = ctxt.connection().object_server();
+    if server.interface::<_, SettingsPortal>(ctxt.path()).await.is_ok() {
+        SettingsPortal::setting_changed(ctxt, namespace, key, value.try_clone()?).await?;
+    }
+    if server.interface::<_, SettingsFrontend>(ctxt.path()).await.is_ok() {
+        SettingsFrontend::setting_changed(ctxt, namespace, key, value.try_clone()?).await?;
+    }
+    Ok(())
+}
+
+/// Emit `SettingChanged` for every change that left a value, like
+/// `emit_setting_changed`
+///
+/// Used after changing several settings at once, such as when importing
+/// them; see `emit_values`.
+pub async fn emit_changes(ctxt: &zbus::SignalContext<'_>, changes: &[SettingChange]) -> zbus::Result<()> {
+    let mut values = Vec::new();
+    for change in changes {
+        if let Some(value) = &change.new {
+            values.push((SettingKey::new(&change.namespace, &change.key), value.to_owned_value()?));
+        }
+    }
+    emit_values(ctxt, &values).await
+}
+
+/// The signals of every change that left a value, skipping values that
+/// cannot be copied
+fn signal_values(changes: &[SettingChange]) -> Vec<(SettingKey, OwnedValue)> {
+    changes
+        .iter()
+        .filter_map(|change| {
+            let value = change.new.as_ref()?.to_owned_value().ok()?;
+            Some((SettingKey::new(&change.namespace, &change.key), value))
+        })
+        .collect()
+}
+
+/// Emit `SettingChanged` for each of `values`, sending the signals together
+/// rather than waiting for each in turn
+///
+/// Batches of more than one setting are also announced with a single
+/// `SettingsChangedBatch` when the management interface is exported at the
+/// same path or at `MANAGEMENT_PATH`, so tools can apply them at once.
+pub(crate) async fn emit_values(ctxt: &zbus::SignalContext<'_>, values: &[(SettingKey, OwnedValue)]) -> zbus::Result<()> {
+    let server = ctxt.connection().object_server();
+    let portal = server.interface::<_, SettingsPortal>(ctxt.path()).await.is_ok();
+    let frontend = server.interface::<_, SettingsFrontend>(ctxt.path()).await.is_ok();
+    let mut signals = Vec::new();
+    for (key, value) in values {
+        if portal {
+            let signal = SettingsPortal::setting_changed(ctxt, &key.namespace, &key.key, value.try_clone()?.into());
+            signals.push(signal.boxed());
+        }
+        if frontend {
+            let signal = SettingsFrontend::setting_changed(ctxt, &key.namespace, &key.key, value.try_clone()?.into());
+            signals.push(signal.boxed());
+        }
+    }
+    futures_util::future::try_join_all(signals).await?;
+
+    #[cfg(feature = "management")]
+    if values.len() > 1 {
+        // Next to the portal interfaces, or at a path of its own
+        let ctxt = match server.interface::<_, SettingsManagement>(ctxt.path()).await {
+            Ok(_) => Some(ctxt.clone()),
+            Err(_) if server.interface::<_, SettingsManagement>(MANAGEMENT_PATH).await.is_ok() => {
+                let management = zbus::SignalContext::new(ctxt.connection(), MANAGEMENT_PATH)?;
+                Some(match ctxt.destination() {
+                    Some(destination) => management.set_destination(destination.to_owned()),
+                    None => management,
+                })
+            }
+            Err(_) => None,
+        };
+        if let Some(ctxt) = ctxt {
+            let batch = values
+                .iter()
+                .map(|(key, value)| Ok((key.namespace.to_string(), key.key.to_string(), value.try_clone()?)))
+                .collect::<zbus::Result<_>>()?;
+            SettingsManagement::settings_changed_batch(&ctxt, batch).await?;
+        }
+    }
+    Ok(())
+}
+
+/// Errors returned by `Read` on the impl interface, where `[compat]` may
+/// ask for the generic `Failed` of older backends instead of `NotFound`
+#[derive(Debug, zbus::DBusError)]
+#[zbus(prefix = "org.freedesktop")]
+enum ReadError {
+    #[zbus(error)]
+    ZBus(zbus::Error),
+    #[zbus(name = "portal.Error.NotFound")]
+    NotFound(String),
+    #[zbus(name = "DBus.Error.Failed")]
+    Failed(String),
+}
+
+#[interface(name = "org.freedesktop.impl.portal.Settings")]
+impl SettingsPortal {
+    /// Read a single setting
+    ///
+    /// Unknown settings, those of denied namespaces and those the policy
+    /// hides from the caller fail with `org.freedesktop.portal.Error.NotFound`.
+    async fn read(
+        &self,
+        #[zbus(connection)] connection: &zbus::Connection,
+        #[zbus(header)] header: zbus::message::Header<'_>,
+        namespace: &str,
+        key: &str,
+    ) -> Result<OwnedValue, ReadError> {
+        let compat = self.store.compat();
+        let value = match self.store.is_denied(namespace, key) {
+            true => None,
+            false => {
+                let (caller, store) =
+                    self.store.serve(connection, &header).await.map_err(|e| ReadError::ZBus(e.into()))?;
+                match store.is_visible(&caller, namespace, key) {
+                    true => store.read(namespace, key).await,
+                    false => None,
+                }
+            }
+        };
+        let Some(value) = value else {
+            return Err(match compat.failed_not_found {
+                true => ReadError::Failed("Setting not found".to_string()),
+                false => ReadError::NotFound("Requested setting not found".to_string()),
+            });
+        };
+        let value = value
+            .to_owned_value()
+            .map_err(|e| ReadError::Failed(format!("Cannot copy setting: {}", e)))?;
+        match compat.double_wrapped_read {
+            true => Value::Value(Box::new(Value::from(value)))
+                .try_into()
+                .map_err(|e: zbus::zvariant::Error| ReadError::ZBus(e.into())),
+            false => Ok(value),
+        }
+    }
+
+    /// Read all settings, optionally filtered by namespaces and trailing
+    /// globs like `org.gnome.*`
+    async fn read_all(
+        &self,
+        #[zbus(connection)] connection: &zbus::Connection,
+        #[zbus(header)] header: zbus::message::Header<'_>,
+        namespaces: Vec<String>,
+    ) -> zbus::fdo::Result<SettingsReply> {
+        let (caller, store) = self.store.serve(connection, &header).await?;
+        store
+            .read_all_call(namespaces, &caller)
+            .await
+            .map_err(|e| zbus::fdo::Error::InvalidArgs(format!("{:#}", e)))
+    }
+
+    /// Signal emitted when a setting changes
+    #[zbus(signal)]
+    async fn setting_changed(
+        signal_ctxt: &zbus::SignalContext<'_>,
+        namespace: &str,
+        key: &str,
+        value: Value<'_>,
+    ) -> zbus::Result<()>;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use zbus::zvariant::Str;
+
+    #[tokio::test]
+    async fn test_settings_store_creation() {
+        let store = SettingsStore::new();
+        let value = store.read("org.freedesktop.appearance", "color-scheme").await;
+        assert!(value.is_some());
+    }
+
+    #[tokio::test]
+    async fn test_read_write_setting() {
+        let store = SettingsStore::new();
+        
+        // Write a new value
+        store
+            .write("org.freedesktop.appearance", "color-scheme", Value::U32(1).try_into().unwrap())
+            .await
+            .unwrap();
+        
+        // Read it back
+        let value = store.read("org.freedesktop.appearance", "color-scheme").await;
+        assert!(value.is_some());
+        let val: u32 = value.unwrap().0.as_ref().try_into().unwrap();
+        assert_eq!(val, 1);
+    }
+
+    #[tokio::test]
+    async fn test_validation() {
+        let store = SettingsStore::new();
+        
+        // Valid value
+        assert!(store
+            .write("org.freedesktop.appearance", "color-scheme", Value::U32(1).try_into().unwrap())
+            .await
+            .is_ok());
+        
+        // Invalid value (out of range)
+        assert!(store
+            .write("org.freedesktop.appearance", "color-scheme", Value::U32(5).try_into().unwrap())
+            .await
+            .is_err());
+        
+        // Invalid type
+        assert!(store
+            .write("org.freedesktop.appearance", "color-scheme", Value::Str(Str::from("invalid")).try_into().unwrap())
+            .await
+            .is_err());
+    }
+
+    #[test]
+    fn test_validation_errors_are_structured() {
+        let value = Value::Str(Str::from_static("dark")).try_into().unwrap();
+        let error = SettingsStore::validate_setting("org.freedesktop.appearance", "color-scheme", &value).unwrap_err();
+        let invalid = error.find::<ValidationError>().unwrap();
+        assert_eq!(invalid.to_string(), "color-scheme must be u in 0..=2, not s");
+        assert_eq!(
+            invalid.encode(),
+            "namespace=org.freedesktop.appearance\nkey=color-scheme\nexpected=u\nconstraint=0..=2\nreceived=s"
+        );
+        assert_eq!(ValidationError::parse(&invalid.encode()).as_ref(), Some(invalid));
+        assert_eq!(ValidationError::parse("color-scheme must be u"), None);
+    }
+
+    #[test]
+    fn test_font_names() {
+        let check = |key, font: &'static str| {
+            let value = Value::Str(Str::from_static(font)).try_into().unwrap();
+            SettingsStore::validate_setting("org.gnome.desktop.interface", key, &value)
+        };
+        assert!(check("font-name", "Cantarell Bold 11").is_ok());
+        assert!(check("monospace-font-name", "Source Code Pro 10").is_ok());
+        let error = check("font-name", "Cantarell").unwrap_err();
+        assert_eq!(
+            error.find::<ValidationError>().unwrap().to_string(),
+            "font-name must be s in \"Family [Style] Size\" with a size of 1..=256"
+        );
+        assert!(check("monospace-font-name", "Monospace 1000").is_err());
+    }
+
+    proptest::proptest! {
+        #[test]
+        fn test_accepted_values_survive_dbus(
+            value in strategy::value(),
+            (namespace, key) in proptest::sample::select(
+                schema::BUILTIN.iter().map(|s| (s.namespace, s.key)).chain([("com.example", "value")]).collect::<Vec<_>>()
+            ),
+        ) {
+            let value = OwnedValue::try_from(value).unwrap();
+            let runtime = tokio::runtime::Builder::new_current_thread().build().unwrap();
+            runtime.block_on(async {
+                let store = SettingsStore::new();
+                if store.write(namespace, key, value.try_clone().unwrap()).await.is_err() {
+                    return;
+                }
+                // Read replies carry the stored value as a variant
+                let reply = store.read(namespace, key).await.unwrap();
+                let context = zbus::zvariant::serialized::Context::new_dbus(zbus::zvariant::LE, 0);
+                let (received, _) = zbus::zvariant::to_bytes(context, &reply).unwrap().deserialize::<OwnedValue>().unwrap();
+                assert_eq!(received, value);
+
+                let copy = SettingsStore::new();
+                copy.write(namespace, key, received).await.unwrap();
+                assert_eq!(*copy.read(namespace, key).await.unwrap().0, value);
+            });
+        }
+    }
+
+    #[test]
+    fn test_accent_color_range() {
+        let validate = |color: (f64, f64, f64)| {
+            let value = Value::Structure(color.into()).try_into().unwrap();
+            SettingsStore::validate_setting("org.freedesktop.appearance", "accent-color", &value)
+        };
+        assert!(validate((0.0, 0.5, 1.0)).is_ok());
+        assert!(validate(schema::ACCENT_COLOR_UNSET).is_ok());
+        assert!(validate((1.5, 0.0, 0.0)).is_err());
+        assert!(validate((-1.0, 0.0, 0.0)).is_err());
+        assert!(validate((f64::NAN, 0.0, 0.0)).is_err());
+        assert!(validate((f64::INFINITY, 0.0, 0.0)).is_err());
+
+        let short = Value::from((0.5,)).try_into().unwrap();
+        assert!(SettingsStore::validate_setting("org.freedesktop.appearance", "accent-color", &short).is_err());
+    }
+
+    #[test]
+    fn test_non_finite_doubles_are_rejected() {
+        let validate = |key, value: Value<'static>| {
+            let value = value.try_into().unwrap();
+            SettingsStore::validate_setting("com.example", key, &value)
+        };
+        assert!(validate("ratio", Value::F64(0.5)).is_ok());
+        for number in [f64::NAN, f64::INFINITY, f64::NEG_INFINITY] {
+            let error = validate("ratio", Value::F64(number)).unwrap_err();
+            assert_eq!(error.to_string(), "ratio must be d in finite numbers");
+            // Nested in containers too
+            assert!(validate("ratios", Value::from(vec![0.5, number])).is_err());
+            assert!(validate("point", Value::from((1u32, number))).is_err());
+            assert!(validate("wrapped", Value::Value(Box::new(Value::F64(number)))).is_err());
+        }
+        let error = validate("accent", Value::from((0.5, f64::NAN, 0.5))).unwrap_err();
+        assert_eq!(error.find::<ValidationError>().unwrap().expected, "(ddd)");
+    }
+
+    #[cfg(feature = "gnome")]
+    #[test]
+    fn test_clock_format_choices() {
+        let format = |v: &'static str| -> OwnedValue { Value::Str(Str::from_static(v)).try_into().unwrap() };
+        let validate = |v| SettingsStore::validate_setting("org.gnome.desktop.interface", "clock-format", &format(v));
+        assert!(validate("12h").is_ok());
+        assert!(validate("24h").is_ok());
+        let err = validate("25h").unwrap_err();
+        let err = err.find::<ValidationError>().unwrap();
+        assert_eq!(err.constraint.as_deref(), Some("12h|24h"));
+        assert_eq!(err.to_string(), "clock-format must be s in 12h|24h");
+    }
+
+    #[tokio::test]
+    async fn test_schema_constraints() {
+        let config = Config::parse(
+            r#"
+            [schema."com.example.Kiosk"]
+            retention-days = { type = "i", min = 1, max = 365 }
+            clock = { type = "s", choices = ["12h", "24h"] }
+            label = { type = "s", min_length = 1, max_length = 8 }
+
+            [settings."com.example.Kiosk"]
+            retention-days = 30
+            "#,
+        )
+        .unwrap();
+        let store = SettingsStore::from_config(&config).unwrap();
+        let write = |key: &'static str, value: Value<'static>| {
+            let store = store.clone();
+            async move { store.write("com.example.Kiosk", key, value.try_into().unwrap()).await }
+        };
+        assert!(write("retention-days", Value::I32(365)).await.is_ok());
+        let error = write("retention-days", Value::I32(366)).await.unwrap_err();
+        assert_eq!(error.find::<ValidationError>().unwrap().constraint.as_deref(), Some("1..=365"));
+        assert!(write("retention-days", Value::U32(7)).await.is_err());
+        assert!(write("clock", Value::from("12h")).await.is_ok());
+        assert!(write("clock", Value::from("noon")).await.is_err());
+        assert!(write("label", Value::from("Lobby 1")).await.is_ok());
+        assert!(write("label", Value::from("Main Lobby")).await.is_err());
+        assert!(write("label", Value::from("")).await.is_err());
+        // Undeclared keys are not constrained
+        assert!(write("other", Value::I32(-1)).await.is_ok());
+
+        let invalid = Config::parse(
+            r#"
+            [schema."com.example.Kiosk"]
+            retention-days = { type = "i", min = 1 }
+
+            [settings."com.example.Kiosk"]
+            retention-days = 0
+            "#,
+        )
+        .unwrap();
+        assert!(SettingsStore::from_config(&invalid).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_coercion() {
+        let config = Config::parse(
+            r#"
+            [schema."org.freedesktop.appearance"]
+            color-scheme = { type = "u", coerce = true }
+
+            [schema."com.example.Kiosk"]
+            enabled = { type = "b", coerce = true }
+            strict = { type = "b" }
+            "#,
+        )
+        .unwrap();
+        let store = SettingsStore::from_config(&config).unwrap();
+        let read = |key| {
+            let store = store.clone();
+            async move { store.read("com.example.Kiosk", key).await.unwrap().0.as_ref().try_clone().unwrap() }
+        };
+
+        store.write("org.freedesktop.appearance", "color-scheme", OwnedValue::from(1i32)).await.unwrap();
+        let scheme = store.read("org.freedesktop.appearance", "color-scheme").await.unwrap();
+        assert_eq!(*scheme.0, OwnedValue::from(1u32));
+        assert!(store.write("org.freedesktop.appearance", "color-scheme", OwnedValue::from(-1i32)).await.is_err());
+
+        let text = |s: &'static str| OwnedValue::try_from(Value::from(s)).unwrap();
+        store.write("com.example.Kiosk", "enabled", text("false")).await.unwrap();
+        assert_eq!(read("enabled").await, OwnedValue::from(false));
+        assert!(store.validate("com.example.Kiosk", "enabled", &text("true")).await.is_ok());
+        assert!(store.write("com.example.Kiosk", "enabled", text("yes")).await.is_err());
+        // Only keys that opt in are coerced
+        assert!(store.write("com.example.Kiosk", "strict", text("true")).await.is_err());
+
+        store
+            .write_many_from(ChangeSource::Api, vec![("com.example.Kiosk".into(), "enabled".into(), text("true"))])
+            .await
+            .unwrap();
+        assert_eq!(read("enabled").await, OwnedValue::from(true));
+    }
+
+    #[cfg(feature = "gnome")]
+    #[tokio::test]
+    async fn test_aliases() {
+        let config = Config::parse(
+            r#"
+            [aliases."com.example.Kiosk"]
+            theme = { namespace = "org.gnome.desktop.interface", key = "gtk-theme" }
+            old-clock = { key = "clock" }
+            "#,
+        )
+        .unwrap();
+        let store = SettingsStore::from_config(&config).unwrap();
+        let kiosk = || async { store.read_all(vec!["com.example.Kiosk".to_string()]).await };
+        let theme = store.read("org.gnome.desktop.interface", "gtk-theme").await.unwrap();
+        assert_eq!(kiosk().await["com.example.Kiosk"]["theme"], theme);
+        assert_eq!(store.read("com.example.Kiosk", "theme").await, Some(theme));
+
+        // Writes of the alias change its target, and both names show the value
+        let dark = OwnedValue::try_from(Value::from("Adwaita-dark")).unwrap();
+        store.write("com.example.Kiosk", "theme", dark.try_clone().unwrap()).await.unwrap();
+        let theme = store.read("org.gnome.desktop.interface", "gtk-theme").await.unwrap();
+        assert_eq!(*theme.0, dark);
+        assert_eq!(kiosk().await["com.example.Kiosk"]["theme"], theme);
+        let layers = store.layers(vec!["com.example.Kiosk".to_string()]).await;
+        assert_eq!(layers["com.example.Kiosk"]["theme"], Layer::Runtime);
+        assert!(store.validate("com.example.Kiosk", "theme", &OwnedValue::from(1u32)).await.is_err());
+
+        assert!(store.reset("com.example.Kiosk", "theme").await);
+        let theme = store.read("org.gnome.desktop.interface", "gtk-theme").await.unwrap();
+        assert_eq!(kiosk().await["com.example.Kiosk"]["theme"], theme);
+
+        // Aliases of a target without a value are left out too
+        assert!(!kiosk().await["com.example.Kiosk"].contains_key("old-clock"));
+        store.write("com.example.Kiosk", "clock", OwnedValue::from(12u32)).await.unwrap();
+        assert_eq!(kiosk().await["com.example.Kiosk"].len(), 3);
+        assert!(store.delete("com.example.Kiosk", "old-clock").await);
+        assert!(store.read("com.example.Kiosk", "clock").await.is_none());
+        assert_eq!(kiosk().await["com.example.Kiosk"].len(), 1);
+
+        for text in [
+            "[aliases.a]\nb = { key = \"b\" }",
+            "[aliases.a]\nb = { key = \"c\" }\nc = { key = \"d\" }",
+            "[aliases.\"org.gnome.desktop.interface\"]\nclock-format = { key = \"clock\" }",
+            "disabled_namespaces = [\"a\"]\n[aliases.a]\nb = { key = \"c\" }",
+        ] {
+            assert!(SettingsStore::from_config(&Config::parse(text).unwrap()).is_err(), "{}", text);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_read_only_keys() {
+        let config = Config::parse(
+            r#"
+            [settings."com.example.Kiosk"]
+            locked = true
+
+            [schema."com.example.Kiosk"]
+            locked = { type = "b", read_only = true }
+
+            [aliases."com.example.Kiosk"]
+            old-locked = { key = "locked" }
+            "#,
+        )
+        .unwrap();
+        let store = SettingsStore::from_config(&config).unwrap();
+        assert!(store.is_read_only("com.example.Kiosk", "old-locked"));
+
+        for key in ["locked", "old-locked"] {
+            let error = store.write("com.example.Kiosk", key, OwnedValue::from(false)).await.unwrap_err();
+            assert_eq!(error.find::<ReadOnlyError>(), Some(&ReadOnlyError::new("com.example.Kiosk", "locked")));
+            assert!(!store.delete("com.example.Kiosk", key).await);
+        }
+        let values = vec![("com.example.Kiosk".into(), "locked".into(), OwnedValue::from(true))];
+        let error = store.write_many_from(ChangeSource::Api, values).await.unwrap_err();
+        assert!(error.find::<ReadOnlyError>().is_some());
+        let locked = store.read("com.example.Kiosk", "locked").await.unwrap();
+        assert_eq!(*locked.0, OwnedValue::from(true));
+    }
+
+    #[tokio::test]
+    async fn test_read_all_with_filter() {
+        let store = SettingsStore::new();
+        
+        // Read all settings in a specific namespace
+        let result = store.read_all(vec!["org.freedesktop.appearance".to_string()]).await;
+        assert!(result.contains_key("org.freedesktop.appearance"));
+        assert!(!result.contains_key("org.gnome.desktop.interface"));
+    }
+
+    // Needs the built-in GNOME defaults
+    #[cfg(feature = "gnome")]
+    #[tokio::test]
+    async fn test_read_all_no_filter() {
+        let store = SettingsStore::new();
+        
+        // Read all settings
+        let result = store.read_all(vec![]).await;
+        assert!(result.contains_key("org.freedesktop.appearance"));
+        assert!(result.contains_key("org.gnome.desktop.interface"));
+        assert!(result.contains_key("org.gnome.desktop.privacy"));
+    }
+
+    struct Recorder(std::sync::Mutex<Vec<(ChangeKind, ChangeSource, String)>>);
+
+    impl ChangeObserver for Recorder {
+        fn on_change(&self, change: &SettingChange) {
+            self.0
+                .lock()
+                .unwrap()
+                .push((change.kind, change.source, change.key.clone()));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_observers_see_mutations() {
+        let store = SettingsStore::new();
+        let recorder = Arc::new(Recorder(std::sync::Mutex::new(Vec::new())));
+        store.add_observer(recorder.clone());
+
+        store
+            .write_from(ChangeSource::DBus, "org.freedesktop.appearance", "contrast", Value::U32(1).try_into().unwrap())
+            .await
+            .unwrap();
+        // Rejected writes are not reported
+        assert!(store
+            .write("org.freedesktop.appearance", "contrast", Value::U32(7).try_into().unwrap())
+            .await
+            .is_err());
+        assert!(store.reset("org.freedesktop.appearance", "contrast").await);
+        assert!(store.delete("org.freedesktop.appearance", "accent-color").await);
+        assert!(!store.delete("org.freedesktop.appearance", "accent-color").await);
+
+        let seen = recorder.0.lock().unwrap().clone();
+        assert_eq!(
+            seen,
+            vec![
+                (ChangeKind::Write, ChangeSource::DBus, "contrast".to_string()),
+                (ChangeKind::Reset, ChangeSource::Api, "contrast".to_string()),
+                (ChangeKind::Delete, ChangeSource::Api, "accent-color".to_string()),
+            ]
+        );
+
+        let contrast: u32 = store
+            .read("org.freedesktop.appearance", "contrast")
+            .await
+            .unwrap()
+            .0
+            .as_ref()
+            .try_into()
+            .unwrap();
+        assert_eq!(contrast, 0);
+    }
+
+    #[tokio::test]
+    async fn test_changes_record_the_caller() {
+        struct Callers(std::sync::Mutex<Vec<Option<Caller>>>);
+        impl ChangeObserver for Callers {
+            fn on_change(&self, change: &SettingChange) {
+                self.0.lock().unwrap().push(change.caller.clone());
+            }
+        }
+
+        let store = SettingsStore::new();
+        let callers = Arc::new(Callers(std::sync::Mutex::new(Vec::new())));
+        store.add_observer(callers.clone());
+        let caller = Caller {
+            uid: Some(1000),
+            ..Caller::default()
+        };
+        caller
+            .clone()
+            .run(store.write_from(ChangeSource::DBus, "org.freedesktop.appearance", "contrast", OwnedValue::from(1u32)))
+            .await
+            .unwrap();
+        assert!(store.reset("org.freedesktop.appearance", "contrast").await);
+        assert_eq!(*callers.0.lock().unwrap(), vec![Some(caller), None]);
+    }
+
+    #[tokio::test]
+    async fn test_store_from_config() {
+        let config = Config::parse(
+            r#"
+            [settings."org.freedesktop.appearance"]
+            color-scheme = 1
+            "#,
+        )
+        .unwrap();
+        let store = SettingsStore::from_config(&config).unwrap();
+        store
+            .write("org.freedesktop.appearance", "color-scheme", Value::U32(2).try_into().unwrap())
+            .await
+            .unwrap();
+
+        // Reset restores the configured value rather than the built-in one
+        store.reset("org.freedesktop.appearance", "color-scheme").await;
+        let value: u32 = store
+            .read("org.freedesktop.appearance", "color-scheme")
+            .await
+            .unwrap()
+            .0
+            .as_ref()
+            .try_into()
+            .unwrap();
+        assert_eq!(value, 1);
+
+        let invalid = Config::parse(
+            r#"
+            [settings."org.freedesktop.appearance"]
+            color-scheme = 9
+            "#,
+        )
+        .unwrap();
+        assert!(SettingsStore::from_config(&invalid).is_err());
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn test_concurrent_writes_are_not_lost() {
+        let store = SettingsStore::new();
+        let writers: Vec<_> = (0..32u32)
+            .map(|i| {
+                let store = store.clone();
+                tokio::spawn(async move {
+                    store
+                        .write("org.example", &format!("key-{}", i), Value::U32(i).try_into().unwrap())
+                        .await
+                        .unwrap();
+                })
+            })
+            .collect();
+        for writer in writers {
+            writer.await.unwrap();
+        }
+        assert_eq!(store.read_all(vec!["org.example".to_string()]).await["org.example"].len(), 32);
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 8)]
+    async fn test_stress_readers_writers_subscribers() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        const WRITERS: u32 = 16;
+        const WRITES: u32 = 100;
+        const NAMESPACES: u32 = 4;
+
+        struct Count(AtomicUsize);
+        impl ChangeObserver for Count {
+            fn on_change(&self, _change: &SettingChange) {
+                self.0.fetch_add(1, Ordering::SeqCst);
+            }
+        }
+
+        let store = SettingsStore::new();
+        let all = Arc::new(Count(AtomicUsize::new(0)));
+        let subscribers: Vec<Arc<Count>> = (0..100).map(|_| Arc::new(Count(AtomicUsize::new(0)))).collect();
+        store.add_observer(all.clone());
+        for (i, subscriber) in subscribers.iter().enumerate() {
+            store.add_namespace_observer(&format!("org.example.stress{}", i as u32 % NAMESPACES), subscriber.clone());
+        }
+        let namespaces: Vec<String> = (0..NAMESPACES).map(|n| format!("org.example.stress{}", n)).collect();
+        let done = Arc::new(std::sync::atomic::AtomicBool::new(false));
+
+        let readers: Vec<_> = (0..200)
+            .map(|i| {
+                let (store, done, namespaces) = (store.clone(), done.clone(), namespaces.clone());
+                tokio::spawn(async move {
+                    // Generations seen by one reader never go back
+                    let mut seen: HashMap<String, u64> = HashMap::new();
+                    while !done.load(Ordering::SeqCst) {
+                        let filter = match i % 3 {
+                            0 => vec![],
+                            _ => vec![namespaces[i % namespaces.len()].clone()],
+                        };
+                        for (namespace, generation) in store.generations(filter.clone()).await {
+                            let previous = seen.insert(namespace.clone(), generation).unwrap_or(0);
+                            assert!(generation >= previous, "{} went back from {} to {}", namespace, previous, generation);
+                        }
+                        let reply = store.read_all_reply(filter).await;
+                        assert!(reply.values().all(|keys| !keys.is_empty()));
+                        store.read("org.freedesktop.appearance", "contrast").await.unwrap();
+                        tokio::task::yield_now().await;
+                    }
+                })
+            })
+            .collect();
+        let writers: Vec<_> = (0..WRITERS)
+            .map(|w| {
+                let store = store.clone();
+                tokio::spawn(async move {
+                    for i in 0..WRITES {
+                        let namespace = format!("org.example.stress{}", i % NAMESPACES);
+                        let key = format!("writer-{}-{}", w, i);
+                        store.write(&namespace, &key, OwnedValue::from(i)).await.unwrap();
+                    }
+                })
+            })
+            .collect();
+
+        let run = async {
+            for writer in writers {
+                writer.await.unwrap();
+            }
+            done.store(true, Ordering::SeqCst);
+            for reader in readers {
+                reader.await.unwrap();
+            }
+        };
+        tokio::time::timeout(std::time::Duration::from_secs(60), run)
+            .await
+            .expect("readers and writers deadlocked");
+
+        let total = (WRITERS * WRITES) as usize;
+        assert_eq!(all.0.load(Ordering::SeqCst), total);
+        // Each namespace observer sees exactly the writes to its namespace
+        for subscriber in &subscribers {
+            assert_eq!(subscriber.0.load(Ordering::SeqCst), total / NAMESPACES as usize);
+        }
+        let settings = store.read_all(namespaces.clone()).await;
+        assert_eq!(settings.values().map(HashMap::len).sum::<usize>(), total);
+        // Every write got its own generation
+        assert_eq!(store.generations(vec![]).await.values().max(), Some(&(shards::FIRST_GENERATION + total as u64)));
+    }
+
+    #[tokio::test]
+    async fn test_write_many_is_all_or_nothing() {
+        let store = SettingsStore::new();
+        let appearance = "org.freedesktop.appearance".to_string();
+        let rejected = store
+            .write_many_from(
+                ChangeSource::DBus,
+                vec![
+                    (appearance.clone(), "contrast".to_string(), Value::U32(1).try_into().unwrap()),
+                    (appearance.clone(), "color-scheme".to_string(), Value::U32(9).try_into().unwrap()),
+                ],
+            )
+            .await
+            .unwrap_err();
+        assert!(rejected.to_string().starts_with("org.freedesktop.appearance/color-scheme:"));
+        let contrast: u32 = store.read(&appearance, "contrast").await.unwrap().0.as_ref().try_into().unwrap();
+        assert_eq!(contrast, 0);
+
+        store
+            .write_many_from(
+                ChangeSource::DBus,
+                vec![
+                    (appearance.clone(), "contrast".to_string(), Value::U32(1).try_into().unwrap()),
+                    (appearance.clone(), "color-scheme".to_string(), Value::U32(2).try_into().unwrap()),
+                ],
+            )
+            .await
+            .unwrap();
+        let scheme: u32 = store.read(&appearance, "color-scheme").await.unwrap().0.as_ref().try_into().unwrap();
+        assert_eq!(scheme, 2);
+    }
+
+    #[tokio::test]
+    async fn test_reads_share_the_stored_value() {
+        let store = SettingsStore::new();
+        let first = store.read("org.freedesktop.appearance", "contrast").await.unwrap();
+        let second = store.read("org.freedesktop.appearance", "contrast").await.unwrap();
+        assert!(Arc::ptr_eq(&first.0, &second.0));
+        assert!(Arc::ptr_eq(&first.0, &first.clone().0));
+        assert_eq!(first.to_owned_value().unwrap(), OwnedValue::from(0u32));
+    }
+
+    #[test]
+    fn test_borrowed_lookup() {
+        use std::collections::hash_map::RandomState;
+        use std::hash::BuildHasher;
+
+        let hasher = RandomState::new();
+        let key = SettingKey::new("org.freedesktop.appearance", "contrast");
+        let borrowed: &dyn Lookup = &("org.freedesktop.appearance", "contrast");
+        assert_eq!(hasher.hash_one(&key), hasher.hash_one(borrowed));
+
+        let settings = HashMap::from([(key, 1)]);
+        assert_eq!(settings.get(borrowed), Some(&1));
+        assert_eq!(settings.get(&("org.freedesktop.appearance", "color-scheme") as &dyn Lookup), None);
+        // The parts are not concatenated before hashing
+        assert_eq!(settings.get(&("org.freedesktop.appearancec", "ontrast") as &dyn Lookup), None);
+    }
+
+    #[tokio::test]
+    async fn test_builtin_defaults_are_converted_once() {
+        let first = SettingsStore::new().read("org.freedesktop.appearance", "contrast").await.unwrap();
+        let second = SettingsStore::new().read("org.freedesktop.appearance", "contrast").await.unwrap();
+        assert!(Arc::ptr_eq(&first.0, &second.0));
+    }
+
+    #[tokio::test]
+    async fn test_limits() {
+        let store = SettingsStore::new().with_limits(LimitsConfig {
+            max_namespaces: 2,
+            max_keys: 2,
+            max_keys_per_sender: 0,
+            max_value_size: 64,
+            ..LimitsConfig::default()
+        });
+        let value = || OwnedValue::from(1i32);
+        store.write("com.example.a", "one", value()).await.unwrap();
+        store.write("com.example.a", "two", value()).await.unwrap();
+        assert!(store.write("com.example.a", "three", value()).await.is_err());
+        // Replacing an existing key never counts against the limits
+        store.write("com.example.a", "two", OwnedValue::from(2i32)).await.unwrap();
+
+        store.write("com.example.b", "one", value()).await.unwrap();
+        assert!(store.write("com.example.c", "one", value()).await.is_err());
+        assert!(store
+            .write_many_from(
+                ChangeSource::Api,
+                vec![
+                    ("com.example.b".to_string(), "two".to_string(), value()),
+                    ("com.example.b".to_string(), "three".to_string(), value()),
+                ],
+            )
+            .await
+            .is_err());
+        assert!(store.read("com.example.b", "two").await.is_none());
+
+        // Registered namespaces only have their values bounded
+        store.write("org.freedesktop.appearance", "extra", value()).await.unwrap();
+        let long = OwnedValue::try_from(Value::from("x".repeat(100))).unwrap();
+        assert!(store.write("org.freedesktop.appearance", "extra", long).await.is_err());
+
+        store.delete("com.example.b", "one").await;
+        store.write("com.example.c", "one", value()).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_read_all_replies_are_shared_until_a_change() {
+        let store = SettingsStore::new();
+        let appearance = || vec!["org.freedesktop.appearance".to_string()];
+        let first = store.read_all_reply(appearance()).await;
+        assert!(std::ptr::eq(&*first, &*store.read_all_reply(appearance()).await));
+        let unfiltered = store.read_all_reply(vec![]).await;
+        assert!(!std::ptr::eq(&*first, &*unfiltered));
+        // Replies share the keys instead of copying them
+        let keys = |reply: &SettingsReply| reply["org.freedesktop.appearance"].clone();
+        assert!(Arc::ptr_eq(&keys(&first), &keys(&unfiltered)));
+        assert_eq!(*first["org.freedesktop.appearance"]["contrast"].0, OwnedValue::from(0u32));
+
+        store
+            .write("org.freedesktop.appearance", "contrast", Value::U32(1).try_into().unwrap())
+            .await
+            .unwrap();
+        let second = store.read_all_reply(appearance()).await;
+        assert_eq!(*second["org.freedesktop.appearance"]["contrast"].0, OwnedValue::from(1u32));
+        assert_eq!(*first["org.freedesktop.appearance"]["contrast"].0, OwnedValue::from(0u32));
+
+        // Changes to other namespaces keep the reply
+        store.write("com.example", "key", OwnedValue::from(1i32)).await.unwrap();
+        assert!(std::ptr::eq(&*second, &*store.read_all_reply(appearance()).await));
+    }
+
+    // Needs the built-in GNOME defaults
+    #[cfg(feature = "gnome")]
+    #[tokio::test]
+    async fn test_namespace_generations_and_observers() {
+        struct Count(std::sync::atomic::AtomicUsize);
+        impl ChangeObserver for Count {
+            fn on_change(&self, _change: &SettingChange) {
+                self.0.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+            }
+        }
+
+        let store = SettingsStore::new();
+        let count = Arc::new(Count(Default::default()));
+        store.add_namespace_observer("org.freedesktop.appearance", count.clone());
+        let before = store.generations(vec![]).await;
+
+        store.write("com.example", "key", OwnedValue::from(1i32)).await.unwrap();
+        store
+            .write("org.freedesktop.appearance", "contrast", OwnedValue::from(1u32))
+            .await
+            .unwrap();
+        assert_eq!(count.0.load(std::sync::atomic::Ordering::SeqCst), 1);
+
+        let after = store.generations(vec![]).await;
+        let privacy = "org.gnome.desktop.privacy";
+        assert_eq!(after[privacy], before[privacy]);
+        assert!(after["org.freedesktop.appearance"] > after["com.example"]);
+        assert_eq!(store.generations(vec![privacy.to_string()]).await.len(), 1);
+    }
+
+    #[cfg(feature = "gnome")]
+    #[tokio::test]
+    async fn test_namespace_filters() {
+        let store = SettingsStore::new();
+        let filters = |patterns: &[&str]| patterns.iter().map(|p| p.to_string()).collect::<Vec<_>>();
+        let valid = ["org.freedesktop.appearance", "org.gnome.*", "org.*", "*", "com.example-1.my_app"];
+        for pattern in valid {
+            store.check_namespace_filters(&filters(&[pattern])).unwrap();
+        }
+        let invalid = ["", "*.appearance", "org.*.appearance", "org.gnome*", "org.gnome.**", "org..gnome", ".org", "org.gnome.", "org gnome", "org.gnöme", "org.\n"];
+        for pattern in invalid {
+            let filter = filters(&["org.freedesktop.appearance", pattern]);
+            assert!(store.check_namespace_filters(&filter).is_err(), "{:?} was accepted", pattern);
+        }
+        // Stores taking any namespace still refuse malformed globs
+        let any = SettingsStore::new().with_limits(LimitsConfig {
+            allow_any_namespace: true,
+            ..LimitsConfig::default()
+        });
+        any.check_namespace_filters(&filters(&["org gnome", "my app.*"])).unwrap();
+        assert!(any.check_namespace_filters(&filters(&["org.gno*"])).is_err());
+
+        let names = |settings: HashMap<String, HashMap<String, SettingValue>>| {
+            let mut names: Vec<_> = settings.into_keys().collect();
+            names.sort();
+            names
+        };
+        let gnome = names(store.read_all(filters(&["org.gnome.*"])).await);
+        assert!(!gnome.is_empty() && gnome.iter().all(|n| n.starts_with("org.gnome.")), "{:?}", gnome);
+        assert_eq!(names(store.read_all(filters(&["*"])).await), names(store.read_all(vec![]).await));
+        // A glob is a prefix of whole elements, and the prefix alone is no match
+        assert!(store.read_all(filters(&["org.freedesktop.appearance.*"])).await.is_empty());
+        let generations = store.generations(filters(&["org.gnome.*"])).await;
+        assert_eq!(generations.len(), gnome.len());
+
+        // Namespaces created under a glob show up in its cached reply
+        let (generation, reply) = store.read_all_if_changed(filters(&["com.example.*"]), 0).await;
+        assert!(reply.unwrap().is_empty());
+        store.write("com.example.New", "key", OwnedValue::from(1i32)).await.unwrap();
+        let (_, reply) = store.read_all_if_changed(filters(&["com.example.*"]), generation).await;
+        assert!(reply.unwrap().contains_key("com.example.New"));
+    }
+
+    #[tokio::test]
+    async fn test_compat() {
+        use crate::testing::{TestPortal, PATH};
+
+        let filters = |patterns: &[&str]| patterns.iter().map(|p| p.to_string()).collect::<Vec<_>>();
+        let store = SettingsStore::new();
+        assert!(store.read_all_call(filters(&["org.*.appearance"]), &Caller::default()).await.is_err());
+        let call = |portal: &TestPortal| {
+            let client = portal.client.clone();
+            async move {
+                let settings = zbus::Proxy::new(&client, config::DEFAULT_BUS_NAME, PATH, "org.freedesktop.impl.portal.Settings")
+                    .await
+                    .unwrap();
+                let value: OwnedValue = settings.call("Read", &("org.freedesktop.appearance", "color-scheme")).await.unwrap();
+                let error = settings.call::<_, _, OwnedValue>("Read", &("org.freedesktop.appearance", "missing")).await;
+                let Err(zbus::Error::MethodError(name, ..)) = error else { panic!("{:?}", error) };
+                (value, name.to_string())
+            }
+        };
+
+        let portal = TestPortal::p2p(SettingsStore::new()).await.unwrap();
+        let (value, error) = call(&portal).await;
+        assert_eq!(value.value_signature(), "u");
+        assert_eq!(error, "org.freedesktop.portal.Error.NotFound");
+
+        let compat = CompatConfig {
+            double_wrapped_read: true,
+            exact_match_read_all: true,
+            failed_not_found: true,
+        };
+        let store = SettingsStore::new().with_compat(compat);
+        // Filters are names, so globs match nothing and are not refused
+        assert!(store.read_all_call(filters(&["org.*.appearance"]), &Caller::default()).await.unwrap().is_empty());
+        assert!(store.read_all_call(filters(&["org.freedesktop.*"]), &Caller::default()).await.unwrap().is_empty());
+        assert_eq!(store.read_all_call(filters(&["org.freedesktop.appearance"]), &Caller::default()).await.unwrap().len(), 1);
+        let portal = TestPortal::p2p(store).await.unwrap();
+        let (value, error) = call(&portal).await;
+        assert_eq!(value.value_signature(), "v");
+        assert_eq!(error, "org.freedesktop.DBus.Error.Failed");
+    }
+
+    #[tokio::test]
+    async fn test_policy() {
+        use crate::testing::{TestPortal, PATH};
+
+        let policy = Policy::parse("[default]\nallow = [\"org.freedesktop.*\"]").unwrap();
+        let store = SettingsStore::new().with_policy(policy);
+        store.write("com.example", "secret", OwnedValue::from(1i32)).await.unwrap();
+        // Peer-to-peer callers are unknown, so the default rule applies
+        let portal = TestPortal::p2p(store).await.unwrap();
+        let settings = zbus::Proxy::new(&portal.client, config::DEFAULT_BUS_NAME, PATH, "org.freedesktop.impl.portal.Settings")
+            .await
+            .unwrap();
+        let _: OwnedValue = settings.call("Read", &("org.freedesktop.appearance", "color-scheme")).await.unwrap();
+        let error = settings.call::<_, _, OwnedValue>("Read", &("com.example", "secret")).await;
+        let Err(zbus::Error::MethodError(name, ..)) = error else { panic!("{:?}", error) };
+        assert_eq!(name.as_str(), "org.freedesktop.portal.Error.NotFound");
+        let reply: HashMap<String, HashMap<String, OwnedValue>> = settings.call("ReadAll", &(Vec::<String>::new(),)).await.unwrap();
+        assert!(reply.contains_key("org.freedesktop.appearance"));
+        assert!(!reply.contains_key("com.example"));
+        // The store itself is not limited
+        assert!(portal.store.read("com.example", "secret").await.is_some());
+
+        // Signals reach every client, so only those of namespaces all may see go out
+        use futures_util::StreamExt;
+        let rule = zbus::MatchRule::builder().msg_type(zbus::message::Type::Signal).member("SettingChanged").unwrap().build();
+        let mut signals = zbus::MessageStream::for_match_rule(rule, &portal.client, None).await.unwrap();
+        let path = zbus::zvariant::OwnedObjectPath::try_from(PATH).unwrap();
+        portal.store.set_signal_emitter(Some(SignalEmitter::spawn(portal.server.clone(), path, emitter::DEFAULT_CAPACITY)));
+        portal.store.write("com.example", "secret", OwnedValue::from(2i32)).await.unwrap();
+        portal.store.write("org.freedesktop.appearance", "contrast", OwnedValue::from(1u32)).await.unwrap();
+        let signal = signals.next().await.unwrap().unwrap();
+        let (namespace, _, _): (String, String, OwnedValue) = signal.body().deserialize().unwrap();
+        assert_eq!(namespace, "org.freedesktop.appearance");
+    }
+
+    #[cfg(feature = "management")]
+    #[tokio::test]
+    async fn test_policy_management() {
+        use crate::testing::{TestPortal, PATH};
+
+        let config = Config::parse("sensitive_keys = [\"com.example/*\", \"org.freedesktop.appearance/accent-color\"]").unwrap();
+        let policy = Policy::parse("[default]\nallow = [\"org.freedesktop.*\"]").unwrap();
+        let store = SettingsStore::from_config(&config).unwrap().with_policy(policy);
+        store.write("com.example", "secret", OwnedValue::from(1i32)).await.unwrap();
+        store.write("org.freedesktop.appearance", "contrast", OwnedValue::from(1u32)).await.unwrap();
+        let portal = TestPortal::p2p(store).await.unwrap();
+        let management = zbus::Proxy::new(&portal.client, config::DEFAULT_BUS_NAME, PATH, MANAGEMENT_INTERFACE).await.unwrap();
+        let all = (Vec::<String>::new(),);
+
+        let layers: HashMap<String, HashMap<String, String>> = management.call("Layers", &all).await.unwrap();
+        assert!(layers.contains_key("org.freedesktop.appearance") && !layers.contains_key("com.example"));
+        let generations: HashMap<String, u64> = management.call("Generations", &all).await.unwrap();
+        assert!(generations.contains_key("org.freedesktop.appearance") && !generations.contains_key("com.example"));
+        let (_, modified, settings): (u64, bool, HashMap<String, HashMap<String, OwnedValue>>) =
+            management.call("ReadAllIfChanged", &(Vec::<String>::new(), 0u64)).await.unwrap();
+        assert!(modified && settings.contains_key("org.freedesktop.appearance") && !settings.contains_key("com.example"));
+        let (_, _, changes): (u64, bool, Vec<management::RecentChange>) = management.call("GetRecentChanges", &(0u64,)).await.unwrap();
+        let namespaces: Vec<_> = changes.iter().map(|(_, namespace, _, _)| namespace.as_str()).collect();
+        assert_eq!(namespaces, ["org.freedesktop.appearance"]);
+        let error = management.call::<_, _, HashMap<String, OwnedValue>>("DescribeKey", &("com.example", "secret")).await;
+        let Err(zbus::Error::MethodError(name, ..)) = error else { panic!("{:?}", error) };
+        assert_eq!(name.as_str(), "org.freedesktop.DBus.Error.InvalidArgs");
+        let _: HashMap<String, OwnedValue> = management.call("DescribeKey", &("org.freedesktop.appearance", "contrast")).await.unwrap();
+        let sensitive: Vec<String> = management.call("SensitiveKeys", &()).await.unwrap();
+        assert_eq!(sensitive, ["org.freedesktop.appearance/accent-color"]);
+        let error = management.call::<_, _, ()>("ValidateWrite", &("com.example", "secret", Value::from(2i32))).await;
+        let Err(zbus::Error::MethodError(name, ..)) = error else { panic!("{:?}", error) };
+        assert_eq!(name.as_str(), "org.freedesktop.DBus.Error.AccessDenied");
+    }
+
+    #[cfg(feature = "management")]
+    #[tokio::test]
+    async fn test_read_only_management() {
+        use crate::testing::{TestPortal, PATH};
+
+        let portal = TestPortal::p2p(SettingsStore::new()).await.unwrap();
+        let server = portal.server.object_server();
+        server.remove::<SettingsManagement, _>(PATH).await.unwrap();
+        server.at(PATH, SettingsManagement::new(portal.store.clone()).with_read_only(true)).await.unwrap();
+        let management = zbus::Proxy::new(&portal.client, config::DEFAULT_BUS_NAME, PATH, MANAGEMENT_INTERFACE).await.unwrap();
+        let (namespace, key) = ("org.freedesktop.appearance", "contrast");
+        let value = Value::from(1u32);
+        let many = HashMap::from([(namespace, HashMap::from([(key, Value::from(1u32))]))]);
+
+        let denied = |result: zbus::Result<()>| match result {
+            Err(zbus::Error::MethodError(name, ..)) => name.as_str() == "org.freedesktop.DBus.Error.AccessDenied",
+            _ => false,
+        };
+        assert!(denied(management.call("Write", &(namespace, key, &value)).await));
+        let options = HashMap::<String, Value>::new();
+        assert!(denied(management.call("WriteWithOptions", &(namespace, key, &value, options)).await));
+        assert!(denied(management.call("WriteMany", &(many,)).await));
+        assert!(denied(management.call::<_, _, bool>("Delete", &(namespace, key)).await.map(|_| ())));
+        assert!(denied(management.call::<_, _, bool>("Reset", &(namespace, key)).await.map(|_| ())));
+        assert_eq!(*portal.store.read(namespace, key).await.unwrap().0, OwnedValue::from(0u32));
+
+        // Reads are unaffected
+        let layers: HashMap<String, HashMap<String, String>> = management.call("Layers", &(vec![namespace],)).await.unwrap();
+        assert_eq!(layers[namespace][key], "builtin");
+        let settings = zbus::Proxy::new(&portal.client, config::DEFAULT_BUS_NAME, PATH, "org.freedesktop.impl.portal.Settings")
+            .await
+            .unwrap();
+        let _: OwnedValue = settings.call("Read", &(namespace, key)).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_denied_namespaces() {
+        use crate::testing::{TestPortal, PATH};
+
+        let config = Config::parse("denied_namespaces = [\"com.example.*\"]").unwrap();
+        let store = SettingsStore::from_config(&config).unwrap();
+        store.write("com.example.Proxy", "password", OwnedValue::try_from(Value::from("hunter2")).unwrap()).await.unwrap();
+        assert!(store.is_denied("com.example.Proxy", "password"));
+        assert!(!store.is_denied("org.freedesktop.appearance", "color-scheme"));
+        let portal = TestPortal::p2p(store).await.unwrap();
+        let settings = zbus::Proxy::new(&portal.client, config::DEFAULT_BUS_NAME, PATH, "org.freedesktop.impl.portal.Settings")
+            .await
+            .unwrap();
+        let error = settings.call::<_, _, OwnedValue>("Read", &("com.example.Proxy", "password")).await;
+        let Err(zbus::Error::MethodError(name, ..)) = error else { panic!("{:?}", error) };
+        assert_eq!(name.as_str(), "org.freedesktop.portal.Error.NotFound");
+        let reply: HashMap<String, HashMap<String, OwnedValue>> = settings.call("ReadAll", &(vec!["com.example.*"],)).await.unwrap();
+        assert!(reply.is_empty());
+        // Denied at the interfaces, not in the store
+        assert!(portal.store.read("com.example.Proxy", "password").await.is_some());
+    }
+
+    #[cfg(feature = "management")]
+    #[tokio::test]
+    async fn test_management_writers() {
+        use crate::testing::TestBus;
+
+        // Writers are told apart by the names they own on a bus
+        if !TestBus::available() {
+            return;
+        }
+        let bus = TestBus::start().unwrap();
+        let store = SettingsStore::new();
+        let writers = Config::parse("[management]\nallow_names = [\"com.example.Provisioning\"]").unwrap().management;
+        let _server = zbus::connection::Builder::address(bus.address())
+            .unwrap()
+            .serve_at(MANAGEMENT_PATH, SettingsManagement::new(store.clone()).with_writers(writers))
+            .unwrap()
+            .name(config::DEFAULT_BUS_NAME)
+            .unwrap()
+            .build()
+            .await
+            .unwrap();
+        let client = bus.connect().await.unwrap();
+        let management = zbus::Proxy::new(&client, config::DEFAULT_BUS_NAME, MANAGEMENT_PATH, MANAGEMENT_INTERFACE)
+            .await
+            .unwrap();
+        let write = || async { management.call::<_, _, ()>("Write", &("com.example", "a", Value::from(1i32))).await };
+        let error = write().await;
+        let Err(zbus::Error::MethodError(name, ..)) = error else { panic!("{:?}", error) };
+        assert_eq!(name.as_str(), "org.freedesktop.DBus.Error.AccessDenied");
+        assert!(store.read("com.example", "a").await.is_none());
+
+        client.request_name("com.example.Provisioning").await.unwrap();
+        write().await.unwrap();
+        assert!(store.read("com.example", "a").await.is_some());
+    }
+
+    #[tokio::test]
+    async fn test_read_all_if_changed() {
+        let store = SettingsStore::new();
+        let appearance = || vec!["org.freedesktop.appearance".to_string()];
+        let (generation, reply) = store.read_all_if_changed(appearance(), 0).await;
+        assert_eq!(reply.unwrap().len(), 1);
+        assert!(store.read_all_if_changed(appearance(), generation).await.1.is_none());
+
+        // Only changes to the namespaces asked for count
+        store.write("com.example", "a", OwnedValue::from(1i32)).await.unwrap();
+        let (unfiltered, reply) = store.read_all_if_changed(vec![], generation).await;
+        assert!(reply.is_some());
+        assert!(store.read_all_if_changed(appearance(), generation).await.1.is_none());
+        assert!(store.delete("com.example", "a").await);
+        assert!(store.read_all_if_changed(vec!["com.example".to_string()], unfiltered).await.1.is_some());
+    }
+
+    #[tokio::test]
+    async fn test_string_limits() {
+        let store = SettingsStore::new().with_limits(LimitsConfig {
+            max_string_length: 8,
+            max_name_length: 16,
+            ..LimitsConfig::default()
+        });
+        let text = |s: &str| OwnedValue::try_from(Value::from(s.to_string())).unwrap();
+        store.write("com.example", "motd", text("line\r\n\tx")).await.unwrap();
+        let error = store.write("com.example", "motd", text("123456789")).await.unwrap_err();
+        assert_eq!(error.to_string(), "string of 9 bytes exceeds the maximum of 8");
+        // Strings nested in containers count too
+        let list = OwnedValue::try_from(Value::from(vec!["short", "far too long"])).unwrap();
+        assert!(store.write("com.example", "list", list).await.is_err());
+
+        for bad in ["a\0b", "bell\x07", "esc\x1b[0m", "c1\u{9b}"] {
+            let error = store.write("com.example", "motd", text(bad)).await.unwrap_err();
+            assert!(error.to_string().contains("control characters"), "{:?}: {}", bad, error);
+        }
+        assert!(store.write("com.example", "key\n", text("x")).await.is_err());
+        assert!(store.write("com.example", "a-key-longer-than-16", text("x")).await.is_err());
+        assert!(store.write("com.example.LongerThan16", "key", text("x")).await.is_err());
+
+        let store = SettingsStore::new().with_limits(LimitsConfig::unlimited());
+        store.write("com.example", "motd", text(&"x".repeat(100_000))).await.unwrap();
+        assert!(store.write("com.example", "motd", text("a\0b")).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_strict_mode_rejects_unknown_keys() {
+        let limits = |strict, namespaces: &[&str]| LimitsConfig {
+            strict,
+            strict_namespaces: namespaces.iter().map(|n| n.to_string()).collect(),
+            ..LimitsConfig::default()
+        };
+        let value = || OwnedValue::from(1u32);
+
+        let store = SettingsStore::new().with_limits(limits(true, &[]));
+        store.write("org.freedesktop.appearance", "color-scheme", value()).await.unwrap();
+        let error = store.write("org.freedesktop.appearance", "typo", value()).await.unwrap_err();
+        assert!(error.to_string().contains("unknown setting org.freedesktop.appearance/typo"));
+        assert!(store.write("com.example", "key", value()).await.is_err());
+
+        let store = SettingsStore::new().with_limits(limits(false, &["org.freedesktop.appearance"]));
+        assert!(store.write("org.freedesktop.appearance", "typo", value()).await.is_err());
+        store.write("com.example", "key", value()).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_types_of_existing_keys_are_sticky() {
+        let store = SettingsStore::new();
+        let text = |s: &'static str| OwnedValue::try_from(Value::from(s)).unwrap();
+        store.write("com.example", "theme", text("dark")).await.unwrap();
+        let error = store.write("com.example", "theme", OwnedValue::from(1u32)).await.unwrap_err();
+        let invalid = error.find::<ValidationError>().unwrap();
+        assert_eq!((invalid.expected.as_str(), invalid.received.as_str()), ("s", "u"));
+        assert!(store.validate("com.example", "theme", &OwnedValue::from(1u32)).await.is_err());
+        let batch = vec![("com.example".to_string(), "theme".to_string(), OwnedValue::from(true))];
+        assert!(store.write_many_from(ChangeSource::Api, batch).await.is_err());
+        assert_eq!(*store.read("com.example", "theme").await.unwrap().0, text("dark"));
+
+        // Migrating changes the type, and so does writing after a delete
+        store.validate_migration("com.example", "theme", &OwnedValue::from(1u32)).await.unwrap();
+        store.migrate_from(ChangeSource::Api, "com.example", "theme", OwnedValue::from(1u32)).await.unwrap();
+        assert_eq!(*store.read("com.example", "theme").await.unwrap().0, OwnedValue::from(1u32));
+        assert!(store.delete("com.example", "theme").await);
+        store.write("com.example", "theme", text("light")).await.unwrap();
+        // Migrations still meet the constraints of the setting
+        let error = store
+            .migrate_from(ChangeSource::Api, "org.freedesktop.appearance", "color-scheme", text("dark"))
+            .await
+            .unwrap_err();
+        assert_eq!(error.find::<ValidationError>().unwrap().constraint.as_deref(), Some("0..=2"));
+    }
+
+    #[tokio::test]
+    async fn test_namespaces_must_be_reverse_dns() {
+        let value = || OwnedValue::from(1u32);
+        let store = SettingsStore::new();
+        for namespace in ["com.example", "org.gnome.desktop.a11y", "io.github.meta_flutter.App-1"] {
+            store.write(namespace, "key", value()).await.unwrap();
+        }
+        for namespace in ["appearance", "org..example", ".org.example", "org.example.", "org.2example", "org.ex ample"] {
+            let error = store.write(namespace, "key", value()).await.unwrap_err();
+            assert!(error.to_string().contains("invalid namespace"), "{}", namespace);
+        }
+        assert!(store.write(&format!("org.{}", "a".repeat(252)), "key", value()).await.is_err());
+
+        // Namespaces with defaults are accepted as configured
+        let config = Config::parse("[settings.kiosk]\nkey = 1\n").unwrap();
+        let store = SettingsStore::from_config(&config).unwrap();
+        store.write("kiosk", "key", OwnedValue::from(2i32)).await.unwrap();
+        assert!(store.write("other", "key", value()).await.is_err());
+
+        let config = Config::parse("[limits]\nallow_any_namespace = true\n").unwrap();
+        let store = SettingsStore::from_config(&config).unwrap();
+        store.write("appearance", "key", value()).await.unwrap();
+    }
+
+    #[test]
+    fn test_keys_share_their_namespace() {
+        let store = SettingsStore::new();
+        let appearance = "org.freedesktop.appearance";
+        let keys: Vec<&SettingKey> = store.defaults.keys().filter(|key| &*key.namespace == appearance).collect();
+        assert!(keys.len() > 1);
+        assert!(keys.windows(2).all(|pair| Arc::ptr_eq(&pair[0].namespace, &pair[1].namespace)));
+
+        // The snapshot served uses the same strings
+        let settings = store.settings.load();
+        let (name, namespace) = settings.namespaces().find(|(name, _)| &***name == appearance).unwrap();
+        assert!(Arc::ptr_eq(name, &keys[0].namespace));
+        let (key, _) = namespace.keys.get_key_value(&*keys[0].key).unwrap();
+        assert!(Arc::ptr_eq(key, &keys[0].key));
+    }
+
+    #[tokio::test]
+    async fn test_validators_run_before_locking() {
+        /// Holds writes of `slow` until released and rejects `forbidden`
+        struct Gate(tokio::sync::Notify);
+
+        impl Validator for Gate {
+            fn name(&self) -> &str {
+                "gate"
+            }
+
+            fn validate<'a>(
+                &'a self,
+                _namespace: &'a str,
+                key: &'a str,
+                _value: &'a OwnedValue,
+            ) -> futures_util::future::BoxFuture<'a, Result<()>> {
+                Box::pin(async move {
+                    match key {
+                        "slow" => self.0.notified().await,
+                        "forbidden" => bail!("not allowed"),
+                        _ => {}
+                    }
+                    Ok(())
+                })
+            }
+        }
+
+        let gate = Arc::new(Gate(tokio::sync::Notify::new()));
+        let store = SettingsStore::new().with_validator(gate.clone());
+        let slow = tokio::spawn({
+            let store = store.clone();
+            async move { store.write("com.example", "slow", OwnedValue::from(1i32)).await }
+        });
+        tokio::task::yield_now().await;
+
+        // Writes go through while another one is being validated
+        store.write("com.example", "fast", OwnedValue::from(1i32)).await.unwrap();
+        assert!(store.read("com.example", "slow").await.is_none());
+        gate.0.notify_one();
+        slow.await.unwrap().unwrap();
+        assert!(store.read("com.example", "slow").await.is_some());
+
+        let error = store.write("com.example", "forbidden", OwnedValue::from(1i32)).await.unwrap_err();
+        assert_eq!(format!("{:#}", error), "rejected by gate: not allowed");
+    }
+
+    #[tokio::test]
+    async fn test_key_validators() {
+        /// Accepts only themes in its list
+        struct Themes(&'static [&'static str]);
+
+        impl Validator for Themes {
+            fn name(&self) -> &str {
+                "themes"
+            }
+
+            fn validate<'a>(
+                &'a self,
+                _namespace: &'a str,
+                _key: &'a str,
+                value: &'a OwnedValue,
+            ) -> futures_util::future::BoxFuture<'a, Result<()>> {
+                Box::pin(async move {
+                    match <&str>::try_from(value) {
+                        Ok(theme) if self.0.contains(&theme) => Ok(()),
+                        _ => bail!("no such theme"),
+                    }
+                })
+            }
+        }
+
+        let store = SettingsStore::new();
+        let theme = |name: &'static str| OwnedValue::try_from(Value::from(name)).unwrap();
+        store.write("com.example", "theme", theme("Missing")).await.unwrap();
+
+        store.clone().register_validator("com.example", "theme", Box::new(Themes(&["Adwaita", "HighContrast"])));
+        store.write("com.example", "theme", theme("Adwaita")).await.unwrap();
+        let error = store.write("com.example", "theme", theme("Missing")).await.unwrap_err();
+        assert_eq!(format!("{:#}", error), "rejected by themes: no such theme");
+        // Other keys are not affected
+        store.write("com.example", "cursor", theme("Missing")).await.unwrap();
+
+        store.register_validator("com.example", "theme", Box::new(Themes(&["Adwaita"])));
+        assert!(store.write("com.example", "theme", theme("HighContrast")).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_unchanged_writes_are_not_recorded() {
+        let value = |n: i32| OwnedValue::from(n);
+        let many = |n: i32| {
+            vec![
+                ("com.example".to_string(), "a".to_string(), value(n)),
+                ("com.example".to_string(), "b".to_string(), value(2)),
+            ]
+        };
+        let store = SettingsStore::new();
+        store.write("com.example", "a", value(1)).await.unwrap();
+        store.write("com.example", "b", value(2)).await.unwrap();
+        let seen = store.recent_changes(0).await.generation;
+
+        assert!(!store.write_from(ChangeSource::Api, "com.example", "a", value(1)).await.unwrap());
+        assert!(store.write_many_from(ChangeSource::Api, many(1)).await.unwrap().is_empty());
+        assert_eq!(store.recent_changes(0).await.generation, seen);
+        // Only the settings that change are recorded
+        let changed = store.write_many_from(ChangeSource::Api, many(3)).await.unwrap();
+        assert_eq!(changed, [SettingKey::new("com.example", "a")]);
+        let recent = store.recent_changes(seen).await;
+        let keys: Vec<_> = recent.changes.iter().map(|(_, c)| c.key.as_str()).collect();
+        assert_eq!(keys, ["a"]);
+
+        let store = SettingsStore::new().with_record_unchanged(true);
+        store.write("com.example", "a", value(1)).await.unwrap();
+        let seen = store.recent_changes(0).await.generation;
+        store.write("com.example", "a", value(1)).await.unwrap();
+        assert_eq!(store.recent_changes(0).await.generation, seen + 1);
+    }
+
+    #[cfg(feature = "management")]
+    #[tokio::test]
+    async fn test_unchanged_writes_are_not_signalled() {
+        use crate::testing::{TestPortal, PATH};
+        use futures_util::StreamExt;
+        use zbus::{MatchRule, MessageStream};
+
+        // Without an emitter on the store, the management interface signals
+        let portal = TestPortal::p2p(SettingsStore::new()).await.unwrap();
+        let rule = MatchRule::builder()
+            .msg_type(zbus::message::Type::Signal)
+            .interface("org.freedesktop.impl.portal.Settings")
+            .unwrap()
+            .member("SettingChanged")
+            .unwrap()
+            .build();
+        let mut signals = MessageStream::for_match_rule(rule, &portal.client, None).await.unwrap();
+        let management = zbus::Proxy::new(&portal.client, config::DEFAULT_BUS_NAME, PATH, MANAGEMENT_INTERFACE)
+            .await
+            .unwrap();
+        for _ in 0..2 {
+            let () = management.call("Write", &("com.example", "a", Value::from(1i32))).await.unwrap();
+        }
+        let batch = HashMap::from([("com.example", HashMap::from([("a", Value::from(1i32)), ("b", Value::from(2i32))]))]);
+        let () = management.call("WriteMany", &(batch,)).await.unwrap();
+
+        let mut keys = Vec::new();
+        while keys.len() < 2 {
+            let signal = signals.next().await.unwrap().unwrap();
+            let (_, key, _): (String, String, OwnedValue) = signal.body().deserialize().unwrap();
+            keys.push(key);
+        }
+        assert_eq!(keys, ["a", "b"]);
+        let generation = portal.store.recent_changes(0).await.generation;
+        let () = management.call("Write", &("com.example", "b", Value::from(2i32))).await.unwrap();
+        let () = management.call("Write", &("com.example", "c", Value::from(3i32))).await.unwrap();
+        let signal = signals.next().await.unwrap().unwrap();
+        let (_, key, _): (String, String, OwnedValue) = signal.body().deserialize().unwrap();
+        assert_eq!(key, "c");
+        assert_eq!(portal.store.recent_changes(0).await.generation, generation + 1);
+    }
+
+    #[tokio::test]
+    async fn test_recent_changes_follow_generations() {
+        let store = SettingsStore::new().with_journal_size(2);
+        store.write("com.example", "a", OwnedValue::from(1i32)).await.unwrap();
+        let seen = store.recent_changes(0).await.generation;
+        let values = vec![
+            ("com.example".to_string(), "b".to_string(), OwnedValue::from(2i32)),
+            ("com.example".to_string(), "c".to_string(), OwnedValue::from(3i32)),
+        ];
+        store.write_many_from(ChangeSource::Api, values).await.unwrap();
+
+        let recent = store.recent_changes(seen).await;
+        assert!(recent.complete);
+        assert_eq!(recent.generation, store.generations(vec![]).await["com.example"]);
+        let keys: Vec<_> = recent.changes.iter().map(|(g, c)| (*g, c.key.as_str())).collect();
+        assert_eq!(keys, [(seen + 1, "b"), (seen + 2, "c")]);
+
+        assert!(store.delete("com.example", "b").await);
+        let recent = store.recent_changes(seen).await;
+        assert!(!recent.complete);
+        assert_eq!(recent.changes.last().map(|(_, c)| c.new.is_none()), Some(true));
+    }
+
+    #[tokio::test]
+    async fn test_layers() {
+        let config = Config::parse(
+            r#"
+            [settings."org.freedesktop.appearance"]
+            color-scheme = 1
+            "#,
+        )
+        .unwrap();
+        let store = SettingsStore::from_config(&config).unwrap();
+        store
+            .write("org.freedesktop.appearance", "contrast", Value::U32(1).try_into().unwrap())
+            .await
+            .unwrap();
+        store
+            .write("org.example", "custom", Value::U32(1).try_into().unwrap())
+            .await
+            .unwrap();
+
+        let layers = store.layers(vec!["org.freedesktop.appearance".to_string(), "org.example".to_string()]).await;
+        let appearance = &layers["org.freedesktop.appearance"];
+        assert_eq!(appearance["color-scheme"], Layer::Config);
+        assert_eq!(appearance["contrast"], Layer::Runtime);
+        assert_eq!(appearance["accent-color"], Layer::Builtin);
+        assert_eq!(layers["org.example"]["custom"], Layer::Runtime);
+        assert_eq!(layers.len(), 2);
+
+        // Writing the default value back counts as the default again
+        store.reset("org.freedesktop.appearance", "contrast").await;
+        assert_eq!(store.layers(vec![]).await["org.freedesktop.appearance"]["contrast"], Layer::Builtin);
+    }
+
+    #[tokio::test]
+    async fn test_disabled_namespaces() {
+        let config = Config::parse(r#"disabled_namespaces = ["org.gnome.desktop.privacy"]"#).unwrap();
+        let store = SettingsStore::from_config(&config).unwrap();
+
+        let all = store.read_all(vec![]).await;
+        assert!(!all.contains_key("org.gnome.desktop.privacy"));
+        assert!(all.contains_key("org.freedesktop.appearance"));
+        assert!(store
+            .read("org.gnome.desktop.privacy", "remember-recent-files")
+            .await
+            .is_none());
+
+        let value: OwnedValue = Value::Bool(false).try_into().unwrap();
+        assert!(store
+            .write("org.gnome.desktop.privacy", "remember-recent-files", value)
+            .await
+            .is_err());
+        assert!(!store.reset("org.gnome.desktop.privacy", "remember-recent-files").await);
+
+        let configured = Config::parse(
+            r#"
+            disabled_namespaces = ["org.gnome.desktop.privacy"]
+
+            [settings."org.gnome.desktop.privacy"]
+            remember-recent-files = false
+            "#,
+        )
+        .unwrap();
+        assert!(SettingsStore::from_config(&configured).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_validate_does_not_mutate() {
+        let store = SettingsStore::new();
+        let value: OwnedValue = Value::U32(2).try_into().unwrap();
+        assert!(store.validate("org.freedesktop.appearance", "color-scheme", &value).await.is_ok());
+
+        let invalid: OwnedValue = Value::U32(3).try_into().unwrap();
+        assert!(store.validate("org.freedesktop.appearance", "color-scheme", &invalid).await.is_err());
+
+        let current: u32 = store
+            .read("org.freedesktop.appearance", "color-scheme")
+            .await
+            .unwrap()
+            .0
+            .as_ref()
+            .try_into()
+            .unwrap();
+        assert_eq!(current, 0);
     }
 }
@@ -0,0 +1,168 @@
+use crate::{Caller, ChangeObserver, ChangeSource, SettingChange, SettingKey, SettingValue, SettingsStore};
+use futures_util::StreamExt;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use zbus::zvariant::OwnedObjectPath;
+use zbus::{Connection, Proxy};
+
+const LOGIND_NAME: &str = "org.freedesktop.login1";
+const LOGIND_PATH: &str = "/org/freedesktop/login1";
+const LOGIND_MANAGER: &str = "org.freedesktop.login1.Manager";
+
+/// A value written for the length of a login session
+struct Override {
+    /// Object path of the session in logind
+    session: String,
+    /// The value of the setting before the override, if it had one
+    before: Option<SettingValue>,
+}
+
+/// Values written for the length of the writer's logind session, restored
+/// when the session ends
+///
+/// Each overridden setting keeps a stack of overrides, the latest on top.
+/// When a session ends, the settings it holds the top override of get the
+/// value they had before it; the overrides of sessions ended earlier are
+/// skipped. A change made otherwise, such as a plain `Write`, replaces every
+/// override of the setting for good. Overrides are changed with
+/// `ChangeSource::Session`, which the state file does not persist, so a
+/// restart also drops them. Clones share the same overrides.
+#[derive(Clone, Default)]
+pub struct SessionOverrides {
+    keys: Arc<Mutex<HashMap<SettingKey, Vec<Override>>>>,
+}
+
+/// The logind manager on the system bus of `system`
+async fn manager(system: &Connection) -> zbus::Result<Proxy<'static>> {
+    Proxy::new(system, LOGIND_NAME, LOGIND_PATH, LOGIND_MANAGER).await
+}
+
+impl SessionOverrides {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Object path of the login session `caller` runs in, asking logind on
+    /// the system bus of `system`
+    pub async fn session_of(system: &Connection, caller: &Caller) -> zbus::Result<String> {
+        let Some(pid) = caller.pid else {
+            return Err(zbus::Error::Failure("the process of the caller is unknown".to_string()));
+        };
+        let session: OwnedObjectPath = manager(system).await?.call("GetSessionByPID", &(pid,)).await?;
+        Ok(session.to_string())
+    }
+
+    /// Record that `session` overrode `key`, which had the value `before`
+    pub fn insert(&self, session: &str, key: SettingKey, before: Option<SettingValue>) {
+        let session = session.to_string();
+        self.keys.lock().unwrap().entry(key).or_default().push(Override { session, before });
+    }
+
+    /// Forget the overrides of `session`, returning the values to restore
+    fn end(&self, session: &str) -> Vec<(SettingKey, Option<SettingValue>)> {
+        let mut restore = Vec::new();
+        self.keys.lock().unwrap().retain(|key, overrides| {
+            while let Some(i) = overrides.iter().position(|o| o.session == session) {
+                let ended = overrides.remove(i);
+                match overrides.get_mut(i) {
+                    // The override above it now returns to what preceded both
+                    Some(above) => above.before = ended.before,
+                    None => restore.push((key.clone(), ended.before)),
+                }
+            }
+            !overrides.is_empty()
+        });
+        restore
+    }
+
+    /// Restore what each setting `session` overrode was before it
+    pub async fn end_session(&self, session: &str, store: &SettingsStore) {
+        for (key, before) in self.end(session) {
+            match before {
+                Some(value) => {
+                    let restored = match value.to_owned_value() {
+                        Ok(value) => store.migrate_from(ChangeSource::Session, &key.namespace, &key.key, value).await,
+                        Err(e) => Err(e.into()),
+                    };
+                    if let Err(e) = restored {
+                        eprintln!("Cannot restore {}/{} after session {}: {:#}", key.namespace, key.key, session, e);
+                    }
+                }
+                None => {
+                    store.delete_from(ChangeSource::Session, &key.namespace, &key.key).await;
+                }
+            }
+        }
+    }
+
+    /// Restore the settings overridden by each login session as logind on
+    /// the system bus of `system` reports its end, until the connection
+    /// closes
+    pub async fn run(self, system: Connection, store: SettingsStore) -> zbus::Result<()> {
+        let mut removed = manager(&system).await?.receive_signal("SessionRemoved").await?;
+        while let Some(message) = removed.next().await {
+            let Ok((_, session)) = message.body().deserialize::<(String, OwnedObjectPath)>() else {
+                continue;
+            };
+            self.end_session(session.as_str(), &store).await;
+        }
+        Ok(())
+    }
+}
+
+impl ChangeObserver for SessionOverrides {
+    fn on_change(&self, change: &SettingChange) {
+        // Changes made otherwise become the value to keep
+        if change.source == ChangeSource::Session {
+            return;
+        }
+        self.keys
+            .lock()
+            .unwrap()
+            .remove(&SettingKey::new(&change.namespace, &change.key));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use zbus::zvariant::OwnedValue;
+
+    #[tokio::test]
+    async fn test_overrides_are_restored() {
+        let store = SettingsStore::new();
+        let overrides = SessionOverrides::new();
+        store.add_observer(Arc::new(overrides.clone()));
+        let scheme = SettingKey::new("org.freedesktop.appearance", "color-scheme");
+        let read = || async {
+            let value: u32 = store.read(&scheme.namespace, &scheme.key).await.unwrap().0.as_ref().try_into().unwrap();
+            value
+        };
+
+        // Two sessions override the setting, the first one ends first
+        let before = store.read(&scheme.namespace, &scheme.key).await;
+        store.write_from(ChangeSource::Session, &scheme.namespace, &scheme.key, OwnedValue::from(1u32)).await.unwrap();
+        overrides.insert("/s/1", scheme.clone(), before);
+        let before = store.read(&scheme.namespace, &scheme.key).await;
+        store.write_from(ChangeSource::Session, &scheme.namespace, &scheme.key, OwnedValue::from(2u32)).await.unwrap();
+        overrides.insert("/s/2", scheme.clone(), before);
+        overrides.end_session("/s/1", &store).await;
+        assert_eq!(read().await, 2);
+        overrides.end_session("/s/2", &store).await;
+        assert_eq!(read().await, 0);
+
+        // A plain write is kept
+        let before = store.read(&scheme.namespace, &scheme.key).await;
+        store.write_from(ChangeSource::Session, &scheme.namespace, &scheme.key, OwnedValue::from(1u32)).await.unwrap();
+        overrides.insert("/s/3", scheme.clone(), before);
+        store.write(&scheme.namespace, &scheme.key, OwnedValue::from(2u32)).await.unwrap();
+        overrides.end_session("/s/3", &store).await;
+        assert_eq!(read().await, 2);
+
+        // Keys created by a session go away with it
+        store.write_from(ChangeSource::Session, "com.example", "a", OwnedValue::from(1i32)).await.unwrap();
+        overrides.insert("/s/4", SettingKey::new("com.example", "a"), None);
+        overrides.end_session("/s/4", &store).await;
+        assert!(store.read("com.example", "a").await.is_none());
+    }
+}
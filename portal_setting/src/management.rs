@@ -0,0 +1,784 @@
+use crate::config::ManagementConfig;
+use crate::{
+    emit_setting_changed, emit_values, Caller, ChangeKind, ChangeSource, ReadOnlyError, RefusedChange, Scope, SenderKeys,
+    SessionOverrides, SettingKey, SettingsReply, SettingsStore, SignalEmitter, ValidationError,
+};
+use std::collections::HashMap;
+use std::future::Future;
+use zbus::fdo::DBusProxy;
+use zbus::message::Header;
+use zbus::names::BusName;
+use zbus::object_server::SignalContext;
+use zbus::zvariant::OwnedValue;
+use zbus::{fdo, interface, Connection, DBusError};
+
+/// A change reported by `GetRecentChanges`: generation, namespace, key, and
+/// the new value unless the key was removed
+pub(crate) type RecentChange = (u64, String, String, Vec<OwnedValue>);
+
+/// Name of the management interface, served next to the portal interface
+pub const MANAGEMENT_INTERFACE: &str = "io.github.meta_flutter.PortalSettings.Management";
+
+/// Object path of the management interface when only some callers may use
+/// it, apart from the portal interfaces every application calls
+pub const MANAGEMENT_PATH: &str = "/io/github/meta_flutter/PortalSettings";
+
+/// The options of `WriteWithOptions`
+struct WriteOptions {
+    scope: Scope,
+    /// Whether the value may have another type than the current one
+    migrate: bool,
+    /// The login session the value is written for, if only for one
+    login_session: Option<String>,
+}
+
+impl Default for WriteOptions {
+    fn default() -> Self {
+        Self {
+            scope: Scope::Persistent,
+            migrate: false,
+            login_session: None,
+        }
+    }
+}
+
+/// D-Bus interface for changing settings
+///
+/// The portal interface itself is read-only; administration tools use this
+/// interface to modify the store. Successful changes emit `SettingChanged` on
+/// the portal interfaces exported at the same object path: through the
+/// store when it emits them itself, through the emitter when one is set, and
+/// before returning otherwise.
+pub struct SettingsManagement {
+    store: SettingsStore,
+    dry_run: bool,
+    read_only: bool,
+    writers: ManagementConfig,
+    senders: SenderKeys,
+    /// The system bus, to ask logind about sessions, and their overrides
+    sessions: Option<(Connection, SessionOverrides)>,
+    emitter: Option<SignalEmitter>,
+}
+
+impl SettingsManagement {
+    pub fn new(store: SettingsStore) -> Self {
+        Self {
+            store,
+            dry_run: false,
+            read_only: false,
+            writers: ManagementConfig::default(),
+            senders: SenderKeys::new(),
+            sessions: None,
+            emitter: None,
+        }
+    }
+
+    /// Queue signals on `emitter` instead of emitting them before replying
+    pub fn with_emitter(mut self, emitter: SignalEmitter) -> Self {
+        self.emitter = Some(emitter);
+        self
+    }
+
+    /// Count created keys in `senders`, shared with earlier exports and with
+    /// `SenderKeys::run`
+    pub fn with_senders(mut self, senders: SenderKeys) -> Self {
+        self.senders = senders;
+        self
+    }
+
+    /// Accept writes for the length of the caller's login session, asking
+    /// logind on the system bus of `system` which session that is
+    ///
+    /// `overrides` should observe the store and `SessionOverrides::run`,
+    /// so the values are restored when the session ends.
+    pub fn with_login_sessions(mut self, system: Connection, overrides: SessionOverrides) -> Self {
+        self.sessions = Some((system, overrides));
+        self
+    }
+
+    /// Validate mutations and report success without applying them
+    pub fn with_dry_run(mut self, dry_run: bool) -> Self {
+        self.dry_run = dry_run;
+        self
+    }
+
+    /// Refuse every change with `AccessDenied`, leaving the other methods
+    /// working
+    pub fn with_read_only(mut self, read_only: bool) -> Self {
+        self.read_only = read_only;
+        self
+    }
+
+    /// Answer only the callers `writers` allows, failing every other call
+    /// with `AccessDenied`
+    ///
+    /// Export the interface at `MANAGEMENT_PATH` then, with signals queued on
+    /// the store's emitter: it emits `SettingChanged` at the path of the
+    /// portal interfaces.
+    pub fn with_writers(mut self, writers: ManagementConfig) -> Self {
+        self.writers = writers;
+        self
+    }
+
+    pub fn store(&self) -> &SettingsStore {
+        &self.store
+    }
+
+    /// The keys among `keys` that `sender` would create, failing if they
+    /// exceed its quota
+    async fn check_quota(
+        &self,
+        store: &SettingsStore,
+        sender: Option<&str>,
+        keys: &[(&str, &str)],
+    ) -> fdo::Result<Vec<SettingKey>> {
+        let mut created = Vec::new();
+        for (namespace, key) in keys {
+            if !store.is_registered(namespace) && store.read(namespace, key).await.is_none() {
+                created.push(SettingKey::new(*namespace, *key));
+            }
+        }
+        let quota = store.limits().max_keys_per_sender;
+        if let Some(sender) = sender {
+            if quota > 0 && self.senders.count(sender) + created.len() > quota {
+                return Err(fdo::Error::LimitsExceeded(format!(
+                    "{} may create at most {} keys in namespaces without defaults",
+                    sender, quota
+                )));
+            }
+        }
+        Ok(created)
+    }
+
+    /// Fail unless the sender of `header` owns one of the allowed names or
+    /// runs as one of the allowed users
+    ///
+    /// Peer-to-peer callers cannot be told apart, so they are refused too.
+    async fn admit(&self, connection: &Connection, header: &Header<'_>) -> fdo::Result<()> {
+        if !self.writers.is_restricted() {
+            return Ok(());
+        }
+        let Some(sender) = header.sender() else {
+            return Err(fdo::Error::AccessDenied("unknown callers may not manage settings".to_string()));
+        };
+        let dbus = DBusProxy::new(connection).await?;
+        for name in &self.writers.allow_names {
+            if let Ok(owner) = dbus.get_name_owner(BusName::from(name.inner().clone())).await {
+                if owner.as_str() == sender.as_str() {
+                    return Ok(());
+                }
+            }
+        }
+        if !self.writers.allow_uids.is_empty() {
+            let caller = Caller::of(connection, header).await;
+            if caller.uid.is_some_and(|uid| self.writers.allow_uids.contains(&uid)) {
+                return Ok(());
+            }
+        }
+        Err(fdo::Error::AccessDenied(format!("{} may not manage settings", sender)))
+    }
+
+    /// The logind session of `caller`, for a write lasting as long
+    async fn login_session(&self, caller: &Caller) -> fdo::Result<String> {
+        let Some((system, _)) = &self.sessions else {
+            return Err(fdo::Error::NotSupported("login sessions are not tracked".to_string()));
+        };
+        if self.store.user_stores().is_some() {
+            return Err(fdo::Error::NotSupported(
+                "login-session writes are not supported with a store per user".to_string(),
+            ));
+        }
+        SessionOverrides::session_of(system, caller)
+            .await
+            .map_err(|e| fdo::Error::Failed(format!("cannot find the login session of {}: {}", caller, e)))
+    }
+
+    /// Fail if any of `keys` is in a denied namespace
+    fn check_denied(&self, keys: &[(&str, &str)]) -> fdo::Result<()> {
+        match keys.iter().find(|(namespace, key)| self.store.is_denied(namespace, key)) {
+            Some((namespace, key)) => Err(fdo::Error::AccessDenied(format!(
+                "{}/{} is in a namespace this service does not serve",
+                namespace, key
+            ))),
+            None => Ok(()),
+        }
+    }
+
+    /// The sender of `header` and the store serving it, once it is admitted
+    async fn serve(&self, connection: &Connection, header: &Header<'_>) -> fdo::Result<(Caller, SettingsStore)> {
+        self.admit(connection, header).await?;
+        self.store.serve(connection, header).await
+    }
+
+    /// Whether `namespace`/`key` is served and the policy shows it to `caller`
+    fn shows(&self, caller: &Caller, namespace: &str, key: &str) -> bool {
+        !self.store.is_denied(namespace, key) && self.store.is_visible(caller, namespace, key)
+    }
+
+    /// The sender of `header` and the store serving it, failing unless the
+    /// service accepts changes, none of `keys` is in a denied namespace, the
+    /// sender is admitted and the policy lets it change each of them
+    ///
+    /// Unlike readers, writers are always resolved, for the audit log.
+    async fn authorize(
+        &self,
+        connection: &Connection,
+        header: &Header<'_>,
+        keys: &[(&str, &str)],
+    ) -> fdo::Result<(Caller, SettingsStore)> {
+        if self.read_only {
+            return Err(fdo::Error::AccessDenied("settings are read-only in this service".to_string()));
+        }
+        self.check_denied(keys)?;
+        self.admit(connection, header).await?;
+        let caller = Caller::of(connection, header).await;
+        match keys.iter().find(|(namespace, key)| !self.store.is_visible(&caller, namespace, key)) {
+            Some((namespace, key)) => Err(fdo::Error::AccessDenied(format!(
+                "the policy does not allow changing {}/{}",
+                namespace, key
+            ))),
+            None => {
+                let store = self.store.store_for(&caller, header).await.map_err(|e| {
+                    fdo::Error::Failed(format!("cannot load the settings of {}: {:#}", caller, e))
+                })?;
+                Ok((caller, store))
+            }
+        }
+    }
+
+    /// Await `call`, a mutation of `keys`, telling the observers of the store
+    /// about it if it fails
+    async fn audited<T>(
+        &self,
+        connection: &Connection,
+        header: &Header<'_>,
+        kind: ChangeKind,
+        keys: &[(&str, &str)],
+        call: impl Future<Output = fdo::Result<T>>,
+    ) -> fdo::Result<T> {
+        let result = call.await;
+        if let Err(e) = &result {
+            let caller = Caller::of(connection, header).await;
+            for (namespace, key) in keys {
+                self.store.report_refused(&RefusedChange {
+                    namespace: namespace.to_string(),
+                    key: key.to_string(),
+                    kind,
+                    caller: Some(caller.clone()),
+                    error: e.name().to_string(),
+                    message: e.description().unwrap_or_default().to_string(),
+                });
+            }
+        }
+        result
+    }
+
+    async fn emit(
+        &self,
+        store: &SettingsStore,
+        ctxt: &SignalContext<'_>,
+        namespace: &str,
+        key: &str,
+        value: OwnedValue,
+    ) -> fdo::Result<()> {
+        // Already queued while the change was published, or hidden from some
+        if store.emits_signals() || !store.is_public(namespace, key) {
+            return Ok(());
+        }
+        match &self.emitter {
+            Some(emitter) => emitter.emit(namespace, key, value),
+            None => emit_setting_changed(ctxt, namespace, key, &value).await?,
+        }
+        Ok(())
+    }
+
+    /// Emit the signals of several changes at once
+    async fn emit_batch(
+        &self,
+        store: &SettingsStore,
+        ctxt: &SignalContext<'_>,
+        mut values: Vec<(SettingKey, OwnedValue)>,
+    ) -> fdo::Result<()> {
+        if store.emits_signals() {
+            return Ok(());
+        }
+        values.retain(|(key, _)| store.is_public(&key.namespace, &key.key));
+        match &self.emitter {
+            Some(emitter) => emitter.emit_many(values),
+            None => emit_values(ctxt, &values).await?,
+        }
+        Ok(())
+    }
+
+    async fn validate_in(&self, store: &SettingsStore, namespace: &str, key: &str, value: OwnedValue) -> fdo::Result<()> {
+        store.validate(namespace, key, &value).await.map_err(rejected)
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    async fn write_scoped(
+        &self,
+        store: &SettingsStore,
+        ctxt: &SignalContext<'_>,
+        sender: Option<&str>,
+        namespace: &str,
+        key: &str,
+        value: OwnedValue,
+        options: WriteOptions,
+    ) -> fdo::Result<()> {
+        // Signals carry the name the value is stored under
+        let (namespace, key) = store.canonical(namespace, key);
+        if self.dry_run && options.migrate {
+            return store.validate_migration(namespace, key, &value).await.map_err(rejected);
+        }
+        if self.dry_run {
+            return self.validate_in(store, namespace, key, value).await;
+        }
+
+        let created = self.check_quota(store, sender, &[(namespace, key)]).await?;
+        let value = store.coerce(namespace, key, value);
+        let signal_value = value
+            .try_clone()
+            .map_err(|e| fdo::Error::InvalidArgs(e.to_string()))?;
+        let (source, before) = match &options.login_session {
+            Some(_) => (ChangeSource::Session, store.read(namespace, key).await),
+            None => (ChangeSource::DBus, None),
+        };
+        let written = match options.migrate {
+            true => store.migrate_from(source, namespace, key, value).await,
+            false => store.write_from(source, namespace, key, value).await,
+        };
+        let changed = written.map_err(rejected)?;
+        if let (Some(session), Some((_, overrides))) = (&options.login_session, &self.sessions) {
+            overrides.insert(session, SettingKey::new(namespace, key), before);
+        }
+        if let Some(sender) = sender {
+            for key in created {
+                self.senders.insert(sender, key, options.scope);
+            }
+        }
+
+        match changed {
+            true => self.emit(store, ctxt, namespace, key, signal_value).await,
+            false => Ok(()),
+        }
+    }
+}
+
+#[interface(name = "io.github.meta_flutter.PortalSettings.Management")]
+impl SettingsManagement {
+    /// Validate and store a setting
+    ///
+    /// Keys created in namespaces without defaults count against the quota
+    /// of the calling connection.
+    async fn write(
+        &self,
+        #[zbus(signal_context)] ctxt: SignalContext<'_>,
+        #[zbus(connection)] connection: &Connection,
+        #[zbus(header)] header: Header<'_>,
+        namespace: &str,
+        key: &str,
+        value: OwnedValue,
+    ) -> fdo::Result<()> {
+        let keys = [(namespace, key)];
+        self.audited(connection, &header, ChangeKind::Write, &keys, async {
+            let (caller, store) = self.authorize(connection, &header, &keys).await?;
+            let sender = header.sender().map(|s| s.as_str());
+            caller
+                .run(self.write_scoped(&store, &ctxt, sender, namespace, key, value, WriteOptions::default()))
+                .await
+        })
+        .await
+    }
+
+    /// `Write` with options:
+    ///
+    /// - `session-scoped` (b): delete the key when the calling connection
+    ///   closes, if this call creates it
+    /// - `migrate` (b): accept a value of another type than the current
+    ///   one, which plain writes refuse
+    /// - `login-session` (b): keep the value only until the caller's logind
+    ///   session ends, then restore the one it replaced
+    #[allow(clippy::too_many_arguments)]
+    async fn write_with_options(
+        &self,
+        #[zbus(signal_context)] ctxt: SignalContext<'_>,
+        #[zbus(connection)] connection: &Connection,
+        #[zbus(header)] header: Header<'_>,
+        namespace: &str,
+        key: &str,
+        value: OwnedValue,
+        options: HashMap<String, OwnedValue>,
+    ) -> fdo::Result<()> {
+        let keys = [(namespace, key)];
+        self.audited(connection, &header, ChangeKind::Write, &keys, async {
+            let (caller, store) = self.authorize(connection, &header, &keys).await?;
+            let mut write_options = WriteOptions::default();
+            for (name, option) in &options {
+                match name.as_str() {
+                    "session-scoped" => {
+                        let session = bool::try_from(option)
+                            .map_err(|_| fdo::Error::InvalidArgs("session-scoped must be a boolean".to_string()))?;
+                        if session && self.store.user_stores().is_some() {
+                            return Err(fdo::Error::NotSupported(
+                                "session-scoped keys are not supported with a store per user".to_string(),
+                            ));
+                        }
+                        if session {
+                            write_options.scope = Scope::Session;
+                        }
+                    }
+                    "migrate" => {
+                        write_options.migrate = bool::try_from(option)
+                            .map_err(|_| fdo::Error::InvalidArgs("migrate must be a boolean".to_string()))?;
+                    }
+                    "login-session" => {
+                        let login_session = bool::try_from(option)
+                            .map_err(|_| fdo::Error::InvalidArgs("login-session must be a boolean".to_string()))?;
+                        if login_session {
+                            write_options.login_session = Some(self.login_session(&caller).await?);
+                        }
+                    }
+                    _ => return Err(fdo::Error::InvalidArgs(format!("unknown option {}", name))),
+                }
+            }
+            let sender = header.sender().map(|s| s.as_str());
+            caller
+                .run(self.write_scoped(&store, &ctxt, sender, namespace, key, value, write_options))
+                .await
+        })
+        .await
+    }
+
+    /// Validate every setting, then store all of them or none
+    ///
+    /// `SettingChanged` is emitted for each setting that changed once all are
+    /// stored, followed by one `SettingsChangedBatch` listing them.
+    async fn write_many(
+        &self,
+        #[zbus(signal_context)] ctxt: SignalContext<'_>,
+        #[zbus(connection)] connection: &Connection,
+        #[zbus(header)] header: Header<'_>,
+        settings: HashMap<String, HashMap<String, OwnedValue>>,
+    ) -> fdo::Result<()> {
+        let names: Vec<(String, String)> = settings
+            .iter()
+            .flat_map(|(namespace, keys)| keys.keys().map(move |key| (namespace.clone(), key.clone())))
+            .collect();
+        let keys: Vec<(&str, &str)> = names.iter().map(|(n, k)| (n.as_str(), k.as_str())).collect();
+        self.audited(connection, &header, ChangeKind::Write, &keys, async {
+            let (caller, store) = self.authorize(connection, &header, &keys).await?;
+            let mut values = Vec::new();
+            let mut signals = Vec::new();
+            for (namespace, keys) in settings {
+                for (key, value) in keys {
+                    let (namespace, key) = store.canonical(&namespace, &key);
+                    let (namespace, key) = (namespace.to_string(), key.to_string());
+                    let value = store.coerce(&namespace, &key, value);
+                    let signal_value = value
+                        .try_clone()
+                        .map_err(|e| fdo::Error::InvalidArgs(e.to_string()))?;
+                    signals.push((SettingKey::new(&namespace, &key), signal_value));
+                    values.push((namespace, key, value));
+                }
+            }
+
+            if self.dry_run {
+                for (namespace, key, value) in values {
+                    self.validate_in(&store, &namespace, &key, value).await?;
+                }
+                return Ok(());
+            }
+            let sender = header.sender().map(|s| s.as_str());
+            let keys: Vec<(&str, &str)> = values.iter().map(|(n, k, _)| (n.as_str(), k.as_str())).collect();
+            let created = self.check_quota(&store, sender, &keys).await?;
+            let changed = caller
+                .run(store.write_many_from(ChangeSource::DBus, values))
+                .await
+                .map_err(rejected)?;
+            // Values written again are not announced
+            signals.retain(|(key, _)| changed.contains(key));
+            if let Some(sender) = sender {
+                for key in created {
+                    self.senders.insert(sender, key, Scope::Persistent);
+                }
+            }
+
+            self.emit_batch(&store, &ctxt, signals).await
+        })
+        .await
+    }
+
+    /// Remove a setting, returning whether it existed
+    ///
+    /// No signal is emitted since `SettingChanged` cannot express removal.
+    async fn delete(
+        &self,
+        #[zbus(connection)] connection: &Connection,
+        #[zbus(header)] header: Header<'_>,
+        namespace: &str,
+        key: &str,
+    ) -> fdo::Result<bool> {
+        let keys = [(namespace, key)];
+        self.audited(connection, &header, ChangeKind::Delete, &keys, async {
+            let (caller, store) = self.authorize(connection, &header, &keys).await?;
+            if store.is_read_only(namespace, key) {
+                return Err(rejected(ReadOnlyError::new(namespace, key).into()));
+            }
+            if self.dry_run {
+                return Ok(store.read(namespace, key).await.is_some());
+            }
+            Ok(caller.run(store.delete_from(ChangeSource::DBus, namespace, key)).await)
+        })
+        .await
+    }
+
+    /// Restore a setting to its default, returning whether anything changed
+    async fn reset(
+        &self,
+        #[zbus(signal_context)] ctxt: SignalContext<'_>,
+        #[zbus(connection)] connection: &Connection,
+        #[zbus(header)] header: Header<'_>,
+        namespace: &str,
+        key: &str,
+    ) -> fdo::Result<bool> {
+        let keys = [(namespace, key)];
+        self.audited(connection, &header, ChangeKind::Reset, &keys, async {
+            let (caller, store) = self.authorize(connection, &header, &keys).await?;
+            if self.dry_run {
+                return Ok(store.read(namespace, key).await.is_some());
+            }
+
+            let (namespace, key) = store.canonical(namespace, key);
+            if !caller.run(store.reset_from(ChangeSource::DBus, namespace, key)).await {
+                return Ok(false);
+            }
+            if let Some(value) = store.read(namespace, key).await {
+                let value = value
+                    .to_owned_value()
+                    .map_err(|e| fdo::Error::Failed(format!("Cannot copy setting: {}", e)))?;
+                self.emit(&store, &ctxt, namespace, key, value).await?;
+            }
+            Ok(true)
+        })
+        .await
+    }
+
+    /// Report where each current value comes from (`builtin`, `backend`,
+    /// `config` or `runtime`), for the given namespaces or all of them
+    async fn layers(
+        &self,
+        #[zbus(connection)] connection: &Connection,
+        #[zbus(header)] header: Header<'_>,
+        namespaces: Vec<String>,
+    ) -> fdo::Result<HashMap<String, HashMap<String, String>>> {
+        let (caller, store) = self.serve(connection, &header).await?;
+        Ok(store
+            .layers(namespaces)
+            .await
+            .into_iter()
+            .map(|(namespace, keys)| {
+                let keys: HashMap<_, _> = keys
+                    .into_iter()
+                    .filter(|(key, _)| self.shows(&caller, &namespace, key))
+                    .map(|(key, layer)| (key, layer.as_str().to_string()))
+                    .collect();
+                (namespace, keys)
+            })
+            .filter(|(_, keys)| !keys.is_empty())
+            .collect())
+    }
+
+    /// Report the generation of the last change to each namespace, for the
+    /// given namespaces or all of them
+    ///
+    /// A namespace whose generation is unchanged need not be read again.
+    async fn generations(
+        &self,
+        #[zbus(connection)] connection: &Connection,
+        #[zbus(header)] header: Header<'_>,
+        namespaces: Vec<String>,
+    ) -> fdo::Result<HashMap<String, u64>> {
+        let (caller, store) = self.serve(connection, &header).await?;
+        let mut generations = store.generations(namespaces).await;
+        generations.retain(|namespace, _| {
+            !store.denied_namespaces().contains(namespace) && self.store.policy().allows(&caller, namespace)
+        });
+        Ok(generations)
+    }
+
+    /// `ReadAll`, unless none of the namespaces changed after
+    /// `known_generation`, for clients polling instead of following signals
+    ///
+    /// Returns the generation to pass next time, whether anything changed, and
+    /// the settings, empty if nothing changed. 0 always returns the settings.
+    #[zbus(out_args("generation", "modified", "settings"))]
+    async fn read_all_if_changed(
+        &self,
+        #[zbus(connection)] connection: &Connection,
+        #[zbus(header)] header: Header<'_>,
+        namespaces: Vec<String>,
+        known_generation: u64,
+    ) -> fdo::Result<(u64, bool, SettingsReply)> {
+        let (caller, store) = self.serve(connection, &header).await?;
+        Ok(match store.read_all_if_changed(namespaces, known_generation).await {
+            (generation, Some(settings)) => (generation, true, store.visible(settings, &caller)),
+            (generation, None) => (generation, false, SettingsReply::default()),
+        })
+    }
+
+    /// Report the changes made after `since_generation`, for clients catching
+    /// up after being away
+    ///
+    /// Returns the current generation, to pass on the next call; whether the
+    /// changes are complete, which they are not once the journal dropped some
+    /// of them; and each change as its generation, namespace, key and new
+    /// value, an empty array for removed keys.
+    #[zbus(out_args("generation", "complete", "changes"))]
+    async fn get_recent_changes(
+        &self,
+        #[zbus(connection)] connection: &Connection,
+        #[zbus(header)] header: Header<'_>,
+        since_generation: u64,
+    ) -> fdo::Result<(u64, bool, Vec<RecentChange>)> {
+        let (caller, store) = self.serve(connection, &header).await?;
+        let recent = store.recent_changes(since_generation).await;
+        let changes = recent
+            .changes
+            .into_iter()
+            .filter(|(_, change)| self.shows(&caller, &change.namespace, &change.key))
+            .map(|(generation, change)| {
+                let value = change
+                    .new
+                    .map(|value| value.to_owned_value())
+                    .transpose()
+                    .map_err(|e| fdo::Error::Failed(format!("Cannot copy setting: {}", e)))?;
+                Ok((generation, change.namespace, change.key, value.into_iter().collect()))
+            })
+            .collect::<fdo::Result<_>>()?;
+        Ok((recent.generation, recent.complete, changes))
+    }
+
+    /// Report how many queued signals were emitted, coalesced with a later
+    /// change, or dropped; all zero when signals are emitted before replying
+    async fn signal_stats(
+        &self,
+        #[zbus(connection)] connection: &Connection,
+        #[zbus(header)] header: Header<'_>,
+    ) -> fdo::Result<HashMap<String, u64>> {
+        self.admit(connection, &header).await?;
+        let stats = self.emitter.as_ref().map(SignalEmitter::stats).unwrap_or_default();
+        Ok(HashMap::from([
+            ("emitted".to_string(), stats.emitted),
+            ("coalesced".to_string(), stats.coalesced),
+            ("dropped".to_string(), stats.dropped),
+        ]))
+    }
+
+    /// Signal emitted after several settings changed together, such as by
+    /// `WriteMany`, listing each namespace, key and new value
+    ///
+    /// `SettingChanged` is still emitted for each of them first.
+    #[zbus(signal)]
+    pub(crate) async fn settings_changed_batch(
+        signal_ctxt: &SignalContext<'_>,
+        changes: Vec<(String, String, OwnedValue)>,
+    ) -> zbus::Result<()>;
+
+    /// What is known about a setting: its `type`, whether it is `read-only`
+    /// and `sensitive` (b), and its `description`, `default` and
+    /// `constraint` (s, e.g. `1..=365`) when it has them
+    async fn describe_key(
+        &self,
+        #[zbus(connection)] connection: &Connection,
+        #[zbus(header)] header: Header<'_>,
+        namespace: &str,
+        key: &str,
+    ) -> fdo::Result<HashMap<String, OwnedValue>> {
+        let (caller, store) = self.serve(connection, &header).await?;
+        if !self.shows(&caller, namespace, key) {
+            return Err(fdo::Error::InvalidArgs(format!("unknown setting {}/{}", namespace, key)));
+        }
+        let (namespace, key) = store.canonical(namespace, key);
+        let builtin = crate::schema::lookup(namespace, key);
+        let constraint = store.constraint(namespace, key);
+        let default = store.default_value(namespace, key);
+        let current = store.read(namespace, key).await;
+        let signature = match (constraint, builtin, current.as_ref().or(default.as_ref())) {
+            (Some(constraint), _, _) => constraint.signature.clone(),
+            (None, Some(builtin), _) => builtin.default.signature().to_string(),
+            (None, None, Some(value)) => value.0.value_signature().to_string(),
+            (None, None, None) => {
+                return Err(fdo::Error::InvalidArgs(format!("unknown setting {}/{}", namespace, key)));
+            }
+        };
+
+        let text = |text: &str| OwnedValue::from(zbus::zvariant::Str::from(text.to_string()));
+        let mut description = HashMap::new();
+        description.insert("type".to_string(), text(&signature));
+        description.insert("read-only".to_string(), OwnedValue::from(store.is_read_only(namespace, key)));
+        description.insert("sensitive".to_string(), OwnedValue::from(store.sensitive_keys().contains(namespace, key)));
+        if let Some(builtin) = builtin {
+            description.insert("description".to_string(), text(builtin.description));
+        }
+        if let Some(default) = default {
+            let default = default
+                .to_owned_value()
+                .map_err(|e| fdo::Error::Failed(format!("Cannot copy setting: {}", e)))?;
+            description.insert("default".to_string(), default);
+        }
+        if let Some(constraint) = constraint.map(|c| c.to_string()).filter(|c| !c.is_empty()) {
+            description.insert("constraint".to_string(), text(&constraint));
+        }
+        Ok(description)
+    }
+
+    /// The `namespace/key` globs of the settings whose values are kept out of
+    /// logs and exports, but for those in namespaces the policy hides from
+    /// the caller
+    async fn sensitive_keys(
+        &self,
+        #[zbus(connection)] connection: &Connection,
+        #[zbus(header)] header: Header<'_>,
+    ) -> fdo::Result<Vec<String>> {
+        let (caller, store) = self.serve(connection, &header).await?;
+        let mut patterns = store.sensitive_keys().patterns();
+        patterns.retain(|pattern| {
+            let (namespace, key) = pattern.split_once('/').unwrap_or((pattern, "*"));
+            self.shows(&caller, namespace, key)
+        });
+        Ok(patterns)
+    }
+
+    /// Run the full validation path for a value without storing it
+    async fn validate_write(
+        &self,
+        #[zbus(connection)] connection: &Connection,
+        #[zbus(header)] header: Header<'_>,
+        namespace: &str,
+        key: &str,
+        value: OwnedValue,
+    ) -> fdo::Result<()> {
+        self.check_denied(&[(namespace, key)])?;
+        let (caller, store) = self.serve(connection, &header).await?;
+        if !self.store.is_visible(&caller, namespace, key) {
+            return Err(fdo::Error::AccessDenied(format!(
+                "the policy does not allow changing {}/{}",
+                namespace, key
+            )));
+        }
+        self.validate_in(&store, namespace, key, value).await
+    }
+}
+
+/// The reply to a rejected change: `AccessDenied` for read-only settings,
+/// otherwise `InvalidArgs`, with the `ValidationError` encoded when the value
+/// broke the constraints of its setting
+fn rejected(error: crate::Error) -> fdo::Error {
+    if let Some(read_only) = error.find::<ReadOnlyError>() {
+        return fdo::Error::AccessDenied(read_only.to_string());
+    }
+    match error.find::<ValidationError>() {
+        Some(invalid) => fdo::Error::InvalidArgs(invalid.encode()),
+        None => fdo::Error::InvalidArgs(format!("{:#}", error)),
+    }
+}
@@ -0,0 +1,329 @@
+//! Deterministic interleavings of store operations
+//!
+//! The threads of a model run one at a time and switch only at yield points,
+//! which the store places where it holds no lock. `explore` runs a model once
+//! for every order in which its threads can pass their yield points, so an
+//! ordering bug shows up in every test run rather than once in a while.
+
+use std::cell::RefCell;
+use std::collections::BTreeSet;
+use std::future::Future;
+use std::pin::pin;
+use std::sync::{Arc, Condvar, Mutex};
+use std::task::{Context, Poll};
+use std::thread;
+
+pub type Thread = Box<dyn FnOnce() + Send>;
+
+/// Models with more interleavings than this are too large to explore
+const MAX_RUNS: usize = 100_000;
+
+#[derive(Default)]
+struct State {
+    /// The one thread allowed to run
+    running: Option<usize>,
+    /// Threads waiting at a yield point
+    waiting: BTreeSet<usize>,
+    finished: usize,
+}
+
+#[derive(Default)]
+struct Scheduler {
+    state: Mutex<State>,
+    changed: Condvar,
+}
+
+impl Scheduler {
+    /// Wait at a yield point until thread `id` is scheduled again
+    fn pause(&self, id: usize) {
+        let mut state = self.state.lock().unwrap();
+        state.running = None;
+        state.waiting.insert(id);
+        self.changed.notify_all();
+        while state.running != Some(id) {
+            state = self.changed.wait(state).unwrap();
+        }
+    }
+}
+
+/// Marks a thread finished, even if it panics
+struct Finished(Arc<Scheduler>);
+
+impl Drop for Finished {
+    fn drop(&mut self) {
+        let mut state = self.0.state.lock().unwrap();
+        state.running = None;
+        state.finished += 1;
+        self.0.changed.notify_all();
+    }
+}
+
+thread_local! {
+    static CURRENT: RefCell<Option<(usize, Arc<Scheduler>)>> = const { RefCell::new(None) };
+}
+
+/// Let another thread of the model run; does nothing outside of models
+pub fn yield_point() {
+    let current = CURRENT.with(|current| current.borrow().clone());
+    if let Some((id, scheduler)) = current {
+        scheduler.pause(id);
+    }
+}
+
+/// Run the threads returned by `model` under every interleaving, calling the
+/// check returned with them after each run; returns the number of runs
+pub fn explore<C: FnOnce()>(mut model: impl FnMut() -> (Vec<Thread>, C)) -> usize {
+    let mut prefix = Vec::new();
+    for runs in 1..=MAX_RUNS {
+        let (threads, check) = model();
+        let choices = run(threads, &prefix);
+        check();
+
+        // Take the next alternative of the last choice that has one
+        let Some(last) = choices.iter().rposition(|&(chosen, options)| chosen + 1 < options) else {
+            return runs;
+        };
+        prefix = choices[..last].iter().map(|&(chosen, _)| chosen).collect();
+        prefix.push(choices[last].0 + 1);
+    }
+    panic!("the model has more than {} interleavings", MAX_RUNS);
+}
+
+/// Run `threads` once, scheduling the waiting thread at index `prefix[i]` at
+/// the i-th switch and the first one after the prefix
+///
+/// Returns each choice with the number of threads there were to choose from.
+fn run(threads: Vec<Thread>, prefix: &[usize]) -> Vec<(usize, usize)> {
+    let scheduler = Arc::new(Scheduler::default());
+    let count = threads.len();
+    let handles: Vec<_> = threads
+        .into_iter()
+        .enumerate()
+        .map(|(id, thread)| {
+            let scheduler = scheduler.clone();
+            thread::spawn(move || {
+                let _finished = Finished(scheduler.clone());
+                CURRENT.with(|current| *current.borrow_mut() = Some((id, scheduler.clone())));
+                scheduler.pause(id);
+                thread();
+            })
+        })
+        .collect();
+
+    let mut choices = Vec::new();
+    let mut state = scheduler.state.lock().unwrap();
+    loop {
+        while state.running.is_some() || state.waiting.len() + state.finished < count {
+            state = scheduler.changed.wait(state).unwrap();
+        }
+        if state.finished == count {
+            break;
+        }
+        let chosen = prefix.get(choices.len()).copied().unwrap_or(0);
+        choices.push((chosen, state.waiting.len()));
+        let id = *state.waiting.iter().nth(chosen).unwrap();
+        state.waiting.remove(&id);
+        state.running = Some(id);
+        scheduler.changed.notify_all();
+    }
+    drop(state);
+
+    for handle in handles {
+        if let Err(panic) = handle.join() {
+            std::panic::resume_unwind(panic);
+        }
+    }
+    choices
+}
+
+/// Poll a store future, which completes without waiting
+pub fn block_on<F: Future>(future: F) -> F::Output {
+    let waker = futures_util::task::noop_waker();
+    match pin!(future).poll(&mut Context::from_waker(&waker)) {
+        Poll::Ready(output) => output,
+        Poll::Pending => panic!("store operations do not wait"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{ChangeObserver, SettingChange, SettingValue, SettingsStore};
+    use std::collections::{HashMap, HashSet};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use zbus::zvariant::OwnedValue;
+
+    const NAMESPACE: &str = "com.example.model";
+
+    #[derive(Default)]
+    struct Recorder(Mutex<Vec<SettingChange>>);
+
+    impl ChangeObserver for Recorder {
+        fn on_change(&self, change: &SettingChange) {
+            if change.namespace == NAMESPACE {
+                self.0.lock().unwrap().push(change.clone());
+            }
+        }
+    }
+
+    fn write(store: &SettingsStore, key: &str, value: u32) {
+        block_on(store.write(NAMESPACE, key, OwnedValue::from(value))).unwrap();
+    }
+
+    fn values(store: &SettingsStore) -> HashMap<String, SettingValue> {
+        let mut all = block_on(store.read_all(vec![NAMESPACE.to_string()]));
+        all.remove(NAMESPACE).unwrap_or_default()
+    }
+
+    /// Whether the recorded changes, applied in order to `snapshot`, each
+    /// start from the value the previous one left and end at `last`
+    fn replays(mut snapshot: HashMap<String, SettingValue>, recorder: &Recorder, last: &HashMap<String, SettingValue>) -> bool {
+        for change in recorder.0.lock().unwrap().iter() {
+            if snapshot.get(&change.key) != change.old.as_ref() {
+                return false;
+            }
+            match &change.new {
+                Some(new) => snapshot.insert(change.key.clone(), new.clone()),
+                None => snapshot.remove(&change.key),
+            };
+        }
+        snapshot == *last
+    }
+
+    #[test]
+    fn test_every_interleaving_is_explored() {
+        let orders = Arc::new(Mutex::new(HashSet::new()));
+        let runs = explore(|| {
+            let order = Arc::new(Mutex::new(Vec::new()));
+            let threads = ["a", "b"]
+                .map(|name| {
+                    let order = order.clone();
+                    Box::new(move || {
+                        order.lock().unwrap().push(format!("{}1", name));
+                        yield_point();
+                        order.lock().unwrap().push(format!("{}2", name));
+                    }) as Thread
+                })
+                .into();
+            let orders = orders.clone();
+            (threads, move || {
+                orders.lock().unwrap().insert(order.lock().unwrap().clone());
+            })
+        });
+        assert_eq!(runs, 6);
+        assert_eq!(orders.lock().unwrap().len(), 6);
+    }
+
+    #[test]
+    fn test_snapshot_and_observe_miss_nothing() {
+        let runs = explore(|| {
+            let store = SettingsStore::new();
+            let recorder = Arc::new(Recorder::default());
+            let snapshot = Arc::new(Mutex::new(HashMap::new()));
+
+            let writer = store.clone();
+            let reader = (store.clone(), recorder.clone(), snapshot.clone());
+            let threads: Vec<Thread> = vec![
+                Box::new(move || {
+                    write(&writer, "a", 1);
+                    write(&writer, "a", 2);
+                    assert!(block_on(writer.delete(NAMESPACE, "a")));
+                }),
+                Box::new(move || {
+                    let (store, recorder, snapshot) = reader;
+                    let mut all = block_on(store.read_all_and_observe(vec![NAMESPACE.to_string()], recorder));
+                    *snapshot.lock().unwrap() = all.remove(NAMESPACE).unwrap_or_default();
+                }),
+            ];
+            (threads, move || {
+                let snapshot = snapshot.lock().unwrap().clone();
+                assert!(replays(snapshot, &recorder, &values(&store)));
+            })
+        });
+        assert!(runs > 1);
+    }
+
+    #[test]
+    fn test_reading_then_observing_can_miss_a_change() {
+        let missed = Arc::new(AtomicUsize::new(0));
+        explore(|| {
+            let store = SettingsStore::new();
+            let recorder = Arc::new(Recorder::default());
+            let snapshot = Arc::new(Mutex::new(HashMap::new()));
+
+            let writer = store.clone();
+            let reader = (store.clone(), recorder.clone(), snapshot.clone());
+            let threads: Vec<Thread> = vec![
+                Box::new(move || write(&writer, "a", 1)),
+                Box::new(move || {
+                    let (store, recorder, snapshot) = reader;
+                    *snapshot.lock().unwrap() = values(&store);
+                    yield_point();
+                    store.add_observer(recorder);
+                }),
+            ];
+            let missed = missed.clone();
+            (threads, move || {
+                let snapshot = snapshot.lock().unwrap().clone();
+                if !replays(snapshot, &recorder, &values(&store)) {
+                    missed.fetch_add(1, Ordering::SeqCst);
+                }
+            })
+        });
+        assert!(missed.load(Ordering::SeqCst) > 0);
+    }
+
+    #[test]
+    fn test_observers_see_changes_in_publish_order() {
+        let runs = explore(|| {
+            let store = SettingsStore::new();
+            let recorder = Arc::new(Recorder::default());
+            store.add_observer(recorder.clone());
+
+            let threads = [1, 2]
+                .map(|value| {
+                    let store = store.clone();
+                    Box::new(move || write(&store, "a", value)) as Thread
+                })
+                .into();
+            (threads, move || {
+                assert!(replays(HashMap::new(), &recorder, &values(&store)));
+            })
+        });
+        assert!(runs > 2);
+    }
+
+    #[test]
+    fn test_observers_may_change_the_store() {
+        /// Mirrors every change of `a` to `b`
+        struct Mirror(SettingsStore);
+
+        impl ChangeObserver for Mirror {
+            fn on_change(&self, change: &SettingChange) {
+                if let (NAMESPACE, "a", Some(value)) = (change.namespace.as_str(), change.key.as_str(), &change.new) {
+                    let value = value.to_owned_value().unwrap();
+                    block_on(self.0.write(NAMESPACE, "b", value)).unwrap();
+                }
+            }
+        }
+
+        explore(|| {
+            let store = SettingsStore::new();
+            let recorder = Arc::new(Recorder::default());
+            store.add_observer(Arc::new(Mirror(store.clone())));
+            store.add_observer(recorder.clone());
+
+            let threads = [1, 2]
+                .map(|value| {
+                    let store = store.clone();
+                    Box::new(move || write(&store, "a", value)) as Thread
+                })
+                .into();
+            (threads, move || {
+                let values = values(&store);
+                assert_eq!(values.get("a"), values.get("b"));
+                assert!(replays(HashMap::new(), &recorder, &values));
+            })
+        });
+    }
+}
@@ -0,0 +1,98 @@
+use crate::error::{bail, Result};
+use crate::schema::is_clean_text;
+
+/// Match `text` against a shell-style glob
+///
+/// `*` matches any run of characters (including dots, so
+/// `org.gnome.*` covers every GNOME namespace) and `?` any single
+/// character. There are no character classes or escapes.
+pub fn glob_match(pattern: &str, text: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let text: Vec<char> = text.chars().collect();
+
+    // Greedy matching with backtracking to the most recent `*`
+    let (mut p, mut t) = (0, 0);
+    let mut star: Option<(usize, usize)> = None;
+    while t < text.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                star = Some((p, t));
+                p += 1;
+            }
+            Some(&c) if c == '?' || c == text[t] => {
+                p += 1;
+                t += 1;
+            }
+            _ => match star {
+                Some((star_p, star_t)) => {
+                    p = star_p + 1;
+                    t = star_t + 1;
+                    star = Some((star_p, star_t + 1));
+                }
+                None => return false,
+            },
+        }
+    }
+    pattern[p..].iter().all(|&c| c == '*')
+}
+
+/// Fail unless `pattern` is a `ReadAll` namespace filter: a namespace, or
+/// whole elements followed by a `*` element matching any further ones
+/// (`org.gnome.*`, or `*` alone), since the portal specification allows
+/// globbing only on trailing elements
+///
+/// Elements are held to the characters of reverse-DNS names unless
+/// `any_namespace`, for stores accepting namespaces of any form.
+pub fn check_namespace_pattern(pattern: &str, any_namespace: bool) -> Result<()> {
+    let namespace = match pattern.strip_suffix('*') {
+        Some("") => return Ok(()),
+        Some(prefix) => match prefix.strip_suffix('.') {
+            Some(namespace) => namespace,
+            None => bail!("`*` must stand for whole elements, as in org.example.*"),
+        },
+        None => pattern,
+    };
+    if namespace.is_empty() {
+        bail!("empty namespace");
+    }
+    if namespace.contains('*') {
+        bail!("`*` is only allowed as the last element");
+    }
+    if !is_clean_text(namespace) {
+        bail!("control characters are not allowed");
+    }
+    let valid_element =
+        |element: &str| !element.is_empty() && element.chars().all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-');
+    if !any_namespace && !namespace.split('.').all(valid_element) {
+        bail!("expected elements of ASCII letters, digits, `_` and `-` separated by dots");
+    }
+    Ok(())
+}
+
+/// Whether `namespace` is selected by `pattern`, a valid namespace filter
+pub(crate) fn matches_namespace(pattern: &str, namespace: &str) -> bool {
+    match pattern.strip_suffix('*') {
+        Some(prefix) => namespace.starts_with(prefix),
+        None => pattern == namespace,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_glob_match() {
+        assert!(glob_match("org.gnome.*", "org.gnome.desktop.interface"));
+        assert!(glob_match("*", ""));
+        assert!(glob_match("org.freedesktop.appearance", "org.freedesktop.appearance"));
+        assert!(glob_match("org.*.appearance", "org.freedesktop.appearance"));
+        assert!(glob_match("org.gnome.desktop.?rivacy", "org.gnome.desktop.privacy"));
+        assert!(glob_match("*a*b*", "xaxxbx"));
+
+        assert!(!glob_match("org.gnome.*", "org.freedesktop.appearance"));
+        assert!(!glob_match("org.gnome", "org.gnome.desktop"));
+        assert!(!glob_match("?", ""));
+        assert!(!glob_match("*a*b", "xaxxbx"));
+    }
+}
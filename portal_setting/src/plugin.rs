@@ -0,0 +1,347 @@
+//! Settings backends and change observers loaded from shared objects
+//!
+//! A plugin exports `portal_setting_plugin_init`, returning a pointer to a
+//! `PluginVTable` that stays valid until its `destroy` callback is called.
+//! See `include/portal-setting-plugin.h` for the C declarations.
+//!
+//! Values cross the ABI as a D-Bus signature and the value in TOML syntax,
+//! e.g. `u` and `1`, `s` and `"Adwaita"` or `(ddd)` and `[0.2, 0.4, 0.6]`.
+
+use crate::state::{toml_to_typed, value_to_toml};
+use crate::{ChangeObserver, SettingChange, SettingsBackend};
+use crate::error::{bail, Context, Result};
+use libloading::Library;
+use std::ffi::{c_char, c_int, c_void, CStr, CString};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use zbus::zvariant::OwnedValue;
+
+/// Version of `PluginVTable`; plugins built for another version are rejected
+pub const ABI_VERSION: u32 = 1;
+
+/// Name of the function every plugin exports
+pub const INIT_SYMBOL: &str = "portal_setting_plugin_init";
+
+/// Callback handed to `PluginVTable::load` for reporting one setting
+///
+/// Returns 0 if the setting was accepted.
+pub type EmitFn = unsafe extern "C" fn(
+    ctx: *mut c_void,
+    namespace: *const c_char,
+    key: *const c_char,
+    signature: *const c_char,
+    value: *const c_char,
+) -> c_int;
+
+/// Entry points of a plugin; every callback is optional
+#[repr(C)]
+pub struct PluginVTable {
+    pub abi_version: u32,
+    pub name: *const c_char,
+    /// Passed back as the first argument of every callback
+    pub data: *mut c_void,
+    /// Report settings by calling `emit(ctx, ...)`, returning 0 on success
+    pub load: Option<unsafe extern "C" fn(data: *mut c_void, ctx: *mut c_void, emit: EmitFn) -> c_int>,
+    /// Called after every change; `signature` and `value` are NULL when the
+    /// key no longer exists. May be called from any thread.
+    pub on_change: Option<
+        unsafe extern "C" fn(
+            data: *mut c_void,
+            namespace: *const c_char,
+            key: *const c_char,
+            kind: *const c_char,
+            source: *const c_char,
+            signature: *const c_char,
+            value: *const c_char,
+        ),
+    >,
+    /// Release `data` before the plugin is unloaded
+    pub destroy: Option<unsafe extern "C" fn(data: *mut c_void)>,
+}
+
+type InitFn = unsafe extern "C" fn() -> *const PluginVTable;
+
+/// A loaded plugin, usable as a `SettingsBackend` and a `ChangeObserver`
+pub struct Plugin {
+    name: String,
+    vtable: *const PluginVTable,
+    // Unloaded only after `Drop::drop` called `destroy`
+    _library: Option<Library>,
+}
+
+// SAFETY: the plugin ABI requires callbacks to be callable from any thread
+unsafe impl Send for Plugin {}
+unsafe impl Sync for Plugin {}
+
+impl Plugin {
+    /// Load the shared object at `path` and initialize it
+    pub fn open(path: &Path) -> Result<Self> {
+        // SAFETY: loading a plugin runs its initializers; plugins are trusted
+        // code installed by the system integrator
+        unsafe {
+            let library = Library::new(path)?;
+            let init = *library.get::<InitFn>(INIT_SYMBOL.as_bytes())?;
+            let vtable = init();
+            let fallback = path.file_stem().unwrap_or_default().to_string_lossy().into_owned();
+            Self::from_vtable(vtable, Some(library), fallback)
+        }
+    }
+
+    /// # Safety
+    ///
+    /// `vtable` must be null or point to a `PluginVTable` that stays valid
+    /// while the returned plugin lives.
+    unsafe fn from_vtable(vtable: *const PluginVTable, library: Option<Library>, fallback_name: String) -> Result<Self> {
+        let Some(table) = vtable.as_ref() else {
+            bail!("{} returned NULL", INIT_SYMBOL);
+        };
+        if table.abi_version != ABI_VERSION {
+            bail!(
+                "plugin ABI version {} is not supported (expected {})",
+                table.abi_version,
+                ABI_VERSION
+            );
+        }
+        let name = match table.name.is_null() {
+            true => fallback_name,
+            false => CStr::from_ptr(table.name).to_string_lossy().into_owned(),
+        };
+        Ok(Self {
+            name,
+            vtable,
+            _library: library,
+        })
+    }
+
+    fn vtable(&self) -> &PluginVTable {
+        // SAFETY: checked for null in `from_vtable`, valid until dropped
+        unsafe { &*self.vtable }
+    }
+}
+
+impl Drop for Plugin {
+    fn drop(&mut self) {
+        let vtable = self.vtable();
+        if let Some(destroy) = vtable.destroy {
+            // SAFETY: called once, with the plugin's own data
+            unsafe { destroy(vtable.data) };
+        }
+    }
+}
+
+/// Settings collected through `emit` during `PluginVTable::load`
+#[derive(Default)]
+struct Collected {
+    values: Vec<(String, String, OwnedValue)>,
+    error: Option<crate::Error>,
+}
+
+unsafe extern "C" fn emit(
+    ctx: *mut c_void,
+    namespace: *const c_char,
+    key: *const c_char,
+    signature: *const c_char,
+    value: *const c_char,
+) -> c_int {
+    let collected = &mut *(ctx as *mut Collected);
+    let result = (|| -> Result<_> {
+        if [namespace, key, signature, value].iter().any(|p| p.is_null()) {
+            bail!("NULL argument");
+        }
+        let namespace = CStr::from_ptr(namespace).to_str()?;
+        let key = CStr::from_ptr(key).to_str()?;
+        let signature = CStr::from_ptr(signature).to_str()?;
+        let text = CStr::from_ptr(value).to_str()?;
+
+        let converted = text
+            .parse::<toml::Value>()
+            .map_err(crate::Error::from)
+            .and_then(|v| toml_to_typed(signature, &v))
+            .and_then(|v| Ok(OwnedValue::try_from(v)?))
+            .with_context(|| format!("{}/{}", namespace, key))?;
+        Ok((namespace.to_string(), key.to_string(), converted))
+    })();
+
+    match result {
+        Ok(setting) => {
+            collected.values.push(setting);
+            0
+        }
+        Err(e) => {
+            collected.error.get_or_insert(e);
+            -1
+        }
+    }
+}
+
+impl SettingsBackend for Plugin {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn load(&self) -> Result<Vec<(String, String, OwnedValue)>> {
+        let vtable = self.vtable();
+        let Some(load) = vtable.load else {
+            return Ok(Vec::new());
+        };
+
+        let mut collected = Collected::default();
+        // SAFETY: `collected` outlives the call and is only used by `emit`
+        let status = unsafe { load(vtable.data, &mut collected as *mut Collected as *mut c_void, emit) };
+        if let Some(e) = collected.error {
+            return Err(e);
+        }
+        if status != 0 {
+            bail!("load failed with status {}", status);
+        }
+        Ok(collected.values)
+    }
+}
+
+impl ChangeObserver for Plugin {
+    fn on_change(&self, change: &SettingChange) {
+        let vtable = self.vtable();
+        let Some(on_change) = vtable.on_change else {
+            return;
+        };
+
+        let value = match &change.new {
+            Some(value) => match value_to_toml(&value.0) {
+                Ok(toml) => Some((value.0.value_signature().to_string(), toml.to_string())),
+                Err(e) => {
+                    eprintln!(
+                        "Plugin {}: cannot pass {}/{}: {:#}",
+                        self.name, change.namespace, change.key, e
+                    );
+                    return;
+                }
+            },
+            None => None,
+        };
+
+        let cstring = |s: &str| CString::new(s.replace('\0', ""));
+        let (Ok(namespace), Ok(key), Ok(kind), Ok(source)) = (
+            cstring(&change.namespace),
+            cstring(&change.key),
+            cstring(&change.kind.to_string()),
+            cstring(&change.source.to_string()),
+        ) else {
+            return;
+        };
+        let value = value.map(|(signature, text)| (cstring(&signature), cstring(&text)));
+        let (signature, value) = match &value {
+            Some((Ok(signature), Ok(value))) => (signature.as_ptr(), value.as_ptr()),
+            Some(_) => return,
+            None => (std::ptr::null(), std::ptr::null()),
+        };
+
+        // SAFETY: all strings outlive the call
+        unsafe {
+            on_change(
+                vtable.data,
+                namespace.as_ptr(),
+                key.as_ptr(),
+                kind.as_ptr(),
+                source.as_ptr(),
+                signature,
+                value,
+            )
+        };
+    }
+}
+
+/// Load every `*.so` file in `dir`, in file name order
+pub fn load_dir(dir: &Path) -> Result<Vec<Arc<Plugin>>> {
+    let mut paths: Vec<PathBuf> = std::fs::read_dir(dir)
+        .with_context(|| format!("cannot read plugin directory {}", dir.display()))?
+        .filter_map(|entry| entry.ok().map(|e| e.path()))
+        .filter(|path| path.extension().is_some_and(|ext| ext == "so"))
+        .collect();
+    paths.sort();
+
+    paths
+        .iter()
+        .map(|path| {
+            Plugin::open(path)
+                .map(Arc::new)
+                .with_context(|| format!("cannot load plugin {}", path.display()))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{ChangeKind, ChangeSource, SettingValue};
+    use std::sync::Mutex;
+    use zbus::zvariant::Value;
+
+    static CHANGES: Mutex<Vec<String>> = Mutex::new(Vec::new());
+
+    unsafe extern "C" fn test_load(_data: *mut c_void, ctx: *mut c_void, emit: EmitFn) -> c_int {
+        emit(ctx, c"org.example.vendor".as_ptr(), c"brightness".as_ptr(), c"u".as_ptr(), c"42".as_ptr());
+        emit(ctx, c"org.example.vendor".as_ptr(), c"tint".as_ptr(), c"(ddd)".as_ptr(), c"[0.5, 0.5, 1.0]".as_ptr())
+    }
+
+    unsafe extern "C" fn test_on_change(
+        _data: *mut c_void,
+        namespace: *const c_char,
+        key: *const c_char,
+        kind: *const c_char,
+        source: *const c_char,
+        signature: *const c_char,
+        value: *const c_char,
+    ) {
+        let s = |p: *const c_char| match p.is_null() {
+            true => "NULL".to_string(),
+            false => CStr::from_ptr(p).to_string_lossy().into_owned(),
+        };
+        CHANGES.lock().unwrap().push(
+            [namespace, key, kind, source, signature, value].map(s).join(" "),
+        );
+    }
+
+    fn vtable(abi_version: u32) -> *const PluginVTable {
+        Box::leak(Box::new(PluginVTable {
+            abi_version,
+            name: c"test".as_ptr(),
+            data: std::ptr::null_mut(),
+            load: Some(test_load),
+            on_change: Some(test_on_change),
+            destroy: None,
+        }))
+    }
+
+    #[test]
+    fn test_plugin_backend_and_observer() {
+        let plugin = unsafe { Plugin::from_vtable(vtable(ABI_VERSION), None, String::new()) }.unwrap();
+        assert_eq!(SettingsBackend::name(&plugin), "test");
+
+        let values = plugin.load().unwrap();
+        assert_eq!(values.len(), 2);
+        assert_eq!(values[0].2, OwnedValue::from(42u32));
+        assert_eq!(values[1].2.value_signature(), "(ddd)");
+
+        for (kind, new) in [(ChangeKind::Write, Some(Value::I32(-1))), (ChangeKind::Delete, None)] {
+            plugin.on_change(&SettingChange {
+                namespace: "org.example".to_string(),
+                key: "k".to_string(),
+                kind,
+                source: ChangeSource::DBus,
+                old: None,
+                new: new.map(|v| SettingValue::new(v.try_into().unwrap())),
+                caller: None,
+            });
+        }
+        assert_eq!(
+            *CHANGES.lock().unwrap(),
+            ["org.example k write dbus i -1", "org.example k delete dbus NULL NULL"]
+        );
+    }
+
+    #[test]
+    fn test_plugin_abi_mismatch() {
+        assert!(unsafe { Plugin::from_vtable(vtable(ABI_VERSION + 1), None, String::new()) }.is_err());
+        assert!(unsafe { Plugin::from_vtable(std::ptr::null(), None, String::new()) }.is_err());
+    }
+}
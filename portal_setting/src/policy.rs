@@ -0,0 +1,306 @@
+use crate::error::{bail, Context, Result};
+use crate::pattern::{check_namespace_pattern, matches_namespace};
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::future::Future;
+use std::path::Path;
+use zbus::fdo::DBusProxy;
+use zbus::message::Header;
+use zbus::names::BusName;
+use zbus::Connection;
+
+/// Who is calling, as far as it is known
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Caller {
+    /// Unique bus name of the calling connection, e.g. `:1.42`
+    pub sender: Option<String>,
+    /// Application id, e.g. `org.gnome.TextEditor`, known for Flatpak apps
+    pub app_id: Option<String>,
+    pub uid: Option<u32>,
+    pub pid: Option<u32>,
+    /// Security label of the connection, such as its AppArmor profile
+    pub security_label: Option<String>,
+}
+
+tokio::task_local! {
+    static CALLER: Caller;
+}
+
+impl Caller {
+    /// The sender of `header` on `connection`, as reported by the bus
+    ///
+    /// The app id is read from the `/.flatpak-info` of sandboxed senders.
+    /// Peer-to-peer connections have no bus to ask, so their callers are
+    /// unknown.
+    pub async fn of(connection: &Connection, header: &Header<'_>) -> Self {
+        let Some(sender) = header.sender() else {
+            return Self::default();
+        };
+        let Ok(dbus) = DBusProxy::new(connection).await else {
+            return Self::default();
+        };
+        let mut caller = match dbus.get_connection_credentials(BusName::from(sender.clone())).await {
+            Ok(credentials) => Self {
+                uid: credentials.unix_user_id(),
+                pid: credentials.process_id(),
+                security_label: credentials.linux_security_label().map(|label| security_label(label)),
+                ..Self::default()
+            },
+            // Buses older than GetConnectionCredentials
+            Err(_) => Self {
+                uid: dbus.get_connection_unix_user(BusName::from(sender.clone())).await.ok(),
+                pid: dbus.get_connection_unix_process_id(BusName::from(sender.clone())).await.ok(),
+                ..Self::default()
+            },
+        };
+        caller.sender = Some(sender.to_string());
+        caller.app_id = caller.pid.and_then(flatpak_app_id);
+        caller
+    }
+
+    /// Run `future` on behalf of this caller, so the changes it makes record
+    /// who asked for them
+    pub async fn run<F: Future>(self, future: F) -> F::Output {
+        CALLER.scope(self, future).await
+    }
+
+    /// The caller the current task runs on behalf of, if any
+    pub fn current() -> Option<Self> {
+        CALLER.try_with(Self::clone).ok()
+    }
+}
+
+impl fmt::Display for Caller {
+    /// `sender=... app_id=... uid=... pid=... label=...`, leaving out what is
+    /// unknown, or `-` if nothing is known
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut fields = Vec::new();
+        if let Some(sender) = &self.sender {
+            fields.push(format!("sender={}", sender));
+        }
+        if let Some(app_id) = &self.app_id {
+            fields.push(format!("app_id={}", app_id));
+        }
+        if let Some(uid) = self.uid {
+            fields.push(format!("uid={}", uid));
+        }
+        if let Some(pid) = self.pid {
+            fields.push(format!("pid={}", pid));
+        }
+        if let Some(label) = &self.security_label {
+            fields.push(format!("label={}", label));
+        }
+        match fields.is_empty() {
+            true => f.write_str("-"),
+            false => f.write_str(&fields.join(" ")),
+        }
+    }
+}
+
+/// A security label as text, without the NUL the bus may end it with
+fn security_label(label: &[u8]) -> String {
+    let label = label.strip_suffix(&[0]).unwrap_or(label);
+    String::from_utf8_lossy(label).into_owned()
+}
+
+/// The app id of process `pid` if it runs in a Flatpak sandbox, which
+/// mounts `/.flatpak-info` at the root of the sandbox
+fn flatpak_app_id(pid: u32) -> Option<String> {
+    let info = std::fs::read_to_string(format!("/proc/{}/root/.flatpak-info", pid)).ok()?;
+    parse_flatpak_info(&info)
+}
+
+/// The `name` of the `[Application]` group of a `.flatpak-info` file
+fn parse_flatpak_info(info: &str) -> Option<String> {
+    let mut group = "";
+    for line in info.lines().map(str::trim) {
+        if let Some(name) = line.strip_prefix('[').and_then(|line| line.strip_suffix(']')) {
+            group = name;
+        } else if let Some((name, value)) = line.split_once('=') {
+            if group == "Application" && name.trim() == "name" && !value.trim().is_empty() {
+                return Some(value.trim().to_string());
+            }
+        }
+    }
+    None
+}
+
+/// Namespaces a rule lets callers read and write
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Rule {
+    /// Namespace filters, like those of `ReadAll`; every namespace if absent
+    #[serde(default)]
+    pub allow: Option<Vec<String>>,
+    /// Namespace filters refused even when allowed
+    #[serde(default)]
+    pub deny: Vec<String>,
+}
+
+impl Rule {
+    pub fn allows(&self, namespace: &str) -> bool {
+        let allowed = match &self.allow {
+            Some(allow) => allow.iter().any(|pattern| matches_namespace(pattern, namespace)),
+            None => true,
+        };
+        allowed && !self.deny.iter().any(|pattern| matches_namespace(pattern, namespace))
+    }
+
+    fn check(&self) -> Result<()> {
+        for pattern in self.allow.iter().flatten().chain(&self.deny) {
+            check_namespace_pattern(pattern, true).with_context(|| format!("invalid namespace filter {:?}", pattern))?;
+        }
+        Ok(())
+    }
+}
+
+/// Which namespaces each caller may see and change, from `policy.toml`
+///
+/// The rule of the caller's app id applies, or else that of its uid, or
+/// else `default`. Callers of the interfaces only see the namespaces their
+/// rule allows: other settings are not found, left out of `ReadAll` and
+/// cannot be written. Without a policy every caller sees everything.
+///
+/// App ids are those of the callers themselves, so app rules only match
+/// apps calling the frontend interface directly: on the impl interface the
+/// caller is xdg-desktop-portal, which gets the rule of its uid.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Policy {
+    #[serde(default)]
+    pub default: Rule,
+    /// Rules by app id
+    #[serde(default)]
+    pub apps: BTreeMap<String, Rule>,
+    /// Rules by uid, like `[users.1000]`
+    #[serde(default)]
+    pub users: BTreeMap<String, Rule>,
+}
+
+impl Policy {
+    pub fn load(path: &Path) -> Result<Self> {
+        let text = std::fs::read_to_string(path)
+            .with_context(|| format!("cannot read policy {}", path.display()))?;
+        Self::parse(&text).with_context(|| format!("invalid policy {}", path.display()))
+    }
+
+    pub fn parse(text: &str) -> Result<Self> {
+        let policy: Self = toml::from_str(text)?;
+        policy.default.check().context("default")?;
+        for (app_id, rule) in &policy.apps {
+            if app_id.is_empty() {
+                bail!("apps: empty app id");
+            }
+            rule.check().with_context(|| format!("apps.\"{}\"", app_id))?;
+        }
+        for (uid, rule) in &policy.users {
+            if uid.parse::<u32>().is_err() {
+                bail!("users: {:?} is not a uid", uid);
+            }
+            rule.check().with_context(|| format!("users.{}", uid))?;
+        }
+        Ok(policy)
+    }
+
+    /// Whether every caller sees everything, so callers need not be resolved
+    pub fn is_open(&self) -> bool {
+        let open = |rule: &Rule| rule.allow.is_none() && rule.deny.is_empty();
+        open(&self.default) && self.apps.values().all(open) && self.users.values().all(open)
+    }
+
+    /// The rule that applies to `caller`
+    pub fn rule(&self, caller: &Caller) -> &Rule {
+        let app = caller.app_id.as_ref().and_then(|app_id| self.apps.get(app_id));
+        let user = || caller.uid.and_then(|uid| self.users.get(&uid.to_string()));
+        app.or_else(user).unwrap_or(&self.default)
+    }
+
+    pub fn allows(&self, caller: &Caller, namespace: &str) -> bool {
+        self.rule(caller).allows(namespace)
+    }
+
+    /// Whether every rule allows `namespace`, so its changes can be
+    /// broadcast
+    pub fn allows_everyone(&self, namespace: &str) -> bool {
+        self.default.allows(namespace)
+            && self.apps.values().all(|rule| rule.allows(namespace))
+            && self.users.values().all(|rule| rule.allows(namespace))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rules() {
+        let policy = Policy::parse(
+            r#"
+            [default]
+            allow = ["org.freedesktop.appearance"]
+
+            [apps."org.gnome.Settings"]
+
+            [apps."com.example.Kiosk"]
+            allow = ["org.freedesktop.*", "com.example.*"]
+            deny = ["com.example.Secret"]
+
+            [users.0]
+            deny = ["org.gnome.desktop.privacy"]
+            "#,
+        )
+        .unwrap();
+        assert!(!policy.is_open());
+        let caller = |app_id: Option<&str>, uid| Caller {
+            app_id: app_id.map(str::to_string),
+            uid,
+            ..Caller::default()
+        };
+
+        let unknown = caller(None, None);
+        assert!(policy.allows(&unknown, "org.freedesktop.appearance"));
+        assert!(!policy.allows(&unknown, "org.gnome.desktop.interface"));
+
+        // An app rule applies before the rule of the uid
+        let settings = caller(Some("org.gnome.Settings"), Some(0));
+        assert!(policy.allows(&settings, "org.gnome.desktop.privacy"));
+        let kiosk = caller(Some("com.example.Kiosk"), Some(1000));
+        assert!(policy.allows(&kiosk, "com.example.Kiosk"));
+        assert!(!policy.allows(&kiosk, "com.example.Secret"));
+        assert!(!policy.allows(&kiosk, "org.gnome.desktop.interface"));
+
+        let root = caller(Some("org.example.Other"), Some(0));
+        assert!(policy.allows(&root, "org.gnome.desktop.interface"));
+        assert!(!policy.allows(&root, "org.gnome.desktop.privacy"));
+
+        assert!(policy.allows_everyone("org.freedesktop.appearance"));
+        assert!(!policy.allows_everyone("org.gnome.desktop.interface"));
+        assert!(Policy::parse("").unwrap().allows_everyone("com.example.Secret"));
+        assert!(Policy::parse("").unwrap().is_open());
+        assert!(Policy::parse("[default]\nallow = [\"org.*.appearance\"]").is_err());
+        assert!(Policy::parse("[users.root]\nallow = []").is_err());
+    }
+
+    #[test]
+    fn test_flatpak_info() {
+        let info = "[Application]\nname=org.gnome.TextEditor\nruntime=runtime/org.gnome.Platform/x86_64/47\n\n[Instance]\nname=other\n";
+        assert_eq!(parse_flatpak_info(info).as_deref(), Some("org.gnome.TextEditor"));
+        assert_eq!(parse_flatpak_info("[Instance]\nname=org.example.App\n"), None);
+        assert_eq!(parse_flatpak_info(""), None);
+        assert_eq!(security_label(b"unconfined\0"), "unconfined");
+    }
+
+    #[test]
+    fn test_display() {
+        assert_eq!(Caller::default().to_string(), "-");
+        let caller = Caller {
+            sender: Some(":1.42".to_string()),
+            app_id: Some("org.gnome.TextEditor".to_string()),
+            uid: Some(1000),
+            pid: Some(42),
+            security_label: None,
+        };
+        assert_eq!(caller.to_string(), "sender=:1.42 app_id=org.gnome.TextEditor uid=1000 pid=42");
+    }
+}
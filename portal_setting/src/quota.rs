@@ -0,0 +1,115 @@
+use crate::{ChangeObserver, ChangeSource, SettingChange, SettingKey, SettingsStore};
+use futures_util::StreamExt;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use zbus::fdo::DBusProxy;
+use zbus::Connection;
+
+/// Keys one sender created in unregistered namespaces
+#[derive(Default)]
+struct Created {
+    keys: HashMap<SettingKey, Scope>,
+}
+
+/// How long a created key outlives its sender
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Scope {
+    /// Kept until deleted
+    Persistent,
+    /// Deleted when the sender's connection closes
+    Session,
+}
+
+/// Which D-Bus sender created which unregistered keys
+///
+/// Each sender may create up to `LimitsConfig::max_keys_per_sender` keys in
+/// namespaces without defaults. Keys that are deleted or reset no longer
+/// count; `run` removes session-scoped keys once their sender disconnects.
+/// Clones share the same records.
+#[derive(Clone, Default)]
+pub struct SenderKeys {
+    senders: Arc<Mutex<HashMap<String, Created>>>,
+}
+
+impl SenderKeys {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Number of keys `sender` created that still exist
+    pub fn count(&self, sender: &str) -> usize {
+        self.senders.lock().unwrap().get(sender).map_or(0, |created| created.keys.len())
+    }
+
+    /// Record that `sender` created `key`
+    pub fn insert(&self, sender: &str, key: SettingKey, scope: Scope) {
+        let mut senders = self.senders.lock().unwrap();
+        senders.entry(sender.to_string()).or_default().keys.insert(key, scope);
+    }
+
+    /// Forget `sender`, returning its session-scoped keys
+    fn remove_sender(&self, sender: &str) -> Vec<SettingKey> {
+        let created = self.senders.lock().unwrap().remove(sender).unwrap_or_default();
+        created
+            .keys
+            .into_iter()
+            .filter(|(_, scope)| *scope == Scope::Session)
+            .map(|(key, _)| key)
+            .collect()
+    }
+
+    /// Delete the session-scoped keys of every sender that disconnects from
+    /// the bus of `connection`, until the connection closes
+    pub async fn run(self, connection: Connection, store: SettingsStore) -> zbus::Result<()> {
+        let dbus = DBusProxy::new(&connection).await?;
+        let mut changes = dbus.receive_name_owner_changed().await?;
+        while let Some(change) = changes.next().await {
+            let Ok(args) = change.args() else { continue };
+            // A unique name losing its owner is a closed connection
+            if !args.name().starts_with(':') || args.new_owner().is_some() {
+                continue;
+            }
+            for key in self.remove_sender(args.name()) {
+                store.delete_from(ChangeSource::DBus, &key.namespace, &key.key).await;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl ChangeObserver for SenderKeys {
+    fn on_change(&self, change: &SettingChange) {
+        // Only deletions and resets of keys without a default remove them
+        if change.new.is_some() {
+            return;
+        }
+        let key = SettingKey::new(&change.namespace, &change.key);
+        for created in self.senders.lock().unwrap().values_mut() {
+            created.keys.remove(&key);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use zbus::zvariant::OwnedValue;
+
+    #[tokio::test]
+    async fn test_deleted_keys_no_longer_count() {
+        let store = SettingsStore::new();
+        let senders = SenderKeys::new();
+        store.add_observer(Arc::new(senders.clone()));
+
+        store.write("com.example", "a", OwnedValue::from(1i32)).await.unwrap();
+        store.write("com.example", "b", OwnedValue::from(1i32)).await.unwrap();
+        senders.insert(":1.1", SettingKey::new("com.example", "a"), Scope::Persistent);
+        senders.insert(":1.1", SettingKey::new("com.example", "b"), Scope::Session);
+        assert_eq!(senders.count(":1.1"), 2);
+
+        store.delete("com.example", "a").await;
+        assert_eq!(senders.count(":1.1"), 1);
+        assert_eq!(senders.remove_sender(":1.1"), [SettingKey::new("com.example", "b")]);
+        assert_eq!(senders.count(":1.1"), 0);
+    }
+}
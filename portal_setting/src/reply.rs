@@ -0,0 +1,122 @@
+use crate::shards::Shards;
+use crate::SettingValue;
+use serde::{Serialize, Serializer};
+use std::collections::HashMap;
+use std::ops::Deref;
+use std::sync::Arc;
+use zbus::zvariant::{Signature, Type};
+
+/// Settings by namespace and key, as returned by `ReadAll`; each namespace's
+/// keys are shared with the snapshot they were read from
+pub type Settings = HashMap<Arc<str>, Arc<HashMap<Arc<str>, SettingValue>>>;
+
+/// Namespace filters remembered; more distinct filters start over
+const MAX_FILTERS: usize = 16;
+
+/// A `ReadAll` reply shared by every call that asks for the same settings
+///
+/// Building one copies no setting, only a pointer per namespace, so a large
+/// `ReadAll` costs next to nothing however many keys it returns. Serializes
+/// like the `a{sa{sv}}` map it wraps, without copying it.
+#[derive(Debug, Clone, Default)]
+pub struct SettingsReply(Arc<Settings>);
+
+impl Deref for SettingsReply {
+    type Target = Settings;
+
+    fn deref(&self) -> &Settings {
+        &self.0
+    }
+}
+
+impl SettingsReply {
+    /// This reply with only the namespaces `keep` is true for, still sharing
+    /// their keys
+    pub(crate) fn retain(self, keep: impl Fn(&str) -> bool) -> Self {
+        if self.0.keys().all(|namespace| keep(namespace)) {
+            return self;
+        }
+        let kept = self
+            .0
+            .iter()
+            .filter(|(namespace, _)| keep(namespace))
+            .map(|(namespace, keys)| (namespace.clone(), keys.clone()))
+            .collect();
+        Self(Arc::new(kept))
+    }
+}
+
+impl Serialize for SettingsReply {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_map(self.0.iter().map(|(namespace, keys)| (namespace, &**keys)))
+    }
+}
+
+impl Type for SettingsReply {
+    fn signature() -> Signature<'static> {
+        <HashMap<String, HashMap<String, SettingValue>>>::signature()
+    }
+}
+
+/// Replies by namespace filter, each with the generations it was built from
+///
+/// A reply stays valid until one of the namespaces it covers changes, so
+/// changes elsewhere do not rebuild it.
+pub(crate) struct ReplyCache {
+    replies: HashMap<Vec<String>, (Vec<Option<u64>>, SettingsReply)>,
+}
+
+impl ReplyCache {
+    pub(crate) fn new() -> Self {
+        Self {
+            replies: HashMap::new(),
+        }
+    }
+
+    /// The reply for `namespaces` in `shards`, or all of them, unless a
+    /// cached one is still valid
+    pub(crate) fn get(&mut self, shards: &Shards, mut namespaces: Vec<String>) -> SettingsReply {
+        namespaces.sort();
+        namespaces.dedup();
+        // Without a filter, any change matters, and so it does for globs,
+        // which cover namespaces yet to be created
+        let generations: Vec<Option<u64>> = match namespaces.is_empty() {
+            true => vec![Some(shards.generation())],
+            false => namespaces
+                .iter()
+                .map(|pattern| match pattern.ends_with('*') {
+                    true => Some(shards.generation()),
+                    false => shards.namespace(pattern).map(|n| n.generation),
+                })
+                .collect(),
+        };
+        if let Some((built, reply)) = self.replies.get(&namespaces) {
+            if *built == generations {
+                return reply.clone();
+            }
+        }
+
+        let reply = SettingsReply(Arc::new(share(shards, &namespaces)));
+        if self.replies.len() >= MAX_FILTERS && !self.replies.contains_key(&namespaces) {
+            self.replies.clear();
+        }
+        self.replies.insert(namespaces, (generations, reply.clone()));
+        reply
+    }
+}
+
+/// The keys of the namespaces `namespaces` select, or of all of them, shared
+/// with `shards`
+fn share(shards: &Shards, namespaces: &[String]) -> Settings {
+    match namespaces.is_empty() {
+        true => shards
+            .namespaces()
+            .map(|(name, namespace)| (name.clone(), namespace.keys.clone()))
+            .collect(),
+        false => namespaces
+            .iter()
+            .flat_map(|pattern| shards.matching(pattern))
+            .map(|(name, namespace)| (name.clone(), namespace.keys.clone()))
+            .collect(),
+    }
+}
@@ -0,0 +1,141 @@
+use crate::error::Result;
+use crate::pattern::matches_namespace;
+use crate::{schema, Config};
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+/// Where the service found itself running
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Sandbox {
+    /// A Flatpak sandbox, which has `/.flatpak-info`
+    Flatpak,
+    /// A container, by the name of its manager (`docker`, `podman`,
+    /// `systemd-nspawn`, ...)
+    Container(String),
+    /// None detected, but `[sandbox] mode = "on"`
+    Configured,
+}
+
+impl fmt::Display for Sandbox {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Sandbox::Flatpak => f.write_str("a Flatpak sandbox"),
+            Sandbox::Container(manager) => write!(f, "a {} container", manager),
+            Sandbox::Configured => f.write_str("sandbox mode"),
+        }
+    }
+}
+
+/// The sandbox the service runs in, if any
+pub fn detect() -> Option<Sandbox> {
+    detect_in(Path::new("/"), std::env::var("container").ok().as_deref())
+}
+
+/// The sandbox of a process with the root directory `root` and the
+/// `container` environment variable that container managers set
+fn detect_in(root: &Path, container: Option<&str>) -> Option<Sandbox> {
+    if root.join(".flatpak-info").exists() {
+        return Some(Sandbox::Flatpak);
+    }
+    if let Some(manager) = container.filter(|manager| !manager.is_empty()) {
+        return Some(Sandbox::Container(manager.to_string()));
+    }
+    if root.join("run/.containerenv").exists() {
+        return Some(Sandbox::Container("podman".to_string()));
+    }
+    if root.join(".dockerenv").exists() {
+        return Some(Sandbox::Container("docker".to_string()));
+    }
+    None
+}
+
+/// Apply the conservative defaults of a sandbox to `config`, returning the
+/// namespaces no longer served
+///
+/// Built-in namespaces not matched by `[sandbox] namespaces` are disabled,
+/// dropping their configured values and aliases, and clients can no longer
+/// add keys without a default. Fails on invalid namespace filters.
+pub fn restrict(config: &mut Config) -> Result<Vec<String>> {
+    config.sandbox.check()?;
+    let mut hidden: Vec<String> = Vec::new();
+    for setting in schema::BUILTIN {
+        let namespace = setting.namespace;
+        let served = config.sandbox.namespaces.iter().any(|pattern| matches_namespace(pattern, namespace));
+        if !served && !config.is_disabled(namespace) {
+            config.disabled_namespaces.push(namespace.to_string());
+            hidden.push(namespace.to_string());
+        }
+    }
+    let is_hidden = |namespace: &str| hidden.iter().any(|n| n == namespace);
+    config.settings.retain(|namespace, _| !is_hidden(namespace));
+    config.aliases.retain(|namespace, keys| {
+        keys.retain(|_, alias| !is_hidden(alias.namespace.as_deref().unwrap_or(namespace)));
+        !is_hidden(namespace) && !keys.is_empty()
+    });
+    config.limits.strict = true;
+    Ok(hidden)
+}
+
+/// Where the state file `path` is kept instead when sandboxed: in the
+/// session's runtime directory, so nothing is written to the host and
+/// changes last for the session only; `None` without one
+pub fn session_state_path(path: &Path) -> Option<PathBuf> {
+    let runtime_dir = std::env::var_os("XDG_RUNTIME_DIR").filter(|dir| !dir.is_empty())?;
+    let mut file_name = std::ffi::OsString::from("portal-settings-");
+    file_name.push(path.file_name()?);
+    Some(Path::new(&runtime_dir).join(file_name))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_detect() {
+        let root = std::env::temp_dir().join(format!("portal-sandbox-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&root);
+        std::fs::create_dir_all(root.join("run")).unwrap();
+        assert_eq!(detect_in(&root, None), None);
+        assert_eq!(detect_in(&root, Some("")), None);
+        assert_eq!(detect_in(&root, Some("systemd-nspawn")), Some(Sandbox::Container("systemd-nspawn".to_string())));
+        std::fs::write(root.join(".dockerenv"), "").unwrap();
+        assert_eq!(detect_in(&root, None), Some(Sandbox::Container("docker".to_string())));
+        std::fs::write(root.join(".flatpak-info"), "[Application]\nname=org.example.Test\n").unwrap();
+        assert_eq!(detect_in(&root, Some("podman")), Some(Sandbox::Flatpak));
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[cfg(feature = "gnome")]
+    #[tokio::test]
+    async fn test_restrict() {
+        use crate::SettingsStore;
+        use zbus::zvariant::OwnedValue;
+
+        let mut config = Config::parse(
+            r#"
+            [settings."org.gnome.desktop.interface"]
+            clock-format = "12h"
+
+            [settings."com.example.Kiosk"]
+            clock = "24h"
+            "#,
+        )
+        .unwrap();
+        let hidden = restrict(&mut config).unwrap();
+        assert!(hidden.iter().any(|n| n == "org.gnome.desktop.interface"));
+        assert!(!hidden.iter().any(|n| n == "org.freedesktop.appearance"));
+
+        let store = SettingsStore::from_config(&config).unwrap();
+        assert!(store.read("org.freedesktop.appearance", "color-scheme").await.is_some());
+        assert!(store.read("org.gnome.desktop.interface", "clock-format").await.is_none());
+        assert!(store.read("com.example.Kiosk", "clock").await.is_some());
+        assert!(store.write("com.example.Kiosk", "other", OwnedValue::from(1u32)).await.is_err());
+    }
+
+    #[test]
+    fn test_invalid_namespaces() {
+        let mut config = Config::parse("[sandbox]\nnamespaces = [\"org.*.appearance\"]\n").unwrap();
+        assert!(restrict(&mut config).is_err());
+        assert_eq!(crate::config::check("[sandbox]\nnamespaces = [\"org.*.appearance\"]\n").len(), 1);
+    }
+}
@@ -0,0 +1,399 @@
+use crate::error::{bail, Result};
+use crate::ValidationError;
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::fmt;
+use zbus::zvariant::{OwnedValue, Str, Value};
+
+/// Default value of a built-in setting
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DefaultValue {
+    U32(u32),
+    I32(i32),
+    Bool(bool),
+    Str(&'static str),
+    /// RGB tuple with components in 0.0-1.0
+    Rgb(f64, f64, f64),
+}
+
+impl DefaultValue {
+    pub fn to_value(self) -> OwnedValue {
+        let value = match self {
+            DefaultValue::U32(v) => Value::U32(v),
+            DefaultValue::I32(v) => Value::I32(v),
+            DefaultValue::Bool(v) => Value::Bool(v),
+            DefaultValue::Str(v) => Value::Str(Str::from_static(v)),
+            DefaultValue::Rgb(r, g, b) => Value::Structure((r, g, b).into()),
+        };
+        value.try_into().unwrap()
+    }
+
+    /// D-Bus signature of the value
+    pub fn signature(self) -> &'static str {
+        match self {
+            DefaultValue::U32(_) => "u",
+            DefaultValue::I32(_) => "i",
+            DefaultValue::Bool(_) => "b",
+            DefaultValue::Str(_) => "s",
+            DefaultValue::Rgb(..) => "(ddd)",
+        }
+    }
+}
+
+/// `accent-color` meaning "no accent color"
+///
+/// The spec treats any out-of-range color as unset; this is the one
+/// out-of-range value the portal accepts.
+pub const ACCENT_COLOR_UNSET: (f64, f64, f64) = (-1.0, -1.0, -1.0);
+
+/// Description of a setting known to the portal
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct KeySchema {
+    pub namespace: &'static str,
+    pub key: &'static str,
+    pub description: &'static str,
+    pub default: DefaultValue,
+    /// Accepted values of a string setting; empty accepts any string
+    pub choices: &'static [&'static str],
+}
+
+/// All settings served out of the box
+///
+/// The `org.gnome.desktop.*` namespaces require the `gnome` feature.
+///
+/// Defaults follow the XDG portal spec:
+/// https://flatpak.github.io/xdg-desktop-portal/docs/doc-org.freedesktop.portal.Settings.html
+pub static BUILTIN: &[KeySchema] = &[
+    // org.freedesktop.appearance namespace
+    KeySchema {
+        namespace: "org.freedesktop.appearance",
+        key: "color-scheme",
+        description: "Color scheme preference (0: no preference, 1: dark, 2: light)",
+        default: DefaultValue::U32(0),
+        choices: &[],
+    },
+    KeySchema {
+        namespace: "org.freedesktop.appearance",
+        key: "accent-color",
+        description: "Accent color as RGB values (0.0-1.0)",
+        default: DefaultValue::Rgb(0.0, 0.0, 0.0),
+        choices: &[],
+    },
+    KeySchema {
+        namespace: "org.freedesktop.appearance",
+        key: "contrast",
+        description: "Contrast preference (0: no preference, 1: high contrast)",
+        default: DefaultValue::U32(0),
+        choices: &[],
+    },
+    // org.gnome.desktop.interface namespace
+    #[cfg(feature = "gnome")]
+    KeySchema {
+        namespace: "org.gnome.desktop.interface",
+        key: "gtk-theme",
+        description: "GTK theme name",
+        default: DefaultValue::Str("Adwaita"),
+        choices: &[],
+    },
+    #[cfg(feature = "gnome")]
+    KeySchema {
+        namespace: "org.gnome.desktop.interface",
+        key: "icon-theme",
+        description: "Icon theme name",
+        default: DefaultValue::Str("Adwaita"),
+        choices: &[],
+    },
+    #[cfg(feature = "gnome")]
+    KeySchema {
+        namespace: "org.gnome.desktop.interface",
+        key: "cursor-theme",
+        description: "Cursor theme name",
+        default: DefaultValue::Str("Adwaita"),
+        choices: &[],
+    },
+    #[cfg(feature = "gnome")]
+    KeySchema {
+        namespace: "org.gnome.desktop.interface",
+        key: "font-name",
+        description: "Default font",
+        default: DefaultValue::Str("Cantarell 11"),
+        choices: &[],
+    },
+    #[cfg(feature = "gnome")]
+    KeySchema {
+        namespace: "org.gnome.desktop.interface",
+        key: "monospace-font-name",
+        description: "Monospace font",
+        default: DefaultValue::Str("Source Code Pro 10"),
+        choices: &[],
+    },
+    #[cfg(feature = "gnome")]
+    KeySchema {
+        namespace: "org.gnome.desktop.interface",
+        key: "clock-format",
+        description: "Clock format preference (\"12h\" or \"24h\")",
+        default: DefaultValue::Str("24h"),
+        choices: &["12h", "24h"],
+    },
+    // org.gnome.desktop.privacy namespace
+    #[cfg(feature = "gnome")]
+    KeySchema {
+        namespace: "org.gnome.desktop.privacy",
+        key: "remember-recent-files",
+        description: "Whether to remember recently opened files",
+        default: DefaultValue::Bool(true),
+        choices: &[],
+    },
+    #[cfg(feature = "gnome")]
+    KeySchema {
+        namespace: "org.gnome.desktop.privacy",
+        key: "recent-files-max-age",
+        description: "Maximum age in days for recent files",
+        default: DefaultValue::I32(30),
+        choices: &[],
+    },
+];
+
+/// Look up a built-in setting
+pub fn lookup(namespace: &str, key: &str) -> Option<&'static KeySchema> {
+    BUILTIN
+        .iter()
+        .find(|s| s.namespace == namespace && s.key == key)
+}
+
+/// Whether `namespace` is a reverse-DNS name like those of the portal
+/// specification: at most 255 bytes of at least two elements separated by
+/// dots, each of ASCII letters, digits, `_` and `-` and not starting with a
+/// digit
+pub fn is_reverse_dns(namespace: &str) -> bool {
+    let valid_element = |element: &str| {
+        element.chars().all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+            && element.chars().next().is_some_and(|c| !c.is_ascii_digit())
+    };
+    namespace.len() <= 255 && namespace.contains('.') && namespace.split('.').all(valid_element)
+}
+
+/// Whether `text` is free of NULs and of control characters other than tab,
+/// line feed and carriage return
+pub fn is_clean_text(text: &str) -> bool {
+    !text.chars().any(|c| c.is_control() && !matches!(c, '\t' | '\n' | '\r'))
+}
+
+/// The first string in `value`, at any depth and including dictionary keys,
+/// that `reject` is true for
+pub(crate) fn find_string(value: &Value<'_>, reject: &dyn Fn(&str) -> bool) -> Option<String> {
+    find_nested(value, &|value| match value {
+        Value::Str(text) if reject(text.as_str()) => Some(text.to_string()),
+        _ => None,
+    })
+}
+
+/// The first double in `value`, at any depth, that is NaN or infinite
+pub(crate) fn find_non_finite(value: &Value<'_>) -> Option<f64> {
+    find_nested(value, &|value| match value {
+        Value::F64(number) if !number.is_finite() => Some(*number),
+        _ => None,
+    })
+}
+
+/// What `found` returns for `value` or, failing that, for the first value
+/// nested in it that it returns something for
+fn find_nested<T>(value: &Value<'_>, found: &dyn Fn(&Value<'_>) -> Option<T>) -> Option<T> {
+    if let Some(result) = found(value) {
+        return Some(result);
+    }
+    match value {
+        Value::Value(inner) => find_nested(inner, found),
+        Value::Array(array) => array.inner().iter().find_map(|item| find_nested(item, found)),
+        Value::Dict(dict) => dict
+            .iter()
+            .find_map(|(key, value)| find_nested(key, found).or_else(|| find_nested(value, found))),
+        Value::Structure(structure) => structure.fields().iter().find_map(|field| find_nested(field, found)),
+        _ => None,
+    }
+}
+
+/// Constraints declared for settings in the `[schema]` tables of the
+/// configuration, keyed by namespace and key
+pub type Constraints = BTreeMap<String, BTreeMap<String, Constraint>>;
+
+/// Declarative constraint of a setting, e.g.
+/// `{ type = "i", min = 1, max = 365 }`
+///
+/// Written values must have the declared type and meet every other field
+/// that is set. Built-in settings keep their own checks as well.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Constraint {
+    /// D-Bus signature: `b`, `i`, `u`, `x`, `d`, `s` or `as`
+    #[serde(rename = "type")]
+    pub signature: String,
+    /// Smallest accepted number
+    pub min: Option<f64>,
+    /// Largest accepted number
+    pub max: Option<f64>,
+    /// Accepted strings
+    pub choices: Option<Vec<String>>,
+    /// Regular expression strings must match; anchor it to match all of them
+    pub pattern: Option<Pattern>,
+    /// Fewest characters of a string or elements of an array
+    pub min_length: Option<usize>,
+    /// Most characters of a string or elements of an array
+    pub max_length: Option<usize>,
+    /// Convert written values of a close type: `i` to `u` and back when in
+    /// range, and the strings `true` and `false` to `b`
+    #[serde(default)]
+    pub coerce: bool,
+    /// Reject every change, leaving the setting at its default
+    #[serde(default)]
+    pub read_only: bool,
+}
+
+impl Constraint {
+    /// Reject constraints that cannot apply to their type
+    pub fn check(&self) -> Result<()> {
+        let numeric = matches!(self.signature.as_str(), "i" | "u" | "x" | "d");
+        let string = self.signature == "s";
+        if !matches!(self.signature.as_str(), "b" | "i" | "u" | "x" | "d" | "s" | "as") {
+            bail!("unsupported type {}", self.signature);
+        }
+        if (self.min.is_some() || self.max.is_some()) && !numeric {
+            bail!("min and max only apply to numbers");
+        }
+        if (self.choices.is_some() || self.pattern.is_some()) && !string {
+            bail!("choices and pattern only apply to strings");
+        }
+        if (self.min_length.is_some() || self.max_length.is_some()) && !(string || self.signature == "as") {
+            bail!("min_length and max_length only apply to strings and arrays of strings");
+        }
+        Ok(())
+    }
+
+    /// `value` converted to the declared type, if `coerce` is set and it can
+    /// be converted without loss
+    pub fn coerce(&self, value: &OwnedValue) -> Option<OwnedValue> {
+        if !self.coerce {
+            return None;
+        }
+        let coerced = match (self.signature.as_str(), &**value) {
+            ("u", Value::I32(v)) => Value::U32(u32::try_from(*v).ok()?),
+            ("i", Value::U32(v)) => Value::I32(i32::try_from(*v).ok()?),
+            ("b", Value::Str(s)) if s.as_str() == "true" => Value::Bool(true),
+            ("b", Value::Str(s)) if s.as_str() == "false" => Value::Bool(false),
+            _ => return None,
+        };
+        OwnedValue::try_from(coerced).ok()
+    }
+
+    /// Accept `value` for `namespace`/`key` if it meets this constraint
+    pub fn validate(&self, namespace: &str, key: &str, value: &OwnedValue) -> Result<()> {
+        if value.value_signature().as_str() == self.signature && self.accepts(value) {
+            return Ok(());
+        }
+        let invalid = ValidationError::new(namespace, key, &self.signature, value);
+        let description = self.to_string();
+        Err(match description.is_empty() {
+            true => invalid,
+            false => invalid.with_constraint(description),
+        }
+        .into())
+    }
+
+    /// Whether a value of the declared type meets the other fields
+    fn accepts(&self, value: &OwnedValue) -> bool {
+        let number = match &**value {
+            Value::I32(v) => Some(f64::from(*v)),
+            Value::U32(v) => Some(f64::from(*v)),
+            Value::I64(v) => Some(*v as f64),
+            Value::F64(v) => Some(*v),
+            _ => None,
+        };
+        if let Some(number) = number {
+            if number.is_nan() || self.min.is_some_and(|min| number < min) || self.max.is_some_and(|max| number > max) {
+                return false;
+            }
+        }
+        let length = match &**value {
+            Value::Str(s) => Some(s.chars().count()),
+            Value::Array(a) => Some(a.len()),
+            _ => None,
+        };
+        if let Some(length) = length {
+            if self.min_length.is_some_and(|min| length < min) || self.max_length.is_some_and(|max| length > max) {
+                return false;
+            }
+        }
+        if let Value::Str(s) = &**value {
+            if self.choices.as_ref().is_some_and(|choices| !choices.iter().any(|c| c == s.as_str())) {
+                return false;
+            }
+            if self.pattern.as_ref().is_some_and(|pattern| !pattern.is_match(s)) {
+                return false;
+            }
+        }
+        true
+    }
+}
+
+// The constraints besides the type, e.g. `1..=365, /^[a-z]+$/`; empty if
+// there are none
+impl fmt::Display for Constraint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut parts = Vec::new();
+        let range = |min: Option<String>, max: Option<String>| match (min, max) {
+            (None, None) => None,
+            (min, max) => Some(format!("{}..={}", min.unwrap_or_default(), max.unwrap_or_default())),
+        };
+        parts.extend(range(self.min.map(|v| v.to_string()), self.max.map(|v| v.to_string())));
+        if let Some(choices) = &self.choices {
+            parts.push(choices.join("|"));
+        }
+        if let Some(pattern) = &self.pattern {
+            parts.push(format!("/{}/", pattern.as_str()));
+        }
+        let length = range(self.min_length.map(|v| v.to_string()), self.max_length.map(|v| v.to_string()));
+        parts.extend(length.map(|length| format!("length {}", length)));
+        f.write_str(&parts.join(", "))
+    }
+}
+
+/// A regular expression of a `pattern` constraint
+///
+/// Matching needs the `regex` feature; without it, declaring a pattern is an
+/// error.
+#[derive(Debug, Clone)]
+pub struct Pattern {
+    source: String,
+    #[cfg(feature = "regex")]
+    regex: regex::Regex,
+}
+
+impl Pattern {
+    pub fn new(source: &str) -> Result<Self> {
+        #[cfg(feature = "regex")]
+        {
+            let regex = regex::Regex::new(source).map_err(crate::Error::msg)?;
+            Ok(Self { source: source.to_string(), regex })
+        }
+        #[cfg(not(feature = "regex"))]
+        bail!("cannot use pattern {:?}: built without the `regex` feature", source)
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.source
+    }
+
+    pub fn is_match(&self, text: &str) -> bool {
+        #[cfg(feature = "regex")]
+        return self.regex.is_match(text);
+        #[cfg(not(feature = "regex"))]
+        unreachable!("patterns cannot be created without the `regex` feature: {}", text)
+    }
+}
+
+impl<'de> Deserialize<'de> for Pattern {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> std::result::Result<Self, D::Error> {
+        let source = String::deserialize(deserializer)?;
+        Pattern::new(&source).map_err(serde::de::Error::custom)
+    }
+}
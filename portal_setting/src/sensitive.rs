@@ -0,0 +1,77 @@
+use crate::error::{bail, Result};
+use crate::pattern::glob_match;
+use std::sync::Arc;
+use zbus::zvariant::Value;
+
+/// Written in place of the values of sensitive settings
+pub const REDACTED: &str = "<redacted>";
+
+/// Settings whose values are kept out of logs, dumps and exports, such as
+/// proxy credentials in a custom namespace
+///
+/// Each pattern is `namespace/key`, both halves globs as in `glob_match`, so
+/// `com.example.Proxy/*` covers a whole namespace. Clones share the patterns.
+#[derive(Debug, Clone, Default)]
+pub struct SensitiveKeys(Arc<Vec<(String, String)>>);
+
+impl SensitiveKeys {
+    pub fn new<S: AsRef<str>>(patterns: impl IntoIterator<Item = S>) -> Result<Self> {
+        let mut parsed = Vec::new();
+        for pattern in patterns {
+            let pattern = pattern.as_ref();
+            match pattern.split_once('/') {
+                Some((namespace, key)) if !namespace.is_empty() && !key.is_empty() => {
+                    parsed.push((namespace.to_string(), key.to_string()));
+                }
+                _ => bail!("{:?}: expected namespace/key, e.g. com.example.Proxy/password", pattern),
+            }
+        }
+        Ok(Self(Arc::new(parsed)))
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    pub fn contains(&self, namespace: &str, key: &str) -> bool {
+        self.0
+            .iter()
+            .any(|(n, k)| glob_match(n, namespace) && glob_match(k, key))
+    }
+
+    /// The patterns, as configured
+    pub fn patterns(&self) -> Vec<String> {
+        self.0.iter().map(|(namespace, key)| format!("{}/{}", namespace, key)).collect()
+    }
+
+    /// `value` in GVariant text format, or `REDACTED` if `namespace`/`key`
+    /// is sensitive
+    pub fn show(&self, namespace: &str, key: &str, value: &Value<'_>) -> String {
+        match self.contains(namespace, key) {
+            true => REDACTED.to_string(),
+            false => value.to_string(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sensitive_keys() {
+        let sensitive = SensitiveKeys::new(["com.example.Proxy/*", "org.example.*/password"]).unwrap();
+        assert!(sensitive.contains("com.example.Proxy", "user"));
+        assert!(sensitive.contains("org.example.Mail", "password"));
+        assert!(!sensitive.contains("org.example.Mail", "server"));
+        assert!(!sensitive.contains("org.freedesktop.appearance", "color-scheme"));
+        assert_eq!(sensitive.show("com.example.Proxy", "user", &Value::from("alice")), REDACTED);
+        let server = Value::from("mail.example.com");
+        assert_eq!(sensitive.show("org.example.Mail", "server", &server), server.to_string());
+        assert_eq!(sensitive.patterns(), ["com.example.Proxy/*", "org.example.*/password"]);
+
+        assert!(SensitiveKeys::new(["com.example.Proxy"]).is_err());
+        assert!(SensitiveKeys::new(["/password"]).is_err());
+        assert!(SensitiveKeys::default().is_empty());
+    }
+}
@@ -0,0 +1,207 @@
+use crate::pattern::matches_namespace;
+use crate::{SettingKey, SettingValue};
+use std::collections::HashMap;
+use std::sync::Arc;
+
+/// Generation of a new store; 0 comes before any store, so clients without
+/// settings can pass it to see everything as changed
+pub(crate) const FIRST_GENERATION: u64 = 1;
+
+/// The settings of one namespace
+///
+/// Cloning shares the keys; they are copied by the first change to a clone.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct Namespace {
+    /// Store-wide generation of the last change to this namespace
+    pub(crate) generation: u64,
+    pub(crate) keys: Arc<HashMap<Arc<str>, SettingValue>>,
+}
+
+/// All settings, by namespace
+///
+/// Cloning shares the keys of every namespace, and changing a clone copies
+/// only the namespace it changes, so a snapshot costs one pointer per
+/// namespace.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct Shards {
+    /// Increased by every change; namespaces record it when they change
+    generation: u64,
+    /// Generation of the last change that removed a namespace
+    removed: u64,
+    namespaces: HashMap<Arc<str>, Namespace>,
+}
+
+impl Shards {
+    pub(crate) fn new(settings: HashMap<SettingKey, SettingValue>) -> Self {
+        let mut namespaces: HashMap<Arc<str>, HashMap<Arc<str>, SettingValue>> = HashMap::new();
+        for (key, value) in settings {
+            namespaces.entry(key.namespace).or_default().insert(key.key, value);
+        }
+        let namespace = |keys| Namespace {
+            generation: FIRST_GENERATION,
+            keys: Arc::new(keys),
+        };
+        Self {
+            generation: FIRST_GENERATION,
+            removed: 0,
+            namespaces: namespaces.into_iter().map(|(name, keys)| (name, namespace(keys))).collect(),
+        }
+    }
+
+    /// Generation of the last change to any namespace
+    pub(crate) fn generation(&self) -> u64 {
+        self.generation
+    }
+
+    /// Whether a change after `generation` affected any of `namespaces`, or
+    /// any namespace without a filter
+    ///
+    /// A generation this store has not reached comes from an earlier one and
+    /// counts as changed.
+    pub(crate) fn changed_since(&self, namespaces: &[String], generation: u64) -> bool {
+        // Removed namespaces leave no generation behind, so any removal counts
+        if namespaces.is_empty() || generation > self.generation || self.removed > generation {
+            return generation != self.generation;
+        }
+        namespaces
+            .iter()
+            .flat_map(|pattern| self.matching(pattern))
+            .any(|(_, namespace)| namespace.generation > generation)
+    }
+
+    pub(crate) fn namespace(&self, namespace: &str) -> Option<&Namespace> {
+        self.namespaces.get(namespace)
+    }
+
+    pub(crate) fn namespaces(&self) -> impl Iterator<Item = (&Arc<str>, &Namespace)> {
+        self.namespaces.iter()
+    }
+
+    /// The namespaces a `ReadAll` filter selects, looking plain names up
+    /// directly
+    pub(crate) fn matching<'a>(&'a self, pattern: &'a str) -> Box<dyn Iterator<Item = (&'a Arc<str>, &'a Namespace)> + 'a> {
+        match pattern.ends_with('*') {
+            true => Box::new(self.namespaces.iter().filter(move |(name, _)| matches_namespace(pattern, name))),
+            false => Box::new(self.namespaces.get_key_value(pattern).into_iter()),
+        }
+    }
+
+    pub(crate) fn get(&self, namespace: &str, key: &str) -> Option<&SettingValue> {
+        self.namespaces.get(namespace)?.keys.get(key)
+    }
+
+    pub(crate) fn contains(&self, namespace: &str, key: &str) -> bool {
+        self.get(namespace, key).is_some()
+    }
+
+    pub(crate) fn insert(&mut self, namespace: &str, key: &str, value: SettingValue) -> Option<SettingValue> {
+        self.generation += 1;
+        self.put(namespace, key, value)
+    }
+
+    /// Remove a key, and its namespace once it is empty
+    pub(crate) fn remove(&mut self, namespace: &str, key: &str) -> Option<SettingValue> {
+        if !self.contains(namespace, key) {
+            return None;
+        }
+        self.generation += 1;
+        self.take(namespace, key)
+    }
+
+    /// Set or remove a copy of another key's value, as part of the change
+    /// that changed that key
+    ///
+    /// The generation is not increased, but the namespace records it.
+    pub(crate) fn mirror(&mut self, namespace: &str, key: &str, value: Option<SettingValue>) {
+        match value {
+            Some(value) => {
+                self.put(namespace, key, value);
+            }
+            None => {
+                self.take(namespace, key);
+            }
+        }
+    }
+
+    fn put(&mut self, namespace: &str, key: &str, value: SettingValue) -> Option<SettingValue> {
+        let shard = match self.namespaces.get_mut(namespace) {
+            Some(shard) => shard,
+            None => self.namespaces.entry(Arc::from(namespace)).or_default(),
+        };
+        shard.generation = self.generation;
+        let keys = Arc::make_mut(&mut shard.keys);
+        // Replacing a value keeps the key's string
+        match keys.get_mut(key) {
+            Some(current) => Some(std::mem::replace(current, value)),
+            None => keys.insert(Arc::from(key), value),
+        }
+    }
+
+    fn take(&mut self, namespace: &str, key: &str) -> Option<SettingValue> {
+        let shard = self.namespaces.get_mut(namespace)?;
+        if !shard.keys.contains_key(key) {
+            return None;
+        }
+        shard.generation = self.generation;
+        let old = Arc::make_mut(&mut shard.keys).remove(key);
+        if shard.keys.is_empty() {
+            self.namespaces.remove(namespace);
+            self.removed = self.generation;
+        }
+        old
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use zbus::zvariant::OwnedValue;
+
+    #[test]
+    fn test_changes_copy_only_their_namespace() {
+        let value = || SettingValue::new(OwnedValue::from(1u32));
+        let mut shards = Shards::new(HashMap::from([
+            (SettingKey::new("a", "x"), value()),
+            (SettingKey::new("b", "x"), value()),
+        ]));
+        let before = shards.clone();
+
+        shards.insert("a", "y", value());
+        let keys = |shards: &Shards, namespace| shards.namespace(namespace).unwrap().keys.clone();
+        assert!(!Arc::ptr_eq(&keys(&shards, "a"), &keys(&before, "a")));
+        assert!(Arc::ptr_eq(&keys(&shards, "b"), &keys(&before, "b")));
+        assert!(before.get("a", "y").is_none());
+        assert_eq!(shards.namespace("a").unwrap().generation, 2);
+        assert_eq!(shards.namespace("b").unwrap().generation, 1);
+
+        assert!(shards.remove("b", "y").is_none());
+        assert_eq!(shards.generation(), 2);
+        assert!(shards.remove("b", "x").is_some());
+        assert!(shards.namespace("b").is_none());
+        assert_eq!(shards.generation(), 3);
+    }
+
+    #[test]
+    fn test_changed_since() {
+        let value = || SettingValue::new(OwnedValue::from(1u32));
+        let mut shards = Shards::new(HashMap::from([
+            (SettingKey::new("a", "x"), value()),
+            (SettingKey::new("b", "x"), value()),
+        ]));
+        let filter = |names: &[&str]| -> Vec<String> { names.iter().map(|n| n.to_string()).collect() };
+        assert!(shards.changed_since(&[], 0));
+        assert!(!shards.changed_since(&[], FIRST_GENERATION));
+
+        shards.insert("a", "y", value());
+        assert!(shards.changed_since(&filter(&["a"]), FIRST_GENERATION));
+        assert!(!shards.changed_since(&filter(&["b", "c"]), FIRST_GENERATION));
+        // From a store that got further
+        assert!(shards.changed_since(&filter(&["b"]), 10));
+
+        shards.insert("c", "x", value());
+        assert!(shards.changed_since(&filter(&["c"]), 2));
+        let seen = shards.generation();
+        shards.remove("c", "x");
+        assert!(shards.changed_since(&filter(&["c"]), seen));
+    }
+}
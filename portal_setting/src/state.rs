@@ -0,0 +1,832 @@
+use crate::error::{bail, Result};
+use zbus::zvariant::{Array, Dict, ObjectPath, Signature, Str, StructureBuilder, Value};
+#[cfg(feature = "persistence")]
+use {
+    crate::{ChangeKind, ChangeObserver, ChangeSource, SettingChange},
+    crate::config::{self, Config, Diagnostic, StateFormat},
+    crate::SettingsStore,
+    crate::error::Context,
+    serde::Deserialize,
+    std::collections::BTreeMap,
+    std::fmt::Write as _,
+    std::fs::{self, File},
+    std::io::Write as _,
+    std::path::{Path, PathBuf},
+    std::sync::atomic::{AtomicBool, Ordering},
+    std::sync::mpsc::{self, Sender},
+    std::sync::{Arc, Mutex, OnceLock, Weak},
+    std::time::Duration,
+    zbus::zvariant::OwnedValue,
+};
+
+/// Settings changed at runtime, persisted across restarts
+///
+/// The file is rewritten atomically after every change, or once per
+/// `debounce` interval after `flush_after` is called. Each entry records
+/// the D-Bus signature next to the value, so values round-trip without
+/// losing their type:
+///
+/// ```toml
+/// [[setting]]
+/// namespace = "org.freedesktop.appearance"
+/// key = "color-scheme"
+/// signature = "u"
+/// value = 1
+/// ```
+///
+/// Resetting or deleting a key removes its entry, so the configured or
+/// built-in default applies again after a restart.
+///
+/// With `StateFormat::Binary` the entries are written in the D-Bus wire
+/// format instead, which loads much faster but cannot be edited by hand.
+/// Files in either format are read, so switching formats keeps the entries.
+#[cfg(feature = "persistence")]
+pub struct StateFile {
+    path: PathBuf,
+    format: StateFormat,
+    entries: Mutex<BTreeMap<(String, String), OwnedValue>>,
+    /// Set by changes not saved yet
+    dirty: AtomicBool,
+    /// Held while saving, so concurrent saves do not share the temporary file
+    saving: Mutex<()>,
+    /// Wakes the flushing thread, when changes are coalesced
+    flusher: OnceLock<Sender<()>>,
+    /// Cleared until `load` has read the file, which is not written before
+    loaded: AtomicBool,
+}
+
+#[cfg(feature = "persistence")]
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct StateDocument {
+    #[serde(default)]
+    setting: Vec<StateEntry>,
+}
+
+#[cfg(feature = "persistence")]
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct StateEntry {
+    namespace: String,
+    key: String,
+    signature: String,
+    value: toml::Spanned<toml::Value>,
+}
+
+#[cfg(feature = "persistence")]
+impl StateFile {
+    /// Open the state file at `path`, loading its entries if it exists
+    pub fn open(path: impl Into<PathBuf>) -> Result<Self> {
+        let path = path.into();
+        let entries = read(&path)?;
+        Ok(Self {
+            path,
+            format: StateFormat::default(),
+            entries: Mutex::new(entries),
+            dirty: AtomicBool::new(false),
+            saving: Mutex::new(()),
+            flusher: OnceLock::new(),
+            loaded: AtomicBool::new(true),
+        })
+    }
+
+    /// The state file at `path`, without reading it yet
+    ///
+    /// Changes are recorded right away, but nothing is written until `load`
+    /// has read the existing entries.
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self {
+            path: path.into(),
+            format: StateFormat::default(),
+            entries: Mutex::new(BTreeMap::new()),
+            dirty: AtomicBool::new(false),
+            saving: Mutex::new(()),
+            flusher: OnceLock::new(),
+            loaded: AtomicBool::new(false),
+        }
+    }
+
+    /// Read the file from a blocking thread and return its entries
+    ///
+    /// Entries for settings changed since `new` keep their newer value, in
+    /// the file as in the returned entries. Changes recorded meanwhile are
+    /// saved once the file is read.
+    pub async fn load(&self) -> Result<Vec<(String, String, OwnedValue)>> {
+        let path = self.path.clone();
+        let persisted = tokio::task::spawn_blocking(move || read(&path))
+            .await
+            .context("cannot read the state file")??;
+        let mut loaded = Vec::new();
+        {
+            let mut entries = self.entries.lock().unwrap();
+            for ((namespace, key), value) in persisted {
+                if entries.contains_key(&(namespace.clone(), key.clone())) {
+                    continue;
+                }
+                if let Ok(copy) = value.try_clone() {
+                    loaded.push((namespace.clone(), key.clone(), copy));
+                }
+                entries.insert((namespace, key), value);
+            }
+            self.loaded.store(true, Ordering::SeqCst);
+        }
+        if self.dirty.load(Ordering::SeqCst) {
+            self.changed();
+        }
+        Ok(loaded)
+    }
+
+    /// Save changes from a background thread, at most once per `debounce`
+    ///
+    /// The first change after a save is written `debounce` later, together
+    /// with every change made in between. Call `save` before exiting so the
+    /// last changes are not lost.
+    pub fn flush_after(self: &Arc<Self>, debounce: Duration) -> Result<()> {
+        let (sender, receiver) = mpsc::channel::<()>();
+        if self.flusher.set(sender).is_err() {
+            bail!("changes to {} are already flushed in the background", self.path.display());
+        }
+        let state: Weak<Self> = Arc::downgrade(self);
+        std::thread::Builder::new()
+            .name("state-flush".to_string())
+            .spawn(move || {
+                // Ends once the state file is dropped with its sender
+                while receiver.recv().is_ok() {
+                    std::thread::sleep(debounce);
+                    while receiver.try_recv().is_ok() {}
+                    let Some(state) = state.upgrade() else { break };
+                    if let Err(e) = state.save_if_dirty() {
+                        eprintln!("{:#}", e);
+                    }
+                }
+            })
+            .context("cannot start the state flushing thread")?;
+        Ok(())
+    }
+
+    /// Write the file in `format`, TOML unless set
+    pub fn with_format(mut self, format: StateFormat) -> Self {
+        self.format = format;
+        self
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Persisted values to apply on top of the defaults
+    pub fn entries(&self) -> Vec<(String, String, OwnedValue)> {
+        self.entries
+            .lock()
+            .unwrap()
+            .iter()
+            .filter_map(|((namespace, key), value)| {
+                value
+                    .try_clone()
+                    .ok()
+                    .map(|v| (namespace.clone(), key.clone(), v))
+            })
+            .collect()
+    }
+
+    /// Write all entries to disk, once they are loaded
+    pub fn save(&self) -> Result<()> {
+        if !self.loaded.load(Ordering::SeqCst) {
+            return Ok(());
+        }
+        let _saving = self.saving.lock().unwrap();
+        self.dirty.store(false, Ordering::SeqCst);
+        let contents = {
+            let entries = self.entries.lock().unwrap();
+            match self.format {
+                StateFormat::Toml => serialize(&entries)?.into_bytes(),
+                StateFormat::Binary => encode(&entries)?,
+            }
+        };
+        write_atomic(&self.path, &contents)
+            .with_context(|| format!("cannot write state file {}", self.path.display()))
+    }
+
+    fn save_if_dirty(&self) -> Result<()> {
+        match self.dirty.load(Ordering::SeqCst) {
+            true => self.save(),
+            false => Ok(()),
+        }
+    }
+
+    /// Mark the entries as changed and save them, now or after the debounce
+    fn changed(&self) {
+        self.dirty.store(true, Ordering::SeqCst);
+        if let Some(flusher) = self.flusher.get() {
+            if flusher.send(()).is_ok() {
+                return;
+            }
+        }
+        if let Err(e) = self.save() {
+            eprintln!("{:#}", e);
+        }
+    }
+}
+
+#[cfg(feature = "persistence")]
+impl ChangeObserver for StateFile {
+    fn on_change(&self, change: &SettingChange) {
+        // Overrides end with their login session, or with the service
+        if change.source == ChangeSource::Session {
+            return;
+        }
+        let id = (change.namespace.clone(), change.key.clone());
+        {
+            let mut entries = self.entries.lock().unwrap();
+            match (change.kind, &change.new) {
+                (ChangeKind::Write, Some(value)) => match value.0.try_clone() {
+                    Ok(value) => {
+                        entries.insert(id, value);
+                    }
+                    Err(e) => {
+                        eprintln!("Not persisting {}/{}: {}", change.namespace, change.key, e);
+                        return;
+                    }
+                },
+                _ => {
+                    entries.remove(&id);
+                }
+            }
+        }
+        self.changed();
+    }
+}
+
+#[cfg(feature = "persistence")]
+fn read(path: &Path) -> Result<BTreeMap<(String, String), OwnedValue>> {
+    let parsed = match fs::read(path) {
+        Ok(contents) if contents.starts_with(BINARY_MAGIC) => decode(&contents[BINARY_MAGIC.len()..]),
+        Ok(contents) => std::str::from_utf8(&contents)
+            .map_err(crate::Error::from)
+            .and_then(parse),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(BTreeMap::new()),
+        Err(e) => return Err(e).with_context(|| format!("cannot read state file {}", path.display())),
+    };
+    parsed.with_context(|| format!("invalid state file {}", path.display()))
+}
+
+#[cfg(feature = "persistence")]
+fn parse(text: &str) -> Result<BTreeMap<(String, String), OwnedValue>> {
+    let document: StateDocument = toml::from_str(text)?;
+    let mut entries = BTreeMap::new();
+    for entry in document.setting {
+        let value = toml_to_typed(&entry.signature, entry.value.get_ref())
+            .with_context(|| format!("{}/{}", entry.namespace, entry.key))?;
+        entries.insert((entry.namespace, entry.key), value.try_into()?);
+    }
+    Ok(entries)
+}
+
+/// Whether `contents` are a state file rather than a configuration file:
+/// binary, or TOML with `[[setting]]` entries
+#[cfg(feature = "persistence")]
+pub fn is_state_file(contents: &[u8]) -> bool {
+    contents.starts_with(BINARY_MAGIC)
+        || std::str::from_utf8(contents)
+            .ok()
+            .and_then(|text| toml::from_str::<toml::Table>(text).ok())
+            .is_some_and(|table| table.contains_key("setting"))
+}
+
+/// Parse a state file and validate every entry against the constraints of
+/// `config`, reporting every problem
+///
+/// Entries of binary files cannot be located.
+#[cfg(feature = "persistence")]
+pub fn check(contents: &[u8], config: &Config) -> Vec<Diagnostic> {
+    let mut entries = Vec::new();
+    match contents.strip_prefix(BINARY_MAGIC) {
+        Some(bytes) => match decode(bytes) {
+            Ok(decoded) => entries.extend(decoded.into_iter().map(|((namespace, key), value)| (None, namespace, key, Ok(value)))),
+            Err(e) => {
+                return vec![Diagnostic {
+                    location: None,
+                    message: format!("{:#}", e),
+                }];
+            }
+        },
+        None => {
+            let text = String::from_utf8_lossy(contents);
+            let document: StateDocument = match toml::from_str(&text) {
+                Ok(document) => document,
+                Err(e) => {
+                    return vec![Diagnostic {
+                        location: e.span().map(|span| config::location(&text, span.start)),
+                        message: e.message().trim_end().to_string(),
+                    }];
+                }
+            };
+            for entry in document.setting {
+                let value = toml_to_typed(&entry.signature, entry.value.get_ref())
+                    .and_then(|v| Ok(OwnedValue::try_from(v)?));
+                let location = config::location(&text, entry.value.span().start);
+                entries.push((Some(location), entry.namespace, entry.key, value));
+            }
+        }
+    }
+
+    let mut diagnostics = Vec::new();
+    for (location, namespace, key, value) in entries {
+        let result = value.and_then(|v| SettingsStore::validate_constrained(config, &namespace, &key, &v));
+        if let Err(e) = result {
+            diagnostics.push(Diagnostic {
+                location,
+                message: format!("{}/{}: {:#}", namespace, key, e),
+            });
+        }
+    }
+    diagnostics
+}
+
+#[cfg(feature = "persistence")]
+fn serialize(entries: &BTreeMap<(String, String), OwnedValue>) -> Result<String> {
+    let mut out = String::from("# Settings changed at runtime, written by portal-setting-service\n");
+    for ((namespace, key), value) in entries {
+        let converted = value_to_toml(value)
+            .with_context(|| format!("cannot persist {}/{}", namespace, key))?;
+        let _ = write!(
+            out,
+            "\n[[setting]]\nnamespace = {}\nkey = {}\nsignature = {}\nvalue = {}\n",
+            toml::Value::String(namespace.clone()),
+            toml::Value::String(key.clone()),
+            toml::Value::String(value.value_signature().to_string()),
+            converted,
+        );
+    }
+    Ok(out)
+}
+
+/// Start of binary state files, followed by the entries as D-Bus `a(ssv)`
+#[cfg(feature = "persistence")]
+const BINARY_MAGIC: &[u8] = b"portal-settings-state\0\x01";
+
+#[cfg(feature = "persistence")]
+fn dbus_context() -> zbus::zvariant::serialized::Context {
+    zbus::zvariant::serialized::Context::new_dbus(zbus::zvariant::LE, 0)
+}
+
+#[cfg(feature = "persistence")]
+fn decode(bytes: &[u8]) -> Result<BTreeMap<(String, String), OwnedValue>> {
+    let data = zbus::zvariant::serialized::Data::new(bytes, dbus_context());
+    let (entries, _): (Vec<(String, String, OwnedValue)>, _) = data.deserialize()?;
+    Ok(entries.into_iter().map(|(namespace, key, value)| ((namespace, key), value)).collect())
+}
+
+#[cfg(feature = "persistence")]
+fn encode(entries: &BTreeMap<(String, String), OwnedValue>) -> Result<Vec<u8>> {
+    let mut values = Vec::with_capacity(entries.len());
+    for ((namespace, key), value) in entries {
+        // Descriptors would not be valid after a restart
+        if value.value_signature().contains('h') {
+            bail!("cannot persist {}/{}: file descriptors are not supported", namespace, key);
+        }
+        values.push((namespace, key, &**value));
+    }
+    let mut contents = BINARY_MAGIC.to_vec();
+    contents.extend_from_slice(&zbus::zvariant::to_bytes(dbus_context(), &values)?);
+    Ok(contents)
+}
+
+/// Replace `path` so readers never observe a partially written file
+#[cfg(feature = "persistence")]
+fn write_atomic(path: &Path, contents: &[u8]) -> std::io::Result<()> {
+    let mut tmp = path.as_os_str().to_owned();
+    tmp.push(".tmp");
+    let tmp = PathBuf::from(tmp);
+
+    let mut file = File::create(&tmp)?;
+    file.write_all(contents)?;
+    file.sync_all()?;
+    fs::rename(&tmp, path)
+}
+
+/// Convert a D-Bus value to TOML
+///
+/// Integers, doubles, booleans, strings, object paths and signatures map to
+/// their TOML counterparts, arrays and structures to arrays and string-keyed
+/// dictionaries to tables. Variants and file descriptors are not supported.
+pub fn value_to_toml(value: &Value<'_>) -> Result<toml::Value> {
+    Ok(match value {
+        Value::U8(v) => toml::Value::Integer((*v).into()),
+        Value::Bool(v) => toml::Value::Boolean(*v),
+        Value::I16(v) => toml::Value::Integer((*v).into()),
+        Value::U16(v) => toml::Value::Integer((*v).into()),
+        Value::I32(v) => toml::Value::Integer((*v).into()),
+        Value::U32(v) => toml::Value::Integer((*v).into()),
+        Value::I64(v) => toml::Value::Integer(*v),
+        Value::U64(v) => match i64::try_from(*v) {
+            Ok(v) => toml::Value::Integer(v),
+            Err(_) => bail!("{} does not fit in a TOML integer", v),
+        },
+        Value::F64(v) => toml::Value::Float(*v),
+        Value::Str(v) => toml::Value::String(v.to_string()),
+        Value::ObjectPath(v) => toml::Value::String(v.to_string()),
+        Value::Signature(v) => toml::Value::String(v.to_string()),
+        Value::Array(array) => toml::Value::Array(
+            array
+                .inner()
+                .iter()
+                .map(value_to_toml)
+                .collect::<Result<_>>()?,
+        ),
+        Value::Dict(dict) => {
+            let mut table = toml::Table::new();
+            for (k, v) in dict.iter() {
+                let Value::Str(k) = k else {
+                    bail!("only string-keyed dictionaries are supported");
+                };
+                table.insert(k.to_string(), value_to_toml(v)?);
+            }
+            toml::Value::Table(table)
+        }
+        Value::Structure(structure) => toml::Value::Array(
+            structure
+                .fields()
+                .iter()
+                .map(value_to_toml)
+                .collect::<Result<_>>()?,
+        ),
+        other => bail!("unsupported value type {}", other.value_signature()),
+    })
+}
+
+/// Convert TOML produced by `value_to_toml` back to a value of `signature`
+pub fn toml_to_typed(signature: &str, value: &toml::Value) -> Result<Value<'static>> {
+    let (ty, rest) = split_type(signature)?;
+    if !rest.is_empty() {
+        bail!("signature {} is not a single complete type", signature);
+    }
+
+    fn integer<T: TryFrom<i64>>(value: &toml::Value) -> Result<T> {
+        match value {
+            toml::Value::Integer(i) => T::try_from(*i).map_err(|_| crate::Error::msg(format!("{} is out of range", i))),
+            _ => bail!("expected an integer"),
+        }
+    }
+    fn string(value: &toml::Value) -> Result<String> {
+        match value {
+            toml::Value::String(s) => Ok(s.clone()),
+            _ => bail!("expected a string"),
+        }
+    }
+    fn array(value: &toml::Value) -> Result<&[toml::Value]> {
+        match value {
+            toml::Value::Array(items) => Ok(items),
+            _ => bail!("expected an array"),
+        }
+    }
+
+    Ok(match ty.as_bytes()[0] {
+        b'y' => Value::U8(integer(value)?),
+        b'n' => Value::I16(integer(value)?),
+        b'q' => Value::U16(integer(value)?),
+        b'i' => Value::I32(integer(value)?),
+        b'u' => Value::U32(integer(value)?),
+        b'x' => Value::I64(integer(value)?),
+        b't' => Value::U64(integer(value)?),
+        b'b' => match value {
+            toml::Value::Boolean(b) => Value::Bool(*b),
+            _ => bail!("expected a boolean"),
+        },
+        b'd' => match value {
+            toml::Value::Float(f) => Value::F64(*f),
+            toml::Value::Integer(i) => Value::F64(*i as f64),
+            _ => bail!("expected a number"),
+        },
+        b's' => Value::Str(Str::from(string(value)?)),
+        b'o' => Value::ObjectPath(ObjectPath::try_from(string(value)?)?),
+        b'g' => Value::Signature(Signature::try_from(string(value)?)?),
+        b'a' if ty.as_bytes().get(1) == Some(&b'{') => {
+            let (key_ty, rest) = split_type(&ty[2..])?;
+            let (value_ty, _) = split_type(rest)?;
+            if key_ty != "s" {
+                bail!("only string-keyed dictionaries are supported");
+            }
+            let toml::Value::Table(table) = value else {
+                bail!("expected a table");
+            };
+            let mut dict = Dict::new(
+                Signature::try_from(key_ty.to_string())?,
+                Signature::try_from(value_ty.to_string())?,
+            );
+            for (k, v) in table {
+                dict.append(Value::Str(Str::from(k.clone())), toml_to_typed(value_ty, v)?)?;
+            }
+            Value::Dict(dict)
+        }
+        b'a' => {
+            let element_ty = &ty[1..];
+            let mut converted = Array::new(Signature::try_from(element_ty.to_string())?);
+            for item in array(value)? {
+                converted.append(toml_to_typed(element_ty, item)?)?;
+            }
+            Value::Array(converted)
+        }
+        b'(' => {
+            let items = array(value)?;
+            let mut fields = &ty[1..ty.len() - 1];
+            let mut builder = StructureBuilder::new();
+            for item in items {
+                if fields.is_empty() {
+                    bail!("too many structure fields");
+                }
+                let (field_ty, rest) = split_type(fields)?;
+                builder = builder.append_field(toml_to_typed(field_ty, item)?);
+                fields = rest;
+            }
+            if !fields.is_empty() {
+                bail!("missing structure fields");
+            }
+            Value::Structure(builder.build())
+        }
+        _ => bail!("unsupported signature {}", ty),
+    })
+}
+
+/// Split the first complete type off a signature
+fn split_type(signature: &str) -> Result<(&str, &str)> {
+    let bytes = signature.as_bytes();
+    let end = match bytes.first() {
+        None => bail!("incomplete signature"),
+        Some(b'a') => 1 + split_type(&signature[1..])?.0.len(),
+        Some(&open @ (b'(' | b'{')) => {
+            let close = if open == b'(' { b')' } else { b'}' };
+            let mut i = 1;
+            while bytes.get(i) != Some(&close) {
+                i += split_type(&signature[i..])?.0.len();
+            }
+            i + 1
+        }
+        Some(_) => 1,
+    };
+    Ok(signature.split_at(end))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[cfg(feature = "persistence")]
+    use crate::{ChangeSource, SettingsStore, SettingValue};
+
+    #[cfg(feature = "persistence")]
+    fn temp_path(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("portal-state-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir.join("state.toml")
+    }
+
+    #[test]
+    fn test_typed_round_trip() {
+        let mut dict = Dict::new(Signature::try_from("s").unwrap(), Signature::try_from("i").unwrap());
+        dict.append(Value::from("a"), Value::I32(1)).unwrap();
+
+        let values = vec![
+            Value::U8(7),
+            Value::U32(1),
+            Value::I64(-5_000_000_000),
+            Value::U64(42),
+            Value::F64(0.25),
+            Value::from("Adwaita"),
+            Value::ObjectPath(ObjectPath::try_from("/org/example").unwrap()),
+            Value::Structure((0.5, 0.25, 1.0).into()),
+            Value::Array(Array::from(vec!["a".to_string(), "b".to_string()])),
+            Value::Array(Array::new(Signature::try_from("u").unwrap())),
+            Value::Dict(dict),
+        ];
+        for value in values {
+            let signature = value.value_signature().to_string();
+            let converted = value_to_toml(&value).unwrap();
+            let restored = toml_to_typed(&signature, &converted).unwrap();
+            assert_eq!(restored, value, "{}", signature);
+        }
+
+        assert!(toml_to_typed("u", &toml::Value::Integer(-1)).is_err());
+        assert!(toml_to_typed("(dd", &toml::Value::Array(vec![])).is_err());
+        assert!(value_to_toml(&Value::Value(Box::new(Value::U32(1)))).is_err());
+    }
+
+    #[cfg(feature = "persistence")]
+    proptest::proptest! {
+        #[test]
+        fn test_state_formats_are_lossless(value in crate::strategy::value()) {
+            let entries = BTreeMap::from([(
+                ("com.example".to_string(), "value".to_string()),
+                OwnedValue::try_from(value.try_clone().unwrap()).unwrap(),
+            )]);
+            let binary = encode(&entries).unwrap();
+            assert_eq!(decode(&binary[BINARY_MAGIC.len()..]).unwrap(), entries);
+
+            // TOML integers are signed, so only larger u64s are refused
+            match serialize(&entries) {
+                Ok(text) => assert_eq!(parse(&text).unwrap(), entries),
+                Err(_) => assert!(format!("{:?}", value).contains("U64")),
+            }
+        }
+    }
+
+    #[cfg(feature = "persistence")]
+    #[tokio::test]
+    async fn test_state_persists_mutations() {
+        let path = temp_path("persist");
+        let state = Arc::new(StateFile::open(&path).unwrap());
+        assert!(state.entries().is_empty());
+
+        let store = SettingsStore::new();
+        store.add_observer(state.clone());
+        store
+            .write("org.freedesktop.appearance", "color-scheme", Value::U32(1).try_into().unwrap())
+            .await
+            .unwrap();
+        store
+            .write("org.freedesktop.appearance", "contrast", Value::U32(1).try_into().unwrap())
+            .await
+            .unwrap();
+        store
+            .write("com.example", "ratio", Value::F64(0.5).try_into().unwrap())
+            .await
+            .unwrap();
+        store.reset("org.freedesktop.appearance", "contrast").await;
+
+        let reopened = StateFile::open(&path).unwrap();
+        let entries: Vec<(String, String, SettingValue)> = reopened
+            .entries()
+            .into_iter()
+            .map(|(ns, key, v)| (ns, key, SettingValue::new(v)))
+            .collect();
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0].1, "ratio");
+        assert_eq!(*entries[0].2 .0, OwnedValue::from(0.5f64));
+        assert_eq!(entries[1].1, "color-scheme");
+        assert_eq!(*entries[1].2 .0, OwnedValue::from(1u32));
+
+        fs::remove_dir_all(path.parent().unwrap()).unwrap();
+    }
+
+    #[cfg(feature = "persistence")]
+    #[tokio::test]
+    async fn test_binary_state_round_trips() {
+        let path = temp_path("binary");
+        let state = Arc::new(StateFile::open(&path).unwrap().with_format(StateFormat::Binary));
+        let store = SettingsStore::new();
+        store.add_observer(state.clone());
+        let accent = OwnedValue::try_from(Value::Structure((0.5, 0.25, 1.0).into())).unwrap();
+        store
+            .write("org.freedesktop.appearance", "accent-color", accent.try_clone().unwrap())
+            .await
+            .unwrap();
+        store.write("com.example", "name", OwnedValue::from(Str::from("a"))).await.unwrap();
+        assert!(fs::read(&path).unwrap().starts_with(BINARY_MAGIC));
+
+        // Either format is read, so switching keeps the entries
+        let reopened = StateFile::open(&path).unwrap();
+        let entries = reopened.entries();
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[1].2, accent);
+        reopened.save().unwrap();
+        assert!(fs::read_to_string(&path).unwrap().contains("[[setting]]"));
+        assert_eq!(StateFile::open(&path).unwrap().entries(), entries);
+
+        fs::write(&path, [BINARY_MAGIC, b"garbage"].concat()).unwrap();
+        assert!(StateFile::open(&path).is_err());
+
+        fs::remove_dir_all(path.parent().unwrap()).unwrap();
+    }
+
+    #[cfg(feature = "persistence")]
+    #[test]
+    fn test_check_valid_state_file() {
+        let config = Config::parse("[schema.\"com.example\"]\nretention-days = { type = \"u\", max = 365 }\n").unwrap();
+        let entries = BTreeMap::from([
+            (("org.freedesktop.appearance".to_string(), "color-scheme".to_string()), OwnedValue::from(1u32)),
+            (("com.example".to_string(), "retention-days".to_string()), OwnedValue::from(30u32)),
+        ]);
+        let text = serialize(&entries).unwrap();
+        assert!(is_state_file(text.as_bytes()));
+        assert!(check(text.as_bytes(), &config).is_empty());
+
+        let binary = encode(&entries).unwrap();
+        assert!(is_state_file(&binary));
+        assert!(check(&binary, &config).is_empty());
+
+        assert!(!is_state_file(config::seed().as_bytes()));
+    }
+
+    #[cfg(feature = "persistence")]
+    #[test]
+    fn test_check_invalid_state_file() {
+        let config = Config::parse("[schema.\"com.example\"]\nretention-days = { type = \"u\", max = 365 }\n").unwrap();
+        let text = r#"[[setting]]
+namespace = "org.freedesktop.appearance"
+key = "color-scheme"
+signature = "u"
+value = 5
+
+[[setting]]
+namespace = "com.example"
+key = "retention-days"
+signature = "u"
+value = 400
+
+[[setting]]
+namespace = "com.example"
+key = "ratio"
+signature = "u"
+value = -1
+"#;
+        let diagnostics = check(text.as_bytes(), &config);
+        let locations: Vec<_> = diagnostics.iter().map(|d| d.location).collect();
+        assert_eq!(locations, [Some((5, 9)), Some((11, 9)), Some((17, 9))]);
+        assert!(diagnostics[0].message.starts_with("org.freedesktop.appearance/color-scheme: "));
+        assert!(diagnostics[1].message.contains("retention-days"));
+
+        let unknown = check(b"[[setting]]\nnamespace = \"com.example\"\nkey = \"a\"\nvalue = 1\nflag = true\n", &config);
+        assert_eq!(unknown.len(), 1);
+        assert!(unknown[0].location.is_some());
+
+        let entries = BTreeMap::from([(
+            ("org.freedesktop.appearance".to_string(), "color-scheme".to_string()),
+            OwnedValue::from(5u32),
+        )]);
+        let binary = check(&encode(&entries).unwrap(), &config);
+        assert_eq!(binary.len(), 1);
+        assert_eq!(binary[0].location, None);
+        assert_eq!(check(&[BINARY_MAGIC, b"garbage"].concat(), &config).len(), 1);
+    }
+
+    #[cfg(feature = "persistence")]
+    #[tokio::test]
+    async fn test_flush_coalesces_changes() {
+        let path = temp_path("flush");
+        let state = Arc::new(StateFile::open(&path).unwrap());
+        state.flush_after(Duration::from_millis(100)).unwrap();
+        assert!(state.flush_after(Duration::from_millis(100)).is_err());
+
+        let store = SettingsStore::new();
+        store.add_observer(state.clone());
+        for scheme in [1u32, 2, 1] {
+            store
+                .write("org.freedesktop.appearance", "color-scheme", Value::U32(scheme).try_into().unwrap())
+                .await
+                .unwrap();
+        }
+        assert!(!path.exists());
+
+        std::thread::sleep(Duration::from_millis(500));
+        let entries = StateFile::open(&path).unwrap().entries();
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].2, OwnedValue::from(1u32));
+
+        fs::remove_dir_all(path.parent().unwrap()).unwrap();
+    }
+
+    #[cfg(feature = "persistence")]
+    #[tokio::test]
+    async fn test_load_keeps_newer_changes() {
+        let path = temp_path("load");
+        let persisted = Arc::new(StateFile::open(&path).unwrap());
+        let store = SettingsStore::new();
+        store.add_observer(persisted.clone());
+        store
+            .write("org.freedesktop.appearance", "color-scheme", Value::U32(1).try_into().unwrap())
+            .await
+            .unwrap();
+        store
+            .write("org.freedesktop.appearance", "contrast", Value::U32(1).try_into().unwrap())
+            .await
+            .unwrap();
+        let written = fs::read_to_string(&path).unwrap();
+
+        let state = Arc::new(StateFile::new(&path));
+        let store = SettingsStore::new();
+        store.add_observer(state.clone());
+        store
+            .write("org.freedesktop.appearance", "color-scheme", Value::U32(2).try_into().unwrap())
+            .await
+            .unwrap();
+        // Not written before the file is read
+        state.save().unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), written);
+
+        let loaded = state.load().await.unwrap();
+        assert_eq!(loaded.len(), 1);
+        for (namespace, key, value) in loaded {
+            assert!(store.restore_from(ChangeSource::State, &namespace, &key, value).await.unwrap());
+        }
+        let color_scheme = OwnedValue::from(1u32);
+        let kept = store.restore_from(ChangeSource::State, "org.freedesktop.appearance", "color-scheme", color_scheme);
+        assert!(!kept.await.unwrap());
+
+        let entries = StateFile::open(&path).unwrap().entries();
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0].2, OwnedValue::from(2u32));
+        assert_eq!(entries[1].2, OwnedValue::from(1u32));
+
+        fs::remove_dir_all(path.parent().unwrap()).unwrap();
+    }
+}
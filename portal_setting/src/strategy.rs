@@ -0,0 +1,61 @@
+//! Generated values for property tests
+//!
+//! Covers every type the state file can hold: the basic types, arrays of
+//! them, string-keyed dictionaries and structures. Doubles are never NaN, so
+//! equal values compare equal.
+
+use proptest::prelude::*;
+use zbus::zvariant::{Array, Dict, ObjectPath, Signature, StructureBuilder, Value};
+
+/// D-Bus strings cannot hold NUL
+fn string() -> impl Strategy<Value = String> {
+    any::<String>().prop_map(|s| s.replace('\0', ""))
+}
+
+fn double() -> impl Strategy<Value = f64> {
+    use proptest::num::f64::{INFINITE, NEGATIVE, NORMAL, POSITIVE, SUBNORMAL, ZERO};
+    POSITIVE | NEGATIVE | NORMAL | SUBNORMAL | ZERO | INFINITE
+}
+
+/// A value of a basic type
+pub fn basic() -> impl Strategy<Value = Value<'static>> {
+    prop_oneof![
+        any::<u8>().prop_map(Value::U8),
+        any::<bool>().prop_map(Value::Bool),
+        any::<i16>().prop_map(Value::I16),
+        any::<u16>().prop_map(Value::U16),
+        any::<i32>().prop_map(Value::I32),
+        any::<u32>().prop_map(Value::U32),
+        any::<i64>().prop_map(Value::I64),
+        any::<u64>().prop_map(Value::U64),
+        double().prop_map(Value::F64),
+        string().prop_map(Value::from),
+        "(/[A-Za-z0-9_]{1,8}){0,3}".prop_map(|path| {
+            let path = if path.is_empty() { "/".to_string() } else { path };
+            Value::ObjectPath(ObjectPath::try_from(path).unwrap())
+        }),
+        prop::sample::select(vec!["s", "u", "as", "a{sv}", "(ddd)"])
+            .prop_map(|s| Value::Signature(Signature::from_static_str(s).unwrap())),
+    ]
+}
+
+/// A basic value, or a container of basic values
+pub fn value() -> impl Strategy<Value = Value<'static>> {
+    prop_oneof![
+        4 => basic(),
+        1 => prop::collection::vec(any::<u32>(), 0..4).prop_map(|v| Value::Array(Array::from(v))),
+        1 => prop::collection::vec(string(), 0..4).prop_map(|v| Value::Array(Array::from(v))),
+        1 => prop::collection::vec(double(), 0..4).prop_map(|v| Value::Array(Array::from(v))),
+        1 => prop::collection::btree_map(string(), any::<i32>(), 0..4).prop_map(|entries| {
+            let mut dict = Dict::new(Signature::from_static_str("s").unwrap(), Signature::from_static_str("i").unwrap());
+            for (key, value) in entries {
+                dict.append(Value::from(key), Value::I32(value)).unwrap();
+            }
+            Value::Dict(dict)
+        }),
+        1 => prop::collection::vec(basic(), 1..4).prop_map(|fields| {
+            let builder = fields.into_iter().fold(StructureBuilder::new(), |b, field| b.append_field(field));
+            Value::Structure(builder.build())
+        }),
+    ]
+}
@@ -0,0 +1,24 @@
+use std::future::Future;
+use tokio::task::JoinHandle;
+
+/// Spawn `future` on the current runtime, named `name` in tokio-console
+///
+/// Tasks are only named with the `console` feature in a build with
+/// `RUSTFLAGS="--cfg tokio_unstable"`; otherwise this is `tokio::spawn`.
+pub fn spawn<F>(name: &str, future: F) -> JoinHandle<F::Output>
+where
+    F: Future + Send + 'static,
+    F::Output: Send + 'static,
+{
+    #[cfg(all(feature = "console", tokio_unstable))]
+    return tokio::task::Builder::new()
+        .name(name)
+        .spawn(future)
+        .expect("cannot spawn a task outside of a running runtime");
+
+    #[cfg(not(all(feature = "console", tokio_unstable)))]
+    {
+        let _ = name;
+        tokio::spawn(future)
+    }
+}
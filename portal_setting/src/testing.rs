@@ -0,0 +1,382 @@
+//! A portal served on a private bus, for end-to-end tests
+//!
+//! `TestPortal::start` launches `dbus-daemon --session` in a temporary
+//! directory, serves a store on it under `config::DEFAULT_BUS_NAME` and
+//! hands out a second connection to call it with, as applications would.
+//! Where `dbus-daemon` is not installed, the two connections talk
+//! peer-to-peer instead; calls and signals behave the same, but there is no
+//! bus to own names on or to ask about them.
+//!
+//! Code that only needs a store can take a `SettingsAccess` and be tested
+//! against a `MockSettingsStore` without any connection.
+
+use crate::config::DEFAULT_BUS_NAME;
+use crate::error::{Context, Error, Result};
+use crate::{SettingValue, SettingsAccess, SettingsFrontend, SettingsPortal, SettingsStore};
+use futures_util::future::BoxFuture;
+use std::collections::HashMap;
+use std::io::{BufRead, BufReader};
+use std::os::unix::net::UnixStream;
+use std::path::PathBuf;
+use std::process::{Child, Command, Stdio};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+use zbus::connection::Builder;
+use zbus::zvariant::OwnedValue;
+use zbus::{Connection, Guid};
+
+/// Object path the interfaces are served at
+pub const PATH: &str = "/org/freedesktop/portal/desktop";
+
+/// A `dbus-daemon` of its own, killed when dropped
+pub struct TestBus {
+    daemon: Child,
+    dir: PathBuf,
+    address: String,
+}
+
+impl TestBus {
+    /// Start a session bus listening in a new temporary directory
+    pub fn start() -> Result<Self> {
+        static COUNT: AtomicUsize = AtomicUsize::new(0);
+        let dir = std::env::temp_dir().join(format!(
+            "portal-setting-bus-{}-{}",
+            std::process::id(),
+            COUNT.fetch_add(1, Ordering::Relaxed)
+        ));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).with_context(|| format!("cannot create {}", dir.display()))?;
+        let mut daemon = Command::new("dbus-daemon")
+            .args(["--session", "--nofork", "--print-address"])
+            .arg(format!("--address=unix:path={}", dir.join("bus").display()))
+            .stdout(Stdio::piped())
+            .spawn()
+            .context("cannot start dbus-daemon")?;
+        // Printed once the bus accepts connections
+        let mut address = String::new();
+        BufReader::new(daemon.stdout.take().unwrap()).read_line(&mut address)?;
+        let address = address.trim().to_string();
+        let bus = Self { daemon, dir, address };
+        if bus.address.is_empty() {
+            crate::error::bail!("dbus-daemon exited without printing its address");
+        }
+        Ok(bus)
+    }
+
+    /// Whether `dbus-daemon` can be started
+    pub fn available() -> bool {
+        Command::new("dbus-daemon")
+            .arg("--version")
+            .stdout(Stdio::null())
+            .status()
+            .is_ok_and(|status| status.success())
+    }
+
+    /// D-Bus address of the bus, e.g. for `DBUS_SESSION_BUS_ADDRESS`
+    pub fn address(&self) -> &str {
+        &self.address
+    }
+
+    /// A new connection to the bus
+    pub async fn connect(&self) -> Result<Connection> {
+        Ok(Builder::address(self.address.as_str())?.build().await?)
+    }
+}
+
+impl Drop for TestBus {
+    fn drop(&mut self) {
+        let _ = self.daemon.kill();
+        let _ = self.daemon.wait();
+        let _ = std::fs::remove_dir_all(&self.dir);
+    }
+}
+
+/// A store served with every interface of the service, and a client
+/// connection to it
+pub struct TestPortal {
+    /// The served store, to change or inspect settings behind the bus
+    pub store: SettingsStore,
+    /// The connection the interfaces are served on
+    pub server: Connection,
+    /// A connection to call the portal with; proxies for
+    /// `config::DEFAULT_BUS_NAME` reach the portal on either transport
+    pub client: Connection,
+    bus: Option<TestBus>,
+}
+
+impl TestPortal {
+    /// Serve `store` on a private bus, or peer-to-peer without `dbus-daemon`
+    pub async fn start(store: SettingsStore) -> Result<Self> {
+        match TestBus::available() {
+            true => Self::on_bus(store, TestBus::start()?).await,
+            false => Self::p2p(store).await,
+        }
+    }
+
+    /// Serve `store` on `bus`, owning `config::DEFAULT_BUS_NAME`
+    pub async fn on_bus(store: SettingsStore, bus: TestBus) -> Result<Self> {
+        let server = serve(Builder::address(bus.address())?, &store)?
+            .name(DEFAULT_BUS_NAME)?
+            .build()
+            .await
+            .with_context(|| format!("cannot serve {} on {}", DEFAULT_BUS_NAME, bus.address()))?;
+        let client = bus.connect().await?;
+        Ok(Self {
+            store,
+            server,
+            client,
+            bus: Some(bus),
+        })
+    }
+
+    /// Serve `store` over a socket pair, without a bus
+    pub async fn p2p(store: SettingsStore) -> Result<Self> {
+        let (server_socket, client_socket) = UnixStream::pair()?;
+        // Served before the connection starts, so no early call is lost
+        let (server, client) = tokio::try_join!(
+            serve(Builder::unix_stream(server_socket).server(Guid::generate())?.p2p(), &store)?.build(),
+            Builder::unix_stream(client_socket).p2p().build(),
+        )?;
+        Ok(Self {
+            store,
+            server,
+            client,
+            bus: None,
+        })
+    }
+
+    /// The bus, unless the connections are peer-to-peer
+    pub fn bus(&self) -> Option<&TestBus> {
+        self.bus.as_ref()
+    }
+}
+
+fn serve<'a>(builder: Builder<'a>, store: &SettingsStore) -> Result<Builder<'a>> {
+    let builder = builder
+        .serve_at(PATH, SettingsPortal::with_store(store.clone()))?
+        .serve_at(PATH, SettingsFrontend::new(store.clone()))?;
+    #[cfg(feature = "management")]
+    let builder = builder.serve_at(PATH, crate::SettingsManagement::new(store.clone()))?;
+    Ok(builder)
+}
+
+/// A call made on a `MockSettingsStore`
+#[derive(Debug, PartialEq)]
+pub enum Call {
+    Read { namespace: String, key: String },
+    ReadAll { namespaces: Vec<String> },
+    Write { namespace: String, key: String, value: OwnedValue },
+    Reset { namespace: String, key: String },
+}
+
+/// A store answering with scripted values and errors, recording every call
+///
+/// Writes are accepted without validation unless `fail_write` scripted an
+/// error for the key, and are read back afterwards. Resets remove the value.
+#[derive(Default)]
+pub struct MockSettingsStore {
+    values: Mutex<HashMap<(String, String), SettingValue>>,
+    failures: Mutex<HashMap<(String, String), String>>,
+    calls: Mutex<Vec<Call>>,
+}
+
+impl MockSettingsStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Answer reads of `namespace`/`key` with `value`
+    pub fn with(self, namespace: &str, key: &str, value: OwnedValue) -> Self {
+        let slot = (namespace.to_string(), key.to_string());
+        self.values.lock().unwrap().insert(slot, SettingValue::new(value));
+        self
+    }
+
+    /// Fail every write to `namespace`/`key` with `message`
+    pub fn fail_write(self, namespace: &str, key: &str, message: &str) -> Self {
+        let slot = (namespace.to_string(), key.to_string());
+        self.failures.lock().unwrap().insert(slot, message.to_string());
+        self
+    }
+
+    /// The calls made since the last `take_calls`, oldest first
+    pub fn take_calls(&self) -> Vec<Call> {
+        std::mem::take(&mut *self.calls.lock().unwrap())
+    }
+
+    fn record(&self, call: Call) {
+        self.calls.lock().unwrap().push(call);
+    }
+}
+
+impl SettingsAccess for MockSettingsStore {
+    fn read<'a>(&'a self, namespace: &'a str, key: &'a str) -> BoxFuture<'a, Option<SettingValue>> {
+        Box::pin(async move {
+            self.record(Call::Read {
+                namespace: namespace.to_string(),
+                key: key.to_string(),
+            });
+            let slot = (namespace.to_string(), key.to_string());
+            self.values.lock().unwrap().get(&slot).cloned()
+        })
+    }
+
+    fn read_all(&self, namespaces: Vec<String>) -> BoxFuture<'_, HashMap<String, HashMap<String, SettingValue>>> {
+        Box::pin(async move {
+            let mut settings: HashMap<String, HashMap<String, SettingValue>> = HashMap::new();
+            for ((namespace, key), value) in self.values.lock().unwrap().iter() {
+                if namespaces.is_empty() || namespaces.contains(namespace) {
+                    settings.entry(namespace.clone()).or_default().insert(key.clone(), value.clone());
+                }
+            }
+            self.record(Call::ReadAll { namespaces });
+            settings
+        })
+    }
+
+    fn write<'a>(&'a self, namespace: &'a str, key: &'a str, value: OwnedValue) -> BoxFuture<'a, Result<()>> {
+        Box::pin(async move {
+            let slot = (namespace.to_string(), key.to_string());
+            let result = match self.failures.lock().unwrap().get(&slot) {
+                Some(message) => Err(Error::msg(message)),
+                None => {
+                    let stored = SettingValue::new(value.try_clone()?);
+                    self.values.lock().unwrap().insert(slot, stored);
+                    Ok(())
+                }
+            };
+            self.record(Call::Write {
+                namespace: namespace.to_string(),
+                key: key.to_string(),
+                value,
+            });
+            result
+        })
+    }
+
+    fn reset<'a>(&'a self, namespace: &'a str, key: &'a str) -> BoxFuture<'a, bool> {
+        Box::pin(async move {
+            self.record(Call::Reset {
+                namespace: namespace.to_string(),
+                key: key.to_string(),
+            });
+            let slot = (namespace.to_string(), key.to_string());
+            self.values.lock().unwrap().remove(&slot).is_some()
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures_util::StreamExt;
+    use std::collections::HashMap;
+    use zbus::zvariant::{OwnedValue, Value};
+    use zbus::{MatchRule, MessageStream, Proxy};
+
+    async fn proxy(connection: &Connection, interface: &'static str) -> Proxy<'static> {
+        Proxy::new(connection, DEFAULT_BUS_NAME, PATH, interface).await.unwrap()
+    }
+
+    async fn check_portal(portal: &TestPortal) {
+        // Only the frontend interface has a version
+        let frontend = proxy(&portal.client, "org.freedesktop.portal.Settings").await;
+        let version: u32 = frontend.get_property("version").await.unwrap();
+        assert_eq!(version, 2);
+
+        let settings = proxy(&portal.client, "org.freedesktop.impl.portal.Settings").await;
+
+        let all: HashMap<String, HashMap<String, OwnedValue>> =
+            settings.call("ReadAll", &(vec!["org.freedesktop.appearance"],)).await.unwrap();
+        assert_eq!(all.len(), 1);
+        assert!(all["org.freedesktop.appearance"].contains_key("color-scheme"));
+        let error = settings
+            .call::<_, _, OwnedValue>("Read", &("org.freedesktop.appearance", "missing"))
+            .await
+            .unwrap_err();
+        assert!(matches!(error, zbus::Error::MethodError(..)), "{}", error);
+        let error = settings
+            .call::<_, _, HashMap<String, HashMap<String, OwnedValue>>>("ReadAll", &(vec!["org.*.appearance"],))
+            .await
+            .unwrap_err();
+        let zbus::Error::MethodError(name, ..) = error else { panic!("{}", error) };
+        assert_eq!(name.as_str(), "org.freedesktop.DBus.Error.InvalidArgs");
+
+        // A change behind the bus reaches clients as SettingChanged
+        let rule = MatchRule::builder().msg_type(zbus::message::Type::Signal).member("SettingChanged").unwrap().build();
+        let mut signals = MessageStream::for_match_rule(rule, &portal.client, None).await.unwrap();
+        let value = OwnedValue::try_from(Value::U32(1)).unwrap();
+        portal.store.write("org.freedesktop.appearance", "color-scheme", value).await.unwrap();
+        crate::emit_setting_changed(
+            &zbus::SignalContext::new(&portal.server, PATH).unwrap(),
+            "org.freedesktop.appearance",
+            "color-scheme",
+            &Value::U32(1),
+        )
+        .await
+        .unwrap();
+        let signal = signals.next().await.unwrap().unwrap();
+        let (namespace, key, value): (String, String, OwnedValue) = signal.body().deserialize().unwrap();
+        assert_eq!((namespace.as_str(), key.as_str()), ("org.freedesktop.appearance", "color-scheme"));
+        assert_eq!(u32::try_from(value).unwrap(), 1);
+        let value: OwnedValue = settings.call("Read", &("org.freedesktop.appearance", "color-scheme")).await.unwrap();
+        assert_eq!(u32::try_from(value).unwrap(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_p2p() {
+        let portal = TestPortal::p2p(SettingsStore::new()).await.unwrap();
+        assert!(portal.bus().is_none());
+        check_portal(&portal).await;
+    }
+
+    #[tokio::test]
+    async fn test_mock_settings_store() {
+        let value = |n: u32| OwnedValue::from(n);
+        let mock = MockSettingsStore::new()
+            .with("org.freedesktop.appearance", "color-scheme", value(1))
+            .fail_write("org.freedesktop.appearance", "contrast", "read-only");
+        let settings: &dyn SettingsAccess = &mock;
+
+        assert_eq!(*settings.read("org.freedesktop.appearance", "color-scheme").await.unwrap().0, value(1));
+        settings.write("org.freedesktop.appearance", "color-scheme", value(2)).await.unwrap();
+        assert_eq!(*settings.read("org.freedesktop.appearance", "color-scheme").await.unwrap().0, value(2));
+        let error = settings.write("org.freedesktop.appearance", "contrast", value(1)).await.unwrap_err();
+        assert_eq!(error.to_string(), "read-only");
+        assert!(settings.read_all(vec!["org.gnome.desktop.interface".to_string()]).await.is_empty());
+        assert!(settings.reset("org.freedesktop.appearance", "color-scheme").await);
+
+        let read = |key: &str| Call::Read {
+            namespace: "org.freedesktop.appearance".to_string(),
+            key: key.to_string(),
+        };
+        let calls = mock.take_calls();
+        assert_eq!(calls.len(), 6);
+        assert_eq!(calls[0], read("color-scheme"));
+        assert_eq!(
+            calls[3],
+            Call::Write {
+                namespace: "org.freedesktop.appearance".to_string(),
+                key: "contrast".to_string(),
+                value: value(1),
+            }
+        );
+        assert!(matches!(&calls[5], Call::Reset { key, .. } if key == "color-scheme"));
+        assert!(mock.take_calls().is_empty());
+
+        // The real store behind the same trait
+        let store: &dyn SettingsAccess = &SettingsStore::new();
+        assert!(store.write("org.freedesktop.appearance", "color-scheme", value(9)).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_private_bus() {
+        let portal = TestPortal::start(SettingsStore::new()).await.unwrap();
+        if let Some(bus) = portal.bus() {
+            let dbus = zbus::fdo::DBusProxy::new(&portal.client).await.unwrap();
+            assert!(dbus.name_has_owner(DEFAULT_BUS_NAME.try_into().unwrap()).await.unwrap());
+            assert!(bus.address().starts_with("unix:"), "{}", bus.address());
+        }
+        check_portal(&portal).await;
+    }
+}
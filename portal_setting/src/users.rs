@@ -0,0 +1,146 @@
+use crate::emitter::{self, Destinations};
+use crate::{Result, SettingsStore, SignalEmitter};
+use futures_util::future::BoxFuture;
+use futures_util::StreamExt;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use zbus::fdo::DBusProxy;
+use zbus::zvariant::OwnedObjectPath;
+use zbus::Connection;
+
+/// Makes the store of a user the first time one of its connections calls
+type MakeStore = dyn Fn(u32) -> BoxFuture<'static, Result<SettingsStore>> + Send + Sync;
+
+/// A store for each user of a service on the system bus
+///
+/// Every uid but root's gets its own store, made on its first call, so one
+/// user's choices do not reach the sessions of others. Root and callers
+/// without a uid share the store served. The signals of each store only go
+/// to the connections that called it. Clones share the same stores.
+#[derive(Clone)]
+pub struct UserStores {
+    make: Arc<MakeStore>,
+    /// Where the signals of user stores are emitted, if anywhere
+    signals: Option<(Connection, OwnedObjectPath)>,
+    stores: Arc<tokio::sync::Mutex<HashMap<u32, SettingsStore>>>,
+    /// Connections that called, by uid, with 0 for the shared store
+    peers: Arc<Mutex<HashMap<u32, Destinations>>>,
+}
+
+impl UserStores {
+    pub fn new(make: impl Fn(u32) -> BoxFuture<'static, Result<SettingsStore>> + Send + Sync + 'static) -> Self {
+        Self {
+            make: Arc::new(make),
+            signals: None,
+            stores: Arc::new(tokio::sync::Mutex::new(HashMap::new())),
+            peers: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    /// Emit the signals of each user's store on the interfaces exported at
+    /// `path` on `connection`, to that user's connections
+    pub fn with_signals(mut self, connection: Connection, path: OwnedObjectPath) -> Self {
+        self.signals = Some((connection, path));
+        self
+    }
+
+    /// The connections of `uid` that called, where the signals of its store
+    /// go; those of uid 0 get the signals of the shared store
+    pub fn destinations(&self, uid: u32) -> Destinations {
+        self.peers.lock().unwrap().entry(uid).or_default().clone()
+    }
+
+    /// The store of `uid`, made if this is its first call, or `None` for
+    /// root, which uses the shared store
+    ///
+    /// `sender` is sent the signals of that store from now on.
+    pub async fn get(&self, uid: u32, sender: Option<&str>) -> Result<Option<SettingsStore>> {
+        if let Some(sender) = sender {
+            self.destinations(uid).lock().unwrap().insert(sender.to_string());
+        }
+        if uid == 0 {
+            return Ok(None);
+        }
+        let mut stores = self.stores.lock().await;
+        if let Some(store) = stores.get(&uid) {
+            return Ok(Some(store.clone()));
+        }
+        let store = (self.make)(uid).await?;
+        if let Some((connection, path)) = &self.signals {
+            let emitter = SignalEmitter::spawn_to(
+                connection.clone(),
+                path.clone(),
+                emitter::DEFAULT_CAPACITY,
+                self.destinations(uid),
+            );
+            store.set_signal_emitter(Some(emitter));
+        }
+        stores.insert(uid, store.clone());
+        Ok(Some(store))
+    }
+
+    /// The stores made so far, by uid
+    pub async fn stores(&self) -> Vec<(u32, SettingsStore)> {
+        let stores = self.stores.lock().await;
+        stores.iter().map(|(uid, store)| (*uid, store.clone())).collect()
+    }
+
+    fn forget(&self, sender: &str) {
+        for destinations in self.peers.lock().unwrap().values() {
+            destinations.lock().unwrap().remove(sender);
+        }
+    }
+
+    /// Stop sending signals to connections once they close on the bus of
+    /// `connection`, until the connection closes
+    pub async fn run(self, connection: Connection) -> zbus::Result<()> {
+        let dbus = DBusProxy::new(&connection).await?;
+        let mut changes = dbus.receive_name_owner_changed().await?;
+        while let Some(change) = changes.next().await {
+            let Ok(args) = change.args() else { continue };
+            // A unique name losing its owner is a closed connection
+            if args.name().starts_with(':') && args.new_owner().is_none() {
+                self.forget(args.name());
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures_util::FutureExt;
+    use zbus::zvariant::OwnedValue;
+
+    #[tokio::test]
+    async fn test_stores_are_kept_apart() {
+        let users = UserStores::new(|_| async { Ok(SettingsStore::new()) }.boxed());
+        assert!(users.get(0, Some(":1.1")).await.unwrap().is_none());
+        let alice = users.get(1000, Some(":1.2")).await.unwrap().unwrap();
+        let bob = users.get(1001, Some(":1.3")).await.unwrap().unwrap();
+        alice
+            .write("org.freedesktop.appearance", "color-scheme", OwnedValue::from(1u32))
+            .await
+            .unwrap();
+        let scheme = |store: SettingsStore| async move {
+            let value: u32 = store
+                .read("org.freedesktop.appearance", "color-scheme")
+                .await
+                .unwrap()
+                .0
+                .as_ref()
+                .try_into()
+                .unwrap();
+            value
+        };
+        assert_eq!(scheme(users.get(1000, None).await.unwrap().unwrap()).await, 1);
+        assert_eq!(scheme(bob).await, 0);
+        assert_eq!(users.stores().await.len(), 2);
+
+        assert_eq!(users.destinations(1000).lock().unwrap().len(), 1);
+        users.forget(":1.2");
+        assert!(users.destinations(1000).lock().unwrap().is_empty());
+        assert!(users.destinations(0).lock().unwrap().contains(":1.1"));
+    }
+}
@@ -0,0 +1,117 @@
+use crate::Result;
+use futures_util::future::BoxFuture;
+use std::collections::HashMap;
+use std::fmt;
+use zbus::zvariant::OwnedValue;
+
+/// A check of written values besides the built-in constraints, e.g. against
+/// a schema service or an external policy
+///
+/// Validators run after the built-in checks and before the store is locked,
+/// so a slow one delays only the write it checks. They see the value, not
+/// the store: a check that depends on other settings may race with writes
+/// made meanwhile.
+pub trait Validator: Send + Sync {
+    /// Name used in messages about this validator
+    fn name(&self) -> &str;
+
+    /// Accept `value` for `namespace`/`key`, or fail with the reason
+    fn validate<'a>(&'a self, namespace: &'a str, key: &'a str, value: &'a OwnedValue) -> BoxFuture<'a, Result<()>>;
+}
+
+/// A value that does not meet the constraints of its setting
+///
+/// The management interface replies `InvalidArgs` with the `encode`d form,
+/// which `parse` turns back into this error.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ValidationError {
+    pub namespace: String,
+    pub key: String,
+    /// Signature the setting requires
+    pub expected: String,
+    /// Accepted values of that type, e.g. `0..=2` or `12h|24h`
+    pub constraint: Option<String>,
+    /// Signature of the rejected value
+    pub received: String,
+}
+
+impl ValidationError {
+    pub fn new(namespace: &str, key: &str, expected: &str, value: &OwnedValue) -> Self {
+        Self {
+            namespace: namespace.to_string(),
+            key: key.to_string(),
+            expected: expected.to_string(),
+            constraint: None,
+            received: value.value_signature().to_string(),
+        }
+    }
+
+    pub fn with_constraint(mut self, constraint: impl Into<String>) -> Self {
+        self.constraint = Some(constraint.into());
+        self
+    }
+
+    /// One `field=value` line per field, `constraint` only when there is one
+    pub fn encode(&self) -> String {
+        let mut message = format!("namespace={}\nkey={}\nexpected={}\n", self.namespace, self.key, self.expected);
+        if let Some(constraint) = &self.constraint {
+            message.push_str(&format!("constraint={}\n", constraint));
+        }
+        message.push_str(&format!("received={}", self.received));
+        message
+    }
+
+    /// Decode a message made by `encode`
+    pub fn parse(message: &str) -> Option<Self> {
+        let fields: HashMap<&str, &str> = message.lines().filter_map(|line| line.split_once('=')).collect();
+        let field = |name| fields.get(name).map(|value| value.to_string());
+        Some(Self {
+            namespace: field("namespace")?,
+            key: field("key")?,
+            expected: field("expected")?,
+            constraint: field("constraint"),
+            received: field("received")?,
+        })
+    }
+}
+
+impl fmt::Display for ValidationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} must be {}", self.key, self.expected)?;
+        if let Some(constraint) = &self.constraint {
+            write!(f, " in {}", constraint)?;
+        }
+        if self.received != self.expected {
+            write!(f, ", not {}", self.received)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for ValidationError {}
+
+/// A change to a setting the schema declares read-only
+///
+/// The management interface replies `AccessDenied` with it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ReadOnlyError {
+    pub namespace: String,
+    pub key: String,
+}
+
+impl ReadOnlyError {
+    pub fn new(namespace: &str, key: &str) -> Self {
+        Self {
+            namespace: namespace.to_string(),
+            key: key.to_string(),
+        }
+    }
+}
+
+impl fmt::Display for ReadOnlyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{} is read-only", self.namespace, self.key)
+    }
+}
+
+impl std::error::Error for ReadOnlyError {}
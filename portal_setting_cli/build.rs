@@ -0,0 +1,12 @@
+fn main() {
+    // Generate the gRPC service from its definition, with a bundled protoc
+    #[cfg(feature = "grpc")]
+    {
+        let protoc = protoc_bin_vendored::protoc_bin_path().expect("no bundled protoc for this platform");
+        std::env::set_var("PROTOC", protoc);
+        tonic_prost_build::configure()
+            .build_client(false)
+            .compile_protos(&["proto/portal_setting.proto"], &["proto"])
+            .expect("cannot compile proto/portal_setting.proto");
+    }
+}
@@ -0,0 +1,142 @@
+use anyhow::{bail, Context, Result};
+use nix::errno::Errno;
+use nix::fcntl::{Flock, FlockArg, OFlag};
+use nix::sys::stat::{umask, Mode};
+use nix::unistd::{chdir, dup2, fork, pipe2, setsid, ForkResult};
+use std::fs::{self, File, OpenOptions};
+use std::io::{Read, Write};
+use std::os::fd::AsRawFd;
+use std::path::{Path, PathBuf};
+
+/// Detach from the controlling terminal using the classic double fork
+///
+/// Must be called before any threads (including the tokio runtime) are
+/// started. On return the calling process is the daemon: it runs in its own
+/// session, with `/` as working directory, stdin redirected from `/dev/null`
+/// and stdout/stderr appended to `log_file` (or `/dev/null`).
+///
+/// The original process waits until the daemon reports it is serving with
+/// `Readiness::notify` and exits with 0 then, or with 1 if the daemon exits
+/// first, so whatever started it learns whether startup failed.
+pub fn daemonize(log_file: Option<&Path>) -> Result<Readiness> {
+    // Open the redirection targets first so errors still reach the terminal
+    let stdin = File::open("/dev/null").context("cannot open /dev/null")?;
+    let output = match log_file {
+        Some(path) => OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .with_context(|| format!("cannot open log file {}", path.display()))?,
+        None => OpenOptions::new()
+            .write(true)
+            .open("/dev/null")
+            .context("cannot open /dev/null")?,
+    };
+
+    // Close-on-exec, so only the daemon itself holds the write end open
+    let (reader, writer) = pipe2(OFlag::O_CLOEXEC).context("cannot create readiness pipe")?;
+
+    // SAFETY: no other threads exist yet, so the child cannot inherit locks
+    // held by threads that vanish across the fork.
+    if let ForkResult::Parent { .. } = unsafe { fork() }.context("first fork failed")? {
+        drop(writer);
+        let mut ready = [0u8; 1];
+        match File::from(reader).read(&mut ready) {
+            Ok(1) => std::process::exit(0),
+            _ => {
+                eprintln!("The service exited before it was ready");
+                std::process::exit(1);
+            }
+        }
+    }
+    drop(reader);
+
+    setsid().context("setsid failed")?;
+
+    // Fork again so the daemon is not a session leader and can never
+    // reacquire a controlling terminal
+    // SAFETY: as above, the process is still single-threaded.
+    if let ForkResult::Parent { .. } = unsafe { fork() }.context("second fork failed")? {
+        std::process::exit(0);
+    }
+
+    chdir("/").context("cannot change directory to /")?;
+    umask(Mode::from_bits_truncate(0o022));
+
+    dup2(stdin.as_raw_fd(), 0).context("cannot redirect stdin")?;
+    dup2(output.as_raw_fd(), 1).context("cannot redirect stdout")?;
+    dup2(output.as_raw_fd(), 2).context("cannot redirect stderr")?;
+
+    Ok(Readiness(File::from(writer)))
+}
+
+/// The daemon's end of the pipe the process that started it waits on
+pub struct Readiness(File);
+
+impl Readiness {
+    /// Let the starting process exit successfully
+    pub fn notify(mut self) {
+        let _ = self.0.write_all(&[1]);
+    }
+}
+
+/// A pidfile locked while the service runs and removed again when dropped
+///
+/// The lock keeps a second instance from taking over the pidfile of a
+/// running one; a pidfile left behind by one that crashed is reused.
+pub struct PidFile {
+    path: PathBuf,
+    _file: Flock<File>,
+}
+
+impl PidFile {
+    pub fn create(path: impl Into<PathBuf>) -> Result<Self> {
+        let path = path.into();
+        let file = OpenOptions::new()
+            .create(true)
+            .truncate(false)
+            .read(true)
+            .write(true)
+            .open(&path)
+            .with_context(|| format!("cannot open pidfile {}", path.display()))?;
+        let mut file = match Flock::lock(file, FlockArg::LockExclusiveNonblock) {
+            Ok(file) => file,
+            Err((mut file, Errno::EWOULDBLOCK)) => {
+                let mut pid = String::new();
+                let _ = file.read_to_string(&mut pid);
+                bail!("{} is locked by a running instance (pid {})", path.display(), pid.trim());
+            }
+            Err((_, e)) => return Err(e).with_context(|| format!("cannot lock pidfile {}", path.display())),
+        };
+        file.set_len(0)
+            .and_then(|()| file.write_all(format!("{}\n", std::process::id()).as_bytes()))
+            .with_context(|| format!("cannot write pidfile {}", path.display()))?;
+        Ok(Self { path, _file: file })
+    }
+}
+
+impl Drop for PidFile {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pidfile() {
+        let path = std::env::temp_dir().join(format!("portal-pidfile-{}", std::process::id()));
+        // Left behind by an instance that crashed
+        fs::write(&path, "1234567\n").unwrap();
+
+        let pidfile = PidFile::create(&path).unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), format!("{}\n", std::process::id()));
+        let error = PidFile::create(&path).err().unwrap();
+        assert!(error.to_string().contains("locked by a running instance"));
+
+        drop(pidfile);
+        assert!(!path.exists());
+    }
+}
@@ -0,0 +1,112 @@
+use crate::remote::{Update, UpdateError, Writer};
+use anyhow::Result;
+use futures_util::stream::{self, BoxStream};
+use futures_util::StreamExt;
+use portal_setting::state::value_to_toml;
+use portal_setting::ChangeSource;
+use tokio::net::TcpListener;
+use tokio::sync::broadcast::error::RecvError;
+use tonic::transport::server::TcpIncoming;
+use tonic::{Request, Response, Status};
+use zbus::zvariant::Value;
+
+mod proto {
+    tonic::include_proto!("portal_setting.v1");
+}
+
+use proto::management_server::{Management, ManagementServer};
+use proto::{GetRequest, ListRequest, ListResponse, SetRequest, SetResponse, Setting, WatchRequest};
+
+/// Serve `proto/portal_setting.proto` on `listener`
+pub async fn serve(listener: TcpListener, writer: Writer) -> Result<()> {
+    tonic::transport::Server::builder()
+        .add_service(ManagementServer::new(Service(writer)))
+        .serve_with_incoming(TcpIncoming::from(listener))
+        .await?;
+    Ok(())
+}
+
+struct Service(Writer);
+
+fn setting(namespace: String, key: String, value: &Value<'_>) -> Result<Setting, Status> {
+    let text = value_to_toml(value).map_err(|e| Status::internal(format!("{:#}", e)))?;
+    Ok(Setting {
+        namespace,
+        key,
+        signature: value.value_signature().to_string(),
+        value: text.to_string(),
+    })
+}
+
+fn not_found(namespace: &str, key: &str) -> Status {
+    Status::not_found(format!("{}/{} does not exist", namespace, key))
+}
+
+#[tonic::async_trait]
+impl Management for Service {
+    async fn get(&self, request: Request<GetRequest>) -> Result<Response<Setting>, Status> {
+        let GetRequest { namespace, key } = request.into_inner();
+        let value = self
+            .0
+            .read(&namespace, &key)
+            .await
+            .ok_or_else(|| not_found(&namespace, &key))?;
+        Ok(Response::new(setting(namespace, key, &value.0)?))
+    }
+
+    async fn set(&self, request: Request<SetRequest>) -> Result<Response<SetResponse>, Status> {
+        let SetRequest {
+            namespace,
+            key,
+            signature,
+            value,
+        } = request.into_inner();
+        let invalid = |e: &dyn std::fmt::Display| Status::invalid_argument(format!("{}/{}: {}", namespace, key, e));
+        let value = value.parse::<toml::Value>().map_err(|e| invalid(&e))?;
+
+        match self.0.apply(ChangeSource::Grpc, &namespace, &key, Update { signature, value }).await {
+            Ok(()) => Ok(Response::new(SetResponse {})),
+            Err(UpdateError::NotFound) => Err(not_found(&namespace, &key)),
+            Err(e @ UpdateError::Invalid(_)) => Err(invalid(&e)),
+            Err(e @ UpdateError::Failed(_)) => Err(Status::internal(e.to_string())),
+            Err(e @ (UpdateError::ReadOnly | UpdateError::Denied)) => Err(Status::permission_denied(e.to_string())),
+        }
+    }
+
+    async fn list(&self, request: Request<ListRequest>) -> Result<Response<ListResponse>, Status> {
+        let all = self.0.read_all(request.into_inner().namespaces).await;
+        let mut settings = Vec::new();
+        for (namespace, values) in all {
+            for (key, value) in values {
+                settings.push(setting(namespace.clone(), key, &value.0)?);
+            }
+        }
+        settings.sort_by(|a, b| (&a.namespace, &a.key).cmp(&(&b.namespace, &b.key)));
+        Ok(Response::new(ListResponse { settings }))
+    }
+
+    type WatchStream = BoxStream<'static, Result<Setting, Status>>;
+
+    async fn watch(&self, request: Request<WatchRequest>) -> Result<Response<Self::WatchStream>, Status> {
+        let namespaces = request.into_inner().namespaces;
+        let changes = self.0.subscribe();
+        let stream = stream::unfold((changes, namespaces), |(mut changes, namespaces)| async move {
+            loop {
+                let change = match changes.recv().await {
+                    Ok(change) => change,
+                    // A client too slow to keep up skips the changes it missed
+                    Err(RecvError::Lagged(_)) => continue,
+                    Err(RecvError::Closed) => return None,
+                };
+                let Some(value) = &change.new else {
+                    continue;
+                };
+                if namespaces.is_empty() || namespaces.contains(&change.namespace) {
+                    let setting = setting(change.namespace.clone(), change.key.clone(), &value.0);
+                    return Some((setting, (changes, namespaces)));
+                }
+            }
+        });
+        Ok(Response::new(stream.boxed()))
+    }
+}
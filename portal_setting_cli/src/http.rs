@@ -0,0 +1,129 @@
+use crate::remote::{setting_json, Update, UpdateError, Writer};
+use anyhow::Result;
+use axum::extract::ws::{Message, WebSocket, WebSocketUpgrade};
+use axum::extract::{Path, State};
+use axum::http::StatusCode;
+use axum::response::{IntoResponse, Response};
+use axum::routing::get;
+use axum::{Json, Router};
+use portal_setting::{ChangeSource, SettingChange};
+use serde_json::{json, Map, Value as JsonValue};
+use tokio::net::TcpListener;
+use tokio::sync::broadcast::{self, error::RecvError};
+
+/// Serve the settings as JSON on `listener`
+///
+/// - `GET /settings` returns every setting as `{namespace: {key: setting}}`
+/// - `GET /settings/{namespace}/{key}` returns one setting
+/// - `PUT /settings/{namespace}/{key}` validates and stores a setting
+/// - `/ws` upgrades to a WebSocket sending every change as a text message
+///   `{"namespace": ..., "key": ..., "signature": ..., "value": ...}`
+///
+/// A setting is `{"signature": "u", "value": 1}`; in `PUT` bodies the
+/// signature may be omitted to keep the setting's current type. Failures are
+/// answered with `{"error": message}`.
+pub async fn serve(listener: TcpListener, writer: Writer) -> Result<()> {
+    let router = Router::new()
+        .route("/settings", get(read_all))
+        .route("/settings/{namespace}/{key}", get(read).put(write))
+        .route("/ws", get(feed))
+        .with_state(writer);
+    axum::serve(listener, router).await?;
+    Ok(())
+}
+
+/// A failed request
+struct Error(StatusCode, String);
+
+impl IntoResponse for Error {
+    fn into_response(self) -> Response {
+        (self.0, Json(json!({ "error": self.1 }))).into_response()
+    }
+}
+
+fn not_found(namespace: &str, key: &str) -> Error {
+    Error(StatusCode::NOT_FOUND, format!("{}/{} does not exist", namespace, key))
+}
+
+fn internal(error: impl std::fmt::Display) -> Error {
+    Error(StatusCode::INTERNAL_SERVER_ERROR, format!("{:#}", error))
+}
+
+async fn read_all(State(writer): State<Writer>) -> Result<Json<JsonValue>, Error> {
+    let mut all = Map::new();
+    for (namespace, settings) in writer.read_all(Vec::new()).await {
+        let mut namespace_json = Map::new();
+        for (key, value) in settings {
+            namespace_json.insert(key, setting_json(&value.0).map_err(internal)?);
+        }
+        all.insert(namespace, namespace_json.into());
+    }
+    Ok(Json(all.into()))
+}
+
+async fn read(
+    State(writer): State<Writer>,
+    Path((namespace, key)): Path<(String, String)>,
+) -> Result<Json<JsonValue>, Error> {
+    let value = writer
+        .read(&namespace, &key)
+        .await
+        .ok_or_else(|| not_found(&namespace, &key))?;
+    Ok(Json(setting_json(&value.0).map_err(internal)?))
+}
+
+async fn write(
+    State(writer): State<Writer>,
+    Path((namespace, key)): Path<(String, String)>,
+    Json(update): Json<Update>,
+) -> Result<StatusCode, Error> {
+    match writer.apply(ChangeSource::Http, &namespace, &key, update).await {
+        Ok(()) => Ok(StatusCode::NO_CONTENT),
+        Err(UpdateError::NotFound) => Err(not_found(&namespace, &key)),
+        Err(e @ UpdateError::Invalid(_)) => Err(Error(StatusCode::BAD_REQUEST, format!("{}/{}: {}", namespace, key, e))),
+        Err(e @ UpdateError::Failed(_)) => Err(internal(e)),
+        Err(e @ (UpdateError::ReadOnly | UpdateError::Denied)) => Err(Error(StatusCode::FORBIDDEN, e.to_string())),
+    }
+}
+
+async fn feed(State(writer): State<Writer>, upgrade: WebSocketUpgrade) -> Response {
+    // Subscribe before upgrading, so no change after the request is missed
+    let changes = writer.subscribe();
+    upgrade.on_upgrade(|socket| forward_changes(socket, changes))
+}
+
+async fn forward_changes(mut socket: WebSocket, mut changes: broadcast::Receiver<SettingChange>) {
+    loop {
+        tokio::select! {
+            change = changes.recv() => match change {
+                Ok(change) => {
+                    let Some(text) = change_json(&change) else {
+                        continue;
+                    };
+                    if socket.send(Message::Text(text.into())).await.is_err() {
+                        return;
+                    }
+                }
+                // A client too slow to keep up skips the changes it missed
+                Err(RecvError::Lagged(_)) => continue,
+                Err(RecvError::Closed) => return,
+            },
+            // Messages from the client are ignored
+            message = socket.recv() => {
+                if !matches!(message, Some(Ok(_))) {
+                    return;
+                }
+            }
+        }
+    }
+}
+
+/// `{"namespace": ..., "key": ..., "signature": ..., "value": ...}`
+fn change_json(change: &SettingChange) -> Option<String> {
+    let JsonValue::Object(mut event) = setting_json(&change.new.as_ref()?.0).ok()? else {
+        return None;
+    };
+    event.insert("namespace".to_string(), change.namespace.clone().into());
+    event.insert("key".to_string(), change.key.clone().into());
+    Some(JsonValue::Object(event).to_string())
+}
@@ -1,35 +1,932 @@
-use anyhow::Result;
-use portal_setting::SettingsPortal;
+use anyhow::{bail, Context, Result};
+use clap::{CommandFactory, Parser, Subcommand};
+use clap_complete::Shell;
+use futures_util::StreamExt;
+use portal_setting::config::{MqttConfig, NameLostAction, Seat, StateFormat};
+use portal_setting::{
+    audit, config, ChangeObserver, Config, HookRunner, Policy, SensitiveKeys, SettingsBackend, SettingsStore,
+};
+use std::collections::BTreeMap;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use tokio::signal::unix::{signal, SignalKind};
+use zbus::fdo::{DBusProxy, RequestNameFlags, RequestNameReply};
 use zbus::Connection;
+#[cfg(feature = "management")]
+use portal_setting::{SenderKeys, SessionOverrides};
+#[cfg(feature = "persistence")]
+use {
+    portal_setting::{ChangeSource, StateFile},
+    std::time::Duration,
+    zbus::zvariant::OwnedValue,
+};
+
+mod daemon;
+#[cfg(feature = "grpc")]
+mod grpc;
+#[cfg(feature = "http")]
+mod http;
+mod mirror;
+mod remote;
+#[cfg(feature = "mqtt")]
+mod mqtt;
+#[cfg(feature = "management")]
+mod selftest;
+mod service;
+mod users;
+#[cfg(feature = "logging")]
+mod watch;
+
+/// XDG Desktop Portal Settings service
+#[derive(Parser)]
+#[command(version)]
+struct Args {
+    #[command(subcommand)]
+    command: Option<Command>,
+
+    /// Load settings and options from this TOML file
+    #[arg(long, value_name = "PATH")]
+    config: Option<PathBuf>,
+
+    /// Append a record of every settings change to this file
+    #[arg(long, value_name = "PATH")]
+    audit_log: Option<PathBuf>,
+
+    /// Rotate the audit log once it would grow beyond this many bytes (0 disables rotation) [default: 1048576]
+    #[arg(long, value_name = "BYTES")]
+    audit_log_max_size: Option<u64>,
+
+    /// Number of rotated audit log files to keep [default: 3]
+    #[arg(long, value_name = "N")]
+    audit_log_keep: Option<usize>,
+
+    /// Send a record of every settings change, and of every change refused over D-Bus, to the systemd journal
+    #[arg(long)]
+    audit_journal: bool,
+
+    /// Run executables from this directory after settings change
+    #[arg(long, value_name = "DIR")]
+    hooks_dir: Option<PathBuf>,
+
+    /// Load settings backends and change observers from the shared objects in this directory
+    #[arg(long, value_name = "DIR")]
+    plugin_dir: Option<PathBuf>,
+
+    /// Check writes to org.gnome.* namespaces against the GSettings schemas in this directory [default: /usr/share/glib-2.0/schemas]
+    #[arg(long, value_name = "DIR", num_args = 0..=1, default_missing_value = "/usr/share/glib-2.0/schemas")]
+    gsettings_schemas: Option<PathBuf>,
+
+    /// Limit the namespaces each app and user sees and changes to the rules of this policy file
+    #[arg(long, value_name = "PATH")]
+    policy: Option<PathBuf>,
+
+    /// Connect to the system bus instead of the session bus
+    #[arg(long, conflicts_with = "address")]
+    system: bool,
+
+    /// Keep the settings of each calling user apart, with a state file per uid next to --state-file
+    #[arg(long, requires = "system")]
+    per_user: bool,
+
+    /// Connect to the message bus at this D-Bus address, e.g. unix:path=/tmp/test-bus
+    #[arg(long, value_name = "ADDRESS")]
+    address: Option<String>,
+
+    /// Bus name for the impl (backend) interface [default: org.freedesktop.impl.portal.Settings]
+    #[arg(long, value_name = "NAME")]
+    bus_name: Option<String>,
+
+    /// Also serve org.freedesktop.portal.Settings to applications directly
+    #[arg(long)]
+    frontend: bool,
+
+    /// Bus name for the frontend interface [default: org.freedesktop.portal.Desktop]
+    #[arg(long, value_name = "NAME")]
+    frontend_name: Option<String>,
+
+    /// What to do when another implementation takes over the bus name: exit or passive [default: exit]
+    #[arg(long, value_name = "ACTION")]
+    on_name_lost: Option<NameLostAction>,
+
+    /// Persist settings changed at runtime to this file
+    #[arg(long, value_name = "PATH")]
+    state_file: Option<PathBuf>,
+
+    /// Write the state file after every change instead of coalescing changes
+    #[arg(long)]
+    sync_writes: bool,
+
+    /// Format the state file is written in: toml, or binary to load faster at boot [default: toml]
+    #[arg(long, value_name = "FORMAT")]
+    state_format: Option<StateFormat>,
+
+    /// Serve this graphical seat; seats other than seat0 get the seat appended to the bus name and state file
+    #[arg(long, value_name = "SEAT", env = "XDG_SEAT")]
+    seat: Option<Seat>,
+
+    /// Validate writes made through the management interface without applying them
+    #[arg(long)]
+    dry_run: bool,
+
+    /// Refuse every change over D-Bus, HTTP, gRPC and MQTT, for images whose settings only change through provisioning
+    #[arg(long)]
+    read_only: bool,
+
+    /// Reject writes to keys without a default, like [limits] strict = true
+    #[arg(long)]
+    strict: bool,
+
+    /// Serve a read-only copy of the settings of the org.freedesktop.portal.Settings implementation at this bus name, e.g. org.freedesktop.portal.Desktop
+    #[arg(long, value_name = "NAME")]
+    mirror: Option<String>,
+
+    /// Print every call to the service (with its sender) and every signal it emits
+    #[arg(long)]
+    watch: bool,
+
+    /// Serve the settings as JSON over HTTP on this address, e.g. 127.0.0.1:8080 (no authentication)
+    #[arg(long, value_name = "ADDRESS")]
+    http: Option<SocketAddr>,
+
+    /// Serve the management interface over gRPC on this address (no authentication)
+    #[arg(long, value_name = "ADDRESS")]
+    grpc: Option<SocketAddr>,
+
+    /// Serve tokio-console on this address, e.g. 0.0.0.0:6669 (no authentication)
+    #[arg(long, value_name = "ADDRESS")]
+    console: Option<SocketAddr>,
+
+    /// Run every task on the main thread instead of a worker per CPU, using less memory
+    #[arg(long)]
+    single_threaded: bool,
+
+    /// Stay attached to the terminal (default)
+    #[arg(long, conflicts_with = "daemonize")]
+    foreground: bool,
+
+    /// Detach from the terminal and run in the background
+    #[arg(long)]
+    daemonize: bool,
+
+    /// Write the service's process id to this file
+    #[arg(long, value_name = "PATH")]
+    pidfile: Option<PathBuf>,
+
+    /// Append stdout/stderr to this file when daemonized (default: /dev/null)
+    #[arg(long, value_name = "PATH", requires = "daemonize")]
+    log_file: Option<PathBuf>,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Write a commented configuration file containing the built-in defaults
+    Seed {
+        /// Write to this file instead of stdout
+        #[arg(long, short, value_name = "PATH")]
+        output: Option<PathBuf>,
+
+        /// Overwrite the output file if it already exists
+        #[arg(long)]
+        force: bool,
+    },
+    /// Validate configuration files without starting the service
+    ///
+    /// Every problem is reported with its file and line; the exit status is
+    /// non-zero if any file is invalid.
+    Check {
+        /// Files to check (default: the file given with --config)
+        #[arg(value_name = "PATH")]
+        files: Vec<PathBuf>,
+    },
+    /// Serve the built-in defaults on a private connection and exercise them
+    ///
+    /// Runs the read/write/signal checks of `portal-setting-client test` without a
+    /// session bus; the exit status is non-zero if any check fails.
+    SelfTest,
+    /// Print the D-Bus introspection XML of all served interfaces
+    ///
+    /// No bus connection is made, so this can run at build time to generate
+    /// bindings in other languages.
+    Introspect,
+    /// Print a shell completion script
+    Completions {
+        #[arg(value_name = "SHELL")]
+        shell: Shell,
+    },
+}
+
+impl Args {
+    /// Resolve relative paths before daemonizing changes the working directory
+    fn make_paths_absolute(&mut self) -> Result<()> {
+        for path in [
+            &mut self.config,
+            &mut self.audit_log,
+            &mut self.hooks_dir,
+            &mut self.plugin_dir,
+            &mut self.gsettings_schemas,
+            &mut self.policy,
+            &mut self.state_file,
+            &mut self.pidfile,
+            &mut self.log_file,
+        ]
+        .into_iter()
+        .flatten()
+        {
+            *path = std::path::absolute(&*path)?;
+        }
+        Ok(())
+    }
+}
+
+fn main() -> Result<()> {
+    let mut args = Args::parse();
+
+    match &args.command {
+        Some(Command::Seed { output, force }) => return seed(output.as_deref(), *force),
+        Some(Command::Check { files }) => {
+            let files: Vec<&Path> = match (files.is_empty(), &args.config) {
+                (false, _) => files.iter().map(PathBuf::as_path).collect(),
+                (true, Some(config)) => vec![config.as_path()],
+                (true, None) => bail!("no configuration file to check (pass a path or --config)"),
+            };
+            if !check(&files, args.config.as_deref()) {
+                std::process::exit(1);
+            }
+            return Ok(());
+        }
+        Some(Command::Completions { shell }) => {
+            clap_complete::generate(*shell, &mut Args::command(), "portal-setting-service", &mut std::io::stdout());
+            return Ok(());
+        }
+        Some(Command::Introspect) => {
+            print!("{}", service::introspect());
+            return Ok(());
+        }
+        Some(Command::SelfTest) => {
+            if !self_test()? {
+                std::process::exit(1);
+            }
+            return Ok(());
+        }
+        None => {}
+    }
+
+    let readiness = match args.daemonize {
+        true => {
+            args.make_paths_absolute()?;
+            Some(daemon::daemonize(args.log_file.as_deref())?)
+        }
+        false => None,
+    };
+
+    // The runtime must only be started after forking
+    let runtime = match args.single_threaded {
+        true => tokio::runtime::Builder::new_current_thread().enable_all().build()?,
+        false => tokio::runtime::Runtime::new()?,
+    };
+    runtime.block_on(run(args, readiness))
+}
+
+async fn run(args: Args, readiness: Option<daemon::Readiness>) -> Result<()> {
+    let _pidfile = args.pidfile.as_ref().map(daemon::PidFile::create).transpose()?;
+    // Before any task is spawned, so the console sees all of them
+    if let Some(address) = args.console {
+        start_console(address)?;
+    }
 
-#[tokio::main]
-async fn main() -> Result<()> {
     println!("Starting XDG Portal Settings Service...");
 
-    // Create the settings portal
-    let portal = SettingsPortal::new();
+    let mut config = match &args.config {
+        Some(path) => {
+            println!("Loading configuration from {}", path.display());
+            Config::load(path)?
+        }
+        None => Config::default(),
+    };
+    config.limits.strict |= args.strict;
+    let sandbox = config.sandbox.sandbox();
+    if let Some(sandbox) = &sandbox {
+        println!("Running in {}: applying conservative defaults", sandbox);
+        for namespace in portal_setting::sandbox::restrict(&mut config)? {
+            println!("Not serving {} in {}", namespace, sandbox);
+        }
+    }
+
+    let plugin_dir = args
+        .plugin_dir
+        .or_else(|| config.plugins.as_ref().map(|p| p.dir.clone()));
+    let (backends, plugin_observers) = match plugin_dir {
+        Some(dir) => load_plugins(&dir)?,
+        None => (Vec::new(), Vec::new()),
+    };
+
+    // Create the settings store
+    let mut store = SettingsStore::from_sources(&config, &backends).context("invalid settings")?;
+    let validator = args.gsettings_schemas.as_deref().map(load_gsettings_schemas).transpose()?;
+    if let Some(validator) = &validator {
+        store = store.with_validator(validator.clone());
+    }
+    let policy = match &args.policy {
+        Some(path) => {
+            println!("Applying the access policy in {}", path.display());
+            Some(Policy::load(path)?)
+        }
+        None => None,
+    };
+    if let Some(policy) = &policy {
+        // Behind xdg-desktop-portal every call comes from the portal itself
+        let serves_frontend = args.frontend || config.service.frontend;
+        if !policy.apps.is_empty() && !serves_frontend {
+            bail!("[apps] rules of the policy only match apps calling the frontend; serve it with --frontend");
+        }
+        store = store.with_policy(policy.clone());
+    }
+    if args.dry_run {
+        println!("Dry-run mode: management writes are validated but not applied");
+    }
+    if args.read_only {
+        println!("Read-only mode: settings cannot be changed through the service");
+    }
+    #[cfg(feature = "management")]
+    if config.management.is_restricted() && args.mirror.is_none() {
+        println!("Serving the management interface at {} to allowed callers only", portal_setting::MANAGEMENT_PATH);
+    }
+
+    // Command line options take precedence over the configuration file
+    let state_file = args
+        .state_file
+        .or_else(|| config.state.as_ref().map(|s| s.path.clone()));
+    let state_file = match &args.seat {
+        Some(seat) => state_file.map(|path| seat.state_path(&path)),
+        None => state_file,
+    };
+    // Nothing is written to the host from a sandbox
+    let state_file = match &sandbox {
+        Some(_) => state_file.and_then(|path| {
+            let session = portal_setting::sandbox::session_state_path(&path);
+            if session.is_none() {
+                println!("Not persisting settings to {} without a session runtime directory", path.display());
+            }
+            session
+        }),
+        None => state_file,
+    };
+    #[cfg(feature = "persistence")]
+    let mut user_state = None;
+    #[cfg(feature = "persistence")]
+    let state = match state_file {
+        Some(path) => {
+            let format = args.state_format.or(config.state.as_ref().map(|s| s.format)).unwrap_or_default();
+            let debounce = (!args.sync_writes).then(|| {
+                Duration::from_millis(config.state.as_ref().map_or(config::DEFAULT_STATE_DEBOUNCE_MS, |s| s.debounce_ms))
+            });
+            if args.per_user {
+                user_state = Some(users::StateOptions {
+                    path: path.clone(),
+                    format,
+                    debounce,
+                });
+            }
+            let state = Arc::new(StateFile::new(path).with_format(format));
+            println!("Persisting settings to {}", state.path().display());
+            if let Some(debounce) = debounce {
+                state.flush_after(debounce)?;
+            }
+            store.add_observer(state.clone());
+            Some(state)
+        }
+        None => None,
+    };
+    #[cfg(not(feature = "persistence"))]
+    if let Some(path) = state_file {
+        bail!("cannot persist settings to {}: built without the `persistence` feature", path.display());
+    }
+    // Read while connecting, and apply once the defaults are served
+    #[cfg(feature = "persistence")]
+    let loading = state.clone().map(|state| portal_setting::task::spawn("state-load", async move { state.load().await }));
+
+    // Observers of every user's changes, with a store per user
+    let mut user_observers = Vec::new();
+    let audit_config = config.audit.as_ref();
+    let audit_log = args
+        .audit_log
+        .or_else(|| audit_config.map(|a| a.path.clone()));
+    if let Some(path) = audit_log {
+        let max_bytes = args
+            .audit_log_max_size
+            .or(audit_config.map(|a| a.max_size))
+            .unwrap_or(audit::DEFAULT_MAX_BYTES);
+        let keep = args
+            .audit_log_keep
+            .or(audit_config.map(|a| a.keep))
+            .unwrap_or(audit::DEFAULT_KEEP);
+        let log = open_audit_log(path, max_bytes, keep, store.sensitive_keys().clone())?;
+        store.add_observer(log.clone());
+        user_observers.push(log);
+    }
+    if args.audit_journal {
+        let journal = open_audit_journal(store.sensitive_keys().clone())?;
+        store.add_observer(journal.clone());
+        user_observers.push(journal);
+    }
+
+    let hooks_dir = args
+        .hooks_dir
+        .or_else(|| config.hooks.as_ref().map(|h| h.dir.clone()));
+    if let Some(dir) = hooks_dir {
+        let hooks = HookRunner::new(dir)?;
+        println!("Running change hooks from {}", hooks.dir().display());
+        store.add_observer(Arc::new(hooks));
+    }
+    for observer in plugin_observers {
+        store.add_observer(observer);
+    }
+    #[cfg(feature = "persistence")]
+    let user_states = users::UserStates::default();
+    // The stores of users are made like the shared one when they first call
+    let template = args.per_user.then(|| users::Template {
+        config: config.clone(),
+        backends: backends.clone(),
+        validator: validator.clone(),
+        policy: policy.clone(),
+        observers: user_observers,
+        #[cfg(feature = "persistence")]
+        state: user_state,
+        #[cfg(feature = "persistence")]
+        states: user_states.clone(),
+    });
+
+    let mut bus_name = args.bus_name.unwrap_or(config.service.bus_name);
+    if let Some(seat) = &args.seat {
+        println!("Serving seat {}", seat.name());
+        bus_name = seat.bus_name(&bus_name);
+    }
+    let on_name_lost = args.on_name_lost.unwrap_or(config.service.on_name_lost);
+    let connection = connect(args.system, args.address.as_deref())
+        .await
+        .context("cannot connect to the bus")?;
+    let user_stores = template.map(|template| template.stores().with_signals(connection.clone(), service::path()));
+    if let Some(users) = &user_stores {
+        println!("Keeping the settings of each user apart");
+        store = store.with_user_stores(users.clone());
+        let (connection, users) = (connection.clone(), users.clone());
+        portal_setting::task::spawn("user-peers", async move {
+            if let Err(e) = users.run(connection).await {
+                eprintln!("Not forgetting closed connections: {:#}", e);
+            }
+        });
+    }
+    #[cfg(feature = "management")]
+    let senders = SenderKeys::new();
+    #[cfg(feature = "management")]
+    store.add_observer(Arc::new(senders.clone()));
+    #[cfg(feature = "management")]
+    let sessions = match user_stores.is_none() && args.mirror.is_none() {
+        true => system_bus(args.system, &connection).await.map(|system| (system, SessionOverrides::new())),
+        false => None,
+    };
+    #[cfg(feature = "management")]
+    if let Some((_, overrides)) = &sessions {
+        store.add_observer(Arc::new(overrides.clone()));
+    }
+    let exports = service::Exports {
+        store,
+        #[cfg(feature = "management")]
+        dry_run: args.dry_run,
+        #[cfg(feature = "management")]
+        read_only: args.read_only,
+        without_management: args.mirror.is_some(),
+        #[cfg(feature = "management")]
+        writers: config.management.clone(),
+        #[cfg(feature = "management")]
+        senders: senders.clone(),
+        #[cfg(feature = "management")]
+        sessions: sessions.clone(),
+        // Only root's connections see the changes of the shared store
+        emitter: match &user_stores {
+            Some(users) => service::emitter_to(&connection, users.destinations(0)),
+            None => service::emitter(&connection),
+        },
+        frontend_name: (args.frontend || config.service.frontend)
+            .then(|| args.frontend_name.unwrap_or(config.service.frontend_name)),
+    };
 
-    // Connect to session bus
-    let connection = Connection::session().await?;
+    #[cfg(feature = "management")]
+    {
+        let (connection, store) = (connection.clone(), exports.store.clone());
+        portal_setting::task::spawn("session-keys", async move {
+            if let Err(e) = senders.run(connection, store).await {
+                eprintln!("Not removing session-scoped keys: {:#}", e);
+            }
+        });
+        if let Some((system, overrides)) = sessions {
+            let store = exports.store.clone();
+            portal_setting::task::spawn("login-sessions", async move {
+                if let Err(e) = overrides.run(system, store).await {
+                    eprintln!("Not restoring the values of ended login sessions: {:#}", e);
+                }
+            });
+        }
+    }
+    if args.watch {
+        start_watch(connection.clone(), exports.store.sensitive_keys().clone())?;
+    }
 
-    // Request the well-known name
-    connection
-        .request_name("org.freedesktop.impl.portal.Settings")
-        .await?;
+    let writer = remote::Writer::new(exports.store.clone(), args.dry_run)
+        .with_read_only(exports.without_management || args.read_only);
+    if let Some(name) = args.mirror {
+        println!("Mirroring the settings of {}", name);
+        mirror::start(connection.clone(), name, writer.clone()).await?;
+    }
+    if let Some(address) = args.http {
+        start_http(address, writer.clone()).await?;
+    }
 
-    println!("Service registered at org.freedesktop.impl.portal.Settings");
+    if let Some(address) = args.grpc {
+        start_grpc(address, writer.clone()).await?;
+    }
+    if let Some(mqtt) = config.mqtt {
+        start_mqtt(mqtt, writer)?;
+    }
 
-    // Serve the interface at the standard path
-    connection
-        .object_server()
-        .at("/org/freedesktop/portal/desktop", portal)
-        .await?;
+    // Watch ownership before requesting names so no signal is missed
+    let dbus = DBusProxy::new(&connection).await?;
+    let mut name_lost = dbus.receive_name_lost().await?;
+    let mut name_acquired = dbus.receive_name_acquired().await?;
 
-    println!("Service is ready at /org/freedesktop/portal/desktop");
+    // Request the well-known name, allowing another implementation to take over
+    let mut flags = RequestNameFlags::AllowReplacement | RequestNameFlags::ReplaceExisting;
+    if on_name_lost == NameLostAction::Exit {
+        flags |= RequestNameFlags::DoNotQueue;
+    }
+    let mut exported = false;
+    match connection.request_name_with_flags(bus_name.as_str(), flags).await? {
+        RequestNameReply::PrimaryOwner | RequestNameReply::AlreadyOwner => {
+            println!("Service registered at {}", bus_name);
+
+            // Serve the interfaces at the standard path
+            exports.export(&connection).await?;
+            exported = true;
+            println!("Service is ready at {}", service::PATH);
+        }
+        RequestNameReply::InQueue => {
+            println!("{} is owned by another service, waiting for it", bus_name);
+        }
+        RequestNameReply::Exists => bail!("{} is owned by another service", bus_name),
+    }
+    #[cfg(feature = "persistence")]
+    if let Some(loading) = loading {
+        restore(&exports, loading.await??).await?;
+    }
+    if let Some(readiness) = readiness {
+        readiness.notify();
+    }
     println!("Press Ctrl+C to stop the service");
 
-    // Keep the service running
-    std::future::pending::<()>().await;
+    // Keep the service running until asked to stop
+    let mut sigterm = signal(SignalKind::terminate())?;
+    let mut sigusr1 = signal(SignalKind::user_defined1())?;
+    loop {
+        tokio::select! {
+            _ = tokio::signal::ctrl_c() => break,
+            _ = sigterm.recv() => break,
+            _ = sigusr1.recv() => dump_settings(&exports.store).await,
+            Some(signal) = name_lost.next() => {
+                let name = signal.args()?.name().to_string();
+                if name == bus_name && exported {
+                    println!("Lost {} to another service", bus_name);
+                    exports.unexport(&connection).await?;
+                    exported = false;
+                    #[cfg(feature = "persistence")]
+                    if let Some(state) = &state {
+                        state.save()?;
+                    }
+                    #[cfg(feature = "persistence")]
+                    user_states.save()?;
+                    match on_name_lost {
+                        NameLostAction::Exit => break,
+                        NameLostAction::Passive => println!("Passive until {} is available again", bus_name),
+                    }
+                } else if Some(&name) == exports.frontend_name.as_ref() {
+                    println!("Lost {} to another service, no longer serving the frontend", name);
+                    connection
+                        .object_server()
+                        .remove::<portal_setting::SettingsFrontend, _>(service::PATH)
+                        .await?;
+                }
+            }
+            Some(signal) = name_acquired.next() => {
+                if signal.args()?.name() == bus_name.as_str() && !exported {
+                    println!("Service registered at {}", bus_name);
+                    exports.export(&connection).await?;
+                    exported = true;
+                    println!("Service is ready at {}", service::PATH);
+                }
+            }
+        }
+    }
+
+    println!("Stopping XDG Portal Settings Service");
+    #[cfg(feature = "persistence")]
+    if let Some(state) = &state {
+        state.save()?;
+    }
+    #[cfg(feature = "persistence")]
+    user_states.save()?;
+
+    Ok(())
+}
+
+#[cfg(feature = "management")]
+fn self_test() -> Result<bool> {
+    tokio::runtime::Runtime::new()?.block_on(selftest::run(SettingsStore::new()))
+}
+
+#[cfg(not(feature = "management"))]
+fn self_test() -> Result<bool> {
+    bail!("cannot run the self-test: built without the `management` feature")
+}
+
+/// The system bus, where logind keeps track of login sessions, unless it
+/// cannot be reached
+#[cfg(feature = "management")]
+async fn system_bus(system: bool, connection: &Connection) -> Option<Connection> {
+    if system {
+        return Some(connection.clone());
+    }
+    match Connection::system().await {
+        Ok(system) => Some(system),
+        Err(e) => {
+            eprintln!("Not tracking login sessions: {:#}", e);
+            None
+        }
+    }
+}
+
+/// Print every setting served, as asked for with SIGUSR1, masking the values
+/// of sensitive settings
+async fn dump_settings(store: &SettingsStore) {
+    let settings: BTreeMap<_, BTreeMap<_, _>> = store
+        .read_all(Vec::new())
+        .await
+        .into_iter()
+        .map(|(namespace, keys)| (namespace, keys.into_iter().collect()))
+        .collect();
+    println!("Settings served:");
+    for (namespace, keys) in &settings {
+        for (key, value) in keys {
+            println!("  {} {} {}", namespace, key, store.sensitive_keys().show(namespace, key, &value.0));
+        }
+    }
+}
+
+#[cfg(feature = "logging")]
+fn open_audit_log(path: PathBuf, max_bytes: u64, keep: usize, sensitive: SensitiveKeys) -> Result<Arc<dyn ChangeObserver>> {
+    let log = portal_setting::AuditLog::open(path, max_bytes, keep)?.with_sensitive_keys(sensitive);
+    println!("Auditing settings changes to {}", log.path().display());
+    Ok(Arc::new(log))
+}
+
+#[cfg(not(feature = "logging"))]
+fn open_audit_log(path: PathBuf, _max_bytes: u64, _keep: usize, _sensitive: SensitiveKeys) -> Result<Arc<dyn ChangeObserver>> {
+    bail!("cannot audit changes to {}: built without the `logging` feature", path.display())
+}
+
+#[cfg(feature = "logging")]
+fn open_audit_journal(sensitive: SensitiveKeys) -> Result<Arc<dyn ChangeObserver>> {
+    let journal = portal_setting::AuditJournal::connect()?.with_sensitive_keys(sensitive);
+    println!("Auditing settings changes to the journal as {}", audit::JOURNAL_IDENTIFIER);
+    Ok(Arc::new(journal))
+}
+
+#[cfg(not(feature = "logging"))]
+fn open_audit_journal(_sensitive: SensitiveKeys) -> Result<Arc<dyn ChangeObserver>> {
+    bail!("cannot audit changes to the journal: built without the `logging` feature")
+}
+
+#[cfg(feature = "logging")]
+fn start_watch(connection: Connection, sensitive: SensitiveKeys) -> Result<()> {
+    portal_setting::task::spawn("watch", async move {
+        if let Err(e) = watch::run(connection, sensitive).await {
+            eprintln!("Watching stopped: {:#}", e);
+        }
+    });
+    Ok(())
+}
+
+#[cfg(not(feature = "logging"))]
+fn start_watch(_connection: Connection, _sensitive: SensitiveKeys) -> Result<()> {
+    bail!("cannot watch the service: built without the `logging` feature")
+}
+
+/// Apply persisted values over the served defaults, emitting
+/// `SettingChanged` for each as one batch
+///
+/// Settings changed since startup keep their newer value.
+#[cfg(feature = "persistence")]
+async fn restore(exports: &service::Exports, entries: Vec<(String, String, OwnedValue)>) -> Result<()> {
+    for (key, e) in exports.store.restore_many_from(ChangeSource::State, entries).await {
+        eprintln!("Ignoring persisted {}/{}: {:#}", key.namespace, key.key, e);
+    }
+    Ok(())
+}
+
+type Plugins = (Vec<Arc<dyn SettingsBackend>>, Vec<Arc<dyn ChangeObserver>>);
+
+#[cfg(feature = "plugins")]
+fn load_plugins(dir: &Path) -> Result<Plugins> {
+    let mut plugins: Plugins = (Vec::new(), Vec::new());
+    for plugin in portal_setting::plugin::load_dir(dir)? {
+        println!("Loaded plugin {}", plugin.name());
+        plugins.0.push(plugin.clone());
+        plugins.1.push(plugin);
+    }
+    Ok(plugins)
+}
+
+#[cfg(not(feature = "plugins"))]
+fn load_plugins(dir: &Path) -> Result<Plugins> {
+    bail!(
+        "cannot load plugins from {}: built without the `plugins` feature",
+        dir.display()
+    )
+}
+
+#[cfg(feature = "gsettings")]
+fn load_gsettings_schemas(dir: &Path) -> Result<Arc<dyn portal_setting::Validator>> {
+    let schemas = portal_setting::gschema::GSettingsSchemas::load_dir(dir)?;
+    println!("Checking org.gnome.* writes against {} GSettings schemas in {}", schemas.len(), dir.display());
+    for drift in schemas.drift() {
+        eprintln!("Warning: {}", drift);
+    }
+    Ok(Arc::new(schemas))
+}
+
+#[cfg(not(feature = "gsettings"))]
+fn load_gsettings_schemas(dir: &Path) -> Result<Arc<dyn portal_setting::Validator>> {
+    bail!(
+        "cannot load GSettings schemas from {}: built without the `gsettings` feature",
+        dir.display()
+    )
+}
+
+/// Connect to the bus at `address`, or the system or session bus
+async fn connect(system: bool, address: Option<&str>) -> zbus::Result<Connection> {
+    match (system, address) {
+        (_, Some(address)) => zbus::connection::Builder::address(address)?.build().await,
+        (true, None) => Connection::system().await,
+        (false, None) => Connection::session().await,
+    }
+}
+
+#[cfg(feature = "http")]
+async fn start_http(address: SocketAddr, writer: remote::Writer) -> Result<()> {
+    let listener = tokio::net::TcpListener::bind(address)
+        .await
+        .with_context(|| format!("cannot listen on {}", address))?;
+    println!("HTTP bridge listening on http://{}", listener.local_addr()?);
+    portal_setting::task::spawn("http-bridge", async move {
+        if let Err(e) = http::serve(listener, writer).await {
+            eprintln!("HTTP bridge stopped: {:#}", e);
+        }
+    });
+    Ok(())
+}
+
+#[cfg(not(feature = "http"))]
+async fn start_http(address: SocketAddr, _writer: remote::Writer) -> Result<()> {
+    bail!("cannot serve HTTP on {}: built without the `http` feature", address)
+}
+
+#[cfg(feature = "grpc")]
+async fn start_grpc(address: SocketAddr, writer: remote::Writer) -> Result<()> {
+    let listener = tokio::net::TcpListener::bind(address)
+        .await
+        .with_context(|| format!("cannot listen on {}", address))?;
+    println!("gRPC service listening on {}", listener.local_addr()?);
+    portal_setting::task::spawn("grpc", async move {
+        if let Err(e) = grpc::serve(listener, writer).await {
+            eprintln!("gRPC service stopped: {:#}", e);
+        }
+    });
+    Ok(())
+}
+
+#[cfg(not(feature = "grpc"))]
+async fn start_grpc(address: SocketAddr, _writer: remote::Writer) -> Result<()> {
+    bail!("cannot serve gRPC on {}: built without the `grpc` feature", address)
+}
+
+#[cfg(feature = "console")]
+fn start_console(address: SocketAddr) -> Result<()> {
+    // Without it the console has no tasks to show, and refuses to start
+    if !cfg!(tokio_unstable) {
+        bail!(
+            "cannot serve tokio-console on {}: built without RUSTFLAGS=\"--cfg tokio_unstable\"",
+            address
+        );
+    }
+    console_subscriber::ConsoleLayer::builder()
+        .with_default_env()
+        .server_addr(address)
+        .init();
+    println!("tokio-console listening on {}", address);
+    Ok(())
+}
+
+#[cfg(not(feature = "console"))]
+fn start_console(address: SocketAddr) -> Result<()> {
+    bail!("cannot serve tokio-console on {}: built without the `console` feature", address)
+}
+
+#[cfg(feature = "mqtt")]
+fn start_mqtt(config: MqttConfig, writer: remote::Writer) -> Result<()> {
+    println!("Synchronizing settings with MQTT broker {}:{}", config.host, config.port);
+    portal_setting::task::spawn("mqtt", async move {
+        if let Err(e) = mqtt::run(config, writer).await {
+            eprintln!("MQTT synchronization stopped: {:#}", e);
+        }
+    });
+    Ok(())
+}
+
+#[cfg(not(feature = "mqtt"))]
+fn start_mqtt(config: MqttConfig, _writer: remote::Writer) -> Result<()> {
+    bail!(
+        "cannot synchronize with MQTT broker {}: built without the `mqtt` feature",
+        config.host
+    )
+}
 
+fn seed(output: Option<&Path>, force: bool) -> Result<()> {
+    let text = config::seed();
+    match output {
+        Some(path) => {
+            if path.exists() && !force {
+                bail!("{} already exists (use --force to overwrite)", path.display());
+            }
+            std::fs::write(path, text)
+                .with_context(|| format!("cannot write {}", path.display()))?;
+            eprintln!("Wrote default configuration to {}", path.display());
+        }
+        None => print!("{}", text),
+    }
     Ok(())
 }
+
+/// Report every problem in `files`, returning whether all of them are valid
+///
+/// State files are checked against the constraints of the configuration at
+/// `config`, or the built-in ones.
+fn check(files: &[&Path], config: Option<&Path>) -> bool {
+    let mut valid = true;
+    let mut constraints = None;
+    for path in files {
+        let contents = match std::fs::read(path) {
+            Ok(contents) => contents,
+            Err(e) => {
+                eprintln!("{}: {}", path.display(), e);
+                valid = false;
+                continue;
+            }
+        };
+
+        let diagnostics = match check_state(&contents, config, &mut constraints) {
+            Some(diagnostics) => diagnostics,
+            None => config::check(&String::from_utf8_lossy(&contents)),
+        };
+        if diagnostics.is_empty() {
+            println!("{}: OK", path.display());
+        }
+        for diagnostic in &diagnostics {
+            eprintln!("{}:{}", path.display(), diagnostic);
+        }
+        valid &= diagnostics.is_empty();
+    }
+    valid
+}
+
+/// Check `contents` if they are a state file, loading the configuration at
+/// `path` into `config` the first time
+///
+/// An invalid configuration is reported and its constraints left out.
+#[cfg(feature = "persistence")]
+fn check_state(contents: &[u8], path: Option<&Path>, config: &mut Option<Config>) -> Option<Vec<config::Diagnostic>> {
+    if !portal_setting::state::is_state_file(contents) {
+        return None;
+    }
+    let config = config.get_or_insert_with(|| match path.map(Config::load).transpose() {
+        Ok(config) => config.unwrap_or_default(),
+        Err(e) => {
+            eprintln!("{:#}; checking state files against the built-in constraints only", e);
+            Config::default()
+        }
+    });
+    Some(portal_setting::state::check(contents, config))
+}
+
+#[cfg(not(feature = "persistence"))]
+fn check_state(_contents: &[u8], _path: Option<&Path>, _config: &mut Option<Config>) -> Option<Vec<config::Diagnostic>> {
+    None
+}
@@ -0,0 +1,92 @@
+use crate::remote::Writer;
+use anyhow::Result;
+use futures_util::StreamExt;
+use portal_setting::{task, ChangeSource};
+use portal_setting_proxy::SettingsProxy;
+use std::collections::HashSet;
+use zbus::fdo::DBusProxy;
+use zbus::zvariant::OwnedValue;
+use zbus::Connection;
+
+/// Copy the settings served by `name` into the store and keep following them
+///
+/// Every setting is copied before this returns and again whenever `name` gets
+/// a new owner; `SettingChanged` signals are followed in between. Settings the
+/// other implementation does not have are removed. While `name` has no owner
+/// the last values are kept.
+pub async fn start(connection: Connection, name: String, writer: Writer) -> Result<()> {
+    let upstream = SettingsProxy::builder(&connection)
+        .destination(name.clone())?
+        .build()
+        .await?;
+    // Subscribe before copying, so no change in between is missed
+    let mut changes = upstream.receive_setting_changed().await?;
+    let mut owners = DBusProxy::new(&connection)
+        .await?
+        .receive_name_owner_changed_with_args(&[(0, &name)])
+        .await?;
+    copy_all(&upstream, &name, &writer).await;
+
+    task::spawn("mirror", async move {
+        loop {
+            tokio::select! {
+                Some(signal) = changes.next() => {
+                    let Ok(args) = signal.args() else {
+                        continue;
+                    };
+                    if let Ok(value) = args.value().try_clone() {
+                        copy(&writer, args.namespace(), args.key(), value).await;
+                    }
+                }
+                Some(signal) = owners.next() => match signal.args().map(|args| args.new_owner().is_some()) {
+                    Ok(true) => copy_all(&upstream, &name, &writer).await,
+                    Ok(false) => println!("{} went away, keeping the mirrored settings", name),
+                    Err(_) => {}
+                },
+                else => break,
+            }
+        }
+        eprintln!("Stopped mirroring {}", name);
+    });
+    Ok(())
+}
+
+async fn copy_all(upstream: &SettingsProxy<'_>, name: &str, writer: &Writer) {
+    let all = match upstream.read_all(&[]).await {
+        Ok(all) => all,
+        Err(e) => {
+            eprintln!("Cannot read the settings of {}: {}", name, e);
+            return;
+        }
+    };
+
+    let mut mirrored = HashSet::new();
+    for (namespace, settings) in all {
+        for (key, value) in settings {
+            copy(writer, &namespace, &key, value).await;
+            mirrored.insert((namespace.clone(), key));
+        }
+    }
+    for (namespace, settings) in writer.store().read_all(Vec::new()).await {
+        for key in settings.into_keys() {
+            if !mirrored.contains(&(namespace.clone(), key.clone())) {
+                writer.store().delete_from(ChangeSource::Mirror, &namespace, &key).await;
+            }
+        }
+    }
+    println!("Mirrored {} settings from {}", mirrored.len(), name);
+}
+
+async fn copy(writer: &Writer, namespace: &str, key: &str, value: OwnedValue) {
+    // Not served here, whatever the upstream serves
+    if writer.store().is_denied(namespace, key) {
+        return;
+    }
+    let current = writer.store().read(namespace, key).await;
+    if current.is_some_and(|current| *current.0 == value) {
+        return;
+    }
+    if let Err(e) = writer.write(ChangeSource::Mirror, namespace, key, value).await {
+        eprintln!("Not mirroring {}/{}: {}", namespace, key, e);
+    }
+}
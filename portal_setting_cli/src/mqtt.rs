@@ -0,0 +1,107 @@
+use crate::remote::{setting_json, Update, Writer};
+use anyhow::Result;
+use portal_setting::config::MqttConfig;
+use portal_setting::{ChangeObserver, ChangeSource, DeniedNamespaces, SettingChange};
+use rumqttc::{AsyncClient, Event, MqttOptions, Packet, QoS};
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Requests queued for the broker before changes are dropped
+const QUEUE_CAPACITY: usize = 64;
+
+/// Delay before reconnecting to a broker that cannot be reached
+const RECONNECT_DELAY: Duration = Duration::from_secs(5);
+
+/// Publish every change to the broker and apply the updates it delivers
+///
+/// Changes are published retained to `<topic>/<namespace>/<key>` as
+/// `{"signature": ..., "value": ...}`, and removed keys clear their retained
+/// message. Updates are read from `<set_topic>/<namespace>/<key>` in the same
+/// format, where the signature may be left out; retained updates are applied
+/// again whenever the connection is re-established.
+pub async fn run(config: MqttConfig, writer: Writer) -> Result<()> {
+    let client_id = match &config.client_id {
+        Some(id) => id.clone(),
+        None => format!("portal-setting-{}", nix::unistd::gethostname()?.to_string_lossy()),
+    };
+    let mut options = MqttOptions::new(client_id, &config.host, config.port);
+    options.set_keep_alive(Duration::from_secs(30));
+    let (client, mut events) = AsyncClient::new(options, QUEUE_CAPACITY);
+    writer.store().add_observer(Arc::new(Publisher {
+        client: client.clone(),
+        topic: config.topic.clone(),
+        denied: writer.store().denied_namespaces().clone(),
+    }));
+
+    let set_topic = config.set_topic();
+    loop {
+        match events.poll().await {
+            Ok(Event::Incoming(Packet::ConnAck(_))) => {
+                println!("Connected to MQTT broker {}:{}", config.host, config.port);
+                // Sessions are not persisted, so subscribe on every connection
+                client.try_subscribe(format!("{}/+/+", set_topic), QoS::AtLeastOnce)?;
+            }
+            Ok(Event::Incoming(Packet::Publish(publish))) => {
+                apply(&writer, &set_topic, &publish.topic, &publish.payload).await;
+            }
+            Ok(_) => {}
+            Err(e) => {
+                eprintln!("MQTT broker {}:{}: {}", config.host, config.port, e);
+                tokio::time::sleep(RECONNECT_DELAY).await;
+            }
+        }
+    }
+}
+
+async fn apply(writer: &Writer, set_topic: &str, topic: &str, payload: &[u8]) {
+    // Clearing a retained message delivers an empty one
+    if payload.is_empty() {
+        return;
+    }
+    let Some((namespace, key)) = topic
+        .strip_prefix(set_topic)
+        .and_then(|rest| rest.strip_prefix('/'))
+        .and_then(|rest| rest.split_once('/'))
+    else {
+        return;
+    };
+
+    let result = match serde_json::from_slice::<Update>(payload) {
+        Ok(update) => writer
+            .apply(ChangeSource::Mqtt, namespace, key, update)
+            .await
+            .map_err(|e| e.to_string()),
+        Err(e) => Err(e.to_string()),
+    };
+    match result {
+        Ok(()) => println!("Applied {}/{} from MQTT", namespace, key),
+        Err(e) => eprintln!("Ignoring MQTT update of {}/{}: {}", namespace, key, e),
+    }
+}
+
+/// Publishes every change of the store, but those of denied namespaces
+struct Publisher {
+    client: AsyncClient,
+    topic: String,
+    denied: DeniedNamespaces,
+}
+
+impl ChangeObserver for Publisher {
+    fn on_change(&self, change: &SettingChange) {
+        if self.denied.contains(&change.namespace) {
+            return;
+        }
+        let payload = match &change.new {
+            Some(value) => match setting_json(&value.0) {
+                Ok(json) => json.to_string(),
+                Err(_) => return,
+            },
+            None => String::new(),
+        };
+        let topic = format!("{}/{}/{}", self.topic, change.namespace, change.key);
+        // Queued while disconnected, until the queue is full
+        if let Err(e) = self.client.try_publish(topic, QoS::AtLeastOnce, true, payload) {
+            eprintln!("Not publishing {}/{} over MQTT: {}", change.namespace, change.key, e);
+        }
+    }
+}
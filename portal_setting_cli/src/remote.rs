@@ -0,0 +1,188 @@
+// Only the HTTP bridge uses all of this; builds without it use parts, down to
+// mirroring writing through `Writer`
+#![cfg_attr(not(feature = "http"), allow(dead_code))]
+
+use anyhow::Result;
+use portal_setting::state::{toml_to_typed, value_to_toml};
+use portal_setting::{ChangeObserver, ChangeSource, DeniedNamespaces, SettingChange, SettingValue, SettingsStore};
+use std::collections::HashMap;
+use serde::Deserialize;
+use serde_json::{json, Value as JsonValue};
+use std::fmt;
+use std::sync::Arc;
+use tokio::sync::broadcast;
+use zbus::zvariant::{OwnedValue, Value};
+
+/// A setting as `{"signature": "u", "value": 1}`
+pub fn setting_json(value: &Value<'_>) -> Result<JsonValue> {
+    Ok(json!({
+        "signature": value.value_signature().as_str(),
+        "value": value_to_toml(value)?,
+    }))
+}
+
+/// A setting sent by a remote client; without a signature the setting keeps
+/// its current type
+#[derive(Deserialize)]
+pub struct Update {
+    pub signature: Option<String>,
+    /// Converted like TOML values in the configuration file
+    pub value: toml::Value,
+}
+
+/// Why an update was not applied
+pub enum UpdateError {
+    /// No signature was given and the setting does not exist
+    NotFound,
+    Invalid(portal_setting::Error),
+    Failed(anyhow::Error),
+    /// Remote clients cannot change settings
+    ReadOnly,
+    /// The namespace is denied
+    Denied,
+}
+
+impl fmt::Display for UpdateError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            UpdateError::NotFound => f.write_str("the setting does not exist"),
+            UpdateError::ReadOnly => f.write_str("the settings are read-only"),
+            UpdateError::Denied => f.write_str("the namespace is not served"),
+            UpdateError::Invalid(e) => write!(f, "{:#}", e),
+            UpdateError::Failed(e) => write!(f, "{:#}", e),
+        }
+    }
+}
+
+/// Changes buffered for each watching client before it misses some
+const FEED_CAPACITY: usize = 64;
+
+/// Applies updates from remote clients the way the management interface
+/// applies writes, and feeds changes to watching clients
+#[derive(Clone)]
+pub struct Writer {
+    store: SettingsStore,
+    dry_run: bool,
+    read_only: bool,
+    changes: broadcast::Sender<SettingChange>,
+}
+
+impl Writer {
+    pub fn new(store: SettingsStore, dry_run: bool) -> Self {
+        let (changes, _) = broadcast::channel(FEED_CAPACITY);
+        store.add_observer(Arc::new(ChangeFeed {
+            changes: changes.clone(),
+            denied: store.denied_namespaces().clone(),
+        }));
+        Self {
+            store,
+            dry_run,
+            read_only: false,
+            changes,
+        }
+    }
+
+    /// Reject every update from remote clients
+    pub fn with_read_only(mut self, read_only: bool) -> Self {
+        self.read_only = read_only;
+        self
+    }
+
+    pub fn store(&self) -> &SettingsStore {
+        &self.store
+    }
+
+    /// The value of a setting, unless it does not exist or its namespace is
+    /// denied
+    pub async fn read(&self, namespace: &str, key: &str) -> Option<SettingValue> {
+        match self.store.is_denied(namespace, key) {
+            true => None,
+            false => self.store.read(namespace, key).await,
+        }
+    }
+
+    /// The settings of `namespaces`, or all of them, but denied namespaces
+    pub async fn read_all(&self, namespaces: Vec<String>) -> HashMap<String, HashMap<String, SettingValue>> {
+        let mut all = self.store.read_all(namespaces).await;
+        all.retain(|namespace, _| !self.store.denied_namespaces().contains(namespace));
+        all
+    }
+
+    /// Receive the changes `SettingChanged` is emitted for from now on
+    ///
+    /// Receivers more than `FEED_CAPACITY` changes behind skip the ones they
+    /// missed.
+    pub fn subscribe(&self) -> broadcast::Receiver<SettingChange> {
+        self.changes.subscribe()
+    }
+
+    /// Validate and store `update`, emitting `SettingChanged`
+    pub async fn apply(
+        &self,
+        source: ChangeSource,
+        namespace: &str,
+        key: &str,
+        update: Update,
+    ) -> Result<(), UpdateError> {
+        if self.read_only {
+            return Err(UpdateError::ReadOnly);
+        }
+        if self.store.is_denied(namespace, key) {
+            return Err(UpdateError::Denied);
+        }
+        let signature = match update.signature {
+            Some(signature) => signature,
+            None => match self.store.read(namespace, key).await {
+                Some(current) => current.0.value_signature().to_string(),
+                None => return Err(UpdateError::NotFound),
+            },
+        };
+        let owned = toml_to_typed(&signature, &update.value)
+            .map_err(UpdateError::Invalid)
+            .and_then(|value| OwnedValue::try_from(value).map_err(|e| UpdateError::Failed(e.into())))?;
+
+        if self.dry_run {
+            return self
+                .store
+                .validate(namespace, key, &owned)
+                .await
+                .map_err(UpdateError::Invalid);
+        }
+        self.write(source, namespace, key, owned).await
+    }
+
+    /// Validate and store `value`, emitting `SettingChanged`, even when
+    /// remote clients cannot change settings
+    pub async fn write(
+        &self,
+        source: ChangeSource,
+        namespace: &str,
+        key: &str,
+        value: OwnedValue,
+    ) -> Result<(), UpdateError> {
+        if self.store.is_denied(namespace, key) {
+            return Err(UpdateError::Denied);
+        }
+        let value = self.store.coerce(namespace, key, value);
+        self.store
+            .write_from(source, namespace, key, value)
+            .await
+            .map(|_| ())
+            .map_err(UpdateError::Invalid)
+    }
+}
+
+struct ChangeFeed {
+    changes: broadcast::Sender<SettingChange>,
+    denied: DeniedNamespaces,
+}
+
+impl ChangeObserver for ChangeFeed {
+    fn on_change(&self, change: &SettingChange) {
+        // Removals have no signal, and denied namespaces none either
+        if change.new.is_some() && !self.denied.contains(&change.namespace) {
+            // Fails only while nobody is watching
+            let _ = self.changes.send(change.clone());
+        }
+    }
+}
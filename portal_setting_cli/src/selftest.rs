@@ -0,0 +1,271 @@
+use crate::service::{self, Exports};
+use anyhow::{bail, ensure, Context, Result};
+use futures_util::StreamExt;
+use portal_setting::{schema, SenderKeys, SettingsFrontend, SettingsStore, MANAGEMENT_INTERFACE};
+use std::collections::HashMap;
+use std::os::unix::net::UnixStream;
+use std::time::Duration;
+use zbus::connection::Builder;
+use zbus::message::Type as MessageType;
+use zbus::zvariant::{OwnedValue, Str, Value};
+use zbus::{Connection, Guid, MessageStream, Proxy};
+
+const IMPL_INTERFACE: &str = "org.freedesktop.impl.portal.Settings";
+const FRONTEND_INTERFACE: &str = "org.freedesktop.portal.Settings";
+
+/// Serve `store` on a private peer-to-peer connection and exercise every
+/// interface the way clients do, returning whether all checks passed
+pub async fn run(store: SettingsStore) -> Result<bool> {
+    let (server_socket, client_socket) = UnixStream::pair()?;
+    let (server, client) = tokio::try_join!(
+        Builder::unix_stream(server_socket)
+            .server(Guid::generate())?
+            .p2p()
+            .build(),
+        Builder::unix_stream(client_socket).p2p().build(),
+    )?;
+
+    // Without a bus there are no names to own, so the frontend is
+    // registered directly instead of through `Exports::frontend_name`
+    let exports = Exports {
+        store: store.clone(),
+        dry_run: false,
+        read_only: false,
+        without_management: false,
+        writers: Default::default(),
+        senders: SenderKeys::new(),
+        sessions: None,
+        emitter: service::emitter(&server),
+        frontend_name: None,
+    };
+    exports.export(&server).await?;
+    server
+        .object_server()
+        .at(service::PATH, SettingsFrontend::new(store))
+        .await?;
+
+    let tests = SelfTest {
+        portal: proxy(&client, IMPL_INTERFACE).await?,
+        frontend: proxy(&client, FRONTEND_INTERFACE).await?,
+        management: proxy(&client, MANAGEMENT_INTERFACE).await?,
+        signals: MessageStream::from(&client),
+    };
+    Ok(tests.run_all().await)
+}
+
+async fn proxy(connection: &Connection, interface: &'static str) -> Result<Proxy<'static>> {
+    Ok(Proxy::new(connection, IMPL_INTERFACE, service::PATH, interface).await?)
+}
+
+struct SelfTest {
+    portal: Proxy<'static>,
+    frontend: Proxy<'static>,
+    management: Proxy<'static>,
+    signals: MessageStream,
+}
+
+impl SelfTest {
+    async fn run_all(mut self) -> bool {
+        let mut passed = 0;
+        let mut failed = 0;
+
+        macro_rules! check {
+            ($name:expr, $test:expr) => {
+                match $test.await {
+                    Ok(()) => {
+                        passed += 1;
+                        println!("✓ {}", $name);
+                    }
+                    Err(e) => {
+                        failed += 1;
+                        println!("✗ {}: {:#}", $name, e);
+                    }
+                }
+            };
+        }
+
+        check!("ReadAll without filter", self.read_all());
+        check!("ReadAll with namespace filter", self.read_all_filtered());
+        check!("Read of every built-in setting", self.read_builtin());
+        check!("Read of an unknown setting fails", self.read_unknown());
+        check!("Frontend Read and ReadOne", self.frontend_read());
+        check!("ValidateWrite", self.validate_write());
+        check!("Write, read back and SettingChanged", self.write_matrix());
+        check!("Invalid writes are rejected", self.invalid_writes());
+        check!("Reset restores the default", self.reset());
+
+        println!("{}", "=".repeat(60));
+        println!("{} passed, {} failed", passed, failed);
+        failed == 0
+    }
+
+    async fn read(&self, namespace: &str, key: &str) -> Result<OwnedValue> {
+        Ok(self.portal.call("Read", &(namespace, key)).await?)
+    }
+
+    async fn read_all(&mut self) -> Result<()> {
+        let all: HashMap<String, HashMap<String, OwnedValue>> =
+            self.portal.call("ReadAll", &(Vec::<String>::new(),)).await?;
+        for setting in schema::BUILTIN {
+            ensure!(
+                all.get(setting.namespace)
+                    .is_some_and(|keys| keys.contains_key(setting.key)),
+                "{}/{} missing",
+                setting.namespace,
+                setting.key
+            );
+        }
+        Ok(())
+    }
+
+    async fn read_all_filtered(&mut self) -> Result<()> {
+        let filtered: HashMap<String, HashMap<String, OwnedValue>> = self
+            .portal
+            .call("ReadAll", &(vec!["org.freedesktop.appearance"],))
+            .await?;
+        let namespaces: Vec<&String> = filtered.keys().collect();
+        ensure!(
+            namespaces == ["org.freedesktop.appearance"],
+            "unexpected namespaces {:?}",
+            namespaces
+        );
+        Ok(())
+    }
+
+    async fn read_builtin(&mut self) -> Result<()> {
+        for setting in schema::BUILTIN {
+            let value = self.read(setting.namespace, setting.key).await?;
+            let signature = value.value_signature().to_string();
+            ensure!(
+                signature == setting.default.signature(),
+                "{}/{} has type {} instead of {}",
+                setting.namespace,
+                setting.key,
+                signature,
+                setting.default.signature()
+            );
+        }
+        Ok(())
+    }
+
+    async fn read_unknown(&mut self) -> Result<()> {
+        if self.read("org.example.missing", "key").await.is_ok() {
+            bail!("reading a missing setting succeeded");
+        }
+        Ok(())
+    }
+
+    async fn frontend_read(&mut self) -> Result<()> {
+        let one: OwnedValue = self
+            .frontend
+            .call("ReadOne", &("org.freedesktop.appearance", "color-scheme"))
+            .await?;
+        ensure!(one.value_signature() == "u", "ReadOne returned {}", one.value_signature());
+
+        // Read wraps the value in an additional variant, like xdg-desktop-portal
+        let wrapped: OwnedValue = self
+            .frontend
+            .call("Read", &("org.freedesktop.appearance", "color-scheme"))
+            .await?;
+        ensure!(wrapped.value_signature() == "v", "Read returned {}", wrapped.value_signature());
+        Ok(())
+    }
+
+    async fn validate_write(&mut self) -> Result<()> {
+        let args = ("org.freedesktop.appearance", "color-scheme", Value::U32(1));
+        let () = self.management.call("ValidateWrite", &args).await?;
+
+        let args = ("org.freedesktop.appearance", "color-scheme", Value::U32(9));
+        let result: zbus::Result<()> = self.management.call("ValidateWrite", &args).await;
+        ensure!(result.is_err(), "out of range value was accepted");
+        Ok(())
+    }
+
+    async fn write_matrix(&mut self) -> Result<()> {
+        let cases = [
+            ("org.freedesktop.appearance", "color-scheme", Value::U32(1)),
+            ("org.freedesktop.appearance", "accent-color", Value::Structure((0.2, 0.4, 0.6).into())),
+            ("org.freedesktop.appearance", "contrast", Value::U32(1)),
+            ("org.gnome.desktop.interface", "gtk-theme", Value::Str(Str::from_static("HighContrast"))),
+            ("org.gnome.desktop.interface", "clock-format", Value::Str(Str::from_static("12h"))),
+            ("org.gnome.desktop.privacy", "remember-recent-files", Value::Bool(false)),
+            ("org.gnome.desktop.privacy", "recent-files-max-age", Value::I32(7)),
+            ("org.example.selftest", "custom", Value::Str(Str::from_static("value"))),
+        ];
+
+        for (namespace, key, value) in cases {
+            let expected: OwnedValue = value.try_clone()?.try_into()?;
+            let () = self.management.call("Write", &(namespace, key, value)).await?;
+
+            let read = self.read(namespace, key).await?;
+            ensure!(read == expected, "{}/{} read back {:?}", namespace, key, read);
+
+            // Both the impl and the frontend interface announce the change
+            for interface in [IMPL_INTERFACE, FRONTEND_INTERFACE] {
+                let (ns, k, v) = self.next_setting_changed(interface).await?;
+                ensure!(
+                    ns == namespace && k == key && v == expected,
+                    "unexpected {} SettingChanged for {}/{}",
+                    interface,
+                    ns,
+                    k
+                );
+            }
+        }
+        Ok(())
+    }
+
+    async fn invalid_writes(&mut self) -> Result<()> {
+        let cases = [
+            ("org.freedesktop.appearance", "color-scheme", Value::U32(3)),
+            ("org.freedesktop.appearance", "color-scheme", Value::Str(Str::from_static("dark"))),
+            ("org.freedesktop.appearance", "contrast", Value::U32(2)),
+            ("org.gnome.desktop.interface", "gtk-theme", Value::U32(1)),
+            ("org.gnome.desktop.privacy", "remember-recent-files", Value::I32(1)),
+        ];
+        for (namespace, key, value) in cases {
+            let result: zbus::Result<()> = self.management.call("Write", &(namespace, key, value)).await;
+            ensure!(result.is_err(), "invalid write to {}/{} was accepted", namespace, key);
+        }
+        Ok(())
+    }
+
+    async fn reset(&mut self) -> Result<()> {
+        let changed: bool = self
+            .management
+            .call("Reset", &("org.freedesktop.appearance", "contrast"))
+            .await?;
+        ensure!(changed, "Reset reported no change");
+
+        let value: u32 = self
+            .read("org.freedesktop.appearance", "contrast")
+            .await?
+            .try_into()?;
+        ensure!(value == 0, "contrast is {} after reset", value);
+
+        let (_, key, _) = self.next_setting_changed(IMPL_INTERFACE).await?;
+        ensure!(key == "contrast", "unexpected SettingChanged for {}", key);
+        let _ = self.next_setting_changed(FRONTEND_INTERFACE).await?;
+        Ok(())
+    }
+
+    /// Wait for the next SettingChanged signal of `interface`
+    async fn next_setting_changed(&mut self, interface: &str) -> Result<(String, String, OwnedValue)> {
+        let wait = async {
+            while let Some(message) = self.signals.next().await {
+                let message = message?;
+                let header = message.header();
+                if header.message_type() == MessageType::Signal
+                    && header.interface().is_some_and(|i| i.as_str() == interface)
+                    && header.member().is_some_and(|m| m.as_str() == "SettingChanged")
+                {
+                    return Ok(message.body().deserialize()?);
+                }
+            }
+            bail!("connection closed")
+        };
+        tokio::time::timeout(Duration::from_secs(5), wait)
+            .await
+            .context("timed out waiting for SettingChanged")?
+    }
+}
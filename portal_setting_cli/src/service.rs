@@ -0,0 +1,155 @@
+use anyhow::Result;
+use portal_setting::emitter::{self, Destinations};
+use portal_setting::{SettingsFrontend, SettingsPortal, SettingsStore, SignalEmitter};
+#[cfg(feature = "management")]
+use portal_setting::{config::ManagementConfig, SenderKeys, SessionOverrides, SettingsManagement, MANAGEMENT_PATH};
+use std::fmt::Write;
+use zbus::fdo::{RequestNameFlags, RequestNameReply};
+use zbus::object_server::Interface;
+use zbus::zvariant::{ObjectPath, OwnedObjectPath};
+use zbus::Connection;
+
+/// Object path serving all interfaces
+pub const PATH: &str = "/org/freedesktop/portal/desktop";
+
+/// `PATH`, as an object path
+pub fn path() -> OwnedObjectPath {
+    ObjectPath::from_static_str_unchecked(PATH).into()
+}
+
+/// Emitter of the signals of the interfaces at `PATH` on `connection`
+pub fn emitter(connection: &Connection) -> SignalEmitter {
+    SignalEmitter::spawn(connection.clone(), path(), emitter::DEFAULT_CAPACITY)
+}
+
+/// `emitter`, sending the signals only to `destinations`
+pub fn emitter_to(connection: &Connection, destinations: Destinations) -> SignalEmitter {
+    SignalEmitter::spawn_to(connection.clone(), path(), emitter::DEFAULT_CAPACITY, destinations)
+}
+
+/// The D-Bus objects of the service, exported while it owns its bus name
+pub struct Exports {
+    pub store: SettingsStore,
+    #[cfg(feature = "management")]
+    pub dry_run: bool,
+    /// Serve the management interface, but refuse every change made through
+    /// it, for `--read-only`
+    #[cfg(feature = "management")]
+    pub read_only: bool,
+    /// Leave out the management interface, so settings cannot be changed
+    /// over D-Bus, e.g. while mirroring another service
+    pub without_management: bool,
+    /// Callers allowed to manage settings, answered at `MANAGEMENT_PATH` if
+    /// restricted
+    #[cfg(feature = "management")]
+    pub writers: ManagementConfig,
+    /// Keys created by each D-Bus sender, kept across exports
+    #[cfg(feature = "management")]
+    pub senders: SenderKeys,
+    /// The system bus and the values written for the length of a login
+    /// session, when logind can be asked about sessions
+    #[cfg(feature = "management")]
+    pub sessions: Option<(Connection, SessionOverrides)>,
+    /// Emits the signals of every change to `store` while exported
+    pub emitter: SignalEmitter,
+    /// Bus name for the frontend interface, when serving it
+    pub frontend_name: Option<String>,
+}
+
+impl Exports {
+    pub async fn export(&self, connection: &Connection) -> Result<()> {
+        self.store.set_signal_emitter(Some(self.emitter.clone()));
+        let server = connection.object_server();
+        server
+            .at(PATH, SettingsPortal::with_store(self.store.clone()))
+            .await?;
+        #[cfg(feature = "management")]
+        if !self.without_management {
+            let mut management = SettingsManagement::new(self.store.clone())
+                .with_dry_run(self.dry_run)
+                .with_read_only(self.read_only)
+                .with_writers(self.writers.clone())
+                .with_senders(self.senders.clone());
+            if let Some((system, overrides)) = &self.sessions {
+                management = management.with_login_sessions(system.clone(), overrides.clone());
+            }
+            server.at(self.management_path(), management).await?;
+        }
+
+        if let Some(name) = &self.frontend_name {
+            serve_frontend(connection, SettingsFrontend::new(self.store.clone()), name).await?;
+        }
+        Ok(())
+    }
+
+    /// Stop serving; no further signals are emitted once this returns
+    pub async fn unexport(&self, connection: &Connection) -> Result<()> {
+        self.store.set_signal_emitter(None);
+        let server = connection.object_server();
+        server.remove::<SettingsPortal, _>(PATH).await?;
+        #[cfg(feature = "management")]
+        if !self.without_management {
+            server.remove::<SettingsManagement, _>(self.management_path()).await?;
+        }
+
+        if let Some(name) = &self.frontend_name {
+            server.remove::<SettingsFrontend, _>(PATH).await?;
+            // Not owning the name (any more) is fine
+            let _ = connection.release_name(name.as_str()).await;
+        }
+        Ok(())
+    }
+
+    /// Where the management interface is served: apart from the portal
+    /// interfaces when only some callers may use it
+    #[cfg(feature = "management")]
+    fn management_path(&self) -> &'static str {
+        match self.writers.is_restricted() {
+            true => MANAGEMENT_PATH,
+            false => PATH,
+        }
+    }
+}
+
+/// Serve the frontend interface under `name`, unless another portal owns it
+///
+/// The name is requested with replacement allowed, so a real
+/// xdg-desktop-portal started with `--replace` can still take over.
+async fn serve_frontend(connection: &Connection, frontend: SettingsFrontend, name: &str) -> Result<()> {
+    let server = connection.object_server();
+    server.at(PATH, frontend).await?;
+
+    let reply = connection
+        .request_name_with_flags(
+            name,
+            RequestNameFlags::AllowReplacement | RequestNameFlags::DoNotQueue,
+        )
+        .await?;
+    match reply {
+        RequestNameReply::PrimaryOwner | RequestNameReply::AlreadyOwner => {
+            println!("Frontend registered at {}", name);
+        }
+        _ => {
+            println!("{} is owned by another service, not serving the frontend", name);
+            server.remove::<SettingsFrontend, _>(PATH).await?;
+        }
+    }
+    Ok(())
+}
+
+/// Introspection XML of the object at `PATH` with every interface the
+/// service can serve, generated without a connection
+pub fn introspect() -> String {
+    let store = SettingsStore::new();
+    let mut xml = String::from(
+        "<!DOCTYPE node PUBLIC \"-//freedesktop//DTD D-BUS Object Introspection 1.0//EN\"\n \
+         \"http://www.freedesktop.org/standards/dbus/1.0/introspect.dtd\">\n",
+    );
+    let _ = writeln!(xml, "<node name=\"{}\">", PATH);
+    SettingsPortal::with_store(store.clone()).introspect_to_writer(&mut xml, 2);
+    SettingsFrontend::new(store.clone()).introspect_to_writer(&mut xml, 2);
+    #[cfg(feature = "management")]
+    SettingsManagement::new(store).introspect_to_writer(&mut xml, 2);
+    xml.push_str("</node>\n");
+    xml
+}
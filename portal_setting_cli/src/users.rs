@@ -0,0 +1,90 @@
+use futures_util::FutureExt;
+use portal_setting::{ChangeObserver, Config, Policy, SettingsBackend, SettingsStore, UserStores, Validator};
+use std::sync::Arc;
+#[cfg(feature = "persistence")]
+use {
+    portal_setting::config::{self, StateFormat},
+    portal_setting::{ChangeSource, StateFile},
+    std::path::PathBuf,
+    std::sync::Mutex,
+    std::time::Duration,
+};
+
+/// Where the state file of each user goes, next to the shared one
+#[cfg(feature = "persistence")]
+pub struct StateOptions {
+    /// The shared state file, e.g. `state.toml` for `state.1000.toml`
+    pub path: PathBuf,
+    pub format: StateFormat,
+    /// Coalesce the changes of this long, or write each one right away
+    pub debounce: Option<Duration>,
+}
+
+/// The state files of the user stores made so far
+#[cfg(feature = "persistence")]
+#[derive(Clone, Default)]
+pub struct UserStates(Arc<Mutex<Vec<Arc<StateFile>>>>);
+
+#[cfg(feature = "persistence")]
+impl UserStates {
+    /// Write every user's state file, e.g. before exiting
+    pub fn save(&self) -> portal_setting::Result<()> {
+        for state in self.0.lock().unwrap().iter() {
+            state.save()?;
+        }
+        Ok(())
+    }
+}
+
+/// How the store of each user is made: from the configuration of the shared
+/// store, with a state file of its own
+pub struct Template {
+    pub config: Config,
+    pub backends: Vec<Arc<dyn SettingsBackend>>,
+    pub validator: Option<Arc<dyn Validator>>,
+    pub policy: Option<Policy>,
+    /// Notified of the changes of every user, like the audit log
+    pub observers: Vec<Arc<dyn ChangeObserver>>,
+    #[cfg(feature = "persistence")]
+    pub state: Option<StateOptions>,
+    #[cfg(feature = "persistence")]
+    pub states: UserStates,
+}
+
+impl Template {
+    pub fn stores(self) -> UserStores {
+        let template = Arc::new(self);
+        UserStores::new(move |uid| {
+            let template = template.clone();
+            async move { template.make(uid).await }.boxed()
+        })
+    }
+
+    async fn make(&self, uid: u32) -> portal_setting::Result<SettingsStore> {
+        let mut store = SettingsStore::from_sources(&self.config, &self.backends)?;
+        if let Some(validator) = &self.validator {
+            store = store.with_validator(validator.clone());
+        }
+        if let Some(policy) = &self.policy {
+            store = store.with_policy(policy.clone());
+        }
+        for observer in &self.observers {
+            store.add_observer(observer.clone());
+        }
+        #[cfg(feature = "persistence")]
+        if let Some(options) = &self.state {
+            let state = StateFile::new(config::user_state_path(&options.path, uid)).with_format(options.format);
+            let state = Arc::new(state);
+            if let Some(debounce) = options.debounce {
+                state.flush_after(debounce)?;
+            }
+            store.add_observer(state.clone());
+            for (key, e) in store.restore_many_from(ChangeSource::State, state.load().await?).await {
+                eprintln!("Ignoring persisted {}/{} of uid {}: {:#}", key.namespace, key.key, uid, e);
+            }
+            self.states.0.lock().unwrap().push(state);
+        }
+        println!("Serving the settings of uid {}", uid);
+        Ok(store)
+    }
+}
@@ -0,0 +1,174 @@
+use crate::service;
+use anyhow::Result;
+use futures_util::StreamExt;
+use portal_setting::audit::format_timestamp;
+use portal_setting::sensitive::REDACTED;
+use portal_setting::SensitiveKeys;
+use std::collections::HashMap;
+use std::time::SystemTime;
+use zbus::fdo::DBusProxy;
+use zbus::message::Type as MessageType;
+use zbus::names::BusName;
+use zbus::zvariant::{OwnedValue, Structure, Value};
+use zbus::{Connection, Message, MatchRule, MessageStream};
+
+/// Print every method call to the service and every signal it emits
+///
+/// Calls are observed on the incoming message stream; emitted signals are
+/// received back from the bus through a match rule on our own unique name.
+/// The values of `sensitive` settings are masked.
+pub async fn run(connection: Connection, sensitive: SensitiveKeys) -> Result<()> {
+    let dbus = DBusProxy::new(&connection).await?;
+    let own_signals = MatchRule::builder()
+        .msg_type(MessageType::Signal)
+        .sender(connection.unique_name().cloned().expect("bus connections have a unique name"))?
+        .path(service::PATH)?
+        .build();
+    let mut signals = MessageStream::for_match_rule(own_signals, &connection, None).await?;
+    let mut messages = MessageStream::from(&connection);
+    let mut senders = Senders::default();
+
+    loop {
+        let (message, from_match_rule) = tokio::select! {
+            Some(message) = signals.next() => (message?, true),
+            Some(message) = messages.next() => (message?, false),
+            else => return Ok(()),
+        };
+        let header = message.header();
+        let (Some(interface), Some(member)) = (header.interface(), header.member()) else {
+            continue;
+        };
+
+        match header.message_type() {
+            MessageType::MethodCall if header.path().is_some_and(|p| p.as_str() == service::PATH) => {
+                let sender = match header.sender() {
+                    Some(sender) => senders.describe(&dbus, sender.as_str()).await,
+                    None => "unknown".to_string(),
+                };
+                println!(
+                    "{} call   {} {}.{}{}",
+                    format_timestamp(SystemTime::now()),
+                    sender,
+                    interface,
+                    member,
+                    arguments(&message, &sensitive)
+                );
+            }
+            // The general stream sees the same signals; only print them once
+            MessageType::Signal if from_match_rule => {
+                println!(
+                    "{} signal {}.{}{}",
+                    format_timestamp(SystemTime::now()),
+                    interface,
+                    member,
+                    arguments(&message, &sensitive)
+                );
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Message arguments in GVariant text format
+fn arguments(message: &Message, sensitive: &SensitiveKeys) -> String {
+    if let Some(redacted) = redacted_arguments(message, sensitive) {
+        return redacted;
+    }
+    let body = message.body();
+    if body.signature().is_none_or(|s| s.is_empty()) {
+        return "()".to_string();
+    }
+    match body.deserialize::<Structure>() {
+        Ok(args) => args.to_string(),
+        Err(_) => format!("(<{} arguments>)", body.signature().map(|s| s.to_string()).unwrap_or_default()),
+    }
+}
+
+/// The arguments of a message carrying the value of a sensitive setting,
+/// with such values masked, or `None` for any other message
+///
+/// Values come after their namespace and key (`Write`, `SettingChanged`),
+/// in `(namespace, key, value)` structures (`SettingsChangedBatch`) or in
+/// dictionaries by namespace and key (`WriteMany`).
+fn redacted_arguments(message: &Message, sensitive: &SensitiveKeys) -> Option<String> {
+    if sensitive.is_empty() {
+        return None;
+    }
+    let body = message.body();
+    let quote = |text: &str| Value::from(text).to_string();
+    let show = |namespace: &str, key: &str, value: &OwnedValue| match sensitive.contains(namespace, key) {
+        true => REDACTED.to_string(),
+        false => format!("<{}>", **value),
+    };
+    match body.signature()?.to_string().as_str() {
+        "a(ssv)" => {
+            let changes: Vec<(String, String, OwnedValue)> = body.deserialize().ok()?;
+            if !changes.iter().any(|(namespace, key, _)| sensitive.contains(namespace, key)) {
+                return None;
+            }
+            let changes: Vec<String> = changes
+                .iter()
+                .map(|(namespace, key, value)| {
+                    format!("({}, {}, {})", quote(namespace), quote(key), show(namespace, key, value))
+                })
+                .collect();
+            Some(format!("([{}],)", changes.join(", ")))
+        }
+        "a{sa{sv}}" => {
+            let settings: HashMap<String, HashMap<String, OwnedValue>> = body.deserialize().ok()?;
+            if !settings.iter().any(|(namespace, keys)| keys.keys().any(|key| sensitive.contains(namespace, key))) {
+                return None;
+            }
+            let namespaces: Vec<String> = settings
+                .iter()
+                .map(|(namespace, keys)| {
+                    let keys: Vec<String> = keys
+                        .iter()
+                        .map(|(key, value)| format!("{}: {}", quote(key), show(namespace, key, value)))
+                        .collect();
+                    format!("{}: {{{}}}", quote(namespace), keys.join(", "))
+                })
+                .collect();
+            Some(format!("({{{}}},)", namespaces.join(", ")))
+        }
+        signature if signature.starts_with("ss") && signature.len() > 2 => {
+            let args: Structure = body.deserialize().ok()?;
+            let (Ok(namespace), Ok(key)) = (
+                <&str>::try_from(&args.fields()[0]),
+                <&str>::try_from(&args.fields()[1]),
+            ) else {
+                return None;
+            };
+            if !sensitive.contains(namespace, key) {
+                return None;
+            }
+            let masked = vec![REDACTED; args.fields().len() - 2];
+            Some(format!("({}, {}, {})", quote(namespace), quote(key), masked.join(", ")))
+        }
+        _ => None,
+    }
+}
+
+/// Unique bus names annotated with the process behind them
+#[derive(Default)]
+struct Senders(HashMap<String, String>);
+
+impl Senders {
+    async fn describe(&mut self, dbus: &DBusProxy<'_>, sender: &str) -> String {
+        if let Some(description) = self.0.get(sender) {
+            return description.clone();
+        }
+
+        let process = match BusName::try_from(sender) {
+            Ok(name) => dbus.get_connection_unix_process_id(name).await.ok(),
+            Err(_) => None,
+        }
+        .map(|pid| {
+            let comm = std::fs::read_to_string(format!("/proc/{}/comm", pid)).unwrap_or_default();
+            format!("{} ({}[{}])", sender, comm.trim(), pid)
+        });
+        let description = process.unwrap_or_else(|| sender.to_string());
+        self.0.insert(sender.to_string(), description.clone());
+        description
+    }
+}
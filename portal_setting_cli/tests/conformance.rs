@@ -0,0 +1,135 @@
+use ashpd::desktop::settings::{ColorScheme, Settings};
+use futures_util::StreamExt;
+use portal_setting_proxy::ManagementProxy;
+use std::io::{BufRead, BufReader};
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, Stdio};
+use std::time::{Duration, Instant};
+
+/// Name the service is started under, found through `test.portal`
+const BACKEND_NAME: &str = "org.freedesktop.impl.portal.desktop.test";
+const FRONTEND_NAME: &str = "org.freedesktop.portal.Desktop";
+/// How long the service and the portal may take to appear, and signals to
+/// arrive
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Kills the processes of a test when it ends, however it ends
+struct Processes(Vec<Child>);
+
+impl Drop for Processes {
+    fn drop(&mut self) {
+        for child in &mut self.0 {
+            let _ = child.kill();
+            let _ = child.wait();
+        }
+    }
+}
+
+/// xdg-desktop-portal, from `XDG_DESKTOP_PORTAL` or where distributions
+/// install it
+fn portal_path() -> PathBuf {
+    match std::env::var_os("XDG_DESKTOP_PORTAL") {
+        Some(path) => PathBuf::from(path),
+        None => PathBuf::from("/usr/libexec/xdg-desktop-portal"),
+    }
+}
+
+/// Start a message bus listening in `dir`, returning its address
+fn start_bus(dir: &Path, processes: &mut Processes) -> String {
+    let mut bus = Command::new("dbus-daemon")
+        .args(["--session", "--nofork", "--print-address"])
+        .arg(format!("--address=unix:path={}", dir.join("bus").display()))
+        .stdout(Stdio::piped())
+        .spawn()
+        .expect("cannot start dbus-daemon");
+    let mut address = String::new();
+    BufReader::new(bus.stdout.take().unwrap()).read_line(&mut address).unwrap();
+    processes.0.push(bus);
+    address.trim().to_string()
+}
+
+/// Wait until `name` is owned on the bus of `connection`
+async fn wait_for_name(connection: &zbus::Connection, name: &str) {
+    let bus = zbus::fdo::DBusProxy::new(connection).await.unwrap();
+    let started = Instant::now();
+    while !bus.name_has_owner(name.try_into().unwrap()).await.unwrap() {
+        assert!(started.elapsed() < TIMEOUT, "{} did not appear on the bus", name);
+        tokio::time::sleep(Duration::from_millis(50)).await;
+    }
+}
+
+// Needs dbus-daemon and xdg-desktop-portal; run with `cargo test -p
+// portal_setting_cli --test conformance -- --ignored`
+#[tokio::test]
+#[ignore]
+async fn test_settings_through_xdg_desktop_portal() {
+    let dir = Path::new(env!("CARGO_TARGET_TMPDIR")).join("conformance");
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(dir.join("portals")).unwrap();
+    std::fs::create_dir_all(dir.join("config/xdg-desktop-portal")).unwrap();
+    std::fs::write(
+        dir.join("portals/test.portal"),
+        // UseIn is how portals before 1.17 choose, test-portals.conf how later ones do
+        format!("[portal]\nDBusName={}\nInterfaces=org.freedesktop.impl.portal.Settings\nUseIn=test\n", BACKEND_NAME),
+    )
+    .unwrap();
+    std::fs::write(
+        dir.join("config/xdg-desktop-portal/test-portals.conf"),
+        "[preferred]\ndefault=test\n",
+    )
+    .unwrap();
+    std::fs::write(
+        dir.join("service.toml"),
+        "[settings.\"org.freedesktop.appearance\"]\n\
+         color-scheme = 1\n\
+         accent-color = [0.25, 0.5, 0.75]\n\
+         \n\
+         [settings.\"com.example.Conformance\"]\n\
+         greeting = \"hello\"\n",
+    )
+    .unwrap();
+
+    let mut processes = Processes(Vec::new());
+    let address = start_bus(&dir, &mut processes);
+    // The portal client connects to the session bus
+    std::env::set_var("DBUS_SESSION_BUS_ADDRESS", &address);
+    let connection = zbus::Connection::session().await.unwrap();
+
+    let service = Command::new(env!("CARGO_BIN_EXE_portal-setting-service"))
+        .args(["--address", &address, "--bus-name", BACKEND_NAME, "--config"])
+        .arg(dir.join("service.toml"))
+        .stdout(Stdio::null())
+        .spawn()
+        .unwrap();
+    processes.0.push(service);
+    wait_for_name(&connection, BACKEND_NAME).await;
+
+    let portal = Command::new(portal_path())
+        .env("XDG_DESKTOP_PORTAL_DIR", dir.join("portals"))
+        .env("XDG_CONFIG_HOME", dir.join("config"))
+        .env("XDG_CURRENT_DESKTOP", "test")
+        .spawn()
+        .unwrap_or_else(|e| panic!("cannot start {}: {}", portal_path().display(), e));
+    processes.0.push(portal);
+    wait_for_name(&connection, FRONTEND_NAME).await;
+
+    // Our values, as applications read them
+    let settings = Settings::new().await.unwrap();
+    assert_eq!(settings.color_scheme().await.unwrap(), ColorScheme::PreferDark);
+    let accent = settings.accent_color().await.unwrap();
+    assert_eq!((accent.red(), accent.green(), accent.blue()), (0.25, 0.5, 0.75));
+    let greeting: String = settings.read("com.example.Conformance", "greeting").await.unwrap();
+    assert_eq!(greeting, "hello");
+    let all = settings.read_all(&["org.freedesktop.appearance"]).await.unwrap();
+    assert_eq!(all.len(), 1);
+    assert!(all["org.freedesktop.appearance"].contains_key("contrast"));
+    assert!(settings.read::<u32>("com.example.Conformance", "missing").await.is_err());
+
+    // Our signals, as applications receive them
+    let mut changes = settings.receive_color_scheme_changed().await.unwrap();
+    let management = ManagementProxy::builder(&connection).destination(BACKEND_NAME).unwrap().build().await.unwrap();
+    management.set_color_scheme(portal_setting_proxy::ColorScheme::PreferLight).await.unwrap();
+    let changed = tokio::time::timeout(TIMEOUT, changes.next()).await.expect("no SettingChanged signal");
+    assert_eq!(changed, Some(ColorScheme::PreferLight));
+    assert_eq!(settings.color_scheme().await.unwrap(), ColorScheme::PreferLight);
+}
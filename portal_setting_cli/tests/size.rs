@@ -0,0 +1,40 @@
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+/// The minimal build must stay at least this much smaller than the default one
+const MIN_SAVING_PERCENT: u64 = 15;
+
+/// Build the service in release mode with `features`, returning the size of
+/// the binary
+fn release_size(name: &str, features: &[&str]) -> u64 {
+    let target_dir = Path::new(env!("CARGO_TARGET_TMPDIR")).join("size").join(name);
+    let status = Command::new(env!("CARGO"))
+        .current_dir(env!("CARGO_MANIFEST_DIR"))
+        .args(["build", "--release", "--quiet", "--package", "portal_setting_cli"])
+        .args(features)
+        .arg("--target-dir")
+        .arg(&target_dir)
+        .status()
+        .unwrap();
+    assert!(status.success(), "{} build failed", name);
+
+    let binary: PathBuf = target_dir.join("release").join("portal-setting-service");
+    std::fs::metadata(binary).unwrap().len()
+}
+
+// Two release builds take minutes; run with `cargo test -p portal_setting_cli
+// --test size -- --ignored`
+#[test]
+#[ignore]
+fn test_minimal_build_is_smaller() {
+    let default = release_size("default", &[]);
+    let minimal = release_size("minimal", &["--no-default-features"]);
+    println!("default: {} bytes, minimal: {} bytes", default, minimal);
+    assert!(
+        minimal * 100 <= default * (100 - MIN_SAVING_PERCENT),
+        "minimal build ({} bytes) is less than {}% smaller than the default build ({} bytes)",
+        minimal,
+        MIN_SAVING_PERCENT,
+        default
+    );
+}
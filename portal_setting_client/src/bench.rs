@@ -0,0 +1,157 @@
+use crate::Client;
+use anyhow::{bail, Result};
+use futures_util::{stream, StreamExt};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use zbus::zvariant::OwnedValue;
+
+/// Namespace written by the benchmark, removed again afterwards
+const NAMESPACE: &str = "io.github.meta_flutter.PortalSettings.Bench";
+const KEY: &str = "counter";
+
+/// How long to wait for signals of the last writes
+const SIGNAL_GRACE: Duration = Duration::from_secs(2);
+
+#[derive(Clone, Copy)]
+enum Operation {
+    Read,
+    ReadAll,
+    Write(u32),
+}
+
+/// Run `reads` reads, alternating `Read` and `ReadAll`, and `writes` writes
+/// with up to `concurrency` calls in flight, then print latency percentiles
+/// per method and how long `SettingChanged` took to arrive after each write
+pub async fn run(client: &Client, reads: usize, writes: usize, concurrency: usize) -> Result<()> {
+    if concurrency == 0 {
+        bail!("--concurrency must be at least 1");
+    }
+    let writes = u32::try_from(writes)?;
+
+    // Spread the writes evenly between the reads so both contend for the store
+    let mut operations: Vec<(f64, Operation)> = (0..reads)
+        .map(|i| match i % 2 {
+            0 => (i as f64 / reads as f64, Operation::Read),
+            _ => (i as f64 / reads as f64, Operation::ReadAll),
+        })
+        .chain((0..writes).map(|i| (i as f64 / writes as f64, Operation::Write(i))))
+        .collect();
+    operations.sort_by(|a, b| a.0.total_cmp(&b.0));
+
+    let mut changes = client.portal.receive_setting_changed().await?;
+    let received = Arc::new(Mutex::new(HashMap::new()));
+    let mut collector = tokio::spawn({
+        let received = received.clone();
+        async move {
+            while let Some(signal) = changes.next().await {
+                let now = Instant::now();
+                let Ok(args) = signal.args() else {
+                    continue;
+                };
+                if let (NAMESPACE, Ok(index)) = (args.namespace().as_str(), u32::try_from(args.value())) {
+                    let mut received = received.lock().unwrap();
+                    received.insert(index, now);
+                    if received.len() == writes as usize {
+                        break;
+                    }
+                }
+            }
+        }
+    });
+
+    let started = Instant::now();
+    let results: Vec<(Operation, Instant, Result<Duration>)> = stream::iter(operations)
+        .map(|(_, operation)| async move {
+            let sent = Instant::now();
+            let result = match operation {
+                Operation::Read => client
+                    .read("org.freedesktop.appearance", "color-scheme")
+                    .await
+                    .map(drop),
+                Operation::ReadAll => client.read_all(Vec::new()).await.map(drop),
+                Operation::Write(i) => client.write(NAMESPACE, KEY, OwnedValue::from(i)).await,
+            };
+            (operation, sent, result.map(|()| sent.elapsed()))
+        })
+        .buffer_unordered(concurrency)
+        .collect()
+        .await;
+    let elapsed = started.elapsed();
+
+    if tokio::time::timeout(SIGNAL_GRACE, &mut collector)
+        .await
+        .is_err()
+    {
+        collector.abort();
+    }
+    let received = std::mem::take(&mut *received.lock().unwrap());
+    let _ = client.delete(NAMESPACE, KEY).await;
+
+    let mut latencies: HashMap<&str, Vec<Duration>> = HashMap::new();
+    let mut lags = Vec::new();
+    let mut failures = 0;
+    for (operation, sent, result) in results {
+        let Ok(latency) = result else {
+            failures += 1;
+            continue;
+        };
+        let method = match operation {
+            Operation::Read => "Read",
+            Operation::ReadAll => "ReadAll",
+            Operation::Write(i) => {
+                if let Some(at) = received.get(&i) {
+                    lags.push(at.saturating_duration_since(sent));
+                }
+                "Write"
+            }
+        };
+        latencies.entry(method).or_default().push(latency);
+    }
+
+    let calls = reads + writes as usize;
+    println!(
+        "{} calls in {:.2?} ({:.0} calls/s), concurrency {}",
+        calls,
+        elapsed,
+        calls as f64 / elapsed.as_secs_f64(),
+        concurrency
+    );
+    println!(
+        "{:<12} {:>7} {:>10} {:>10} {:>10} {:>10}",
+        "", "count", "p50", "p90", "p99", "max"
+    );
+    for method in ["Read", "ReadAll", "Write"] {
+        if let Some(samples) = latencies.get_mut(method) {
+            print_row(method, samples);
+        }
+    }
+    if !lags.is_empty() {
+        print_row("Signal lag", &mut lags);
+    }
+    if lags.len() < writes as usize {
+        println!(
+            "{} of {} change signals were not received",
+            writes as usize - lags.len(),
+            writes
+        );
+    }
+    if failures > 0 {
+        bail!("{} of {} calls failed", failures, calls);
+    }
+    Ok(())
+}
+
+fn print_row(label: &str, samples: &mut [Duration]) {
+    samples.sort();
+    let percentile = |p: usize| samples[(samples.len() * p / 100).min(samples.len() - 1)];
+    println!(
+        "{:<12} {:>7} {:>10.2?} {:>10.2?} {:>10.2?} {:>10.2?}",
+        label,
+        samples.len(),
+        percentile(50),
+        percentile(90),
+        percentile(99),
+        samples[samples.len() - 1]
+    );
+}
@@ -0,0 +1,73 @@
+use clap_complete::engine::CompletionCandidate;
+use portal_setting_proxy::backend::ImplSettingsProxyBlocking;
+use portal_setting_proxy::SERVICE_BUS_NAME;
+use std::collections::{BTreeSet, HashMap};
+use std::ffi::OsStr;
+use zbus::blocking::Connection;
+use zbus::zvariant::OwnedValue;
+
+// Completers run while the shell asks for candidates, before the command
+// line is parsed, so they take what they need from the raw words the shell
+// passes after `--`.
+
+/// The words being completed
+fn words() -> Vec<String> {
+    std::env::args().skip_while(|arg| arg != "--").skip(1).collect()
+}
+
+/// The value of the option `name` on the command line
+fn option<'a>(words: &'a [String], name: &str) -> Option<&'a str> {
+    let mut words = words.iter();
+    while let Some(word) = words.next() {
+        if let Some(value) = word.strip_prefix(name).and_then(|rest| rest.strip_prefix('=')) {
+            return Some(value);
+        }
+        if word == name {
+            return words.next().map(String::as_str);
+        }
+    }
+    None
+}
+
+/// All settings of the service named on the command line, or nothing if it
+/// cannot be reached
+fn settings(words: &[String]) -> HashMap<String, HashMap<String, OwnedValue>> {
+    let read_all = || -> zbus::Result<_> {
+        let connection = match option(words, "--address") {
+            Some(address) => zbus::blocking::connection::Builder::address(address)?.build()?,
+            None if words.iter().any(|word| word == "--system") => Connection::system()?,
+            None => Connection::session()?,
+        };
+        ImplSettingsProxyBlocking::builder(&connection)
+            .destination(option(words, "--bus-name").unwrap_or(SERVICE_BUS_NAME))?
+            .build()?
+            .read_all(&[])
+    };
+    read_all().unwrap_or_default()
+}
+
+fn candidates<'a>(names: impl Iterator<Item = &'a String>, current: &OsStr) -> Vec<CompletionCandidate> {
+    let current = current.to_string_lossy();
+    names
+        .filter(|name| name.starts_with(&*current))
+        .collect::<BTreeSet<_>>()
+        .into_iter()
+        .map(CompletionCandidate::new)
+        .collect()
+}
+
+/// Namespaces served by the running service
+pub fn namespaces(current: &OsStr) -> Vec<CompletionCandidate> {
+    candidates(settings(&words()).keys(), current)
+}
+
+/// Keys of the namespaces named on the command line, or of all namespaces
+pub fn keys(current: &OsStr) -> Vec<CompletionCandidate> {
+    let words = words();
+    let settings = settings(&words);
+    let named: Vec<&HashMap<String, OwnedValue>> = words.iter().filter_map(|word| settings.get(word)).collect();
+    match named.is_empty() {
+        true => candidates(settings.values().flat_map(HashMap::keys), current),
+        false => candidates(named.into_iter().flat_map(HashMap::keys), current),
+    }
+}
@@ -0,0 +1,85 @@
+use crate::output::Output;
+use crate::{export, Client};
+use anyhow::{bail, Context, Result};
+use portal_setting::{Config, SettingsStore};
+use serde_json::json;
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+use zbus::zvariant::OwnedValue;
+
+/// Settings by namespace and key
+type Settings = BTreeMap<String, BTreeMap<String, OwnedValue>>;
+
+/// One side of a comparison
+pub enum Source {
+    Service(Client),
+    /// A configuration file, as the service would serve it
+    File(PathBuf),
+}
+
+impl Source {
+    async fn settings(&self) -> Result<Settings> {
+        match self {
+            Source::Service(client) => client.read_all(Vec::new()).await,
+            Source::File(path) => {
+                let text = toml::to_string(&export::read(path)?)?;
+                let store = Config::parse(&text)
+                    .and_then(|config| SettingsStore::from_config(&config))
+                    .with_context(|| format!("invalid config {}", path.display()))?;
+                let mut settings = Settings::new();
+                for (namespace, keys) in store.read_all(Vec::new()).await {
+                    let keys = keys.into_iter().map(|(key, value)| Ok((key, value.to_owned_value()?)));
+                    settings.insert(namespace, keys.collect::<Result<_>>()?);
+                }
+                Ok(settings)
+            }
+        }
+    }
+}
+
+/// Print every setting that is missing on one side or differs, failing if
+/// there is any
+pub async fn run(a: Source, b: Source, output: Output) -> Result<()> {
+    let (a, b) = (a.settings().await?, b.settings().await?);
+    let mut keys: Vec<(&String, &String)> = a
+        .iter()
+        .chain(&b)
+        .flat_map(|(namespace, keys)| keys.keys().map(move |key| (namespace, key)))
+        .collect();
+    keys.sort();
+    keys.dedup();
+
+    let mut differences = 0;
+    for (namespace, key) in keys {
+        let value_a = a.get(namespace).and_then(|keys| keys.get(key));
+        let value_b = b.get(namespace).and_then(|keys| keys.get(key));
+        if value_a == value_b {
+            continue;
+        }
+        differences += 1;
+        match output {
+            Output::Text => match (value_a, value_b) {
+                (Some(value), None) => println!("- {} {} {}", namespace, key, **value),
+                (None, Some(value)) => println!("+ {} {} {}", namespace, key, **value),
+                (Some(old), Some(new)) => println!("~ {} {} {} -> {}", namespace, key, **old, **new),
+                (None, None) => unreachable!(),
+            },
+            Output::Json => {
+                let side = |value: Option<&OwnedValue>| {
+                    value.map(
+                        |v| json!({ "signature": v.value_signature().to_string(), "value": crate::json::to_json(v) }),
+                    )
+                };
+                println!(
+                    "{}",
+                    json!({ "namespace": namespace, "key": key, "a": side(value_a), "b": side(value_b) })
+                );
+            }
+            Output::Quiet => {}
+        }
+    }
+    match differences {
+        0 => Ok(()),
+        n => bail!("{} settings differ", n),
+    }
+}
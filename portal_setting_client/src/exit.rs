@@ -0,0 +1,117 @@
+use portal_setting_proxy::ErrorKind;
+use std::fmt;
+use zbus::fdo;
+
+/// Exit status of failures not covered below
+pub const FAILURE: u8 = 1;
+/// The requested setting does not exist
+pub const NOT_FOUND: u8 = 2;
+/// A value was rejected, by the client or by the service
+pub const VALIDATION: u8 = 3;
+/// The bus or the service cannot be reached
+pub const CONNECTION: u8 = 4;
+/// The setting is read-only
+pub const READ_ONLY: u8 = 5;
+
+/// Context marking a value the client could not convert
+#[derive(Debug)]
+pub struct InvalidValue {
+    pub namespace: String,
+    pub key: String,
+}
+
+impl fmt::Display for InvalidValue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "invalid value for {}/{}", self.namespace, self.key)
+    }
+}
+
+/// The service did not appear on the bus or answer in time
+#[derive(Debug)]
+pub struct ServiceMissing(pub String);
+
+impl fmt::Display for ServiceMissing {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl std::error::Error for ServiceMissing {}
+
+/// An imported file contains invalid settings
+#[derive(Debug)]
+pub struct InvalidImport(pub String);
+
+impl fmt::Display for InvalidImport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl std::error::Error for InvalidImport {}
+
+/// Exit status for `error`
+pub fn status(error: &anyhow::Error) -> u8 {
+    if error.downcast_ref::<InvalidValue>().is_some() || error.is::<InvalidImport>() {
+        return VALIDATION;
+    }
+    for cause in error.chain() {
+        if cause.is::<ServiceMissing>() {
+            return CONNECTION;
+        }
+        if let Some(e) = cause.downcast_ref::<zbus::Error>() {
+            return zbus_status(e);
+        }
+        if let Some(e) = cause.downcast_ref::<fdo::Error>() {
+            return fdo_status(e);
+        }
+    }
+    FAILURE
+}
+
+fn zbus_status(error: &zbus::Error) -> u8 {
+    kind_status(ErrorKind::of(error))
+}
+
+fn fdo_status(error: &fdo::Error) -> u8 {
+    kind_status(ErrorKind::of_fdo(error))
+}
+
+fn kind_status(kind: ErrorKind) -> u8 {
+    match kind {
+        ErrorKind::NotFound => NOT_FOUND,
+        ErrorKind::InvalidValue => VALIDATION,
+        ErrorKind::Unavailable => CONNECTION,
+        ErrorKind::ReadOnly => READ_ONLY,
+        ErrorKind::Other => FAILURE,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::{anyhow, Context};
+
+    #[test]
+    fn test_status() {
+        let invalid = Err::<(), _>(anyhow!("not a u32")).context(InvalidValue {
+            namespace: "com.example".to_string(),
+            key: "count".to_string(),
+        });
+        assert_eq!(status(&invalid.unwrap_err()), VALIDATION);
+        assert_eq!(status(&InvalidImport("no settings".to_string()).into()), VALIDATION);
+
+        let missing = anyhow::Error::new(ServiceMissing("no reply".to_string())).context("cannot read");
+        assert_eq!(status(&missing), CONNECTION);
+
+        let status_of = |e: fdo::Error| status(&anyhow::Error::new(e).context("cannot write"));
+        assert_eq!(status_of(fdo::Error::Failed("com.example/count not found".to_string())), NOT_FOUND);
+        assert_eq!(status_of(fdo::Error::InvalidArgs("out of range".to_string())), VALIDATION);
+        assert_eq!(status_of(fdo::Error::ServiceUnknown("gone".to_string())), CONNECTION);
+        assert_eq!(status_of(fdo::Error::AccessDenied("read-only".to_string())), READ_ONLY);
+        assert_eq!(status_of(fdo::Error::Failed("disk full".to_string())), FAILURE);
+        assert_eq!(status(&zbus::Error::from(fdo::Error::NoReply("timeout".to_string())).into()), CONNECTION);
+
+        assert_eq!(status(&anyhow!("something else")), FAILURE);
+    }
+}
@@ -0,0 +1,72 @@
+use anyhow::{Context, Result};
+use clap::ValueEnum;
+use portal_setting::state::value_to_toml;
+use portal_setting::SensitiveKeys;
+use std::collections::BTreeMap;
+use std::path::Path;
+use zbus::zvariant::OwnedValue;
+
+/// File formats of `export`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Format {
+    /// A configuration file the service loads with `--config`
+    Toml,
+    /// The same document as JSON
+    Json,
+}
+
+impl Format {
+    /// The format matching the extension of `path`, if any
+    pub fn of_path(path: &Path) -> Option<Self> {
+        match path.extension()?.to_str()? {
+            "toml" => Some(Format::Toml),
+            "json" => Some(Format::Json),
+            _ => None,
+        }
+    }
+}
+
+/// Read a file in the format matching its extension, TOML by default
+pub fn read(path: &Path) -> Result<toml::Table> {
+    let text = std::fs::read_to_string(path).with_context(|| format!("cannot read {}", path.display()))?;
+    let document = match Format::of_path(path) {
+        Some(Format::Json) => serde_json::from_str(&text).map_err(anyhow::Error::from),
+        _ => toml::from_str(&text).map_err(anyhow::Error::from),
+    };
+    document.with_context(|| format!("invalid file {}", path.display()))
+}
+
+/// Leave out the settings `sensitive` lists, rather than masking their
+/// values, so importing the export does not overwrite them; returns how many
+/// there were
+pub fn remove_sensitive(settings: &mut BTreeMap<String, BTreeMap<String, OwnedValue>>, sensitive: &SensitiveKeys) -> usize {
+    let mut removed = 0;
+    for (namespace, keys) in settings.iter_mut() {
+        let before = keys.len();
+        keys.retain(|key, _| !sensitive.contains(namespace, key));
+        removed += before - keys.len();
+    }
+    settings.retain(|_, keys| !keys.is_empty());
+    removed
+}
+
+/// `settings` as a configuration file with one `[settings."<namespace>"]`
+/// table per namespace
+pub fn render(settings: &BTreeMap<String, BTreeMap<String, OwnedValue>>, format: Format) -> Result<String> {
+    let mut namespaces = toml::Table::new();
+    for (namespace, keys) in settings {
+        let mut table = toml::Table::new();
+        for (key, value) in keys {
+            let value = value_to_toml(value).with_context(|| format!("cannot export {}/{}", namespace, key))?;
+            table.insert(key.clone(), value);
+        }
+        namespaces.insert(namespace.clone(), table.into());
+    }
+    let mut document = toml::Table::new();
+    document.insert("settings".to_string(), namespaces.into());
+
+    Ok(match format {
+        Format::Toml => toml::to_string(&document)?,
+        Format::Json => serde_json::to_string_pretty(&document)? + "\n",
+    })
+}
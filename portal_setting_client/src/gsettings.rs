@@ -0,0 +1,292 @@
+use crate::value::parse_color;
+use crate::Client;
+use anyhow::{anyhow, bail, Context, Result};
+use portal_setting::state::toml_to_typed;
+use tokio::process::Command;
+use zbus::zvariant::{Structure, Value};
+
+/// How a portal setting is derived from a GSettings key on a GNOME desktop
+#[derive(Clone, Copy)]
+enum Mapping {
+    /// Same value and type
+    Same,
+    /// `color-scheme` enum nick to the portal's 0/1/2
+    ColorScheme,
+    /// `high-contrast` boolean to the portal's 0/1
+    Contrast,
+    /// Named accent color to an RGB triple
+    AccentColor,
+}
+
+/// The GSettings schema and key a portal setting mirrors
+fn host_key<'a>(namespace: &'a str, key: &'a str) -> Option<(&'a str, &'a str, Mapping)> {
+    match (namespace, key) {
+        ("org.freedesktop.appearance", "color-scheme") => {
+            Some(("org.gnome.desktop.interface", "color-scheme", Mapping::ColorScheme))
+        }
+        ("org.freedesktop.appearance", "contrast") => {
+            Some(("org.gnome.desktop.a11y.interface", "high-contrast", Mapping::Contrast))
+        }
+        ("org.freedesktop.appearance", "accent-color") => {
+            Some(("org.gnome.desktop.interface", "accent-color", Mapping::AccentColor))
+        }
+        _ if namespace.starts_with("org.gnome.") => Some((namespace, key, Mapping::Same)),
+        _ => None,
+    }
+}
+
+/// Compare every portal setting with a GSettings equivalent against the
+/// host's value, failing if any differ
+pub async fn compare(client: &Client) -> Result<()> {
+    let settings = client.read_all(Vec::new()).await?;
+
+    let (mut matching, mut differing, mut missing) = (0, 0, 0);
+    for (namespace, keys) in &settings {
+        for (key, value) in keys {
+            let Some((schema, host_key, mapping)) = host_key(namespace, key) else {
+                continue;
+            };
+            let Some(text) = gsettings_get(schema, host_key).await? else {
+                println!("- {} {}: {} {} does not exist on the host", namespace, key, schema, host_key);
+                missing += 1;
+                continue;
+            };
+
+            match host_value(mapping, value.value_signature().as_str(), &text) {
+                Ok(expected) if same(mapping, value, &expected) => {
+                    println!("✓ {} {} {}", namespace, key, **value);
+                    matching += 1;
+                }
+                Ok(expected) => {
+                    let host = match mapping {
+                        Mapping::Same => expected.to_string(),
+                        _ => format!("{} ({} {} = {})", expected, schema, host_key, text),
+                    };
+                    println!("✗ {} {}: portal {}, host {}", namespace, key, **value, host);
+                    differing += 1;
+                }
+                Err(e) => {
+                    println!("✗ {} {}: portal {}, host {} ({:#})", namespace, key, **value, text, e);
+                    differing += 1;
+                }
+            }
+        }
+    }
+
+    println!(
+        "\n{} matching, {} differing, {} not on the host",
+        matching, differing, missing
+    );
+    if differing > 0 {
+        bail!("{} settings differ from the host", differing);
+    }
+    Ok(())
+}
+
+/// `gsettings get`, or `None` if the schema or key does not exist
+async fn gsettings_get(schema: &str, key: &str) -> Result<Option<String>> {
+    let output = Command::new("gsettings")
+        .args(["get", schema, key])
+        .output()
+        .await
+        .context("cannot run gsettings")?;
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        if stderr.contains("No such schema") || stderr.contains("No such key") {
+            return Ok(None);
+        }
+        bail!("gsettings get {} {} failed: {}", schema, key, stderr.trim());
+    }
+    Ok(Some(String::from_utf8(output.stdout)?.trim().to_string()))
+}
+
+/// The portal value the host's GSettings value should map to
+fn host_value(mapping: Mapping, signature: &str, text: &str) -> Result<Value<'static>> {
+    let parsed = parse_gvariant(text)?;
+    let nick = || parsed.as_str().ok_or_else(|| anyhow!("expected a string"));
+    Ok(match mapping {
+        Mapping::Same => toml_to_typed(signature, &parsed)?,
+        Mapping::ColorScheme => Value::U32(match nick()? {
+            "default" => 0,
+            "prefer-dark" => 1,
+            "prefer-light" => 2,
+            other => bail!("unknown color scheme {:?}", other),
+        }),
+        Mapping::Contrast => match parsed {
+            toml::Value::Boolean(high) => Value::U32(high.into()),
+            _ => bail!("expected a boolean"),
+        },
+        Mapping::AccentColor => {
+            // The colors xdg-desktop-portal-gnome reports for each name
+            let hex = match nick()? {
+                "blue" => "#3584e4",
+                "teal" => "#2190a4",
+                "green" => "#3a944a",
+                "yellow" => "#c88800",
+                "orange" => "#ed5b00",
+                "red" => "#e62d42",
+                "pink" => "#d56199",
+                "purple" => "#9141ac",
+                "slate" => "#6f8396",
+                other => bail!("unknown accent color {:?}", other),
+            };
+            Value::from(parse_color(hex)?)
+        }
+    })
+}
+
+fn same(mapping: Mapping, portal: &Value<'_>, host: &Value<'_>) -> bool {
+    match (mapping, portal, host) {
+        // Named colors only round-trip to 8 bits per channel
+        (Mapping::AccentColor, Value::Structure(a), Value::Structure(b)) => {
+            channels(a).zip(channels(b)).all(|(a, b)| (a - b).abs() < 0.5 / 255.0)
+        }
+        _ => portal == host,
+    }
+}
+
+fn channels<'a>(color: &'a Structure<'_>) -> impl Iterator<Item = f64> + 'a {
+    color.fields().iter().map(|field| match field {
+        Value::F64(c) => *c,
+        _ => f64::NAN,
+    })
+}
+
+/// Parse the GVariant text format printed by `gsettings get` into the TOML
+/// representation `toml_to_typed` converts from
+///
+/// Arrays and tuples both become arrays; type annotations are skipped.
+fn parse_gvariant(text: &str) -> Result<toml::Value> {
+    let mut parser = Parser { rest: text };
+    let value = parser.value()?;
+    if !parser.rest.trim().is_empty() {
+        bail!("unexpected {:?} in {:?}", parser.rest, text);
+    }
+    Ok(value)
+}
+
+struct Parser<'a> {
+    rest: &'a str,
+}
+
+impl Parser<'_> {
+    fn value(&mut self) -> Result<toml::Value> {
+        self.rest = self.rest.trim_start();
+
+        // `@as []` and `uint32 7`
+        if let Some(annotated) = self.rest.strip_prefix('@') {
+            self.rest = annotated.split_once(' ').map_or("", |(_, rest)| rest);
+            return self.value();
+        }
+        const TYPE_NAMES: [&str; 11] = [
+            "byte", "int16", "uint16", "int32", "uint32", "int64", "uint64", "double", "handle", "objectpath",
+            "signature",
+        ];
+        for name in TYPE_NAMES {
+            if let Some(rest) = self.rest.strip_prefix(name).and_then(|r| r.strip_prefix(' ')) {
+                self.rest = rest;
+                return self.value();
+            }
+        }
+
+        match self.rest.chars().next() {
+            Some('[') => self.sequence(']'),
+            Some('(') => self.sequence(')'),
+            Some('<') => {
+                self.rest = &self.rest[1..];
+                let value = self.value()?;
+                self.expect('>')?;
+                Ok(value)
+            }
+            Some(quote @ ('\'' | '"')) => self.string(quote),
+            Some(_) => {
+                let end = self
+                    .rest
+                    .find(|c: char| c.is_whitespace() || ",)]>".contains(c))
+                    .unwrap_or(self.rest.len());
+                let (token, rest) = self.rest.split_at(end);
+                self.rest = rest;
+                scalar(token)
+            }
+            None => bail!("unexpected end of value"),
+        }
+    }
+
+    fn sequence(&mut self, close: char) -> Result<toml::Value> {
+        self.rest = &self.rest[1..];
+        let mut items = Vec::new();
+        loop {
+            self.rest = self.rest.trim_start();
+            if let Some(rest) = self.rest.strip_prefix(close) {
+                self.rest = rest;
+                return Ok(toml::Value::Array(items));
+            }
+            items.push(self.value()?);
+            self.rest = self.rest.trim_start();
+            if let Some(rest) = self.rest.strip_prefix(',') {
+                self.rest = rest;
+            } else {
+                self.expect(close)?;
+                return Ok(toml::Value::Array(items));
+            }
+        }
+    }
+
+    fn string(&mut self, quote: char) -> Result<toml::Value> {
+        let mut chars = self.rest[1..].char_indices();
+        let mut string = String::new();
+        while let Some((i, c)) = chars.next() {
+            match c {
+                c if c == quote => {
+                    self.rest = &self.rest[1 + i + 1..];
+                    return Ok(toml::Value::String(string));
+                }
+                '\\' => {
+                    let escaped = match chars.next().map(|(_, c)| c) {
+                        Some('n') => '\n',
+                        Some('t') => '\t',
+                        Some('r') => '\r',
+                        Some(digits @ ('u' | 'U')) => {
+                            let len = if digits == 'u' { 4 } else { 8 };
+                            let hex: String = chars.by_ref().take(len).map(|(_, c)| c).collect();
+                            u32::from_str_radix(&hex, 16)
+                                .ok()
+                                .and_then(char::from_u32)
+                                .ok_or_else(|| anyhow!("invalid escape \\{}{}", digits, hex))?
+                        }
+                        Some(c) => c,
+                        None => break,
+                    };
+                    string.push(escaped);
+                }
+                c => string.push(c),
+            }
+        }
+        bail!("unterminated string")
+    }
+
+    fn expect(&mut self, c: char) -> Result<()> {
+        self.rest = self
+            .rest
+            .trim_start()
+            .strip_prefix(c)
+            .ok_or_else(|| anyhow!("expected {:?} at {:?}", c, self.rest))?;
+        Ok(())
+    }
+}
+
+fn scalar(token: &str) -> Result<toml::Value> {
+    Ok(match token {
+        "true" => toml::Value::Boolean(true),
+        "false" => toml::Value::Boolean(false),
+        _ => {
+            if let Some(hex) = token.strip_prefix("0x") {
+                toml::Value::Integer(i64::from_str_radix(hex, 16)?)
+            } else if let Ok(i) = token.parse() {
+                toml::Value::Integer(i)
+            } else {
+                toml::Value::Float(token.parse().with_context(|| format!("cannot parse {:?}", token))?)
+            }
+        }
+    })
+}
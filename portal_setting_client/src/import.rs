@@ -0,0 +1,90 @@
+use crate::output::Output;
+use crate::{exit, export, Client};
+use anyhow::{anyhow, bail, Result};
+use portal_setting::config::toml_to_value;
+use portal_setting::state::toml_to_typed;
+use portal_setting::SettingsStore;
+use serde_json::json;
+use std::collections::HashMap;
+use std::path::Path;
+use zbus::zvariant::OwnedValue;
+
+/// Validate every setting of the configuration file at `path`, then write
+/// them all with one `WriteMany` call, or none if any is invalid
+pub async fn run(client: &Client, path: &Path, output: Output) -> Result<()> {
+    let document = export::read(path)?;
+    let namespaces = match document.get("settings") {
+        Some(toml::Value::Table(namespaces)) => namespaces.clone(),
+        Some(_) => bail!("{}: settings must be a table", path.display()),
+        None => toml::Table::new(),
+    };
+
+    // Existing keys keep their type, like `set`
+    let current = client.read_all(namespaces.keys().cloned().collect()).await?;
+    let mut settings: HashMap<String, HashMap<String, OwnedValue>> = HashMap::new();
+    let mut results = Vec::new();
+    for (namespace, keys) in &namespaces {
+        let toml::Value::Table(keys) = keys else {
+            results.push((
+                namespace.clone(),
+                String::new(),
+                Err(anyhow!("expected a table of keys")),
+            ));
+            continue;
+        };
+        for (key, value) in keys {
+            let signature = current
+                .get(namespace)
+                .and_then(|keys| keys.get(key))
+                .map(|v| v.value_signature().to_string());
+            let converted = match signature {
+                Some(signature) => toml_to_typed(&signature, value).and_then(|v| Ok(OwnedValue::try_from(v)?)),
+                None => toml_to_value(namespace, key, value),
+            }
+            .and_then(|v| SettingsStore::validate_setting(namespace, key, &v).map(|()| v));
+            match converted {
+                Ok(v) => {
+                    settings.entry(namespace.clone()).or_default().insert(key.clone(), v);
+                    results.push((namespace.clone(), key.clone(), Ok(())));
+                }
+                Err(e) => results.push((namespace.clone(), key.clone(), Err(e.into()))),
+            }
+        }
+    }
+
+    let invalid = results.iter().filter(|(_, _, result)| result.is_err()).count();
+    if invalid == 0 {
+        client.write_many(&settings).await?;
+    }
+    report(&results, invalid == 0, output);
+    match invalid {
+        0 => Ok(()),
+        n => Err(exit::InvalidImport(format!(
+            "{} of {} settings are invalid, nothing was imported",
+            n,
+            results.len()
+        ))
+        .into()),
+    }
+}
+
+/// Print the result of every setting, `ok` only if all were written
+fn report(results: &[(String, String, Result<()>)], written: bool, output: Output) {
+    for (namespace, key, result) in results {
+        match (output, result) {
+            (Output::Quiet, _) => {}
+            (Output::Text, Ok(())) if written => println!("{} {} ok", namespace, key),
+            (Output::Text, Ok(())) => println!("{} {} not written", namespace, key),
+            (Output::Text, Err(e)) => println!("{} {} invalid: {:#}", namespace, key, e),
+            (Output::Json, result) => println!(
+                "{}",
+                json!({
+                    "namespace": namespace,
+                    "key": key,
+                    "written": written && result.is_ok(),
+                    "error": result.as_ref().err().map(|e| format!("{:#}", e)),
+                })
+            ),
+        }
+    }
+}
@@ -0,0 +1,63 @@
+use serde_json::{Map, Number, Value as Json};
+use zbus::zvariant::Value;
+
+/// Convert a D-Bus value to JSON
+///
+/// Numbers, booleans and strings map to their JSON counterparts, arrays and
+/// structures to arrays, and dictionaries to objects (keys converted to
+/// strings). Variants are unwrapped; file descriptors and non-finite doubles
+/// become `null`.
+pub fn to_json(value: &Value<'_>) -> Json {
+    match value {
+        Value::U8(v) => Json::from(*v),
+        Value::Bool(v) => Json::from(*v),
+        Value::I16(v) => Json::from(*v),
+        Value::U16(v) => Json::from(*v),
+        Value::I32(v) => Json::from(*v),
+        Value::U32(v) => Json::from(*v),
+        Value::I64(v) => Json::from(*v),
+        Value::U64(v) => Json::from(*v),
+        Value::F64(v) => Number::from_f64(*v).map(Json::Number).unwrap_or(Json::Null),
+        Value::Str(v) => Json::from(v.as_str()),
+        Value::ObjectPath(v) => Json::from(v.as_str()),
+        Value::Signature(v) => Json::from(v.as_str()),
+        Value::Value(v) => to_json(v),
+        Value::Array(array) => array.inner().iter().map(to_json).collect(),
+        Value::Dict(dict) => {
+            let object: Map<String, Json> = dict
+                .iter()
+                .map(|(k, v)| {
+                    let key = match to_json(k) {
+                        Json::String(s) => s,
+                        other => other.to_string(),
+                    };
+                    (key, to_json(v))
+                })
+                .collect();
+            Json::Object(object)
+        }
+        Value::Structure(structure) => structure.fields().iter().map(to_json).collect(),
+        _ => Json::Null,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+    use std::collections::HashMap;
+
+    #[test]
+    fn test_to_json() {
+        assert_eq!(to_json(&Value::U32(1)), json!(1));
+        assert_eq!(to_json(&Value::Bool(true)), json!(true));
+        assert_eq!(to_json(&Value::F64(0.5)), json!(0.5));
+        assert_eq!(to_json(&Value::F64(f64::NAN)), Json::Null);
+        assert_eq!(to_json(&Value::from("24h")), json!("24h"));
+        assert_eq!(to_json(&Value::Value(Box::new(Value::from(2i32)))), json!(2));
+        assert_eq!(to_json(&Value::from(vec!["a", "b"])), json!(["a", "b"]));
+        assert_eq!(to_json(&Value::from((1.0, 0.0, 0.0))), json!([1.0, 0.0, 0.0]));
+        let dict = HashMap::from([(1u32, "one")]);
+        assert_eq!(to_json(&Value::from(dict)), json!({ "1": "one" }));
+    }
+}
@@ -1,217 +1,590 @@
-use anyhow::Result;
-use std::collections::HashMap;
+use anyhow::{bail, Context, Result};
+use clap::{CommandFactory, Parser, Subcommand};
+use clap_complete::env::Shells;
+use clap_complete::{ArgValueCompleter, CompleteEnv, Shell};
+use futures_util::StreamExt;
+use output::Output;
+use retry::RetryPolicy;
+use value::ValueType;
+use portal_setting::pattern::glob_match;
+use portal_setting::SensitiveKeys;
+use portal_setting_proxy::{ImplSettingsProxy, ManagementProxy, PATH, SERVICE_BUS_NAME};
+use std::collections::{BTreeMap, HashMap};
+use std::io::IsTerminal;
+use std::path::PathBuf;
+use std::process::ExitCode;
 use std::time::Duration;
-use zbus::{Connection, zvariant::OwnedValue};
+use zbus::fdo::DBusProxy;
+use zbus::names::BusName;
+use zbus::zvariant::OwnedValue;
+use zbus::Connection;
+
+mod bench;
+mod complete;
+mod diff;
+mod exit;
+mod export;
+mod gsettings;
+mod import;
+mod json;
+mod output;
+mod retry;
+mod smoke;
+mod table;
+mod tui;
+mod value;
+
+/// Read and change settings of the XDG Portal Settings service
+#[derive(Parser)]
+#[command(version)]
+struct Args {
+    #[command(subcommand)]
+    command: Command,
+
+    /// Connect to the system bus instead of the session bus
+    #[arg(long, global = true, conflicts_with = "address")]
+    system: bool,
+
+    /// Connect to the message bus at this D-Bus address, e.g. unix:path=/tmp/test-bus
+    #[arg(long, value_name = "ADDRESS", global = true)]
+    address: Option<String>,
+
+    /// Bus name of the service
+    #[arg(long, value_name = "NAME", default_value = SERVICE_BUS_NAME, global = true)]
+    bus_name: String,
+
+    /// Object path of the management interface, /io/github/meta_flutter/PortalSettings if only some callers may use it
+    #[arg(long, value_name = "PATH", default_value = PATH, global = true)]
+    management_path: String,
+
+    /// Wait up to this many seconds for the service to appear on the bus
+    #[arg(long, value_name = "SECONDS", default_value_t = 0.0, global = true)]
+    wait_timeout: f64,
+
+    /// Fail calls to the bus and the service that take longer than this many seconds
+    #[arg(long, value_name = "SECONDS", global = true)]
+    timeout: Option<f64>,
+
+    /// Retry calls up to this many times while the bus or the service is unavailable
+    #[arg(long, value_name = "N", default_value_t = 0, global = true)]
+    retries: u32,
+
+    /// Seconds to wait before the first retry, doubling for every further retry
+    #[arg(long, value_name = "SECONDS", default_value_t = 0.5, global = true)]
+    backoff: f64,
+
+    /// Output format
+    #[arg(long, value_enum, default_value_t, global = true)]
+    output: Output,
+
+    /// Print only the raw value for `get` and `monitor`, and no messages
+    #[arg(long, short, global = true, conflicts_with = "output")]
+    quiet: bool,
+
+    /// Do not color tables, also implied by NO_COLOR or output that is not a terminal
+    #[arg(long, global = true)]
+    no_color: bool,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Print the value of a setting
+    Get {
+        #[arg(add = ArgValueCompleter::new(complete::namespaces))]
+        namespace: String,
+        #[arg(add = ArgValueCompleter::new(complete::keys))]
+        key: String,
+    },
+    /// Change a setting
+    ///
+    /// The value gets the type of the setting's current value. New keys
+    /// known to the service's schema get their documented type; for others
+    /// the type follows from the syntax: `true`, `42`, `0.5`, `[1, 2]` or a
+    /// string. Colors can be given as `#RRGGBB`.
+    Set {
+        #[arg(add = ArgValueCompleter::new(complete::namespaces))]
+        namespace: String,
+        #[arg(add = ArgValueCompleter::new(complete::keys))]
+        key: String,
+        #[arg(allow_hyphen_values = true)]
+        value: String,
+
+        /// Type of the value, instead of inferring it
+        #[arg(long = "type", value_enum, value_name = "TYPE")]
+        value_type: Option<ValueType>,
+    },
+    /// List namespaces, or the keys of a namespace
+    List {
+        #[arg(add = ArgValueCompleter::new(complete::namespaces))]
+        namespace: Option<String>,
+    },
+    /// Print all settings, optionally only those of the given namespaces
+    ReadAll {
+        #[arg(add = ArgValueCompleter::new(complete::namespaces))]
+        namespaces: Vec<String>,
+    },
+    /// Save all settings, optionally only those of the given namespaces, as
+    /// a configuration file
+    ///
+    /// The TOML output can be loaded with `portal-setting-service --config`
+    /// to provision another device with the same settings.
+    Export {
+        #[arg(add = ArgValueCompleter::new(complete::namespaces))]
+        namespaces: Vec<String>,
+
+        /// File format, by default the one matching the extension of `--out`, or TOML
+        #[arg(long, value_enum)]
+        format: Option<export::Format>,
+
+        /// Write to this file instead of stdout
+        #[arg(long, value_name = "PATH")]
+        out: Option<PathBuf>,
+
+        /// Also export the settings the service's `sensitive_keys` lists,
+        /// which are left out otherwise
+        #[arg(long)]
+        include_sensitive: bool,
+    },
+    /// Compare the settings of two services, or of a service and a file
+    ///
+    /// Files are read like the service reads `--config`: the built-in
+    /// defaults with the file's settings on top. Exits with status 1 if any
+    /// setting differs.
+    #[command(group = clap::ArgGroup::new("b").required(true))]
+    Diff {
+        /// Bus name of the first service, by default `--bus-name`
+        #[arg(long, value_name = "NAME", conflicts_with = "file_a")]
+        bus_a: Option<String>,
+        /// Configuration file to compare instead of the first service
+        #[arg(long, value_name = "PATH")]
+        file_a: Option<PathBuf>,
+        /// Bus name of the second service
+        #[arg(long, value_name = "NAME", group = "b")]
+        bus_b: Option<String>,
+        /// Configuration file to compare with
+        #[arg(long, value_name = "PATH", group = "b")]
+        file_b: Option<PathBuf>,
+    },
+    /// Apply the settings of a configuration file as one transaction
+    ///
+    /// Every setting is converted and validated first; only if all are valid
+    /// they are written together. Accepts the TOML and JSON of `export`.
+    Import { file: PathBuf },
+    /// Restore a setting to its default
+    Reset {
+        #[arg(add = ArgValueCompleter::new(complete::namespaces))]
+        namespace: String,
+        #[arg(add = ArgValueCompleter::new(complete::keys))]
+        key: String,
+    },
+    /// Print settings changes as they happen
+    Monitor {
+        /// Only show changes in namespaces matching this glob, e.g. `org.gnome.*`
+        #[arg(value_name = "NAMESPACE-GLOB")]
+        namespace: Option<String>,
+
+        /// Only show changes of keys matching this glob, e.g. `color-*`
+        #[arg(long, value_name = "KEY-GLOB")]
+        key: Option<String>,
+
+        /// Only show changes to this value, typed like `set`
+        #[arg(long, value_name = "VALUE", allow_hyphen_values = true)]
+        changed_to: Option<String>,
+
+        /// Exit after the first change of KEY in NAMESPACE to VALUE
+        #[arg(
+            long,
+            num_args = 2,
+            value_names = ["NAMESPACE", "KEY=VALUE"],
+            conflicts_with_all = ["namespace", "key", "changed_to"]
+        )]
+        wait_for: Option<Vec<String>>,
+    },
+    /// Browse and edit settings interactively
+    Tui,
+    /// Compare settings with the host's GSettings values
+    ///
+    /// Checks the `org.gnome.*` namespaces key by key and the
+    /// `org.freedesktop.appearance` keys against the GNOME settings they are
+    /// derived from, using the `gsettings` tool.
+    CompareGsettings,
+    /// Measure call latencies and signal delivery under load
+    ///
+    /// Writes go to a scratch key in the
+    /// `io.github.meta_flutter.PortalSettings.Bench` namespace, which is
+    /// deleted afterwards.
+    Bench {
+        /// Number of reads, alternating `Read` and `ReadAll`
+        #[arg(long, default_value_t = 1000)]
+        reads: usize,
+        /// Number of writes
+        #[arg(long, default_value_t = 100)]
+        writes: usize,
+        /// Number of calls in flight at a time
+        #[arg(long, default_value_t = 8)]
+        concurrency: usize,
+    },
+    /// Read every built-in setting and verify its type
+    Test,
+    /// Print a shell completion script
+    ///
+    /// The script asks the client for candidates, so namespaces and keys are
+    /// completed from the running service.
+    Completions {
+        #[arg(value_name = "SHELL")]
+        shell: Shell,
+    },
+}
 
 #[tokio::main]
-async fn main() -> Result<()> {
-    println!("XDG Portal Settings Client - Testing all settings\n");
-
-    // Wait a moment for the service to be ready
-    tokio::time::sleep(Duration::from_millis(500)).await;
-
-    // Connect to session bus
-    let connection = Connection::session().await?;
-
-    // Create proxy to the settings portal
-    let proxy = zbus::Proxy::new(
-        &connection,
-        "org.freedesktop.impl.portal.Settings",
-        "/org/freedesktop/portal/desktop",
-        "org.freedesktop.impl.portal.Settings",
-    )
-    .await?;
-
-    println!("Connected to org.freedesktop.impl.portal.Settings");
-    println!("{}", "=".repeat(60));
-
-    // Test 1: Read all settings
-    println!("\n[TEST 1] Reading all settings (no filter):");
-    let all_settings: HashMap<String, HashMap<String, OwnedValue>> =
-        proxy.call("ReadAll", &(Vec::<String>::new(),)).await?;
-
-    for (namespace, keys) in &all_settings {
-        println!("  Namespace: {}", namespace);
-        for (key, value) in keys {
-            println!("    {}: {:?}", key, value);
+async fn main() -> ExitCode {
+    // Answers completion requests of the script printed by `completions`
+    CompleteEnv::with_factory(Args::command).complete();
+
+    // clap exits with 2 on usage errors, which means "not found" here
+    let args = match Args::try_parse() {
+        Ok(args) => args,
+        Err(e) => {
+            let _ = e.print();
+            return match e.use_stderr() {
+                true => ExitCode::from(exit::FAILURE),
+                false => ExitCode::SUCCESS,
+            };
         }
-    }
-    println!("✓ ReadAll passed");
+    };
 
-    // Test 2: Read settings from specific namespace
-    println!("\n[TEST 2] Reading org.freedesktop.appearance namespace:");
-    let appearance_settings: HashMap<String, HashMap<String, OwnedValue>> = proxy
-        .call("ReadAll", &(vec!["org.freedesktop.appearance".to_string()],))
-        .await?;
+    match run(args).await {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("Error: {:#}", e);
+            ExitCode::from(exit::status(&e))
+        }
+    }
+}
 
-    assert!(appearance_settings.contains_key("org.freedesktop.appearance"));
-    println!("  Found {} keys", appearance_settings["org.freedesktop.appearance"].len());
-    println!("✓ Filtered ReadAll passed");
+async fn run(args: Args) -> Result<()> {
+    if let Command::Completions { shell } = args.command {
+        let name = shell.to_string();
+        let shells = Shells::builtins();
+        let completer = shells
+            .completer(&name)
+            .with_context(|| format!("no completion support for {}", name))?;
+        let bin = "portal-setting-client";
+        let path = std::env::current_exe().map_or(bin.into(), |path| path.to_string_lossy().into_owned());
+        completer.write_registration("COMPLETE", bin, bin, &path, &mut std::io::stdout())?;
+        return Ok(());
+    }
 
-    // Test 3: Read individual settings
-    println!("\n[TEST 3] Reading individual settings:");
-    
-    // color-scheme
-    let color_scheme: OwnedValue = proxy
-        .call("Read", &("org.freedesktop.appearance", "color-scheme"))
-        .await?;
-    println!("  color-scheme: {:?}", color_scheme);
-    let _: u32 = color_scheme.try_into()?;
-    
-    // gtk-theme
-    let gtk_theme: OwnedValue = proxy
-        .call("Read", &("org.gnome.desktop.interface", "gtk-theme"))
-        .await?;
-    println!("  gtk-theme: {:?}", gtk_theme);
-    let _: String = gtk_theme.try_into()?;
-    
-    // remember-recent-files
-    let recent_files: OwnedValue = proxy
-        .call("Read", &("org.gnome.desktop.privacy", "remember-recent-files"))
+    let retry = RetryPolicy {
+        timeout: args
+            .timeout
+            .map(Duration::try_from_secs_f64)
+            .transpose()
+            .context("invalid --timeout")?,
+        retries: args.retries,
+        backoff: Duration::try_from_secs_f64(args.backoff).context("invalid --backoff")?,
+    };
+    let connection = retry
+        .run(|| async {
+            connect(args.system, args.address.as_deref())
+                .await
+                .context("cannot connect to the bus")
+        })
         .await?;
-    println!("  remember-recent-files: {:?}", recent_files);
-    let _: bool = recent_files.try_into()?;
-    
-    println!("✓ Individual Read passed");
-
-    // Test 4: Test all org.freedesktop.appearance settings
-    println!("\n[TEST 4] Testing org.freedesktop.appearance settings:");
-    
-    // Test color-scheme (u32: 0-2)
-    for i in 0..=2 {
-        let val: OwnedValue = proxy
-            .call("Read", &("org.freedesktop.appearance", "color-scheme"))
-            .await?;
-        let result: u32 = val.try_into()?;
-        println!("  color-scheme value {}: {}", i, result);
+    if args.wait_timeout > 0.0 {
+        let timeout = Duration::try_from_secs_f64(args.wait_timeout).context("invalid --wait-timeout")?;
+        wait_for_name(&connection, &args.bus_name, timeout).await?;
     }
-    
-    // Test accent-color (RGB tuple)
-    let accent: OwnedValue = proxy
-        .call("Read", &("org.freedesktop.appearance", "accent-color"))
-        .await?;
-    let (r, g, b): (f64, f64, f64) = accent.try_into()?;
-    println!("  accent-color: ({}, {}, {})", r, g, b);
-    
-    // Test contrast (u32: 0-1)
-    let contrast: OwnedValue = proxy
-        .call("Read", &("org.freedesktop.appearance", "contrast"))
-        .await?;
-    let contrast_val: u32 = contrast.try_into()?;
-    println!("  contrast: {}", contrast_val);
-    
-    println!("✓ org.freedesktop.appearance tests passed");
-
-    // Test 5: Test all org.gnome.desktop.interface settings
-    println!("\n[TEST 5] Testing org.gnome.desktop.interface settings:");
-    
-    let interface_keys = vec![
-        "gtk-theme",
-        "icon-theme",
-        "cursor-theme",
-        "font-name",
-        "monospace-font-name",
-        "clock-format",
-    ];
-    
-    for key in interface_keys {
-        let val: OwnedValue = proxy
-            .call("Read", &("org.gnome.desktop.interface", key))
-            .await?;
-        let str_val: String = val.try_into()?;
-        println!("  {}: {}", key, str_val);
+    if let Command::Test = args.command {
+        return smoke::run(&connection, &args.bus_name).await;
     }
-    
-    println!("✓ org.gnome.desktop.interface tests passed");
 
-    // Test 6: Test all org.gnome.desktop.privacy settings
-    println!("\n[TEST 6] Testing org.gnome.desktop.privacy settings:");
-    
-    let remember: OwnedValue = proxy
-        .call("Read", &("org.gnome.desktop.privacy", "remember-recent-files"))
-        .await?;
-    let remember_val: bool = remember.try_into()?;
-    println!("  remember-recent-files: {}", remember_val);
-    
-    let max_age: OwnedValue = proxy
-        .call("Read", &("org.gnome.desktop.privacy", "recent-files-max-age"))
-        .await?;
-    let max_age_val: i32 = max_age.try_into()?;
-    println!("  recent-files-max-age: {}", max_age_val);
-    
-    println!("✓ org.gnome.desktop.privacy tests passed");
-
-    // Test 7: Verify value types
-    println!("\n[TEST 7] Verifying all value types:");
-    
-    let mut type_tests_passed = 0;
-    let mut type_tests_total = 0;
-    
-    // Check u32 types
-    for key in ["color-scheme", "contrast"] {
-        type_tests_total += 1;
-        let val: OwnedValue = proxy
-            .call("Read", &("org.freedesktop.appearance", key))
-            .await?;
-        if val.value_signature().as_str() == "u" {
-            type_tests_passed += 1;
-            println!("  ✓ {}: u32", key);
+    let client = Client::new(&connection, args.bus_name, &args.management_path, retry).await?;
+    let output = match args.quiet {
+        true => Output::Quiet,
+        false => args.output,
+    };
+    let color = !args.no_color && std::env::var_os("NO_COLOR").is_none_or(|v| v.is_empty()) && std::io::stdout().is_terminal();
+
+    match args.command {
+        Command::Get { namespace, key } => {
+            output.setting(&namespace, &key, &*client.read(&namespace, &key).await?);
+        }
+        Command::Set { namespace, key, value, value_type } => {
+            let value = match value_type {
+                Some(ty) => value::parse_typed(ty, &value),
+                None => match client.read(&namespace, &key).await {
+                    Ok(current) => value::parse_for_signature(&current.value_signature(), &value),
+                    Err(_) => parse_value(&namespace, &key, &value),
+                },
+            }
+            .context(exit::InvalidValue {
+                namespace: namespace.clone(),
+                key: key.clone(),
+            })?;
+            client.write(&namespace, &key, value.try_clone()?).await?;
+            if output == Output::Json {
+                output.setting(&namespace, &key, &value);
+            }
+        }
+        Command::List { namespace: None } => output.names(client.read_all(Vec::new()).await?.keys()),
+        Command::List { namespace: Some(namespace) } => {
+            let all = client.read_all(vec![namespace.clone()]).await?;
+            match output {
+                Output::Text => table::print(&all, &client.layers(vec![namespace]).await, color),
+                _ => output.names(all.get(&namespace).into_iter().flat_map(BTreeMap::keys)),
+            }
+        }
+        Command::ReadAll { namespaces } => {
+            let all = client.read_all(namespaces.clone()).await?;
+            match output {
+                Output::Text => table::print(&all, &client.layers(namespaces).await, color),
+                _ => output.settings(&all),
+            }
+        }
+        Command::Export { namespaces, format, out, include_sensitive } => {
+            let format = format
+                .or_else(|| out.as_deref().and_then(export::Format::of_path))
+                .unwrap_or(export::Format::Toml);
+            let mut settings = client.read_all(namespaces).await?;
+            if !include_sensitive {
+                let left_out = export::remove_sensitive(&mut settings, &client.sensitive_keys().await);
+                if left_out > 0 && output == Output::Text {
+                    eprintln!("Left out {} sensitive settings; pass --include-sensitive to export them", left_out);
+                }
+            }
+            let text = export::render(&settings, format)?;
+            match out {
+                Some(path) => std::fs::write(&path, text).with_context(|| format!("cannot write {}", path.display()))?,
+                None => print!("{}", text),
+            }
+        }
+        Command::Diff { bus_a, file_a, bus_b, file_b } => {
+            let a = match (bus_a, file_a) {
+                (_, Some(path)) => diff::Source::File(path),
+                (Some(name), None) => diff::Source::Service(Client::new(&connection, name, &args.management_path, retry).await?),
+                (None, None) => diff::Source::Service(client),
+            };
+            let b = match (bus_b, file_b) {
+                (_, Some(path)) => diff::Source::File(path),
+                (Some(name), None) => diff::Source::Service(Client::new(&connection, name, &args.management_path, retry).await?),
+                (None, None) => unreachable!("required by clap"),
+            };
+            diff::run(a, b, output).await?
+        }
+        Command::Import { file } => import::run(&client, &file, output).await?,
+        Command::Reset { namespace, key } => {
+            let changed = client.reset(&namespace, &key).await?;
+            output.reset(&namespace, &key, changed);
         }
+        Command::Monitor { namespace, key, changed_to, wait_for: None } => {
+            let filter = ChangeFilter { namespace, key, value: changed_to };
+            client.monitor(&filter, false, output).await?
+        }
+        Command::Monitor { wait_for: Some(target), .. } => {
+            let (key, value) = target[1]
+                .split_once('=')
+                .with_context(|| format!("expected KEY=VALUE, got {:?}", target[1]))?;
+            let filter = ChangeFilter {
+                namespace: Some(target[0].clone()),
+                key: Some(key.to_string()),
+                value: Some(value.to_string()),
+            };
+            client.monitor(&filter, true, output).await?
+        }
+        Command::Tui => tui::run(&client).await?,
+        Command::CompareGsettings => gsettings::compare(&client).await?,
+        Command::Bench { reads, writes, concurrency } => bench::run(&client, reads, writes, concurrency).await?,
+        Command::Test | Command::Completions { .. } => unreachable!(),
     }
-    
-    // Check tuple type
-    type_tests_total += 1;
-    let val: OwnedValue = proxy
-        .call("Read", &("org.freedesktop.appearance", "accent-color"))
-        .await?;
-    if val.value_signature().as_str() == "(ddd)" {
-        type_tests_passed += 1;
-        println!("  ✓ accent-color: (f64, f64, f64)");
-    }
-    
-    // Check string types
-    for key in ["gtk-theme", "icon-theme", "cursor-theme", "font-name", "monospace-font-name", "clock-format"] {
-        type_tests_total += 1;
-        let val: OwnedValue = proxy
-            .call("Read", &("org.gnome.desktop.interface", key))
+    Ok(())
+}
+
+/// Proxies for the interfaces of the service
+struct Client {
+    portal: ImplSettingsProxy<'static>,
+    management: ManagementProxy<'static>,
+    /// Applied to every call
+    retry: RetryPolicy,
+}
+
+impl Client {
+    async fn new(connection: &Connection, bus_name: String, management_path: &str, retry: RetryPolicy) -> Result<Self> {
+        Ok(Self {
+            portal: ImplSettingsProxy::builder(connection)
+                .destination(bus_name.clone())?
+                .build()
+                .await?,
+            management: ManagementProxy::builder(connection)
+                .destination(bus_name)?
+                .path(management_path.to_string())?
+                .build()
+                .await?,
+            retry,
+        })
+    }
+
+    async fn read(&self, namespace: &str, key: &str) -> Result<OwnedValue> {
+        self.retry
+            .run(|| async { Ok(self.portal.read(namespace, key).await?) })
+            .await
+            .with_context(|| format!("cannot read {}/{}", namespace, key))
+    }
+
+    /// All settings, sorted by namespace and key
+    async fn read_all(&self, namespaces: Vec<String>) -> Result<BTreeMap<String, BTreeMap<String, OwnedValue>>> {
+        let namespaces: Vec<&str> = namespaces.iter().map(String::as_str).collect();
+        let all = self
+            .retry
+            .run(|| async { Ok(self.portal.read_all(&namespaces).await?) })
+            .await
+            .context("cannot read settings")?;
+        Ok(all
+            .into_iter()
+            .map(|(namespace, keys)| (namespace, keys.into_iter().collect()))
+            .collect())
+    }
+
+    /// Where each setting's value comes from, or nothing if the service
+    /// cannot tell (e.g. older versions, or a mirror without the management
+    /// interface)
+    async fn layers(&self, namespaces: Vec<String>) -> table::Layers {
+        let namespaces: Vec<&str> = namespaces.iter().map(String::as_str).collect();
+        self.retry
+            .run(|| async { Ok(self.management.layers(&namespaces).await?) })
+            .await
+            .unwrap_or_default()
+    }
+
+    /// The settings the service keeps out of logs and exports, or none if
+    /// it cannot tell
+    async fn sensitive_keys(&self) -> SensitiveKeys {
+        self.retry
+            .run(|| async { Ok(self.management.sensitive_keys().await?) })
+            .await
+            .ok()
+            .and_then(|patterns| SensitiveKeys::new(patterns).ok())
+            .unwrap_or_default()
+    }
+
+    async fn write(&self, namespace: &str, key: &str, value: OwnedValue) -> Result<()> {
+        self.retry
+            .run(|| async { Ok(self.management.write(namespace, key, &value).await?) })
+            .await
+            .with_context(|| format!("cannot write {}/{}", namespace, key))
+    }
+
+    async fn write_many(&self, settings: &HashMap<String, HashMap<String, OwnedValue>>) -> Result<()> {
+        self.retry
+            .run(|| async { Ok(self.management.write_many(settings).await?) })
+            .await
+            .context("cannot write settings")
+    }
+
+    async fn delete(&self, namespace: &str, key: &str) -> Result<bool> {
+        self.retry
+            .run(|| async { Ok(self.management.delete(namespace, key).await?) })
+            .await
+            .with_context(|| format!("cannot delete {}/{}", namespace, key))
+    }
+
+    /// Print every `SettingChanged` signal passing `filter`, returning after
+    /// the first one if `once` is set
+    ///
+    /// The values of sensitive settings are not printed.
+    async fn monitor(&self, filter: &ChangeFilter, once: bool, output: Output) -> Result<()> {
+        let sensitive = self.sensitive_keys().await;
+        let mut changes = self
+            .retry
+            .run(|| async { Ok(self.portal.receive_setting_changed().await?) })
             .await?;
-        if val.value_signature().as_str() == "s" {
-            type_tests_passed += 1;
-            println!("  ✓ {}: string", key);
+        while let Some(signal) = changes.next().await {
+            let args = signal.args()?;
+            let (namespace, key, value) = (args.namespace(), args.key(), args.value());
+            if !filter.matches(namespace, key, value) {
+                continue;
+            }
+            match output {
+                Output::Text => println!("{} {} {}", namespace, key, sensitive.show(namespace, key, value)),
+                _ if sensitive.contains(namespace, key) => output.redacted(namespace, key),
+                Output::Json | Output::Quiet => output.setting(namespace, key, value),
+            }
+            if once {
+                return Ok(());
+            }
+        }
+        match once {
+            true => bail!("lost the connection to the bus"),
+            false => Ok(()),
         }
     }
-    
-    // Check bool type
-    type_tests_total += 1;
-    let val: OwnedValue = proxy
-        .call("Read", &("org.gnome.desktop.privacy", "remember-recent-files"))
-        .await?;
-    if val.value_signature().as_str() == "b" {
-        type_tests_passed += 1;
-        println!("  ✓ remember-recent-files: bool");
-    }
-    
-    // Check i32 type
-    type_tests_total += 1;
-    let val: OwnedValue = proxy
-        .call("Read", &("org.gnome.desktop.privacy", "recent-files-max-age"))
-        .await?;
-    if val.value_signature().as_str() == "i" {
-        type_tests_passed += 1;
-        println!("  ✓ recent-files-max-age: i32");
-    }
-    
-    println!("✓ Type verification passed ({}/{})", type_tests_passed, type_tests_total);
-
-    // Summary
-    println!("\n{}", "=".repeat(60));
-    println!("ALL TESTS PASSED ✓");
-    println!("Successfully verified all settings for:");
-    println!("  - org.freedesktop.appearance (3 settings)");
-    println!("  - org.gnome.desktop.interface (6 settings)");
-    println!("  - org.gnome.desktop.privacy (2 settings)");
-    println!("Total: 11 settings verified");
-    println!("{}", "=".repeat(60));
 
-    Ok(())
+    async fn reset(&self, namespace: &str, key: &str) -> Result<bool> {
+        self.retry
+            .run(|| async { Ok(self.management.reset(namespace, key).await?) })
+            .await
+            .with_context(|| format!("cannot reset {}/{}", namespace, key))
+    }
+}
+
+/// Which changes `monitor` reports; unset fields match everything
+struct ChangeFilter {
+    /// Glob for the namespace
+    namespace: Option<String>,
+    /// Glob for the key
+    key: Option<String>,
+    /// New value, parsed with the changed value's type
+    value: Option<String>,
+}
+
+impl ChangeFilter {
+    fn matches(&self, namespace: &str, key: &str, value: &OwnedValue) -> bool {
+        self.namespace.as_deref().is_none_or(|p| glob_match(p, namespace))
+            && self.key.as_deref().is_none_or(|p| glob_match(p, key))
+            && self.value.as_deref().is_none_or(|text| {
+                value::parse_for_signature(&value.value_signature(), text).is_ok_and(|expected| expected == *value)
+            })
+    }
+}
+
+/// Connect to the bus at `address`, or the system or session bus
+async fn connect(system: bool, address: Option<&str>) -> zbus::Result<Connection> {
+    match (system, address) {
+        (_, Some(address)) => zbus::connection::Builder::address(address)?.build().await,
+        (true, None) => Connection::system().await,
+        (false, None) => Connection::session().await,
+    }
+}
+
+/// Return as soon as `name` has an owner, or fail after `timeout`
+async fn wait_for_name(connection: &Connection, name: &str, timeout: Duration) -> Result<()> {
+    let bus_name = BusName::try_from(name).with_context(|| format!("invalid bus name {}", name))?;
+    let dbus = DBusProxy::new(connection).await?;
+
+    // Subscribe before checking, so an owner appearing in between is not missed
+    let mut owner_changes = dbus.receive_name_owner_changed_with_args(&[(0, name)]).await?;
+    if dbus.name_has_owner(bus_name).await? {
+        return Ok(());
+    }
+
+    let appeared = async {
+        while let Some(change) = owner_changes.next().await {
+            if change.args()?.new_owner().is_some() {
+                return Ok(());
+            }
+        }
+        bail!("lost the connection to the bus")
+    };
+    tokio::time::timeout(timeout, appeared).await.map_err(|_| {
+        exit::ServiceMissing(format!("{} did not appear on the bus within {:?}", name, timeout))
+    })?
+}
+
+/// Convert a command line value like a configuration file value, treating
+/// text that is not a TOML value as a string
+fn parse_value(namespace: &str, key: &str, text: &str) -> Result<OwnedValue> {
+    let value = text
+        .parse::<toml::Value>()
+        .unwrap_or_else(|_| toml::Value::String(text.to_string()));
+    Ok(portal_setting::config::toml_to_value(namespace, key, &value)?)
 }
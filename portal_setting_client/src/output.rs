@@ -0,0 +1,123 @@
+use crate::json::to_json;
+use clap::ValueEnum;
+use portal_setting::sensitive::REDACTED;
+use serde_json::json;
+use std::collections::BTreeMap;
+use zbus::zvariant::{OwnedValue, Value};
+
+/// How results are printed
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+pub enum Output {
+    /// Human readable, values in GVariant text format
+    #[default]
+    Text,
+    /// One JSON document per result (one per line for `monitor`)
+    Json,
+    /// Plain values without type annotations, no messages (`--quiet`)
+    #[value(skip)]
+    Quiet,
+}
+
+impl Output {
+    /// A single setting, as printed by `get` and `monitor`
+    pub fn setting(self, namespace: &str, key: &str, value: &Value<'_>) {
+        match self {
+            Output::Text => println!("{}", value),
+            Output::Quiet => println!("{}", raw(value)),
+            Output::Json => println!(
+                "{}",
+                json!({
+                    "namespace": namespace,
+                    "key": key,
+                    "signature": value.value_signature().to_string(),
+                    "value": to_json(value),
+                })
+            ),
+        }
+    }
+
+    /// A setting whose value the service keeps out of logs, as printed by
+    /// `monitor`
+    pub fn redacted(self, namespace: &str, key: &str) {
+        match self {
+            Output::Text | Output::Quiet => println!("{}", REDACTED),
+            Output::Json => println!("{}", json!({ "namespace": namespace, "key": key, "redacted": true })),
+        }
+    }
+
+    /// Namespace or key names
+    pub fn names<'a>(self, names: impl Iterator<Item = &'a String>) {
+        match self {
+            Output::Text | Output::Quiet => names.for_each(|name| println!("{}", name)),
+            Output::Json => println!("{}", json!(names.collect::<Vec<_>>())),
+        }
+    }
+
+    pub fn settings(self, settings: &BTreeMap<String, BTreeMap<String, OwnedValue>>) {
+        match self {
+            Output::Text | Output::Quiet => {
+                for (namespace, keys) in settings {
+                    for (key, value) in keys {
+                        println!("{} {} {}", namespace, key, **value);
+                    }
+                }
+            }
+            Output::Json => {
+                let object: serde_json::Map<_, _> = settings
+                    .iter()
+                    .map(|(namespace, keys)| {
+                        let keys: serde_json::Map<_, _> =
+                            keys.iter().map(|(key, value)| (key.clone(), to_json(value))).collect();
+                        (namespace.clone(), keys.into())
+                    })
+                    .collect();
+                println!("{}", serde_json::Value::Object(object));
+            }
+        }
+    }
+
+    pub fn reset(self, namespace: &str, key: &str, changed: bool) {
+        match self {
+            Output::Text if !changed => eprintln!("{}/{} does not exist", namespace, key),
+            Output::Text | Output::Quiet => {}
+            Output::Json => println!(
+                "{}",
+                json!({ "namespace": namespace, "key": key, "changed": changed })
+            ),
+        }
+    }
+}
+
+/// Basic values without the GVariant type annotations and quoting, so shell
+/// scripts can use them directly
+pub fn raw(value: &Value<'_>) -> String {
+    match value {
+        Value::U8(v) => v.to_string(),
+        Value::Bool(v) => v.to_string(),
+        Value::I16(v) => v.to_string(),
+        Value::U16(v) => v.to_string(),
+        Value::I32(v) => v.to_string(),
+        Value::U32(v) => v.to_string(),
+        Value::I64(v) => v.to_string(),
+        Value::U64(v) => v.to_string(),
+        Value::F64(v) => v.to_string(),
+        Value::Str(v) => v.to_string(),
+        Value::ObjectPath(v) => v.to_string(),
+        Value::Signature(v) => v.to_string(),
+        Value::Value(v) => raw(v),
+        other => other.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_raw() {
+        assert_eq!(raw(&Value::U32(1)), "1");
+        assert_eq!(raw(&Value::from("24h")), "24h");
+        assert_eq!(raw(&Value::Value(Box::new(Value::from(true)))), "true");
+        assert_eq!(raw(&Value::from(vec!["a", "b"])), r#"["a", "b"]"#);
+    }
+}
@@ -0,0 +1,113 @@
+use crate::exit;
+use anyhow::Result;
+use std::future::Future;
+use std::time::Duration;
+
+/// How calls to the bus and the service are bounded and retried
+#[derive(Debug, Clone, Copy, Default)]
+pub struct RetryPolicy {
+    /// Time after which a single attempt fails
+    pub timeout: Option<Duration>,
+    /// Attempts made after the first one fails
+    pub retries: u32,
+    /// Delay before the first retry, doubled for every further retry
+    pub backoff: Duration,
+}
+
+impl RetryPolicy {
+    /// Run `call` until it succeeds, fails for another reason than the bus or
+    /// the service being unavailable, or the retries are used up
+    pub async fn run<T, F, Fut>(&self, mut call: F) -> Result<T>
+    where
+        F: FnMut() -> Fut,
+        Fut: Future<Output = Result<T>>,
+    {
+        let mut delay = self.backoff;
+        let mut attempt = 0;
+        loop {
+            let result = match self.timeout {
+                Some(timeout) => tokio::time::timeout(timeout, call()).await.unwrap_or_else(|_| {
+                    Err(exit::ServiceMissing(format!("no reply within {:?}", timeout)).into())
+                }),
+                None => call().await,
+            };
+            match result {
+                Err(e) if attempt < self.retries && exit::status(&e) == exit::CONNECTION => {
+                    tokio::time::sleep(delay).await;
+                    delay = delay.saturating_mul(2);
+                    attempt += 1;
+                }
+                result => return result,
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::Cell;
+
+    #[tokio::test]
+    async fn test_retries() {
+        let policy = RetryPolicy {
+            timeout: None,
+            retries: 2,
+            backoff: Duration::from_millis(1),
+        };
+        let unavailable = || anyhow::Error::from(exit::ServiceMissing("not on the bus".to_string()));
+
+        // Unavailable services are retried until the call succeeds
+        let attempts = Cell::new(0);
+        let result = policy
+            .run(|| async {
+                attempts.set(attempts.get() + 1);
+                match attempts.get() {
+                    3 => Ok(attempts.get()),
+                    _ => Err(unavailable()),
+                }
+            })
+            .await;
+        assert_eq!(result.unwrap(), 3);
+
+        // ... or the retries are used up
+        attempts.set(0);
+        let result: Result<()> = policy
+            .run(|| async {
+                attempts.set(attempts.get() + 1);
+                Err(unavailable())
+            })
+            .await;
+        assert_eq!(exit::status(&result.unwrap_err()), exit::CONNECTION);
+        assert_eq!(attempts.get(), 3);
+
+        // Other failures are not retried
+        attempts.set(0);
+        let result: Result<()> = policy
+            .run(|| async {
+                attempts.set(attempts.get() + 1);
+                Err(anyhow::anyhow!("rejected"))
+            })
+            .await;
+        assert!(result.is_err());
+        assert_eq!(attempts.get(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_timeout() {
+        let policy = RetryPolicy {
+            timeout: Some(Duration::from_millis(10)),
+            retries: 1,
+            backoff: Duration::from_millis(1),
+        };
+        let attempts = Cell::new(0);
+        let result: Result<()> = policy
+            .run(|| async {
+                attempts.set(attempts.get() + 1);
+                std::future::pending().await
+            })
+            .await;
+        assert_eq!(exit::status(&result.unwrap_err()), exit::CONNECTION);
+        assert_eq!(attempts.get(), 2);
+    }
+}
@@ -0,0 +1,106 @@
+use crate::output::raw;
+use crossterm::style::Stylize;
+use std::collections::{BTreeMap, HashMap};
+use zbus::zvariant::OwnedValue;
+
+const HEADER: [&str; 5] = ["NAMESPACE", "KEY", "TYPE", "VALUE", "LAYER"];
+
+/// Layer of each setting by namespace and key, as reported by the service
+pub type Layers = HashMap<String, HashMap<String, String>>;
+
+/// Print `settings` as aligned columns, with the layer each value comes from
+/// (`-` when the service does not report it)
+pub fn print(settings: &BTreeMap<String, BTreeMap<String, OwnedValue>>, layers: &Layers, color: bool) {
+    let (header, rows) = layout(settings, layers);
+    match color {
+        true => println!("{}", header.bold()),
+        false => println!("{}", header),
+    }
+    for (cells, layer) in &rows {
+        match color {
+            // Runtime changes stand out from the configured values
+            true if layer == "runtime" => println!("{}  {}", cells, layer.as_str().yellow()),
+            true => println!("{}  {}", cells, layer.as_str().dark_grey()),
+            false => println!("{}  {}", cells, layer),
+        }
+    }
+}
+
+/// The header line and, for each setting, its padded columns before the
+/// layer and the layer
+fn layout(settings: &BTreeMap<String, BTreeMap<String, OwnedValue>>, layers: &Layers) -> (String, Vec<(String, String)>) {
+    let rows: Vec<[String; 5]> = settings
+        .iter()
+        .flat_map(|(namespace, keys)| {
+            keys.iter().map(move |(key, value)| {
+                let layer = layers.get(namespace).and_then(|keys| keys.get(key));
+                [
+                    namespace.clone(),
+                    key.clone(),
+                    value.value_signature().to_string(),
+                    raw(value),
+                    layer.map_or("-", String::as_str).to_string(),
+                ]
+            })
+        })
+        .collect();
+
+    let mut widths = HEADER.map(|title| title.len());
+    for row in &rows {
+        for (width, cell) in widths.iter_mut().zip(row) {
+            *width = (*width).max(cell.chars().count());
+        }
+    }
+    let pad = |column: usize, text: &str| format!("{:<1$}", text, widths[column]);
+    // The last column is not padded, so lines carry no trailing spaces
+    let last = HEADER.len() - 1;
+
+    let header: Vec<String> = (0..last).map(|column| pad(column, HEADER[column])).collect();
+    let header = format!("{}  {}", header.join("  "), HEADER[last]);
+    let rows = rows
+        .iter()
+        .map(|row| {
+            let cells: Vec<String> = (0..last).map(|column| pad(column, &row[column])).collect();
+            (cells.join("  "), row[last].clone())
+        })
+        .collect();
+    (header, rows)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use zbus::zvariant::Value;
+
+    #[test]
+    fn test_layout() {
+        let mut settings = BTreeMap::new();
+        settings.insert(
+            "org.freedesktop.appearance".to_string(),
+            BTreeMap::from([
+                ("color-scheme".to_string(), OwnedValue::from(1u32)),
+                ("accent-color".to_string(), OwnedValue::try_from(Value::from((1.0, 0.0, 0.0))).unwrap()),
+            ]),
+        );
+        let layers = Layers::from([(
+            "org.freedesktop.appearance".to_string(),
+            HashMap::from([("color-scheme".to_string(), "runtime".to_string())]),
+        )]);
+
+        let (header, rows) = layout(&settings, &layers);
+        assert_eq!(header, "NAMESPACE                   KEY           TYPE   VALUE         LAYER");
+        assert_eq!(
+            rows,
+            [
+                (
+                    "org.freedesktop.appearance  accent-color  (ddd)  (1., 0., 0.)".to_string(),
+                    "-".to_string()
+                ),
+                (
+                    "org.freedesktop.appearance  color-scheme  u      1           ".to_string(),
+                    "runtime".to_string()
+                ),
+            ]
+        );
+    }
+}
@@ -0,0 +1,245 @@
+use crate::output::raw;
+use crate::{value, Client};
+use anyhow::Result;
+use crossterm::event::{Event, EventStream, KeyCode, KeyEvent, KeyEventKind};
+use futures_util::StreamExt;
+use portal_setting::schema;
+use ratatui::layout::{Constraint, Layout};
+use ratatui::style::{Style, Stylize};
+use ratatui::text::Line;
+use ratatui::widgets::{Block, List, ListItem, ListState, Paragraph, Wrap};
+use ratatui::{DefaultTerminal, Frame};
+use std::collections::{BTreeMap, BTreeSet};
+use zbus::zvariant::OwnedValue;
+
+/// Browse and edit settings until the user quits
+///
+/// Values are kept current by following `SettingChanged`, so changes made
+/// elsewhere show up immediately.
+pub async fn run(client: &Client) -> Result<()> {
+    let settings = client.read_all(Vec::new()).await?;
+    let mut app = App {
+        settings,
+        collapsed: BTreeSet::new(),
+        list: ListState::default().with_selected(Some(0)),
+        editing: None,
+        status: "↑/↓ move  ←/→ fold  Enter edit  r reset  q quit".to_string(),
+    };
+
+    let mut terminal = ratatui::init();
+    let result = app.run(&mut terminal, client).await;
+    ratatui::restore();
+    result
+}
+
+enum Row {
+    Namespace(String),
+    Key(String, String),
+}
+
+struct App {
+    settings: BTreeMap<String, BTreeMap<String, OwnedValue>>,
+    /// Namespaces whose keys are hidden
+    collapsed: BTreeSet<String>,
+    list: ListState,
+    /// Text being entered for the selected key
+    editing: Option<String>,
+    status: String,
+}
+
+impl App {
+    async fn run(&mut self, terminal: &mut DefaultTerminal, client: &Client) -> Result<()> {
+        let mut events = EventStream::new();
+        let mut changes = client.portal.receive_setting_changed().await?;
+
+        loop {
+            terminal.draw(|frame| self.draw(frame))?;
+
+            tokio::select! {
+                Some(event) = events.next() => {
+                    if let Event::Key(key) = event? {
+                        if key.kind == KeyEventKind::Press && !self.handle_key(key, client).await {
+                            return Ok(());
+                        }
+                    }
+                }
+                Some(signal) = changes.next() => {
+                    let args = signal.args()?;
+                    let value = args.value().try_clone()?;
+                    self.settings.entry(args.namespace().clone()).or_default().insert(args.key().clone(), value);
+                }
+                else => return Ok(()),
+            }
+        }
+    }
+
+    fn rows(&self) -> Vec<Row> {
+        let mut rows = Vec::new();
+        for (namespace, keys) in &self.settings {
+            rows.push(Row::Namespace(namespace.clone()));
+            if !self.collapsed.contains(namespace) {
+                rows.extend(keys.keys().map(|key| Row::Key(namespace.clone(), key.clone())));
+            }
+        }
+        rows
+    }
+
+    fn selected(&self) -> Option<Row> {
+        self.rows().into_iter().nth(self.list.selected()?)
+    }
+
+    fn value(&self, namespace: &str, key: &str) -> Option<&OwnedValue> {
+        self.settings.get(namespace)?.get(key)
+    }
+
+    /// Returns false when the user asked to quit
+    async fn handle_key(&mut self, key: KeyEvent, client: &Client) -> bool {
+        if let Some(text) = &mut self.editing {
+            match key.code {
+                KeyCode::Char(c) => text.push(c),
+                KeyCode::Backspace => {
+                    text.pop();
+                }
+                KeyCode::Esc => self.editing = None,
+                KeyCode::Enter => self.submit(client).await,
+                _ => {}
+            }
+            return true;
+        }
+
+        let rows = self.rows().len();
+        match key.code {
+            KeyCode::Char('q') | KeyCode::Esc => return false,
+            KeyCode::Up | KeyCode::Char('k') => self.list.select_previous(),
+            KeyCode::Down | KeyCode::Char('j') if self.list.selected().is_some_and(|i| i + 1 < rows) => {
+                self.list.select_next()
+            }
+            KeyCode::Left | KeyCode::Char('h') => self.fold(true),
+            KeyCode::Right | KeyCode::Char('l') => self.fold(false),
+            KeyCode::Enter => match self.selected() {
+                Some(Row::Key(namespace, key)) => {
+                    self.editing = self.value(&namespace, &key).map(|v| raw(v));
+                }
+                Some(Row::Namespace(namespace)) => self.fold(!self.collapsed.contains(&namespace)),
+                None => {}
+            },
+            KeyCode::Char('r') => {
+                if let Some(Row::Key(namespace, key)) = self.selected() {
+                    self.status = match client.reset(&namespace, &key).await {
+                        Ok(_) => format!("Reset {}/{}", namespace, key),
+                        Err(e) => format!("{:#}", e),
+                    };
+                }
+            }
+            _ => {}
+        }
+        true
+    }
+
+    /// Collapse or expand the namespace of the selected row
+    fn fold(&mut self, collapse: bool) {
+        let namespace = match self.selected() {
+            Some(Row::Namespace(namespace)) | Some(Row::Key(namespace, _)) => namespace,
+            None => return,
+        };
+        if collapse {
+            self.collapsed.insert(namespace.clone());
+            let index = self
+                .rows()
+                .iter()
+                .position(|row| matches!(row, Row::Namespace(n) if *n == namespace));
+            self.list.select(index);
+        } else {
+            self.collapsed.remove(&namespace);
+        }
+    }
+
+    async fn submit(&mut self, client: &Client) {
+        let (Some(text), Some(Row::Key(namespace, key))) = (self.editing.take(), self.selected()) else {
+            return;
+        };
+        let Some(current) = self.value(&namespace, &key) else {
+            return;
+        };
+
+        let result = match value::parse_for_signature(&current.value_signature(), &text) {
+            Ok(value) => client.write(&namespace, &key, value).await,
+            Err(e) => Err(e),
+        };
+        self.status = match result {
+            Ok(()) => format!("Changed {}/{}", namespace, key),
+            Err(e) => {
+                // Keep the input so it can be corrected
+                self.editing = Some(text);
+                format!("{:#}", e)
+            }
+        };
+    }
+
+    fn draw(&mut self, frame: &mut Frame) {
+        let [main, status] = Layout::vertical([Constraint::Min(0), Constraint::Length(3)]).areas(frame.area());
+        let [tree, details] =
+            Layout::horizontal([Constraint::Percentage(50), Constraint::Percentage(50)]).areas(main);
+
+        let items: Vec<ListItem> = self
+            .rows()
+            .iter()
+            .map(|row| match row {
+                Row::Namespace(namespace) => {
+                    let marker = if self.collapsed.contains(namespace) { "▸" } else { "▾" };
+                    ListItem::new(Line::from(format!("{} {}", marker, namespace)).bold())
+                }
+                Row::Key(namespace, key) => {
+                    let value = self.value(namespace, key).map(|v| raw(v)).unwrap_or_default();
+                    ListItem::new(format!("    {} = {}", key, value))
+                }
+            })
+            .collect();
+        let list = List::new(items)
+            .block(Block::bordered().title(" Settings "))
+            .highlight_style(Style::new().reversed());
+        frame.render_stateful_widget(list, tree, &mut self.list);
+
+        frame.render_widget(
+            Paragraph::new(self.details())
+                .block(Block::bordered().title(" Details "))
+                .wrap(Wrap { trim: false }),
+            details,
+        );
+
+        let status_line = match &self.editing {
+            Some(text) => Line::from(format!("New value: {}▏", text)).yellow(),
+            None => Line::from(self.status.as_str()),
+        };
+        frame.render_widget(Paragraph::new(status_line).block(Block::bordered()), status);
+    }
+
+    fn details(&self) -> Vec<Line<'static>> {
+        let (namespace, key) = match self.selected() {
+            Some(Row::Key(namespace, key)) => (namespace, key),
+            Some(Row::Namespace(namespace)) => {
+                let count = self.settings.get(&namespace).map_or(0, BTreeMap::len);
+                return vec![
+                    Line::from(namespace).bold(),
+                    Line::from(format!("{} keys", count)),
+                ];
+            }
+            None => return Vec::new(),
+        };
+
+        let mut lines = vec![Line::from(format!("{}/{}", namespace, key)).bold(), Line::from("")];
+        if let Some(value) = self.value(&namespace, &key) {
+            lines.push(Line::from(format!("Type:    {}", value.value_signature())));
+            lines.push(Line::from(format!("Value:   {}", **value)));
+        }
+        match schema::lookup(&namespace, &key) {
+            Some(setting) => {
+                lines.push(Line::from(format!("Default: {}", *setting.default.to_value())));
+                lines.push(Line::from(""));
+                lines.push(Line::from(setting.description));
+            }
+            None => lines.push(Line::from("Not part of the built-in schema").italic()),
+        }
+        lines
+    }
+}
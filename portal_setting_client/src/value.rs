@@ -0,0 +1,137 @@
+use anyhow::{anyhow, bail, Context, Result};
+use clap::ValueEnum;
+use portal_setting::state::toml_to_typed;
+use zbus::zvariant::{OwnedValue, Value};
+
+/// Explicit types for `set --type`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum ValueType {
+    U32,
+    I32,
+    Bool,
+    String,
+    Double,
+    /// `(ddd)` RGB triple, given as `#RRGGBB` or `r,g,b` with components in 0..1
+    Color,
+}
+
+impl ValueType {
+    fn signature(self) -> &'static str {
+        match self {
+            ValueType::U32 => "u",
+            ValueType::I32 => "i",
+            ValueType::Bool => "b",
+            ValueType::String => "s",
+            ValueType::Double => "d",
+            ValueType::Color => "(ddd)",
+        }
+    }
+}
+
+/// Parse `text` as `ty`
+pub fn parse_typed(ty: ValueType, text: &str) -> Result<OwnedValue> {
+    parse_for_signature(ty.signature(), text)
+}
+
+/// Parse `text` as a value of `signature`
+///
+/// Basic types take their plain text form, `(ddd)` also accepts colors;
+/// anything else is read as a TOML value of that signature.
+pub fn parse_for_signature(signature: &str, text: &str) -> Result<OwnedValue> {
+    let value = match signature {
+        "s" => Value::from(text.to_string()),
+        "b" => Value::Bool(parse_bool(text)?),
+        "u" => Value::U32(text.trim().parse().with_context(|| format!("{:?} is not a u32", text))?),
+        "i" => Value::I32(text.trim().parse().with_context(|| format!("{:?} is not an i32", text))?),
+        "d" => Value::F64(text.trim().parse().with_context(|| format!("{:?} is not a double", text))?),
+        "(ddd)" => Value::from(parse_color(text)?),
+        _ => {
+            let toml = text
+                .parse::<toml::Value>()
+                .with_context(|| format!("{:?} is not a TOML value", text))?;
+            toml_to_typed(signature, &toml)?
+        }
+    };
+    Ok(value.try_into()?)
+}
+
+fn parse_bool(text: &str) -> Result<bool> {
+    match text.trim() {
+        "true" | "1" | "yes" | "on" => Ok(true),
+        "false" | "0" | "no" | "off" => Ok(false),
+        _ => bail!("{:?} is not a boolean", text),
+    }
+}
+
+/// `#RRGGBB`, `r,g,b` or `[r, g, b]` as RGB components in 0..1
+pub fn parse_color(text: &str) -> Result<(f64, f64, f64)> {
+    let text = text.trim();
+    if let Some(hex) = text.strip_prefix('#') {
+        if hex.len() != 6 || !hex.chars().all(|c| c.is_ascii_hexdigit()) {
+            bail!("{:?} is not a #RRGGBB color", text);
+        }
+        let channel = |i: usize| u8::from_str_radix(&hex[i..i + 2], 16).map(|c| f64::from(c) / 255.0);
+        return Ok((channel(0)?, channel(2)?, channel(4)?));
+    }
+
+    let components: Vec<f64> = text
+        .trim_start_matches('[')
+        .trim_end_matches(']')
+        .split(',')
+        .map(|c| c.trim().parse::<f64>())
+        .collect::<Result<_, _>>()
+        .map_err(|_| anyhow!("{:?} is not a color (#RRGGBB or r,g,b)", text))?;
+    match components[..] {
+        [r, g, b] => Ok((r, g, b)),
+        _ => bail!("{:?} is not a color (#RRGGBB or r,g,b)", text),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_color() {
+        assert_eq!(parse_color("#FF0080").unwrap(), (1.0, 0.0, 128.0 / 255.0));
+        assert_eq!(parse_color(" #ffffff ").unwrap(), (1.0, 1.0, 1.0));
+        assert_eq!(parse_color("0.2, 0.4,1").unwrap(), (0.2, 0.4, 1.0));
+        assert_eq!(parse_color("[0, 0.5, 1]").unwrap(), (0.0, 0.5, 1.0));
+
+        assert!(parse_color("#FFF").is_err());
+        assert!(parse_color("#GG0000").is_err());
+        assert!(parse_color("0.2,0.4").is_err());
+        assert!(parse_color("red").is_err());
+    }
+
+    #[test]
+    fn test_parse_for_signature() {
+        assert_eq!(parse_for_signature("s", " 24h ").unwrap(), OwnedValue::try_from(Value::from(" 24h ")).unwrap());
+        assert_eq!(parse_for_signature("u", " 7").unwrap(), OwnedValue::from(7u32));
+        assert_eq!(parse_for_signature("i", "-1").unwrap(), OwnedValue::from(-1i32));
+        assert_eq!(parse_for_signature("d", "1.5").unwrap(), OwnedValue::from(1.5f64));
+        assert_eq!(parse_for_signature("b", "on").unwrap(), OwnedValue::from(true));
+        assert_eq!(parse_for_signature("b", "0").unwrap(), OwnedValue::from(false));
+        let color = parse_for_signature("(ddd)", "#000000").unwrap();
+        assert_eq!(color.value_signature().as_str(), "(ddd)");
+        let list = parse_for_signature("as", r#"["a", "b"]"#).unwrap();
+        assert_eq!(list.value_signature().as_str(), "as");
+        // As `set` infers the type from the current value
+        let current = parse_for_signature("a{sd}", "{ scale = 1.0 }").unwrap();
+        let value = parse_for_signature(&current.value_signature(), "{ scale = 2 }").unwrap();
+        assert_eq!(value.value_signature(), current.value_signature());
+
+        assert!(parse_for_signature("u", "-1").is_err());
+        assert!(parse_for_signature("i", "1.5").is_err());
+        assert!(parse_for_signature("b", "maybe").is_err());
+        assert!(parse_for_signature("as", "[1, 2]").is_err());
+    }
+
+    #[test]
+    fn test_parse_typed() {
+        assert_eq!(parse_typed(ValueType::U32, "3").unwrap(), OwnedValue::from(3u32));
+        assert_eq!(parse_typed(ValueType::Bool, "yes").unwrap(), OwnedValue::from(true));
+        let color = parse_typed(ValueType::Color, "1,0,0").unwrap();
+        assert_eq!(<(f64, f64, f64)>::try_from(Value::from(color)).unwrap(), (1.0, 0.0, 0.0));
+    }
+}
@@ -0,0 +1,296 @@
+/*
+ * C API of the XDG Portal Settings store and of clients of a running service
+ *
+ * Link against libportal_setting_ffi. Values are passed as a D-Bus signature
+ * and the value in TOML syntax, e.g. "u" and "1", "s" and "\"Adwaita\"" or
+ * "(ddd)" and "[0.2, 0.4, 0.6]". Functions returning int return
+ * PORTAL_SETTING_OK or a negative status; portal_setting_last_error()
+ * describes the failure.
+ */
+
+#ifndef PORTAL_SETTING_H
+#define PORTAL_SETTING_H
+
+/* Generated by cbindgen from portal_setting_ffi; do not edit */
+
+#include <stdint.h>
+
+/**
+ * Version of this API; incremented on incompatible changes
+ */
+#define PORTAL_SETTING_ABI_VERSION 1
+
+#define PORTAL_SETTING_OK 0
+
+/**
+ * Failures not covered below, including invalid arguments
+ */
+#define PORTAL_SETTING_ERROR -1
+
+#define PORTAL_SETTING_NOT_FOUND -2
+
+#define PORTAL_SETTING_INVALID_VALUE -3
+
+/**
+ * The bus or the service cannot be reached
+ */
+#define PORTAL_SETTING_UNAVAILABLE -4
+
+/**
+ * The setting cannot be changed
+ */
+#define PORTAL_SETTING_READ_ONLY -5
+
+/**
+ * Connection to the service, created by `portal_setting_client_new`
+ */
+typedef struct PortalSettingClient PortalSettingClient;
+
+/**
+ * A settings store in the calling process, created by
+ * `portal_setting_store_new`
+ */
+typedef struct PortalSettingStore PortalSettingStore;
+
+/**
+ * Called for every changed setting; `signature` and `value` are NULL when
+ * the key no longer exists
+ */
+typedef void (*PortalSettingChangeFn)(void *user_data,
+                                      const char *namespace_,
+                                      const char *key,
+                                      const char *signature,
+                                      const char *value);
+
+#ifdef __cplusplus
+extern "C" {
+#endif // __cplusplus
+
+/**
+ * Release a string returned by this library
+ *
+ * # Safety
+ *
+ * `string` must be NULL or returned by this library, and not yet released.
+ */
+void portal_setting_string_free(char *string);
+
+/**
+ * `PORTAL_SETTING_ABI_VERSION` of the loaded library
+ */
+uint32_t portal_setting_abi_version(void);
+
+/**
+ * Message of the last failure on the calling thread, or NULL
+ *
+ * The string stays valid until the next failing call on the same thread.
+ */
+const char *portal_setting_last_error(void);
+
+/**
+ * Connect to the service on the session bus
+ *
+ * `bus_name` may be NULL for the default name. Returns NULL on failure.
+ *
+ * # Safety
+ *
+ * `bus_name` must be NULL or a NUL-terminated string.
+ */
+struct PortalSettingClient *portal_setting_client_new(const char *bus_name);
+
+/**
+ * Disconnect and release `client`, ending all subscriptions
+ *
+ * # Safety
+ *
+ * `client` must be NULL or returned by `portal_setting_client_new`, and must not
+ * be used afterwards. Must not be called from a change callback.
+ */
+void portal_setting_client_free(struct PortalSettingClient *client);
+
+/**
+ * Read a setting as its signature and TOML text
+ *
+ * Both strings must be released with `portal_setting_string_free`.
+ *
+ * # Safety
+ *
+ * `client` must come from `portal_setting_client_new`, the strings must be
+ * NUL-terminated and the output pointers valid for writes.
+ */
+int portal_setting_client_read(const struct PortalSettingClient *client,
+                               const char *namespace_,
+                               const char *key,
+                               char **signature,
+                               char **value);
+
+/**
+ * Change a setting
+ *
+ * `value` is TOML text of type `signature`; with a NULL `signature` the
+ * setting's current type is used.
+ *
+ * # Safety
+ *
+ * `client` must come from `portal_setting_client_new` and the strings must be
+ * NULL (`signature` only) or NUL-terminated.
+ */
+int portal_setting_client_write(const struct PortalSettingClient *client,
+                                const char *namespace_,
+                                const char *key,
+                                const char *signature,
+                                const char *value);
+
+/**
+ * Read `org.freedesktop.appearance` `color-scheme`: 0 for no preference,
+ * 1 for dark and 2 for light
+ *
+ * # Safety
+ *
+ * `client` must come from `portal_setting_client_new` and `scheme` be valid for
+ * writes.
+ */
+int portal_setting_client_color_scheme(const struct PortalSettingClient *client, uint32_t *scheme);
+
+/**
+ * Read `org.freedesktop.appearance` `contrast`: 0 for no preference, 1 for
+ * high contrast
+ *
+ * # Safety
+ *
+ * `client` must come from `portal_setting_client_new` and `contrast` be valid for
+ * writes.
+ */
+int portal_setting_client_contrast(const struct PortalSettingClient *client, uint32_t *contrast);
+
+/**
+ * Read `org.freedesktop.appearance` `accent-color` as components between 0
+ * and 1, returning `PORTAL_SETTING_NOT_FOUND` if it is unset
+ *
+ * # Safety
+ *
+ * `client` must come from `portal_setting_client_new` and the output pointers be
+ * valid for writes.
+ */
+int portal_setting_client_accent_color(const struct PortalSettingClient *client,
+                                       double *red,
+                                       double *green,
+                                       double *blue);
+
+/**
+ * Call `callback` for every changed setting until unsubscribed
+ *
+ * Callbacks run on a thread owned by the client. Returns a subscription id
+ * greater than 0, or a negative status.
+ *
+ * # Safety
+ *
+ * `client` must come from `portal_setting_client_new`; `callback` must be safe to
+ * call from another thread with `user_data` until unsubscribed. Callbacks must
+ * not call client functions other than `portal_setting_client_unsubscribe`: the
+ * others block on the client's runtime, which panics on its own thread.
+ */
+int64_t portal_setting_client_subscribe(const struct PortalSettingClient *client,
+                                        PortalSettingChangeFn callback,
+                                        void *user_data);
+
+/**
+ * End a subscription; the callback is not called after this returns
+ *
+ * A callback still running is waited for, so `user_data` may be released
+ * afterwards. Called from a callback, it returns right away: all callbacks
+ * run on one thread, so no other can be running.
+ *
+ * # Safety
+ *
+ * `client` must come from `portal_setting_client_new`.
+ */
+int portal_setting_client_unsubscribe(const struct PortalSettingClient *client,
+                                      int64_t subscription);
+
+/**
+ * Create a store with the built-in defaults, or the defaults and settings
+ * of the configuration file at `config_path`
+ *
+ * Returns NULL on failure.
+ *
+ * # Safety
+ *
+ * `config_path` must be NULL or a NUL-terminated string.
+ */
+struct PortalSettingStore *portal_setting_store_new(const char *config_path);
+
+/**
+ * Release `store`
+ *
+ * # Safety
+ *
+ * `store` must be NULL or returned by `portal_setting_store_new`, and must
+ * not be used afterwards.
+ */
+void portal_setting_store_free(struct PortalSettingStore *store);
+
+/**
+ * Read a setting as its signature and TOML text
+ *
+ * Both strings must be released with `portal_setting_string_free`.
+ *
+ * # Safety
+ *
+ * `store` must come from `portal_setting_store_new`, the strings must be
+ * NUL-terminated and the output pointers valid for writes.
+ */
+int portal_setting_store_read(const struct PortalSettingStore *store,
+                              const char *namespace_,
+                              const char *key,
+                              char **signature,
+                              char **value);
+
+/**
+ * Validate and store a setting
+ *
+ * `value` is TOML text of type `signature`; with a NULL `signature` the
+ * setting's current type is used. Subscribers are called before this
+ * returns, on the calling thread.
+ *
+ * # Safety
+ *
+ * `store` must come from `portal_setting_store_new` and the strings must be
+ * NULL (`signature` only) or NUL-terminated.
+ */
+int portal_setting_store_write(const struct PortalSettingStore *store,
+                               const char *namespace_,
+                               const char *key,
+                               const char *signature,
+                               const char *value);
+
+/**
+ * Call `callback` for every change of the store until unsubscribed
+ *
+ * Callbacks run on the thread that made the change. Returns a subscription
+ * id greater than 0, or a negative status.
+ *
+ * # Safety
+ *
+ * `store` must come from `portal_setting_store_new`; `callback` must be safe
+ * to call from any thread with `user_data` until unsubscribed.
+ */
+int64_t portal_setting_store_subscribe(const struct PortalSettingStore *store,
+                                       PortalSettingChangeFn callback,
+                                       void *user_data);
+
+/**
+ * End a subscription; the callback is not called after this returns
+ * unless it is already running
+ *
+ * # Safety
+ *
+ * `store` must come from `portal_setting_store_new`.
+ */
+int portal_setting_store_unsubscribe(const struct PortalSettingStore *store, int64_t subscription);
+
+#ifdef __cplusplus
+}  // extern "C"
+#endif  // __cplusplus
+
+#endif  /* PORTAL_SETTING_H */
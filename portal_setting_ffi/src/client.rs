@@ -0,0 +1,407 @@
+use crate::{
+    from_text, out, status, str_arg, to_text, Error, PortalSettingChangeFn, Result, UserData, PORTAL_SETTING_ERROR,
+    PORTAL_SETTING_NOT_FOUND, PORTAL_SETTING_OK,
+};
+use futures_util::StreamExt;
+use portal_setting_proxy::{ImplSettingsProxy, ManagementProxy, SERVICE_BUS_NAME};
+use std::collections::HashMap;
+use std::ffi::{c_char, c_int, c_void, CString};
+use std::sync::atomic::{AtomicBool, AtomicI64, Ordering};
+use std::sync::{Arc, Mutex};
+use tokio::runtime::Runtime;
+use tokio::task::JoinHandle;
+use zbus::zvariant::Value;
+use zbus::Connection;
+
+/// Connection to the service, created by `portal_setting_client_new`
+pub struct PortalSettingClient {
+    runtime: Runtime,
+    portal: ImplSettingsProxy<'static>,
+    management: ManagementProxy<'static>,
+    subscriptions: Mutex<HashMap<i64, Subscription>>,
+    next_subscription: AtomicI64,
+}
+
+/// Task forwarding signals to a C callback until unsubscribed
+struct Subscription {
+    task: JoinHandle<()>,
+    /// Cleared on unsubscribing, for the task may go on to the next signal
+    /// before it notices the abort
+    active: Arc<AtomicBool>,
+}
+
+impl PortalSettingClient {
+    fn new(runtime: Runtime, connection: &Connection, bus_name: &str) -> Result<Self> {
+        let (portal, management) = runtime.block_on(async {
+            let portal = ImplSettingsProxy::builder(connection).destination(bus_name.to_string())?.build().await?;
+            let management = ManagementProxy::builder(connection).destination(bus_name.to_string())?.build().await?;
+            Ok::<_, zbus::Error>((portal, management))
+        })?;
+        Ok(Self {
+            runtime,
+            portal,
+            management,
+            subscriptions: Mutex::new(HashMap::new()),
+            next_subscription: AtomicI64::new(1),
+        })
+    }
+
+    fn read(&self, namespace: &str, key: &str) -> Result<Value<'static>> {
+        let value = self.runtime.block_on(self.portal.read(namespace, key))?;
+        Ok(Value::from(value))
+    }
+}
+
+unsafe fn client<'a>(client: *const PortalSettingClient) -> Result<&'a PortalSettingClient> {
+    client.as_ref().ok_or_else(|| Error::new(PORTAL_SETTING_ERROR, "client is NULL"))
+}
+
+/// Connect to the service on the session bus
+///
+/// `bus_name` may be NULL for the default name. Returns NULL on failure.
+///
+/// # Safety
+///
+/// `bus_name` must be NULL or a NUL-terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn portal_setting_client_new(bus_name: *const c_char) -> *mut PortalSettingClient {
+    let mut client = None;
+    status((|| {
+        let bus_name = match bus_name.is_null() {
+            true => SERVICE_BUS_NAME,
+            false => str_arg(bus_name, "bus_name")?,
+        };
+        let runtime = tokio::runtime::Builder::new_multi_thread()
+            .worker_threads(1)
+            .enable_all()
+            .build()
+            .map_err(|e| Error::new(PORTAL_SETTING_ERROR, e.to_string()))?;
+        let connection = runtime.block_on(Connection::session())?;
+        client = Some(PortalSettingClient::new(runtime, &connection, bus_name)?);
+        Ok(())
+    })());
+    client.map_or(std::ptr::null_mut(), |c| Box::into_raw(Box::new(c)))
+}
+
+/// Disconnect and release `client`, ending all subscriptions
+///
+/// # Safety
+///
+/// `client` must be NULL or returned by `portal_setting_client_new`, and must not
+/// be used afterwards. Must not be called from a change callback.
+#[no_mangle]
+pub unsafe extern "C" fn portal_setting_client_free(client: *mut PortalSettingClient) {
+    if !client.is_null() {
+        drop(Box::from_raw(client));
+    }
+}
+
+/// Read a setting as its signature and TOML text
+///
+/// Both strings must be released with `portal_setting_string_free`.
+///
+/// # Safety
+///
+/// `client` must come from `portal_setting_client_new`, the strings must be
+/// NUL-terminated and the output pointers valid for writes.
+#[no_mangle]
+pub unsafe extern "C" fn portal_setting_client_read(
+    client: *const PortalSettingClient,
+    namespace: *const c_char,
+    key: *const c_char,
+    signature: *mut *mut c_char,
+    value: *mut *mut c_char,
+) -> c_int {
+    status((|| {
+        let client = self::client(client)?;
+        let current = client.read(str_arg(namespace, "namespace")?, str_arg(key, "key")?)?;
+        let (sig, text) = to_text(&current)?;
+        if signature.is_null() || value.is_null() {
+            return Err(Error::new(PORTAL_SETTING_ERROR, "output pointer is NULL"));
+        }
+        out(signature, sig.into_raw())?;
+        out(value, text.into_raw())
+    })())
+}
+
+/// Change a setting
+///
+/// `value` is TOML text of type `signature`; with a NULL `signature` the
+/// setting's current type is used.
+///
+/// # Safety
+///
+/// `client` must come from `portal_setting_client_new` and the strings must be
+/// NULL (`signature` only) or NUL-terminated.
+#[no_mangle]
+pub unsafe extern "C" fn portal_setting_client_write(
+    client: *const PortalSettingClient,
+    namespace: *const c_char,
+    key: *const c_char,
+    signature: *const c_char,
+    value: *const c_char,
+) -> c_int {
+    status((|| {
+        let client = self::client(client)?;
+        let (namespace, key) = (str_arg(namespace, "namespace")?, str_arg(key, "key")?);
+        let text = str_arg(value, "value")?;
+        let signature = match signature.is_null() {
+            true => client.read(namespace, key)?.value_signature().to_string(),
+            false => str_arg(signature, "signature")?.to_string(),
+        };
+
+        let value = from_text(namespace, key, &signature, text)?;
+        client.runtime.block_on(client.management.write(namespace, key, &value))?;
+        Ok(())
+    })())
+}
+
+/// Read `org.freedesktop.appearance` `color-scheme`: 0 for no preference,
+/// 1 for dark and 2 for light
+///
+/// # Safety
+///
+/// `client` must come from `portal_setting_client_new` and `scheme` be valid for
+/// writes.
+#[no_mangle]
+pub unsafe extern "C" fn portal_setting_client_color_scheme(
+    client: *const PortalSettingClient,
+    scheme: *mut u32,
+) -> c_int {
+    status((|| {
+        let client = self::client(client)?;
+        let value = client.runtime.block_on(client.portal.color_scheme())?;
+        out(scheme, value.into())
+    })())
+}
+
+/// Read `org.freedesktop.appearance` `contrast`: 0 for no preference, 1 for
+/// high contrast
+///
+/// # Safety
+///
+/// `client` must come from `portal_setting_client_new` and `contrast` be valid for
+/// writes.
+#[no_mangle]
+pub unsafe extern "C" fn portal_setting_client_contrast(
+    client: *const PortalSettingClient,
+    contrast: *mut u32,
+) -> c_int {
+    status((|| {
+        let client = self::client(client)?;
+        let value = client.runtime.block_on(client.portal.contrast())?;
+        out(contrast, value.into())
+    })())
+}
+
+/// Read `org.freedesktop.appearance` `accent-color` as components between 0
+/// and 1, returning `PORTAL_SETTING_NOT_FOUND` if it is unset
+///
+/// # Safety
+///
+/// `client` must come from `portal_setting_client_new` and the output pointers be
+/// valid for writes.
+#[no_mangle]
+pub unsafe extern "C" fn portal_setting_client_accent_color(
+    client: *const PortalSettingClient,
+    red: *mut f64,
+    green: *mut f64,
+    blue: *mut f64,
+) -> c_int {
+    status((|| {
+        let client = self::client(client)?;
+        let Some(color) = client.runtime.block_on(client.portal.accent_color())? else {
+            return Err(Error::new(PORTAL_SETTING_NOT_FOUND, "the accent color is unset"));
+        };
+        out(red, color.red)?;
+        out(green, color.green)?;
+        out(blue, color.blue)
+    })())
+}
+
+/// Call `callback` for every changed setting until unsubscribed
+///
+/// Callbacks run on a thread owned by the client. Returns a subscription id
+/// greater than 0, or a negative status.
+///
+/// # Safety
+///
+/// `client` must come from `portal_setting_client_new`; `callback` must be safe to
+/// call from another thread with `user_data` until unsubscribed. Callbacks must
+/// not call client functions other than `portal_setting_client_unsubscribe`: the
+/// others block on the client's runtime, which panics on its own thread.
+#[no_mangle]
+pub unsafe extern "C" fn portal_setting_client_subscribe(
+    client: *const PortalSettingClient,
+    callback: PortalSettingChangeFn,
+    user_data: *mut c_void,
+) -> i64 {
+    let mut id = 0;
+    let result = status((|| {
+        let client = self::client(client)?;
+        let callback = callback.ok_or_else(|| Error::new(PORTAL_SETTING_ERROR, "callback is NULL"))?;
+        let user_data = UserData(user_data);
+
+        // Subscribe before returning, so no change after this call is missed
+        let mut changes = client.runtime.block_on(client.portal.receive_setting_changed())?;
+        let active = Arc::new(AtomicBool::new(true));
+        let running = active.clone();
+        let task = client.runtime.spawn(async move {
+            while let Some(signal) = changes.next().await {
+                if !running.load(Ordering::Acquire) {
+                    break;
+                }
+                let Ok(args) = signal.args() else {
+                    continue;
+                };
+                let (namespace, key) = (CString::new(args.namespace().as_str()), CString::new(args.key().as_str()));
+                let (Ok(namespace), Ok(key), Ok((signature, value))) = (namespace, key, to_text(args.value())) else {
+                    continue;
+                };
+                // SAFETY: guaranteed by the caller of `portal_setting_client_subscribe`
+                unsafe {
+                    callback(user_data.get(), namespace.as_ptr(), key.as_ptr(), signature.as_ptr(), value.as_ptr())
+                };
+            }
+        });
+
+        id = client.next_subscription.fetch_add(1, Ordering::Relaxed);
+        client.subscriptions.lock().unwrap().insert(id, Subscription { task, active });
+        Ok(())
+    })());
+    match result {
+        PORTAL_SETTING_OK => id,
+        status => status.into(),
+    }
+}
+
+/// End a subscription; the callback is not called after this returns
+///
+/// A callback still running is waited for, so `user_data` may be released
+/// afterwards. Called from a callback, it returns right away: all callbacks
+/// run on one thread, so no other can be running.
+///
+/// # Safety
+///
+/// `client` must come from `portal_setting_client_new`.
+#[no_mangle]
+pub unsafe extern "C" fn portal_setting_client_unsubscribe(
+    client: *const PortalSettingClient,
+    subscription: i64,
+) -> c_int {
+    status((|| {
+        let client = self::client(client)?;
+        let removed = client.subscriptions.lock().unwrap().remove(&subscription);
+        match removed {
+            Some(Subscription { task, active }) => {
+                active.store(false, Ordering::Release);
+                task.abort();
+                // Blocking on the runtime from its own thread would panic
+                if tokio::runtime::Handle::try_current().is_err() {
+                    let _ = client.runtime.block_on(task);
+                }
+                Ok(())
+            }
+            None => Err(Error::new(PORTAL_SETTING_ERROR, format!("no subscription {}", subscription))),
+        }
+    })())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{portal_setting_last_error, portal_setting_string_free, PORTAL_SETTING_INVALID_VALUE};
+    use portal_setting::testing::TestPortal;
+    use portal_setting::SettingsStore;
+    use std::ffi::CStr;
+    use std::time::{Duration, Instant};
+
+    static CHANGES: Mutex<Vec<String>> = Mutex::new(Vec::new());
+
+    unsafe extern "C" fn record_change(
+        _user_data: *mut c_void,
+        namespace: *const c_char,
+        key: *const c_char,
+        signature: *const c_char,
+        value: *const c_char,
+    ) {
+        let s = |p: *const c_char| CStr::from_ptr(p).to_string_lossy().into_owned();
+        CHANGES.lock().unwrap().push([namespace, key, signature, value].map(s).join(" "));
+    }
+
+    /// A client of a store served over a private socket
+    fn client() -> (TestPortal, Box<PortalSettingClient>) {
+        let runtime = tokio::runtime::Builder::new_multi_thread().worker_threads(1).enable_all().build().unwrap();
+        let portal = runtime.block_on(TestPortal::p2p(SettingsStore::new())).unwrap();
+        let client = PortalSettingClient::new(runtime, &portal.client, SERVICE_BUS_NAME).ok().unwrap();
+        (portal, Box::new(client))
+    }
+
+    #[test]
+    fn test_get_set_subscribe() {
+        let (_portal, client) = client();
+        let client: *const PortalSettingClient = &*client;
+        unsafe {
+            let mut scheme = 7;
+            assert_eq!(portal_setting_client_color_scheme(client, &mut scheme), PORTAL_SETTING_OK);
+            assert_eq!(scheme, 0);
+
+            let subscription = portal_setting_client_subscribe(client, Some(record_change), std::ptr::null_mut());
+            assert!(subscription > 0);
+            assert_eq!(
+                portal_setting_client_write(
+                    client,
+                    c"org.freedesktop.appearance".as_ptr(),
+                    c"color-scheme".as_ptr(),
+                    std::ptr::null(),
+                    c"1".as_ptr()
+                ),
+                PORTAL_SETTING_OK
+            );
+            assert_eq!(
+                portal_setting_client_write(
+                    client,
+                    c"org.freedesktop.appearance".as_ptr(),
+                    c"color-scheme".as_ptr(),
+                    c"u".as_ptr(),
+                    c"9".as_ptr()
+                ),
+                PORTAL_SETTING_INVALID_VALUE
+            );
+            assert!(!portal_setting_last_error().is_null());
+
+            let (mut signature, mut value) = (std::ptr::null_mut(), std::ptr::null_mut());
+            let status = portal_setting_client_read(
+                client,
+                c"org.gnome.desktop.interface".as_ptr(),
+                c"gtk-theme".as_ptr(),
+                &mut signature,
+                &mut value,
+            );
+            assert_eq!(status, PORTAL_SETTING_OK);
+            assert_eq!(CStr::from_ptr(signature), c"s");
+            assert_eq!(CStr::from_ptr(value), c"\"Adwaita\"");
+            portal_setting_string_free(signature);
+            portal_setting_string_free(value);
+
+            assert_eq!(
+                portal_setting_client_read(
+                    client,
+                    c"org.example".as_ptr(),
+                    c"missing".as_ptr(),
+                    &mut signature,
+                    &mut value
+                ),
+                PORTAL_SETTING_NOT_FOUND
+            );
+
+            let deadline = Instant::now() + Duration::from_secs(5);
+            while CHANGES.lock().unwrap().is_empty() && Instant::now() < deadline {
+                std::thread::sleep(Duration::from_millis(10));
+            }
+            assert_eq!(*CHANGES.lock().unwrap(), ["org.freedesktop.appearance color-scheme u 1"]);
+            assert_eq!(portal_setting_client_unsubscribe(client, subscription), PORTAL_SETTING_OK);
+            assert_eq!(portal_setting_client_unsubscribe(client, subscription), PORTAL_SETTING_ERROR);
+        }
+    }
+}
@@ -0,0 +1,179 @@
+//! Stable C API for the settings store and for clients of a running
+//! service, for C/C++ components, dart:ffi and other foreign function
+//! interfaces
+//!
+//! `include/portal-setting.h` is generated from this crate with cbindgen.
+//! Like the plugin ABI, values cross the boundary as a D-Bus signature and
+//! the value in TOML syntax, e.g. `u` and `1`, `s` and `"Adwaita"` or
+//! `(ddd)` and `[0.2, 0.4, 0.6]`.
+
+use portal_setting::state::{toml_to_typed, value_to_toml};
+use portal_setting_proxy::ErrorKind;
+use std::cell::RefCell;
+use std::ffi::{c_char, c_int, c_void, CStr, CString};
+use zbus::zvariant::Value;
+
+mod client;
+mod store;
+
+pub use client::*;
+pub use store::*;
+
+/// Version of this API; incremented on incompatible changes
+pub const PORTAL_SETTING_ABI_VERSION: u32 = 1;
+
+pub const PORTAL_SETTING_OK: c_int = 0;
+/// Failures not covered below, including invalid arguments
+pub const PORTAL_SETTING_ERROR: c_int = -1;
+pub const PORTAL_SETTING_NOT_FOUND: c_int = -2;
+pub const PORTAL_SETTING_INVALID_VALUE: c_int = -3;
+/// The bus or the service cannot be reached
+pub const PORTAL_SETTING_UNAVAILABLE: c_int = -4;
+/// The setting cannot be changed
+pub const PORTAL_SETTING_READ_ONLY: c_int = -5;
+
+/// Called for every changed setting; `signature` and `value` are NULL when
+/// the key no longer exists
+pub type PortalSettingChangeFn = Option<
+    unsafe extern "C" fn(
+        user_data: *mut c_void,
+        namespace_: *const c_char,
+        key: *const c_char,
+        signature: *const c_char,
+        value: *const c_char,
+    ),
+>;
+
+thread_local! {
+    static LAST_ERROR: RefCell<Option<CString>> = const { RefCell::new(None) };
+}
+
+/// A failed call, with the status returned to C
+pub(crate) struct Error {
+    status: c_int,
+    message: String,
+}
+
+impl Error {
+    pub(crate) fn new(status: c_int, message: impl Into<String>) -> Self {
+        Self {
+            status,
+            message: message.into(),
+        }
+    }
+}
+
+impl From<zbus::Error> for Error {
+    fn from(error: zbus::Error) -> Self {
+        let status = match ErrorKind::of(&error) {
+            ErrorKind::NotFound => PORTAL_SETTING_NOT_FOUND,
+            ErrorKind::InvalidValue => PORTAL_SETTING_INVALID_VALUE,
+            ErrorKind::Unavailable => PORTAL_SETTING_UNAVAILABLE,
+            ErrorKind::ReadOnly => PORTAL_SETTING_READ_ONLY,
+            ErrorKind::Other => PORTAL_SETTING_ERROR,
+        };
+        Self::new(status, error.to_string())
+    }
+}
+
+pub(crate) type Result<T> = std::result::Result<T, Error>;
+
+/// Record the error of `result` for `portal_setting_last_error`
+pub(crate) fn status(result: Result<()>) -> c_int {
+    match result {
+        Ok(()) => PORTAL_SETTING_OK,
+        Err(e) => {
+            let message = CString::new(e.message.replace('\0', "")).ok();
+            LAST_ERROR.with(|last| *last.borrow_mut() = message);
+            e.status
+        }
+    }
+}
+
+pub(crate) unsafe fn str_arg<'a>(arg: *const c_char, name: &str) -> Result<&'a str> {
+    if arg.is_null() {
+        return Err(Error::new(PORTAL_SETTING_ERROR, format!("{} is NULL", name)));
+    }
+    CStr::from_ptr(arg).to_str().map_err(|_| Error::new(PORTAL_SETTING_ERROR, format!("{} is not UTF-8", name)))
+}
+
+pub(crate) unsafe fn out<T>(out: *mut T, value: T) -> Result<()> {
+    if out.is_null() {
+        return Err(Error::new(PORTAL_SETTING_ERROR, "output pointer is NULL"));
+    }
+    out.write(value);
+    Ok(())
+}
+
+/// A value as its signature and TOML text
+pub(crate) fn to_text(value: &Value<'_>) -> Result<(CString, CString)> {
+    let text = value_to_toml(value).map_err(|e| Error::new(PORTAL_SETTING_ERROR, format!("{:#}", e)))?;
+    let cstring =
+        |s: String| CString::new(s).map_err(|_| Error::new(PORTAL_SETTING_ERROR, "value contains a NUL byte"));
+    Ok((cstring(value.value_signature().to_string())?, cstring(text.to_string())?))
+}
+
+/// Parse TOML `text` as a value of `signature`
+pub(crate) fn from_text(namespace: &str, key: &str, signature: &str, text: &str) -> Result<Value<'static>> {
+    let invalid = |e: String| Error::new(PORTAL_SETTING_INVALID_VALUE, format!("{}/{}: {}", namespace, key, e));
+    let toml = text.parse::<toml::Value>().map_err(|e| invalid(e.to_string()))?;
+    toml_to_typed(signature, &toml).map_err(|e| invalid(format!("{:#}", e)))
+}
+
+/// `user_data` of a subscription
+pub(crate) struct UserData(*mut c_void);
+
+// SAFETY: subscribers accept calls from another thread
+unsafe impl Send for UserData {}
+unsafe impl Sync for UserData {}
+
+impl UserData {
+    pub(crate) fn get(&self) -> *mut c_void {
+        self.0
+    }
+}
+
+/// Release a string returned by this library
+///
+/// # Safety
+///
+/// `string` must be NULL or returned by this library, and not yet released.
+#[no_mangle]
+pub unsafe extern "C" fn portal_setting_string_free(string: *mut c_char) {
+    if !string.is_null() {
+        drop(CString::from_raw(string));
+    }
+}
+
+/// `PORTAL_SETTING_ABI_VERSION` of the loaded library
+#[no_mangle]
+pub extern "C" fn portal_setting_abi_version() -> u32 {
+    PORTAL_SETTING_ABI_VERSION
+}
+
+/// Message of the last failure on the calling thread, or NULL
+///
+/// The string stays valid until the next failing call on the same thread.
+#[no_mangle]
+pub extern "C" fn portal_setting_last_error() -> *const c_char {
+    LAST_ERROR.with(|last| last.borrow().as_ref().map_or(std::ptr::null(), |e| e.as_ptr()))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    #[test]
+    fn test_header_is_current() {
+        let crate_dir = env!("CARGO_MANIFEST_DIR");
+        let path = Path::new(crate_dir).join("include/portal-setting.h");
+        let mut generated = Vec::new();
+        cbindgen::generate(crate_dir).unwrap().write(&mut generated);
+
+        if std::env::var_os("PORTAL_SETTING_UPDATE_HEADER").is_some() {
+            std::fs::write(&path, &generated).unwrap();
+        }
+        let current = std::fs::read(&path).unwrap();
+        assert!(current == generated, "include/portal-setting.h is outdated, run with PORTAL_SETTING_UPDATE_HEADER=1");
+    }
+}
@@ -0,0 +1,307 @@
+use crate::{
+    from_text, out, status, str_arg, to_text, Error, PortalSettingChangeFn, Result, UserData, PORTAL_SETTING_ERROR,
+    PORTAL_SETTING_INVALID_VALUE, PORTAL_SETTING_NOT_FOUND, PORTAL_SETTING_OK, PORTAL_SETTING_READ_ONLY,
+};
+use portal_setting::{ChangeObserver, Config, ReadOnlyError, SettingChange, SettingsStore};
+use std::collections::HashMap;
+use std::ffi::{c_char, c_int, c_void, CString};
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, AtomicI64, Ordering};
+use std::sync::{Arc, Mutex};
+use tokio::runtime::Runtime;
+use zbus::zvariant::OwnedValue;
+
+/// A settings store in the calling process, created by
+/// `portal_setting_store_new`
+pub struct PortalSettingStore {
+    runtime: Runtime,
+    store: SettingsStore,
+    subscriptions: Mutex<HashMap<i64, Arc<Subscriber>>>,
+    next_subscription: AtomicI64,
+}
+
+impl PortalSettingStore {
+    fn new(store: SettingsStore) -> Result<Self> {
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .build()
+            .map_err(|e| Error::new(PORTAL_SETTING_ERROR, e.to_string()))?;
+        Ok(Self {
+            runtime,
+            store,
+            subscriptions: Mutex::new(HashMap::new()),
+            next_subscription: AtomicI64::new(1),
+        })
+    }
+
+    fn read(&self, namespace: &str, key: &str) -> Result<OwnedValue> {
+        self.runtime
+            .block_on(self.store.read(namespace, key))
+            .ok_or_else(|| Error::new(PORTAL_SETTING_NOT_FOUND, format!("{}/{} does not exist", namespace, key)))?
+            .to_owned_value()
+            .map_err(|e| Error::new(PORTAL_SETTING_ERROR, e.to_string()))
+    }
+}
+
+/// Observer forwarding changes to a C callback until unsubscribed
+struct Subscriber {
+    callback: PortalSettingChangeFn,
+    user_data: UserData,
+    active: AtomicBool,
+}
+
+impl ChangeObserver for Subscriber {
+    fn on_change(&self, change: &SettingChange) {
+        let Some(callback) = self.callback.filter(|_| self.active.load(Ordering::Acquire)) else {
+            return;
+        };
+        let value = match &change.new {
+            Some(value) => match to_text(&value.0) {
+                Ok(text) => Some(text),
+                Err(_) => return,
+            },
+            None => None,
+        };
+        let (namespace, key) = (CString::new(change.namespace.as_str()), CString::new(change.key.as_str()));
+        let (Ok(namespace), Ok(key)) = (namespace, key) else {
+            return;
+        };
+        let (signature, value) = match &value {
+            Some((signature, value)) => (signature.as_ptr(), value.as_ptr()),
+            None => (std::ptr::null(), std::ptr::null()),
+        };
+        // SAFETY: guaranteed by the caller of `portal_setting_store_subscribe`
+        unsafe { callback(self.user_data.get(), namespace.as_ptr(), key.as_ptr(), signature, value) };
+    }
+}
+
+unsafe fn store<'a>(store: *const PortalSettingStore) -> Result<&'a PortalSettingStore> {
+    store.as_ref().ok_or_else(|| Error::new(PORTAL_SETTING_ERROR, "store is NULL"))
+}
+
+/// Create a store with the built-in defaults, or the defaults and settings
+/// of the configuration file at `config_path`
+///
+/// Returns NULL on failure.
+///
+/// # Safety
+///
+/// `config_path` must be NULL or a NUL-terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn portal_setting_store_new(config_path: *const c_char) -> *mut PortalSettingStore {
+    let mut store = None;
+    status((|| {
+        let settings = match config_path.is_null() {
+            true => SettingsStore::new(),
+            false => {
+                let path = str_arg(config_path, "config_path")?;
+                Config::load(Path::new(path))
+                    .and_then(|config| SettingsStore::from_config(&config))
+                    .map_err(|e| Error::new(PORTAL_SETTING_ERROR, format!("{:#}", e)))?
+            }
+        };
+        store = Some(PortalSettingStore::new(settings)?);
+        Ok(())
+    })());
+    store.map_or(std::ptr::null_mut(), |s| Box::into_raw(Box::new(s)))
+}
+
+/// Release `store`
+///
+/// # Safety
+///
+/// `store` must be NULL or returned by `portal_setting_store_new`, and must
+/// not be used afterwards.
+#[no_mangle]
+pub unsafe extern "C" fn portal_setting_store_free(store: *mut PortalSettingStore) {
+    if !store.is_null() {
+        drop(Box::from_raw(store));
+    }
+}
+
+/// Read a setting as its signature and TOML text
+///
+/// Both strings must be released with `portal_setting_string_free`.
+///
+/// # Safety
+///
+/// `store` must come from `portal_setting_store_new`, the strings must be
+/// NUL-terminated and the output pointers valid for writes.
+#[no_mangle]
+pub unsafe extern "C" fn portal_setting_store_read(
+    store: *const PortalSettingStore,
+    namespace: *const c_char,
+    key: *const c_char,
+    signature: *mut *mut c_char,
+    value: *mut *mut c_char,
+) -> c_int {
+    status((|| {
+        let store = self::store(store)?;
+        let current = store.read(str_arg(namespace, "namespace")?, str_arg(key, "key")?)?;
+        let (sig, text) = to_text(&current)?;
+        if signature.is_null() || value.is_null() {
+            return Err(Error::new(PORTAL_SETTING_ERROR, "output pointer is NULL"));
+        }
+        out(signature, sig.into_raw())?;
+        out(value, text.into_raw())
+    })())
+}
+
+/// Validate and store a setting
+///
+/// `value` is TOML text of type `signature`; with a NULL `signature` the
+/// setting's current type is used. Subscribers are called before this
+/// returns, on the calling thread.
+///
+/// # Safety
+///
+/// `store` must come from `portal_setting_store_new` and the strings must be
+/// NULL (`signature` only) or NUL-terminated.
+#[no_mangle]
+pub unsafe extern "C" fn portal_setting_store_write(
+    store: *const PortalSettingStore,
+    namespace: *const c_char,
+    key: *const c_char,
+    signature: *const c_char,
+    value: *const c_char,
+) -> c_int {
+    status((|| {
+        let store = self::store(store)?;
+        let (namespace, key) = (str_arg(namespace, "namespace")?, str_arg(key, "key")?);
+        let text = str_arg(value, "value")?;
+        let signature = match signature.is_null() {
+            true => store.read(namespace, key)?.value_signature().to_string(),
+            false => str_arg(signature, "signature")?.to_string(),
+        };
+
+        let value = from_text(namespace, key, &signature, text)?;
+        let value = OwnedValue::try_from(value).map_err(|e| Error::new(PORTAL_SETTING_ERROR, e.to_string()))?;
+        store.runtime.block_on(store.store.write(namespace, key, value)).map_err(|e| match e.find::<ReadOnlyError>() {
+            Some(_) => Error::new(PORTAL_SETTING_READ_ONLY, format!("{:#}", e)),
+            None => Error::new(PORTAL_SETTING_INVALID_VALUE, format!("{:#}", e)),
+        })
+    })())
+}
+
+/// Call `callback` for every change of the store until unsubscribed
+///
+/// Callbacks run on the thread that made the change. Returns a subscription
+/// id greater than 0, or a negative status.
+///
+/// # Safety
+///
+/// `store` must come from `portal_setting_store_new`; `callback` must be safe
+/// to call from any thread with `user_data` until unsubscribed.
+#[no_mangle]
+pub unsafe extern "C" fn portal_setting_store_subscribe(
+    store: *const PortalSettingStore,
+    callback: PortalSettingChangeFn,
+    user_data: *mut c_void,
+) -> i64 {
+    let mut id = 0;
+    let result = status((|| {
+        let store = self::store(store)?;
+        if callback.is_none() {
+            return Err(Error::new(PORTAL_SETTING_ERROR, "callback is NULL"));
+        }
+        let subscriber = Arc::new(Subscriber {
+            callback,
+            user_data: UserData(user_data),
+            active: AtomicBool::new(true),
+        });
+        store.store.add_observer(subscriber.clone());
+
+        id = store.next_subscription.fetch_add(1, Ordering::Relaxed);
+        store.subscriptions.lock().unwrap().insert(id, subscriber);
+        Ok(())
+    })());
+    match result {
+        PORTAL_SETTING_OK => id,
+        status => status.into(),
+    }
+}
+
+/// End a subscription; the callback is not called after this returns
+/// unless it is already running
+///
+/// # Safety
+///
+/// `store` must come from `portal_setting_store_new`.
+#[no_mangle]
+pub unsafe extern "C" fn portal_setting_store_unsubscribe(
+    store: *const PortalSettingStore,
+    subscription: i64,
+) -> c_int {
+    status((|| {
+        let store = self::store(store)?;
+        let subscriber = store.subscriptions.lock().unwrap().remove(&subscription);
+        match subscriber {
+            // Observers cannot be removed from the store, so it stays registered but silent
+            Some(subscriber) => {
+                subscriber.active.store(false, Ordering::Release);
+                Ok(())
+            }
+            None => Err(Error::new(PORTAL_SETTING_ERROR, format!("no subscription {}", subscription))),
+        }
+    })())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::portal_setting_string_free;
+    use std::ffi::CStr;
+
+    unsafe extern "C" fn record_change(
+        user_data: *mut c_void,
+        namespace: *const c_char,
+        key: *const c_char,
+        signature: *const c_char,
+        value: *const c_char,
+    ) {
+        let changes = &*(user_data as *const Mutex<Vec<String>>);
+        let s = |p: *const c_char| match p.is_null() {
+            true => "NULL".to_string(),
+            false => CStr::from_ptr(p).to_string_lossy().into_owned(),
+        };
+        changes.lock().unwrap().push([namespace, key, signature, value].map(s).join(" "));
+    }
+
+    #[test]
+    fn test_store_read_write_subscribe() {
+        let changes = Mutex::new(Vec::<String>::new());
+        unsafe {
+            let store = portal_setting_store_new(std::ptr::null());
+            assert!(!store.is_null());
+
+            let user_data = &changes as *const _ as *mut c_void;
+            let subscription = portal_setting_store_subscribe(store, Some(record_change), user_data);
+            assert!(subscription > 0);
+
+            let (namespace, key) = (c"org.freedesktop.appearance".as_ptr(), c"accent-color".as_ptr());
+            assert_eq!(
+                portal_setting_store_write(store, namespace, key, std::ptr::null(), c"[0.2, 0.4, 0.6]".as_ptr()),
+                PORTAL_SETTING_OK
+            );
+            assert_eq!(
+                portal_setting_store_write(store, namespace, c"color-scheme".as_ptr(), c"u".as_ptr(), c"9".as_ptr()),
+                PORTAL_SETTING_INVALID_VALUE
+            );
+
+            let (mut signature, mut value) = (std::ptr::null_mut(), std::ptr::null_mut());
+            assert_eq!(portal_setting_store_read(store, namespace, key, &mut signature, &mut value), PORTAL_SETTING_OK);
+            assert_eq!(CStr::from_ptr(signature), c"(ddd)");
+            assert_eq!(CStr::from_ptr(value), c"[0.2, 0.4, 0.6]");
+            portal_setting_string_free(signature);
+            portal_setting_string_free(value);
+            assert_eq!(
+                portal_setting_store_read(store, c"org.example".as_ptr(), key, &mut signature, &mut value),
+                PORTAL_SETTING_NOT_FOUND
+            );
+
+            assert_eq!(portal_setting_store_unsubscribe(store, subscription), PORTAL_SETTING_OK);
+            portal_setting_store_write(store, namespace, key, std::ptr::null(), c"[0.0, 0.0, 0.0]".as_ptr());
+            portal_setting_store_free(store);
+        }
+        assert_eq!(*changes.lock().unwrap(), ["org.freedesktop.appearance accent-color (ddd) [0.2, 0.4, 0.6]"]);
+    }
+}
@@ -0,0 +1,111 @@
+use zbus::zvariant::{Structure, Value};
+
+/// Namespace of the standardized appearance settings
+pub const APPEARANCE_NAMESPACE: &str = "org.freedesktop.appearance";
+
+/// `color-scheme`; unknown values are read as `NoPreference`, as the portal
+/// specification requires
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ColorScheme {
+    #[default]
+    NoPreference,
+    PreferDark,
+    PreferLight,
+}
+
+impl From<u32> for ColorScheme {
+    fn from(value: u32) -> Self {
+        match value {
+            1 => ColorScheme::PreferDark,
+            2 => ColorScheme::PreferLight,
+            _ => ColorScheme::NoPreference,
+        }
+    }
+}
+
+impl From<ColorScheme> for u32 {
+    fn from(scheme: ColorScheme) -> Self {
+        match scheme {
+            ColorScheme::NoPreference => 0,
+            ColorScheme::PreferDark => 1,
+            ColorScheme::PreferLight => 2,
+        }
+    }
+}
+
+/// `contrast`; unknown values are read as `NoPreference`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Contrast {
+    #[default]
+    NoPreference,
+    High,
+}
+
+impl From<u32> for Contrast {
+    fn from(value: u32) -> Self {
+        match value {
+            1 => Contrast::High,
+            _ => Contrast::NoPreference,
+        }
+    }
+}
+
+impl From<Contrast> for u32 {
+    fn from(contrast: Contrast) -> Self {
+        match contrast {
+            Contrast::NoPreference => 0,
+            Contrast::High => 1,
+        }
+    }
+}
+
+/// `accent-color`, with components between 0 and 1
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct AccentColor {
+    pub red: f64,
+    pub green: f64,
+    pub blue: f64,
+}
+
+impl AccentColor {
+    /// `None` unless every component is between 0 and 1
+    pub fn new(red: f64, green: f64, blue: f64) -> Option<Self> {
+        [red, green, blue]
+            .iter()
+            .all(|c| (0.0..=1.0).contains(c))
+            .then_some(Self { red, green, blue })
+    }
+
+    /// Decode a `(ddd)` value; out-of-range colors mean the accent color is
+    /// unset
+    pub fn from_value(value: &Value<'_>) -> zbus::Result<Option<Self>> {
+        // Converting structures of fewer fields panics
+        if value.value_signature() != "(ddd)" {
+            return Err(zbus::zvariant::Error::IncorrectType.into());
+        }
+        let (red, green, blue) = <(f64, f64, f64)>::try_from(Structure::try_from(value.try_clone()?)?)?;
+        Ok(Self::new(red, green, blue))
+    }
+
+    pub fn to_tuple(self) -> (f64, f64, f64) {
+        (self.red, self.green, self.blue)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_appearance_values() {
+        assert_eq!(ColorScheme::from(7), ColorScheme::NoPreference);
+        assert_eq!(u32::from(ColorScheme::from(2)), 2);
+        assert_eq!(Contrast::from(1), Contrast::High);
+
+        let color = Value::from((0.2, 0.4, 0.6));
+        assert_eq!(AccentColor::from_value(&color).unwrap(), AccentColor::new(0.2, 0.4, 0.6));
+        assert_eq!(AccentColor::from_value(&Value::from((-1.0, 0.0, 0.0))).unwrap(), None);
+        assert!(AccentColor::from_value(&Value::U32(1)).is_err());
+        assert!(AccentColor::from_value(&Value::from((0.5,))).is_err());
+    }
+}
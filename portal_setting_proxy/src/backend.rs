@@ -0,0 +1,23 @@
+use std::collections::HashMap;
+use zbus::proxy;
+use zbus::zvariant::OwnedValue;
+
+/// The `org.freedesktop.impl.portal.Settings` backend interface
+#[proxy(
+    interface = "org.freedesktop.impl.portal.Settings",
+    default_service = "org.freedesktop.impl.portal.Settings",
+    default_path = "/org/freedesktop/portal/desktop"
+)]
+pub trait ImplSettings {
+    /// All settings, or those in `namespaces`
+    fn read_all(&self, namespaces: &[&str]) -> zbus::Result<HashMap<String, HashMap<String, OwnedValue>>>;
+
+    /// A single setting
+    fn read(&self, namespace: &str, key: &str) -> zbus::Result<OwnedValue>;
+
+    /// Emitted when a setting changes
+    #[zbus(signal)]
+    fn setting_changed(&self, namespace: String, key: String, value: OwnedValue) -> zbus::Result<()>;
+}
+
+appearance_getters!(ImplSettingsProxy, read);
@@ -0,0 +1,62 @@
+use zbus::fdo;
+
+/// What a failed call means for the caller
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorKind {
+    /// The requested setting does not exist
+    NotFound,
+    /// The service rejected a value
+    InvalidValue,
+    /// The setting cannot be changed
+    ReadOnly,
+    /// The bus or the service cannot be reached
+    Unavailable,
+    Other,
+}
+
+impl ErrorKind {
+    pub fn of(error: &zbus::Error) -> Self {
+        match error {
+            zbus::Error::MethodError(name, message, _) => {
+                let message = message.as_deref().unwrap_or_default();
+                match name.as_str() {
+                    n if n.ends_with(".NotFound") => ErrorKind::NotFound,
+                    // The impl interface of earlier versions, and with `[compat]
+                    // failed_not_found`, reports missing settings as a failure
+                    "org.freedesktop.DBus.Error.Failed" if message.contains("not found") => ErrorKind::NotFound,
+                    "org.freedesktop.DBus.Error.InvalidArgs" => ErrorKind::InvalidValue,
+                    "org.freedesktop.DBus.Error.AccessDenied" => ErrorKind::ReadOnly,
+                    "org.freedesktop.DBus.Error.ServiceUnknown"
+                    | "org.freedesktop.DBus.Error.NameHasNoOwner"
+                    | "org.freedesktop.DBus.Error.NoReply"
+                    | "org.freedesktop.DBus.Error.Disconnected"
+                    | "org.freedesktop.DBus.Error.UnknownObject"
+                    | "org.freedesktop.DBus.Error.UnknownInterface" => ErrorKind::Unavailable,
+                    _ => ErrorKind::Other,
+                }
+            }
+            zbus::Error::FDO(e) => Self::of_fdo(e),
+            zbus::Error::Address(_) | zbus::Error::InputOutput(_) | zbus::Error::Handshake(_) => {
+                ErrorKind::Unavailable
+            }
+            _ => ErrorKind::Other,
+        }
+    }
+
+    pub fn of_fdo(error: &fdo::Error) -> Self {
+        match error {
+            fdo::Error::InvalidArgs(_) => ErrorKind::InvalidValue,
+            fdo::Error::AccessDenied(_) => ErrorKind::ReadOnly,
+            fdo::Error::ServiceUnknown(_)
+            | fdo::Error::NameHasNoOwner(_)
+            | fdo::Error::NoReply(_)
+            | fdo::Error::Disconnected(_)
+            | fdo::Error::UnknownObject(_)
+            | fdo::Error::UnknownInterface(_)
+            | fdo::Error::IOError(_) => ErrorKind::Unavailable,
+            fdo::Error::Failed(message) if message.contains("not found") => ErrorKind::NotFound,
+            fdo::Error::ZBus(e) => Self::of(e),
+            _ => ErrorKind::Other,
+        }
+    }
+}
@@ -0,0 +1,27 @@
+use std::collections::HashMap;
+use zbus::proxy;
+use zbus::zvariant::OwnedValue;
+
+/// The `org.freedesktop.portal.Settings` interface applications use
+#[proxy(
+    interface = "org.freedesktop.portal.Settings",
+    default_service = "org.freedesktop.portal.Desktop",
+    default_path = "/org/freedesktop/portal/desktop"
+)]
+pub trait Settings {
+    /// All settings, or those in `namespaces`
+    fn read_all(&self, namespaces: &[&str]) -> zbus::Result<HashMap<String, HashMap<String, OwnedValue>>>;
+
+    /// A single setting
+    fn read_one(&self, namespace: &str, key: &str) -> zbus::Result<OwnedValue>;
+
+    /// Emitted when a setting changes
+    #[zbus(signal)]
+    fn setting_changed(&self, namespace: String, key: String, value: OwnedValue) -> zbus::Result<()>;
+
+    /// Version of the interface
+    #[zbus(property, name = "version")]
+    fn version(&self) -> zbus::Result<u32>;
+}
+
+appearance_getters!(SettingsProxy, read_one);
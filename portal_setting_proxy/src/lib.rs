@@ -0,0 +1,236 @@
+//! Typed D-Bus proxies for the XDG Portal Settings service
+//!
+//! `SettingsProxy` talks to the `org.freedesktop.portal.Settings` frontend
+//! applications normally use, `ImplSettingsProxy` to the
+//! `org.freedesktop.impl.portal.Settings` backend interface and
+//! `ManagementProxy` to the service's management interface for changing
+//! settings. All three offer typed accessors for the
+//! `org.freedesktop.appearance` settings:
+//!
+//! ```no_run
+//! use portal_setting_proxy::{ColorScheme, ManagementProxy, SettingsProxy};
+//!
+//! # async fn example() -> zbus::Result<()> {
+//! let connection = zbus::Connection::session().await?;
+//! let settings = SettingsProxy::new(&connection).await?;
+//! if settings.color_scheme().await? == ColorScheme::PreferDark {
+//!     println!("dark");
+//! }
+//!
+//! let management = ManagementProxy::new(&connection).await?;
+//! management.set_color_scheme(ColorScheme::PreferLight).await?;
+//! # Ok(())
+//! # }
+//! ```
+
+/// Typed getters for the appearance settings, given the proxy's method for
+/// reading one setting
+macro_rules! appearance_getters {
+    ($proxy:ident, $read:ident) => {
+        impl $proxy<'_> {
+            /// The preferred color scheme
+            pub async fn color_scheme(&self) -> zbus::Result<$crate::ColorScheme> {
+                let value = self.$read($crate::APPEARANCE_NAMESPACE, "color-scheme").await?;
+                Ok($crate::ColorScheme::from(u32::try_from(value)?))
+            }
+
+            /// The accent color, or `None` if it is unset
+            pub async fn accent_color(&self) -> zbus::Result<Option<$crate::AccentColor>> {
+                let value = self.$read($crate::APPEARANCE_NAMESPACE, "accent-color").await?;
+                $crate::AccentColor::from_value(&value)
+            }
+
+            /// The preferred contrast
+            pub async fn contrast(&self) -> zbus::Result<$crate::Contrast> {
+                let value = self.$read($crate::APPEARANCE_NAMESPACE, "contrast").await?;
+                Ok($crate::Contrast::from(u32::try_from(value)?))
+            }
+        }
+    };
+}
+
+mod appearance;
+pub mod backend;
+mod error;
+pub mod frontend;
+pub mod management;
+
+pub use appearance::{AccentColor, ColorScheme, Contrast, APPEARANCE_NAMESPACE};
+pub use backend::ImplSettingsProxy;
+pub use error::ErrorKind;
+pub use frontend::SettingsProxy;
+pub use management::ManagementProxy;
+
+/// Object path of every interface, by default
+pub const PATH: &str = "/org/freedesktop/portal/desktop";
+
+/// Object path of the management interface when the service only lets some
+/// callers use it, e.g. `ManagementProxy::builder(&connection).path(MANAGEMENT_PATH)`
+pub const MANAGEMENT_PATH: &str = "/io/github/meta_flutter/PortalSettings";
+
+/// Bus name the service owns by default
+pub const SERVICE_BUS_NAME: &str = "org.freedesktop.impl.portal.Settings";
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures_util::StreamExt;
+    use portal_setting::testing::TestPortal;
+    use portal_setting::{SettingsStore, ValidationError};
+    use std::collections::HashMap;
+    use zbus::zvariant::OwnedValue;
+
+    #[tokio::test]
+    async fn test_typed_accessors() {
+        let portal = TestPortal::p2p(SettingsStore::new()).await.unwrap();
+        let settings = SettingsProxy::builder(&portal.client).destination(SERVICE_BUS_NAME).unwrap().build().await.unwrap();
+        let backend = ImplSettingsProxy::new(&portal.client).await.unwrap();
+        let management = ManagementProxy::new(&portal.client).await.unwrap();
+
+        assert_eq!(settings.version().await.unwrap(), 2);
+        assert_eq!(settings.color_scheme().await.unwrap(), ColorScheme::NoPreference);
+        assert_eq!(backend.contrast().await.unwrap(), Contrast::NoPreference);
+
+        let mut changes = backend.receive_setting_changed().await.unwrap();
+        management.set_color_scheme(ColorScheme::PreferDark).await.unwrap();
+        let change = changes.next().await.unwrap();
+        let args = change.args().unwrap();
+        assert_eq!((args.namespace(), args.key()), (&APPEARANCE_NAMESPACE.to_string(), &"color-scheme".to_string()));
+        assert_eq!(ColorScheme::from(u32::try_from(args.value()).unwrap()), ColorScheme::PreferDark);
+        assert_eq!(backend.color_scheme().await.unwrap(), ColorScheme::PreferDark);
+
+        let teal = AccentColor::new(0.13, 0.56, 0.64).unwrap();
+        management.set_accent_color(teal).await.unwrap();
+        assert_eq!(settings.accent_color().await.unwrap(), Some(teal));
+
+        let all = settings.read_all(&[APPEARANCE_NAMESPACE]).await.unwrap();
+        assert_eq!(all.len(), 1);
+        assert!(management.set_contrast(Contrast::High).await.is_ok());
+        let error = management
+            .write(APPEARANCE_NAMESPACE, "contrast", &zbus::zvariant::Value::from("high"))
+            .await
+            .unwrap_err();
+        assert_eq!(ErrorKind::of(&error), ErrorKind::InvalidValue);
+        let zbus::Error::MethodError(_, Some(message), _) = error else { panic!("{}", error) };
+        let invalid = ValidationError::parse(&message).unwrap();
+        assert_eq!((invalid.expected.as_str(), invalid.received.as_str()), ("u", "s"));
+        assert_eq!(invalid.constraint.as_deref(), Some("0..=1"));
+        assert!(management.reset(APPEARANCE_NAMESPACE, "contrast").await.unwrap());
+
+        let (generation, complete, changes) = management.get_recent_changes(3).await.unwrap();
+        assert_eq!((generation, complete), (5, true));
+        let contrasts: Vec<u32> = changes
+            .iter()
+            .map(|(_, _, key, value)| {
+                assert_eq!(key, "contrast");
+                u32::try_from(&value[0]).unwrap()
+            })
+            .collect();
+        assert_eq!(contrasts, [Contrast::High.into(), Contrast::NoPreference.into()]);
+
+        let (generation, modified, all) = management.read_all_if_changed(&[APPEARANCE_NAMESPACE], 0).await.unwrap();
+        assert!(modified);
+        assert_eq!(all.len(), 1);
+        let (_, modified, all) = management.read_all_if_changed(&[APPEARANCE_NAMESPACE], generation).await.unwrap();
+        assert!(!modified);
+        assert!(all.is_empty());
+
+        let description = management.describe_key(APPEARANCE_NAMESPACE, "contrast").await.unwrap();
+        assert_eq!(<&str>::try_from(&description["type"]).unwrap(), "u");
+        assert!(!bool::try_from(&description["read-only"]).unwrap());
+        assert_eq!(u32::try_from(&description["default"]).unwrap(), 0);
+        assert!(description.contains_key("description"));
+        let error = management.describe_key("com.example", "missing").await.unwrap_err();
+        assert_eq!(ErrorKind::of(&error), ErrorKind::InvalidValue);
+
+        let mut batches = management.receive_settings_changed_batch().await.unwrap();
+        let keys = HashMap::from([("a", 1u32), ("b", 2)].map(|(key, value)| (key.to_string(), OwnedValue::from(value))));
+        management.write_many(&HashMap::from([("com.example".to_string(), keys)])).await.unwrap();
+        let batch = batches.next().await.unwrap();
+        let mut keys: Vec<_> = batch.args().unwrap().changes().iter().map(|(_, key, _)| key.clone()).collect();
+        keys.sort();
+        assert_eq!(keys, ["a", "b"]);
+    }
+
+    #[tokio::test]
+    async fn test_read_only_keys() {
+        let config = portal_setting::Config::parse("[schema.\"com.example\"]\nlocked = { type = \"u\", read_only = true }\n");
+        let store = SettingsStore::from_config(&config.unwrap()).unwrap();
+        let portal = TestPortal::p2p(store).await.unwrap();
+        let management = ManagementProxy::new(&portal.client).await.unwrap();
+
+        let error = management.write("com.example", "locked", &zbus::zvariant::Value::U32(1)).await.unwrap_err();
+        assert_eq!(ErrorKind::of(&error), ErrorKind::ReadOnly);
+        let error = management.delete("com.example", "locked").await.unwrap_err();
+        assert_eq!(ErrorKind::of(&error), ErrorKind::ReadOnly);
+        let description = management.describe_key("com.example", "locked").await.unwrap();
+        assert!(bool::try_from(&description["read-only"]).unwrap());
+        assert!(!description.contains_key("default"));
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 8)]
+    async fn test_stress_over_dbus() {
+        const WRITERS: u32 = 20;
+        const WRITES: u32 = 25;
+        const SUBSCRIBERS: usize = 10;
+        let total = (WRITERS * WRITES) as usize;
+
+        let portal = TestPortal::p2p(SettingsStore::new()).await.unwrap();
+        let backend = ImplSettingsProxy::new(&portal.client).await.unwrap();
+        let management = ManagementProxy::new(&portal.client).await.unwrap();
+
+        // Subscribed before any write, so every signal must arrive
+        let mut subscribers = Vec::new();
+        for _ in 0..SUBSCRIBERS {
+            let mut changes = backend.receive_setting_changed().await.unwrap();
+            subscribers.push(tokio::spawn(async move {
+                let mut keys = std::collections::HashSet::new();
+                while keys.len() < total {
+                    let change = changes.next().await.unwrap();
+                    keys.insert(change.args().unwrap().key().clone());
+                }
+                keys
+            }));
+        }
+        let done = std::sync::Arc::new(std::sync::atomic::AtomicBool::new(false));
+        let readers: Vec<_> = (0..100)
+            .map(|_| {
+                let (backend, done) = (backend.clone(), done.clone());
+                tokio::spawn(async move {
+                    while !done.load(std::sync::atomic::Ordering::SeqCst) {
+                        backend.read_all(&[APPEARANCE_NAMESPACE]).await.unwrap();
+                        backend.read(APPEARANCE_NAMESPACE, "contrast").await.unwrap();
+                    }
+                })
+            })
+            .collect();
+        let writers: Vec<_> = (0..WRITERS)
+            .map(|w| {
+                let management = management.clone();
+                tokio::spawn(async move {
+                    for i in 0..WRITES {
+                        let key = format!("writer-{}-{}", w, i);
+                        management.write("com.example.stress", &key, &i.into()).await.unwrap();
+                    }
+                })
+            })
+            .collect();
+
+        let run = async {
+            for writer in writers {
+                writer.await.unwrap();
+            }
+            done.store(true, std::sync::atomic::Ordering::SeqCst);
+            for reader in readers {
+                reader.await.unwrap();
+            }
+            for subscriber in subscribers {
+                assert_eq!(subscriber.await.unwrap().len(), total);
+            }
+        };
+        tokio::time::timeout(std::time::Duration::from_secs(60), run)
+            .await
+            .expect("calls or signals stalled");
+        assert_eq!(backend.read_all(&["com.example.stress"]).await.unwrap()["com.example.stress"].len(), total);
+    }
+}
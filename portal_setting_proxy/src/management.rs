@@ -0,0 +1,97 @@
+use crate::{AccentColor, ColorScheme, Contrast, APPEARANCE_NAMESPACE};
+use std::collections::HashMap;
+use zbus::proxy;
+use zbus::zvariant::{OwnedValue, Value};
+
+/// Settings by namespace and key
+pub type Settings = HashMap<String, HashMap<String, OwnedValue>>;
+
+/// A change reported by `get_recent_changes`: generation, namespace, key, and
+/// the new value unless the key was removed
+pub type RecentChange = (u64, String, String, Vec<OwnedValue>);
+
+/// The service's interface for changing settings
+#[proxy(
+    interface = "io.github.meta_flutter.PortalSettings.Management",
+    default_service = "org.freedesktop.impl.portal.Settings",
+    default_path = "/org/freedesktop/portal/desktop"
+)]
+pub trait Management {
+    /// Validate and store a setting
+    fn write(&self, namespace: &str, key: &str, value: &Value<'_>) -> zbus::Result<()>;
+
+    /// `write` with options, e.g. `session-scoped` (b) to delete a key this
+    /// call creates once the connection closes, `login-session` (b) to keep
+    /// the value until the caller's login session ends, or `migrate` (b) to
+    /// change the type of a key
+    fn write_with_options(
+        &self,
+        namespace: &str,
+        key: &str,
+        value: &Value<'_>,
+        options: HashMap<&str, &Value<'_>>,
+    ) -> zbus::Result<()>;
+
+    /// Validate every setting, then store all of them or none
+    fn write_many(&self, settings: &HashMap<String, HashMap<String, OwnedValue>>) -> zbus::Result<()>;
+
+    /// Remove a setting, returning whether it existed
+    fn delete(&self, namespace: &str, key: &str) -> zbus::Result<bool>;
+
+    /// Restore a setting to its default, returning whether anything changed
+    fn reset(&self, namespace: &str, key: &str) -> zbus::Result<bool>;
+
+    /// Where each current value comes from: `builtin`, `backend`, `config`
+    /// or `runtime`
+    fn layers(&self, namespaces: &[&str]) -> zbus::Result<HashMap<String, HashMap<String, String>>>;
+
+    /// Generation of the last change to each namespace; unchanged
+    /// generations mean unchanged settings
+    fn generations(&self, namespaces: &[&str]) -> zbus::Result<HashMap<String, u64>>;
+
+    /// The generation to pass next time, whether any of `namespaces` changed
+    /// after `known_generation`, and their settings if so
+    fn read_all_if_changed(&self, namespaces: &[&str], known_generation: u64) -> zbus::Result<(u64, bool, Settings)>;
+
+    /// The current generation, whether the journal still holds every change
+    /// since `since_generation`, and those changes as generation, namespace,
+    /// key and new value (empty for removed keys)
+    fn get_recent_changes(&self, since_generation: u64) -> zbus::Result<(u64, bool, Vec<RecentChange>)>;
+
+    /// How many queued signals were `emitted`, `coalesced` or `dropped`
+    fn signal_stats(&self) -> zbus::Result<HashMap<String, u64>>;
+
+    /// What is known about a setting: its `type` (s), whether it is
+    /// `read-only` and `sensitive` (b), and its `description`, `default` and `constraint`
+    /// when it has them
+    fn describe_key(&self, namespace: &str, key: &str) -> zbus::Result<HashMap<String, OwnedValue>>;
+
+    /// `namespace/key` globs of the settings whose values are kept out of
+    /// logs and exports
+    fn sensitive_keys(&self) -> zbus::Result<Vec<String>>;
+
+    /// Check a value without storing it
+    fn validate_write(&self, namespace: &str, key: &str, value: &Value<'_>) -> zbus::Result<()>;
+
+    /// Several settings changed together, as namespace, key and new value,
+    /// after `SettingChanged` for each
+    #[zbus(signal)]
+    fn settings_changed_batch(&self, changes: Vec<(String, String, OwnedValue)>) -> zbus::Result<()>;
+}
+
+impl ManagementProxy<'_> {
+    pub async fn set_color_scheme(&self, scheme: ColorScheme) -> zbus::Result<()> {
+        self.write(APPEARANCE_NAMESPACE, "color-scheme", &Value::U32(scheme.into()))
+            .await
+    }
+
+    pub async fn set_accent_color(&self, color: AccentColor) -> zbus::Result<()> {
+        self.write(APPEARANCE_NAMESPACE, "accent-color", &Value::from(color.to_tuple()))
+            .await
+    }
+
+    pub async fn set_contrast(&self, contrast: Contrast) -> zbus::Result<()> {
+        self.write(APPEARANCE_NAMESPACE, "contrast", &Value::U32(contrast.into()))
+            .await
+    }
+}